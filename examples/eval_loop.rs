@@ -1,6 +1,7 @@
+use calculator_max::calculator::ast::Expr;
+use calculator_max::calculator::context::Context;
 use calculator_max::calculator::evaluator::Evaluator;
 use calculator_max::calculator::math_functions::*;
-use meval::Context;
 use std::time::Instant;
 
 fn bench_evaluator_parse_and_eval(ev: &mut Evaluator, expr: &str, iterations: usize) -> f64 {
@@ -16,7 +17,12 @@ fn bench_evaluator_parse_and_eval(ev: &mut Evaluator, expr: &str, iterations: us
     sum
 }
 
-fn make_context() -> Context<'static> {
+fn print_cache_stats(ev: &Evaluator) {
+    println!("  ast cache:    {}", ev.ast_cache_stats());
+    println!("  result cache: {}", ev.result_cache_stats());
+}
+
+fn make_context() -> Context {
     let mut ctx = Context::new();
     ctx.var("pi", pi());
     ctx.var("e", e());
@@ -52,7 +58,7 @@ fn make_context() -> Context<'static> {
 
 fn bench_parse_then_eval_with_context(expr: &str, iterations: usize, ctx: &Context) -> f64 {
     let mut sum = 0.0_f64;
-    if let Ok(parsed) = expr.parse::<meval::Expr>() {
+    if let Ok(parsed) = expr.parse::<Expr>() {
         let start = Instant::now();
         for _ in 0..iterations {
             if let Ok(v) = parsed.eval_with_context(ctx) {
@@ -73,15 +79,20 @@ fn main() {
         "sqrt(12345.6789)",
         "factorial(10)",
         "s_rect(3,4)",
+        // Impure and memory-dependent expressions are re-evaluated every time
+        "random()",
+        "m * 0.5 + 1",
     ];
 
-    let iterations = 200_00; // 200k
+    let iterations = 20_000;
     let mut total = 0.0_f64;
 
     let ctx = make_context();
 
     for e in exprs.iter() {
+        ev.clear_caches();
         total += bench_evaluator_parse_and_eval(&mut ev, e, iterations);
+        print_cache_stats(&ev);
         total += bench_parse_then_eval_with_context(e, iterations, &ctx);
     }
     println!("total checksum={}", total);
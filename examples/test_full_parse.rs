@@ -1,4 +1,4 @@
-use calculator_max::calculator::mods::{Mod, ModCalc, ModDesc, ModRes, ModVar, SimplifiedMod};
+use calculator_max::calculator::mods::SimplifiedMod;
 
 fn main() {
     let content = r#"[a.b.c]
@@ -58,26 +58,17 @@ res = 1.0
                         println!("    Res: {:?}", simplified_mod.res);
 
                         // Convert to legacy Mod structure for compatibility
-                        let legacy_mod = Mod {
-                            desc: ModDesc {
-                                name: simplified_mod.name.clone(),
-                            },
-                            var: ModVar {
-                                needvars: simplified_mod.needs.unwrap_or_else(Vec::new),
-                            },
-                            calc: ModCalc {
-                                howto: simplified_mod.method,
-                            },
-                            res: ModRes {
-                                res: simplified_mod.res,
-                            },
-                        };
-
-                        println!("  Converted to legacy Mod:");
-                        println!("    Desc name: {:?}", legacy_mod.desc.name);
-                        println!("    Calc howto: {:?}", legacy_mod.calc.howto);
-                        println!("    Res res: {:?}", legacy_mod.res.res);
-                        println!("    Var needvars: {:?}", legacy_mod.var.needvars);
+                        match simplified_mod.into_mod() {
+                            Ok(legacy_mod) => {
+                                println!("  Converted to legacy Mod:");
+                                println!("    Type: {:?}", legacy_mod.mod_type);
+                                println!("    Desc name: {:?}", legacy_mod.desc.name);
+                                println!("    Calc howto: {:?}", legacy_mod.calc.howto);
+                                println!("    Res res: {:?}", legacy_mod.res.res);
+                                println!("    Var needvars: {:?}", legacy_mod.var.needvars);
+                            }
+                            Err(reason) => eprintln!("  Invalid mod: {}", reason),
+                        }
                     }
                     Err(e) => {
                         eprintln!("  Failed to parse: {}", e);
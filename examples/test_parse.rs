@@ -1,4 +1,4 @@
-use calculator_max::calculator::mods::{Mod, SimplifiedMod};
+use calculator_max::calculator::mods::SimplifiedMod;
 
 fn main() {
     let content = r#"[a.b.c]
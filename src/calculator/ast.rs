@@ -0,0 +1,294 @@
+//! Abstract syntax tree for calculator expressions
+
+use std::fmt;
+
+use num_bigint::BigInt;
+
+use super::math_functions::AngleMode;
+use super::units::format_factors;
+
+/// Byte range of a node or token in the source expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates a new span
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span covering both spans
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Prefix operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    BitNot,
+}
+
+/// Infix operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+/// Postfix operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostfixOp {
+    Factorial,
+}
+
+impl UnaryOp {
+    /// Source symbol of the operator
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::BitNot => "~",
+        }
+    }
+}
+
+impl BinaryOp {
+    /// Source symbol of the operator
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "xor",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+        }
+    }
+}
+
+impl BinaryOp {
+    /// Whether the operator works on the bits of integers, like `&` and `<<`
+    pub fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr
+        )
+    }
+}
+
+impl PostfixOp {
+    /// Source symbol of the operator
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PostfixOp::Factorial => "!",
+        }
+    }
+}
+
+/// Unit expression such as `m/s^2`: unit names with integer powers
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitExpr {
+    pub factors: Vec<(String, i32)>,
+    pub span: Span,
+}
+
+impl fmt::Display for UnitExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_factors(&self.factors))
+    }
+}
+
+/// Kind of an expression node
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    /// Numeric literal
+    Number(f64),
+
+    /// Hexadecimal, octal or binary literal such as `0xFF`, exact at any size
+    Integer(BigInt),
+
+    /// Imaginary literal such as `4i`, only valid in complex mode
+    Imaginary(f64),
+
+    /// Literal with an angle suffix such as `30°`, converted to the angle mode
+    Angle(f64, AngleMode),
+
+    /// Text in double quotes, e.g. the file name in `import_csv("data.csv", 1)`
+    Text(String),
+
+    /// Variable or constant reference (may be dotted, e.g. `a.b.c`)
+    Ident(String),
+
+    /// Prefix operation
+    Unary { op: UnaryOp, expr: Box<Expr> },
+
+    /// Infix operation
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+
+    /// Postfix operation
+    Postfix { op: PostfixOp, expr: Box<Expr> },
+
+    /// Function call with any number of arguments
+    Call { name: String, args: Vec<Expr> },
+
+    /// List literal such as `[1, 2, 3.5]`
+    List(Vec<Expr>),
+
+    /// Value followed by a unit, e.g. `5 km`
+    Quantity { expr: Box<Expr>, unit: UnitExpr },
+
+    /// Unit conversion, e.g. `60 mph to m/s`
+    Convert { expr: Box<Expr>, unit: UnitExpr },
+
+    /// Equation such as `cos(x) = x`, only valid as an argument of a function that takes its
+    /// arguments unevaluated, e.g. `solve`
+    Equation { lhs: Box<Expr>, rhs: Box<Expr> },
+
+    /// Range such as `0..10`, only valid as an argument of a function that takes its arguments
+    /// unevaluated, e.g. the time span of `ode`
+    Range { start: Box<Expr>, end: Box<Expr> },
+}
+
+/// Expression node together with its location in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    /// Creates a new expression node
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Expr {
+    /// Calls `f` on this node and every node below it, parents first
+    pub fn visit<F: FnMut(&Expr)>(&self, f: &mut F) {
+        f(self);
+        match &self.kind {
+            ExprKind::Number(_)
+            | ExprKind::Integer(_)
+            | ExprKind::Imaginary(_)
+            | ExprKind::Angle(..)
+            | ExprKind::Text(_)
+            | ExprKind::Ident(_) => {}
+            ExprKind::Unary { expr, .. }
+            | ExprKind::Postfix { expr, .. }
+            | ExprKind::Quantity { expr, .. }
+            | ExprKind::Convert { expr, .. } => expr.visit(f),
+            ExprKind::Binary { lhs, rhs, .. }
+            | ExprKind::Equation { lhs, rhs }
+            | ExprKind::Range { start: lhs, end: rhs } => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+            ExprKind::Call { args, .. } | ExprKind::List(args) => args.iter().for_each(|arg| arg.visit(f)),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Number(n) => write!(f, "{}", n),
+            ExprKind::Integer(n) => write!(f, "{}", n),
+            ExprKind::Imaginary(n) => write!(f, "{}i", n),
+            ExprKind::Angle(n, unit) => write!(f, "{}{}", n, unit.suffix()),
+            ExprKind::Text(text) => write!(f, "\"{}\"", text),
+            ExprKind::Ident(name) => write!(f, "{}", name),
+            ExprKind::Unary { op, expr } => write!(f, "({}{})", op.symbol(), expr),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+            ExprKind::Postfix { op, expr } => write!(f, "({}{})", expr, op.symbol()),
+            ExprKind::Quantity { expr, unit } => write!(f, "({} {})", expr, unit),
+            ExprKind::Convert { expr, unit } => write!(f, "({} to {})", expr, unit),
+            ExprKind::Equation { lhs, rhs } => write!(f, "{} = {}", lhs, rhs),
+            ExprKind::Range { start, end } => write!(f, "{}..{}", start, end),
+            ExprKind::Call { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// A top-level statement of the input line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// Expression whose value is the statement result
+    Expr(Expr),
+
+    /// `name = value`, binds a user variable
+    Assign { name: String, name_span: Span, value: Expr },
+
+    /// `name(params) = body`, defines a user function; `body_source` keeps the body as typed
+    Function {
+        name: String,
+        name_span: Span,
+        params: Vec<String>,
+        body: Expr,
+        body_source: String,
+    },
+}
+
+impl Stmt {
+    /// Source range covered by the statement
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr(expr) => expr.span,
+            Stmt::Assign { name_span, value, .. } => name_span.to(value.span),
+            Stmt::Function { name_span, body, .. } => name_span.to(body.span),
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Expr(expr) => write!(f, "{}", expr),
+            Stmt::Assign { name, value, .. } => write!(f, "{} = {}", name, value),
+            Stmt::Function { name, params, body, .. } => write!(f, "{}({}) = {}", name, params.join(", "), body),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Bounded least-recently-used cache with hit/miss statistics
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+
+/// Hit/miss counters and occupancy of a cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that were hits, 0 when nothing was looked up yet
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} evictions, {}/{} entries",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0,
+            self.evictions,
+            self.len,
+            self.capacity
+        )
+    }
+}
+
+/// Map that keeps at most `capacity` entries, evicting the least recently used one
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Entries by the tick of their last use, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Creates an empty cache; a capacity of 0 disables caching
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Looks up an entry and marks it as recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_if(key, |_| true)
+    }
+
+    /// Looks up an entry that is still valid; invalid entries are dropped and count as misses
+    pub fn get_if<Q, F>(&mut self, key: &Q, is_valid: F) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        let valid = self.entries.get(key).map(|(value, _)| is_valid(value));
+        match valid {
+            Some(true) => {
+                self.hits += 1;
+                self.tick += 1;
+                let tick = self.tick;
+                let (_, last_used) = self.entries.get_mut(key)?;
+                let owner = self.order.remove(last_used)?;
+                *last_used = tick;
+                self.order.insert(tick, owner);
+                self.entries.get(key).map(|(value, _)| value)
+            }
+            Some(false) => {
+                self.misses += 1;
+                self.remove(key);
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Inserts or replaces an entry, evicting the least recently used one when full
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            self.evictions += 1;
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    /// Removes an entry, returning its value if it existed
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, last_used) = self.entries.remove(key)?;
+        self.order.remove(&last_used);
+        Some(value)
+    }
+
+    /// Removes every entry; statistics are kept
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    /// Changes the capacity, evicting the least recently used entries if needed
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            self.evictions += 1;
+        }
+    }
+
+    /// Resets the hit, miss and eviction counters
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+        self.evictions = 0;
+    }
+
+    /// Current statistics
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            len: self.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        assert_eq!(cache.get("a"), Some(&1));
+
+        // "b" is now the oldest entry
+        cache.insert("c".to_string(), 3);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.get("c"), Some(&3));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 1, 1));
+        assert_eq!((stats.len, stats.capacity), (2, 2));
+    }
+
+    #[test]
+    fn test_invalid_entries_are_misses() {
+        let mut cache = LruCache::new(4);
+        cache.insert("x".to_string(), 1);
+        assert_eq!(cache.get_if("x", |value| *value == 2), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().misses, 1);
+
+        cache.insert("y".to_string(), 2);
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+        cache.insert("z".to_string(), 3);
+        assert_eq!(cache.len(), 0);
+    }
+}
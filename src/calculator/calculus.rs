@@ -0,0 +1,442 @@
+//! Numerical calculus over expressions: derivatives, definite integrals, sums and products
+//!
+//! Each function takes an expression and the name of the variable it varies, which is bound only
+//! while the expression is evaluated, e.g. `integrate(sin(x), x, 0, pi)`. Derivatives use Ridders'
+//! extrapolation of central differences and integrals adaptive 15-point Gauss–Kronrod quadrature;
+//! both report an error when their error estimate stays above the tolerance.
+//!
+//! Sums and products run over whole numbers and are exact when their terms are, e.g.
+//! `sum(1/k^2, k, 1, 3)` is `49/36`. A `sum` or `prod` of four arguments is such a sum or product
+//! when the second is a plain name that the first mentions; otherwise it adds or multiplies its
+//! arguments.
+
+use std::cell::RefCell;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use super::ast::{BinaryOp, Expr, ExprKind, Span};
+use super::context::{Arity, Scope};
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::value::Value;
+
+/// Most terms a sum or product may have
+pub const MAX_TERMS: i64 = 1_000_000;
+
+/// Most subintervals adaptive integration splits the range into before giving up
+const MAX_SUBINTERVALS: usize = 2000;
+
+/// Nodes of the 15-point Kronrod rule on [-1, 1], positive half, the middle last
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.0,
+];
+
+/// Weights of the 15-point Kronrod rule, matching `KRONROD_NODES`
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_2,
+    0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_8,
+];
+
+/// Weights of the embedded 7-point Gauss rule, whose nodes are every other Kronrod node
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+/// A calculus function callable from expressions
+pub struct CalculusFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result from the unevaluated arguments and the span of the call
+    pub eval: fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value>,
+}
+
+/// All calculus functions, registered by the evaluator
+///
+/// Sums and products over a variable extend the variadic `sum` and `prod` through
+/// [`bounded_sum`] and [`bounded_prod`] instead.
+pub const FUNCTIONS: &[CalculusFn] = &[
+    CalculusFn {
+        name: "deriv",
+        params: "expr, x, at",
+        arity: Arity::Exact(3),
+        eval: deriv,
+    },
+    CalculusFn {
+        name: "integrate",
+        params: "expr, x, a, b",
+        arity: Arity::Exact(4),
+        eval: integrate,
+    },
+];
+
+/// `sum(expr, k, from, to)`, attached to the variadic `sum`
+///
+/// Returns `None`, leaving the call to the ordinary `sum`, unless the call [`is_bounded`].
+pub fn bounded_sum(scope: &dyn Scope, args: &[Expr], span: Span) -> Option<CalcResult<Value>> {
+    bounded(scope, args, span, BinaryOp::Add)
+}
+
+/// `prod(expr, k, from, to)`, attached to the variadic `prod` the same way as [`bounded_sum`]
+pub fn bounded_prod(scope: &dyn Scope, args: &[Expr], span: Span) -> Option<CalcResult<Value>> {
+    bounded(scope, args, span, BinaryOp::Mul)
+}
+
+fn bounded(scope: &dyn Scope, args: &[Expr], span: Span, op: BinaryOp) -> Option<CalcResult<Value>> {
+    is_bounded(args).then(|| series(scope, args, span, op))
+}
+
+/// Whether `sum` or `prod` with these arguments runs over a bound variable
+///
+/// That is the case exactly when there are four arguments, the second is a plain name and the
+/// first mentions it, as in `sum(k^2, k, 1, 10)`. This depends only on how the call is written,
+/// never on which variables exist, so `sum(a, b, c, d)` always adds four numbers and a constant
+/// term is written with the name, e.g. `sum(0*k + 5, k, 1, 10)`.
+pub fn is_bounded(args: &[Expr]) -> bool {
+    let [expr, variable, _, _] = args else {
+        return false;
+    };
+    match &variable.kind {
+        ExprKind::Ident(name) => !name.contains('.') && uses(expr, name),
+        _ => false,
+    }
+}
+
+fn uses(expr: &Expr, name: &str) -> bool {
+    let mut found = false;
+    expr.visit(&mut |node| {
+        found |= matches!(&node.kind, ExprKind::Ident(ident) if ident == name);
+    });
+    found
+}
+
+/// Sum or product of `expr` over whole numbers `from..=to`; empty ranges give 0 or 1
+fn series(scope: &dyn Scope, args: &[Expr], span: Span, op: BinaryOp) -> CalcResult<Value> {
+    let function = if op == BinaryOp::Add { "sum" } else { "prod" };
+    let name = variable(function, &args[1], "e.g. sum(k^2, k, 1, 10)")?;
+    let from = whole_argument(scope, function, &args[2], "the start")?;
+    let to = whole_argument(scope, function, &args[3], "the end")?;
+    if to.saturating_sub(from) >= MAX_TERMS {
+        return Err(invalid_argument(
+            function,
+            &format!("at most {} terms are allowed", MAX_TERMS),
+            span,
+        ));
+    }
+
+    let mut result = Value::Integer(BigInt::from(u8::from(op == BinaryOp::Mul)));
+    for k in from..=to {
+        let term = scope.eval_with(&args[0], &[(name.to_string(), Value::Integer(BigInt::from(k)))])?;
+        result = scope.combine(op, result, term, span)?;
+    }
+    Ok(result)
+}
+
+/// `deriv(expr, x, at)`, the first derivative at a point
+fn deriv(scope: &dyn Scope, args: &[Expr], span: Span) -> CalcResult<Value> {
+    let name = variable("deriv", &args[1], "e.g. deriv(x^3, x, 2)")?;
+    let at = real_argument(scope, "deriv", &args[2], "the point")?;
+    let curve = Curve::new(scope, &args[0], name);
+    let (derivative, error) = ridders(&mut |x| curve.at(x), at);
+
+    if !derivative.is_finite() {
+        return Err(curve.into_error().unwrap_or_else(|| {
+            invalid_argument(
+                "deriv",
+                &format!("the expression is not differentiable at {}", at),
+                span,
+            )
+        }));
+    }
+    if error > tolerance(derivative).max(1e-7 * derivative.abs()) {
+        return Err(no_convergence(
+            "deriv",
+            format!("estimated error {:.1e} at {}", error, at),
+            span,
+        ));
+    }
+    Ok(Value::Real(derivative))
+}
+
+/// `integrate(expr, x, a, b)`, the definite integral from `a` to `b`
+fn integrate(scope: &dyn Scope, args: &[Expr], span: Span) -> CalcResult<Value> {
+    let name = variable("integrate", &args[1], "e.g. integrate(x^2, x, 0, 1)")?;
+    let a = real_argument(scope, "integrate", &args[2], "the lower bound")?;
+    let b = real_argument(scope, "integrate", &args[3], "the upper bound")?;
+    if a == b {
+        return Ok(Value::Integer(BigInt::from(0)));
+    }
+    let curve = Curve::new(scope, &args[0], name);
+    let (value, error) = gauss_kronrod(&mut |x| curve.at(x), a.min(b), a.max(b));
+
+    if value.is_nan() {
+        return Err(curve.into_error().unwrap_or_else(|| {
+            invalid_argument(
+                "integrate",
+                &format!("the integrand is not real on [{}, {}]", a.min(b), a.max(b)),
+                span,
+            )
+        }));
+    }
+    if value.is_infinite() {
+        return Err(no_convergence(
+            "integrate",
+            format!("the integral diverges on [{}, {}]", a.min(b), a.max(b)),
+            span,
+        ));
+    }
+    if error > tolerance(value) {
+        return Err(no_convergence(
+            "integrate",
+            format!("estimated error {:.1e} for {}", error, value),
+            span,
+        )
+        .with_suggestion("split the range at singularities or jumps of the integrand"));
+    }
+    Ok(Value::Real(if a < b { value } else { -value }))
+}
+
+/// Error accepted in a numeric result of the given size
+fn tolerance(value: f64) -> f64 {
+    (1e-10 * value.abs()).max(1e-12)
+}
+
+/// An expression as a real function of one variable
+struct Curve<'a> {
+    scope: &'a dyn Scope,
+    expr: &'a Expr,
+    name: &'a str,
+
+    /// First evaluation error, reported if the result is not finite
+    error: RefCell<Option<CalcError>>,
+}
+
+impl<'a> Curve<'a> {
+    fn new(scope: &'a dyn Scope, expr: &'a Expr, name: &'a str) -> Self {
+        Self {
+            scope,
+            expr,
+            name,
+            error: RefCell::new(None),
+        }
+    }
+
+    /// The value at `x`, NaN where the expression is undefined or not real
+    fn at(&self, x: f64) -> f64 {
+        match self
+            .scope
+            .eval_with(self.expr, &[(self.name.to_string(), Value::Real(x))])
+        {
+            Ok(value) => value.as_real().unwrap_or(f64::NAN),
+            Err(error) => {
+                self.error.borrow_mut().get_or_insert(error);
+                f64::NAN
+            }
+        }
+    }
+
+    fn into_error(self) -> Option<CalcError> {
+        self.error.into_inner()
+    }
+}
+
+/// Derivative at `x` and an estimate of its error, by Ridders' method
+///
+/// Extrapolates central differences with shrinking steps to step size zero, following Numerical
+/// Recipes' `dfridr`. The first step shrinks until the function is defined on both sides, e.g.
+/// for `sqrt(x)` near zero.
+pub fn ridders(f: &mut impl FnMut(f64) -> f64, x: f64) -> (f64, f64) {
+    const SHRINK: f64 = 1.4;
+    const STEPS: usize = 10;
+
+    let central = |f: &mut dyn FnMut(f64) -> f64, h: f64| (f(x + h) - f(x - h)) / (2.0 * h);
+    let mut h = 0.1 * (1.0 + x.abs());
+    let mut first = central(f, h);
+    while !first.is_finite() && h > 1e-6 * (1.0 + x.abs()) {
+        h /= 10.0;
+        first = central(f, h);
+    }
+
+    let mut table = [[0.0; STEPS]; STEPS];
+    table[0][0] = first;
+    let (mut best, mut error) = (first, f64::INFINITY);
+    for i in 1..STEPS {
+        h /= SHRINK;
+        table[0][i] = central(f, h);
+        let mut factor = SHRINK * SHRINK;
+        for j in 1..=i {
+            table[j][i] = (table[j - 1][i] * factor - table[j - 1][i - 1]) / (factor - 1.0);
+            factor *= SHRINK * SHRINK;
+            let estimate = (table[j][i] - table[j - 1][i])
+                .abs()
+                .max((table[j][i] - table[j - 1][i - 1]).abs());
+            if estimate <= error {
+                error = estimate;
+                best = table[j][i];
+            }
+        }
+        // Stop once higher orders make things worse, as rounding starts to dominate
+        if (table[i][i] - table[i - 1][i - 1]).abs() >= 2.0 * error {
+            break;
+        }
+    }
+    (best, error)
+}
+
+/// Integral over `[a, b]` and an estimate of its error, by adaptive Gauss–Kronrod quadrature
+///
+/// The subinterval with the largest error is halved until the total error is within tolerance or
+/// `MAX_SUBINTERVALS` is reached.
+pub fn gauss_kronrod(f: &mut impl FnMut(f64) -> f64, a: f64, b: f64) -> (f64, f64) {
+    let (value, error) = kronrod(f, a, b);
+    let mut intervals = vec![(a, b, value, error)];
+    loop {
+        let value: f64 = intervals.iter().map(|interval| interval.2).sum();
+        let error: f64 = intervals.iter().map(|interval| interval.3).sum();
+        if error <= tolerance(value) || !error.is_finite() || intervals.len() >= MAX_SUBINTERVALS {
+            return (value, error);
+        }
+
+        let worst = (0..intervals.len())
+            .max_by(|&i, &j| intervals[i].3.total_cmp(&intervals[j].3))
+            .expect("there is always an interval");
+        let (a, b, _, _) = intervals[worst];
+        let middle = 0.5 * (a + b);
+        if middle <= a || middle >= b {
+            // The interval cannot be halved any further in floating point
+            return (value, error);
+        }
+        let (left, left_error) = kronrod(f, a, middle);
+        let (right, right_error) = kronrod(f, middle, b);
+        intervals[worst] = (a, middle, left, left_error);
+        intervals.push((middle, b, right, right_error));
+    }
+}
+
+/// The 15-point Kronrod estimate over `[a, b]`, with its difference from the 7-point Gauss
+/// estimate as the error
+fn kronrod(f: &mut impl FnMut(f64) -> f64, a: f64, b: f64) -> (f64, f64) {
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+    let y = f(center);
+    let mut kronrod = y * KRONROD_WEIGHTS[7];
+    let mut gauss = y * GAUSS_WEIGHTS[3];
+    for j in 0..7 {
+        let dx = half * KRONROD_NODES[j];
+        let pair = f(center - dx) + f(center + dx);
+        kronrod += KRONROD_WEIGHTS[j] * pair;
+        if j % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[j / 2] * pair;
+        }
+    }
+    (kronrod * half, ((kronrod - gauss) * half).abs())
+}
+
+/// The name bound by a calculus function, which must be a plain variable name
+fn variable<'a>(function: &str, arg: &'a Expr, example: &str) -> CalcResult<&'a str> {
+    match &arg.kind {
+        ExprKind::Ident(name) if !name.contains('.') => Ok(name),
+        _ => Err(
+            invalid_argument(function, "the second argument must be a variable name", arg.span)
+                .with_suggestion(example.to_string()),
+        ),
+    }
+}
+
+/// Evaluates an argument that must be a finite real number
+pub(super) fn real_argument(scope: &dyn Scope, function: &str, arg: &Expr, what: &str) -> CalcResult<f64> {
+    let value = scope.eval_with(arg, &[])?;
+    value.as_real().filter(|x| x.is_finite()).ok_or_else(|| {
+        invalid_argument(
+            function,
+            &format!("{} must be a real number, got {}", what, value),
+            arg.span,
+        )
+    })
+}
+
+/// Evaluates a bound of a sum or product, which must be a whole number
+fn whole_argument(scope: &dyn Scope, function: &str, arg: &Expr, what: &str) -> CalcResult<i64> {
+    let value = scope.eval_with(arg, &[])?;
+    value.to_integer().and_then(|n| n.to_i64()).ok_or_else(|| {
+        invalid_argument(
+            function,
+            &format!("{} must be a whole number, got {}", what, value),
+            arg.span,
+        )
+    })
+}
+
+pub(super) fn invalid_argument(function: &str, message: &str, span: Span) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: function.to_string(),
+            message: message.to_string(),
+        },
+        span,
+    )
+}
+
+pub(super) fn no_convergence(function: &str, message: String, span: Span) -> CalcError {
+    CalcError::new(
+        ErrorKind::NoConvergence {
+            name: function.to_string(),
+            message,
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ridders() {
+        let (derivative, error) = ridders(&mut |x: f64| x.powi(3), 2.0);
+        assert!((derivative - 12.0).abs() < 1e-10 && error < 1e-9);
+
+        let (derivative, _) = ridders(&mut |x: f64| x.sin(), 1.0);
+        assert!((derivative - 1f64.cos()).abs() < 1e-12);
+
+        // sqrt is undefined left of zero, so the first step shrinks to fit
+        let (derivative, _) = ridders(&mut |x: f64| x.sqrt(), 0.01);
+        assert!((derivative - 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_gauss_kronrod() {
+        let (value, error) = gauss_kronrod(&mut |x: f64| x.sin(), 0.0, std::f64::consts::PI);
+        assert!((value - 2.0).abs() < 1e-14 && error <= tolerance(value));
+
+        let (value, _) = gauss_kronrod(&mut |x: f64| (-x * x).exp(), -10.0, 10.0);
+        assert!((value - std::f64::consts::PI.sqrt()).abs() < 1e-12);
+
+        // Singular at the lower bound, which Gauss–Kronrod nodes never touch
+        let (value, error) = gauss_kronrod(&mut |x: f64| 1.0 / x.sqrt(), 0.0, 1.0);
+        assert!((value - 2.0).abs() < 1e-9 && error <= tolerance(value));
+
+        let (_, error) = gauss_kronrod(&mut |x: f64| 1.0 / x, 0.0, 1.0);
+        assert!(error > 1.0);
+    }
+}
@@ -0,0 +1,610 @@
+//! Evaluation context holding variables and callable functions
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{RangeFrom, RangeInclusive};
+use std::sync::Arc;
+
+use bigdecimal::BigDecimal;
+use num_complex::Complex64;
+use num_rational::BigRational;
+
+use super::ast::{BinaryOp, Expr, Span};
+use super::error::CalcResult;
+use super::math_functions::AngleMode;
+use super::programmer::WordSize;
+use super::units::UnitRegistry;
+use super::value::Value;
+
+/// Native function callable from expressions
+pub type NativeFn = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// Complex version of a native function, used in complex mode
+pub type ComplexFn = Arc<dyn Fn(&[Complex64]) -> Complex64 + Send + Sync>;
+
+/// Exact version of a native function, used when every argument is a fraction
+///
+/// `None` falls back to the native function, e.g. for `sqrt(2)`.
+pub type RationalFn = Arc<dyn Fn(&[BigRational]) -> Option<BigRational> + Send + Sync>;
+
+/// Arbitrary-precision version of a native function, used in decimal mode
+///
+/// Takes the arguments and the precision in significant digits; `None` falls back to the native
+/// function.
+pub type DecimalFn = Arc<dyn Fn(&[BigDecimal], u64) -> Option<BigDecimal> + Send + Sync>;
+
+/// Native function taking and returning whole values, e.g. lists, or explaining invalid arguments
+pub type ValueFn = Arc<dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync>;
+
+/// Version of a native function that explains invalid arguments instead of returning NaN
+pub type CheckedFn = Arc<dyn Fn(&[f64]) -> Result<f64, String> + Send + Sync>;
+
+/// Native function taking its arguments unevaluated, e.g. `solve(x^2 = 2, x)`
+///
+/// Receives the scope of the call, the argument expressions and the span of the call, and
+/// evaluates the arguments itself, binding names such as `x` temporarily through the scope.
+pub type LazyFn = Arc<dyn Fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value> + Send + Sync>;
+
+/// Version of a function taking its arguments unevaluated, tried before the function itself
+///
+/// `None` falls back to the function, e.g. `sum(k^2, k, 1, 10)` sums over `k` while
+/// `sum(1, 2, 3)` stays an ordinary sum.
+pub type UnevaluatedFn = Arc<dyn Fn(&dyn Scope, &[Expr], Span) -> Option<CalcResult<Value>> + Send + Sync>;
+
+/// Evaluation state a lazy function evaluates its arguments in
+pub trait Scope {
+    /// Evaluates `expr` with `bindings` shadowing any variables of the same names
+    fn eval_with(&self, expr: &Expr, bindings: &[(String, Value)]) -> CalcResult<Value>;
+
+    /// Applies an infix operator as in an expression, e.g. to add up terms exactly; errors refer
+    /// to `span`
+    fn combine(&self, op: BinaryOp, a: Value, b: Value, span: Span) -> CalcResult<Value>;
+
+    /// Unit trigonometric functions currently take and return angles in
+    fn angle_mode(&self) -> AngleMode;
+
+    /// Parameters and body of a function defined by an expression, e.g. a user function or mod
+    fn definition(&self, name: &str) -> Option<(Vec<String>, Arc<Expr>)>;
+}
+
+/// Number of arguments a function accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly this many arguments
+    Exact(usize),
+
+    /// At least this many arguments
+    AtLeast(usize),
+
+    /// Between the two counts, inclusive, when trailing arguments are optional
+    Between(usize, usize),
+}
+
+impl Arity {
+    /// Whether a call with `count` arguments is allowed
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Between(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Exact(n)
+    }
+}
+
+impl From<RangeFrom<usize>> for Arity {
+    fn from(range: RangeFrom<usize>) -> Self {
+        Arity::AtLeast(range.start)
+    }
+}
+
+impl From<RangeInclusive<usize>> for Arity {
+    fn from(range: RangeInclusive<usize>) -> Self {
+        Arity::Between(*range.start(), *range.end())
+    }
+}
+
+/// How a trigonometric function depends on the angle mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AngleUse {
+    /// The arguments are angles in the active unit, e.g. `sin`
+    Arguments,
+
+    /// The result is an angle in the active unit, e.g. `asin`
+    Result,
+}
+
+/// How a function computes its result
+#[derive(Clone)]
+pub enum FunctionBody {
+    /// Implemented in Rust
+    Native(NativeFn),
+
+    /// Implemented in Rust over values of any kind, e.g. statistics over lists
+    Values(ValueFn),
+
+    /// Implemented in Rust over unevaluated arguments, e.g. `solve`
+    Lazy(LazyFn),
+
+    /// Defined by an expression over named parameters (mods, user functions)
+    Expr { params: Vec<String>, body: Arc<Expr> },
+}
+
+/// A registered function together with its arity
+#[derive(Clone)]
+pub struct Function {
+    pub arity: Arity,
+    pub body: FunctionBody,
+
+    /// Whether equal arguments always give equal results; impure results are never memoised
+    pub pure: bool,
+
+    /// Complex version of a native function; without one, complex arguments are rejected
+    pub complex: Option<ComplexFn>,
+
+    /// Exact version of a native function; without one, fractions are converted to floats
+    pub rational: Option<RationalFn>,
+
+    /// Decimal version of a native function; without one, decimal mode computes with floats
+    pub decimal: Option<DecimalFn>,
+
+    /// Checked version of a native function, whose error message replaces the generic domain error
+    pub checked: Option<CheckedFn>,
+
+    /// Version taking the arguments unevaluated, for calls it recognises
+    pub unevaluated: Option<UnevaluatedFn>,
+
+    /// Whether a native function takes or returns angles, which it does in radians
+    pub angle: Option<AngleUse>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Function");
+        debug
+            .field("arity", &self.arity)
+            .field("pure", &self.pure)
+            .field("complex", &self.complex.is_some())
+            .field("rational", &self.rational.is_some())
+            .field("decimal", &self.decimal.is_some())
+            .field("checked", &self.checked.is_some())
+            .field("unevaluated", &self.unevaluated.is_some())
+            .field("angle", &self.angle);
+        if let FunctionBody::Expr { params, body } = &self.body {
+            debug.field("params", params).field("body", &body.to_string());
+        }
+        debug.finish()
+    }
+}
+
+/// Variables and functions visible to an expression
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    vars: HashMap<String, Value>,
+    funcs: HashMap<String, Function>,
+
+    /// Whether imaginary literals are allowed and real functions may return complex results
+    complex: bool,
+
+    /// Significant digits of decimal mode; `None` computes with floats
+    precision: Option<u64>,
+
+    /// Width integer results wrap around to in programmer mode; `None` keeps them unbounded
+    word_size: Option<WordSize>,
+
+    /// Units that may follow a value, e.g. `km` in `5 km`
+    units: Arc<UnitRegistry>,
+
+    /// Unit of the angles trigonometric functions take and return
+    angle_mode: AngleMode,
+}
+
+impl Context {
+    /// Creates a context with the standard constants and functions
+    pub fn new() -> Self {
+        let mut ctx = Self::empty();
+        ctx.units = UnitRegistry::builtin();
+
+        ctx.var("pi", std::f64::consts::PI);
+        ctx.var("e", std::f64::consts::E);
+
+        ctx.func("sqrt", f64::sqrt);
+        ctx.func("exp", f64::exp);
+        ctx.func("ln", f64::ln);
+        ctx.func("abs", f64::abs);
+        ctx.func("sin", f64::sin);
+        ctx.func("cos", f64::cos);
+        ctx.func("tan", f64::tan);
+        ctx.func("asin", f64::asin);
+        ctx.func("acos", f64::acos);
+        ctx.func("atan", f64::atan);
+        ctx.func("sinh", f64::sinh);
+        ctx.func("cosh", f64::cosh);
+        ctx.func("tanh", f64::tanh);
+        ctx.func("asinh", f64::asinh);
+        ctx.func("acosh", f64::acosh);
+        ctx.func("atanh", f64::atanh);
+        ctx.func("floor", f64::floor);
+        ctx.func("ceil", f64::ceil);
+        ctx.func("round", f64::round);
+        ctx.func("signum", f64::signum);
+        ctx.func2("atan2", f64::atan2);
+        ctx.mark_angles();
+        ctx.funcn(
+            "max",
+            |args| args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            1..,
+        );
+        ctx.funcn("min", |args| args.iter().copied().fold(f64::INFINITY, f64::min), 1..);
+
+        ctx
+    }
+
+    /// Creates a context without any variables or functions
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a variable
+    pub fn var<S: Into<String>, V: Into<Value>>(&mut self, name: S, value: V) -> &mut Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Enables or disables complex mode
+    pub fn set_complex_mode(&mut self, enabled: bool) -> &mut Self {
+        self.complex = enabled;
+        self
+    }
+
+    /// Whether complex mode is enabled
+    pub fn complex_mode(&self) -> bool {
+        self.complex
+    }
+
+    /// Enables decimal mode with `precision` significant digits, or disables it with `None`
+    ///
+    /// Number literals then become arbitrary-precision decimals, so `0.1 + 0.2` is exactly 0.3.
+    pub fn set_decimal_precision(&mut self, precision: Option<u64>) -> &mut Self {
+        self.precision = precision.map(|digits| digits.max(1));
+        self
+    }
+
+    /// Significant digits of decimal mode, `None` when it is disabled
+    pub fn decimal_precision(&self) -> Option<u64> {
+        self.precision
+    }
+
+    /// Sets the word size integer results wrap around to, or lets them grow freely with `None`
+    pub fn set_word_size(&mut self, word_size: Option<WordSize>) -> &mut Self {
+        self.word_size = word_size;
+        self
+    }
+
+    /// Word size of programmer mode, `None` when integers are unbounded
+    pub fn word_size(&self) -> Option<WordSize> {
+        self.word_size
+    }
+
+    /// Sets the unit trigonometric functions take and return angles in
+    pub fn set_angle_mode(&mut self, mode: AngleMode) -> &mut Self {
+        self.angle_mode = mode;
+        self
+    }
+
+    /// Unit trigonometric functions take and return angles in
+    pub fn angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
+
+    /// Replaces the unit registry
+    pub fn set_units(&mut self, units: Arc<UnitRegistry>) -> &mut Self {
+        self.units = units;
+        self
+    }
+
+    /// Units known to expressions
+    pub fn units(&self) -> &UnitRegistry {
+        &self.units
+    }
+
+    /// Adds or replaces a function taking no arguments, e.g. `random()`
+    pub fn func0<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.funcn(name, move |_| func(), 0)
+    }
+
+    /// Adds or replaces a single argument function
+    pub fn func<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(f64) -> f64 + Send + Sync + 'static,
+    {
+        self.funcn(name, move |args| func(args[0]), 1)
+    }
+
+    /// Adds or replaces a two argument function
+    pub fn func2<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(f64, f64) -> f64 + Send + Sync + 'static,
+    {
+        self.funcn(name, move |args| func(args[0], args[1]), 2)
+    }
+
+    /// Adds or replaces a function taking a slice of arguments
+    ///
+    /// `arity` is a count, a range such as `2..=4` when trailing arguments are optional, or an
+    /// open range such as `1..` for variadic functions; the slice holds exactly the arguments given.
+    /// Variadic functions take the elements of list arguments as separate arguments, e.g.
+    /// `sum([1, 2], 3)`; other functions are applied to each element, e.g. `sqrt([1, 4])`.
+    pub fn funcn<S, F, A>(&mut self, name: S, func: F, arity: A) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+        A: Into<Arity>,
+    {
+        self.funcs.insert(
+            name.into(),
+            Function {
+                arity: arity.into(),
+                body: FunctionBody::Native(Arc::new(func)),
+                pure: true,
+                complex: None,
+                rational: None,
+                decimal: None,
+                checked: None,
+                unevaluated: None,
+                angle: None,
+            },
+        );
+        self
+    }
+
+    /// Adds or replaces a function over values of any kind, such as lists, rather than numbers
+    ///
+    /// Arguments are passed as evaluated, without the element-wise mapping of `funcn`; an error
+    /// message becomes a domain error.
+    pub fn value_funcn<S, F, A>(&mut self, name: S, func: F, arity: A) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+        A: Into<Arity>,
+    {
+        self.funcs.insert(
+            name.into(),
+            Function {
+                arity: arity.into(),
+                body: FunctionBody::Values(Arc::new(func)),
+                pure: true,
+                complex: None,
+                rational: None,
+                decimal: None,
+                checked: None,
+                unevaluated: None,
+                angle: None,
+            },
+        );
+        self
+    }
+
+    /// Adds or replaces a function that takes its arguments unevaluated, e.g. an equation to solve
+    ///
+    /// Errors are reported as returned, so the function picks the span of the offending argument.
+    pub fn lazy_funcn<S, F, A>(&mut self, name: S, func: F, arity: A) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value> + Send + Sync + 'static,
+        A: Into<Arity>,
+    {
+        self.funcs.insert(
+            name.into(),
+            Function {
+                arity: arity.into(),
+                body: FunctionBody::Lazy(Arc::new(func)),
+                pure: true,
+                complex: None,
+                rational: None,
+                decimal: None,
+                checked: None,
+                unevaluated: None,
+                angle: None,
+            },
+        );
+        self
+    }
+
+    /// Adds or replaces a function whose body is an expression over `params`
+    pub fn define<S: Into<String>>(&mut self, name: S, params: Vec<String>, body: Expr) -> &mut Self {
+        self.funcs.insert(
+            name.into(),
+            Function {
+                arity: Arity::Exact(params.len()),
+                body: FunctionBody::Expr {
+                    params,
+                    body: Arc::new(body),
+                },
+                pure: true,
+                complex: None,
+                rational: None,
+                decimal: None,
+                checked: None,
+                unevaluated: None,
+                angle: None,
+            },
+        );
+        self
+    }
+
+    /// Attaches a complex version to a single argument function registered with `func`
+    ///
+    /// Does nothing if no such function exists.
+    pub fn complex_func<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(Complex64) -> Complex64 + Send + Sync + 'static,
+    {
+        self.complex_funcn(name, move |args| func(args[0]))
+    }
+
+    /// Attaches a complex version taking a slice of arguments to an existing native function
+    pub fn complex_funcn<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&[Complex64]) -> Complex64 + Send + Sync + 'static,
+    {
+        if let Some(existing) = self.funcs.get_mut(name) {
+            existing.complex = Some(Arc::new(func));
+        }
+        self
+    }
+
+    /// Attaches an exact version to a single argument function registered with `func`
+    ///
+    /// Does nothing if no such function exists.
+    pub fn rational_func<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&BigRational) -> Option<BigRational> + Send + Sync + 'static,
+    {
+        self.rational_funcn(name, move |args| func(&args[0]))
+    }
+
+    /// Attaches an exact version taking a slice of arguments to an existing native function
+    pub fn rational_funcn<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&[BigRational]) -> Option<BigRational> + Send + Sync + 'static,
+    {
+        if let Some(existing) = self.funcs.get_mut(name) {
+            existing.rational = Some(Arc::new(func));
+        }
+        self
+    }
+
+    /// Attaches a decimal version to a single argument function registered with `func`
+    ///
+    /// Does nothing if no such function exists.
+    pub fn decimal_func<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&BigDecimal, u64) -> Option<BigDecimal> + Send + Sync + 'static,
+    {
+        self.decimal_funcn(name, move |args, precision| func(&args[0], precision))
+    }
+
+    /// Attaches a decimal version taking a slice of arguments to an existing native function
+    pub fn decimal_funcn<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&[BigDecimal], u64) -> Option<BigDecimal> + Send + Sync + 'static,
+    {
+        if let Some(existing) = self.funcs.get_mut(name) {
+            existing.decimal = Some(Arc::new(func));
+        }
+        self
+    }
+
+    /// Attaches a version taking unevaluated arguments to an existing function
+    ///
+    /// It is tried first and returns `None` for calls it leaves to the function. Does nothing if
+    /// no such function exists.
+    pub fn unevaluated_funcn<F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: Fn(&dyn Scope, &[Expr], Span) -> Option<CalcResult<Value>> + Send + Sync + 'static,
+    {
+        if let Some(existing) = self.funcs.get_mut(name) {
+            existing.unevaluated = Some(Arc::new(func));
+        }
+        self
+    }
+
+    /// Adds or replaces a function whose invalid arguments give an error message, e.g. a negative
+    /// standard deviation
+    pub fn checked_funcn<S, F, A>(&mut self, name: S, func: F, arity: A) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(&[f64]) -> Result<f64, String> + Send + Sync + 'static,
+        A: Into<Arity>,
+    {
+        let name = name.into();
+        let func: CheckedFn = Arc::new(func);
+        let native = func.clone();
+        self.funcn(name.clone(), move |args| native(args).unwrap_or(f64::NAN), arity);
+        if let Some(existing) = self.funcs.get_mut(&name) {
+            existing.checked = Some(func);
+        }
+        self
+    }
+
+    /// Flags a native function as impure, e.g. one returning random numbers
+    ///
+    /// Expression-defined functions are impure whenever their body calls an impure function.
+    pub fn mark_impure(&mut self, name: &str) -> &mut Self {
+        if let Some(func) = self.funcs.get_mut(name) {
+            func.pure = false;
+        }
+        self
+    }
+
+    /// Flags a native function as taking or returning angles in the angle mode
+    pub fn mark_angle(&mut self, name: &str, angle: AngleUse) -> &mut Self {
+        if let Some(func) = self.funcs.get_mut(name) {
+            func.angle = Some(angle);
+        }
+        self
+    }
+
+    /// Flags the built-in trigonometric functions, after they have been registered with `func`
+    pub fn mark_angles(&mut self) -> &mut Self {
+        for name in ["sin", "cos", "tan", "csin"] {
+            self.mark_angle(name, AngleUse::Arguments);
+        }
+        for name in ["asin", "acos", "atan", "atan2", "arg"] {
+            self.mark_angle(name, AngleUse::Result);
+        }
+        self
+    }
+
+    /// Removes a variable, returning its value if it existed
+    pub fn remove_var(&mut self, name: &str) -> Option<Value> {
+        self.vars.remove(name)
+    }
+
+    /// Removes a function, returning it if it existed
+    pub fn remove_func(&mut self, name: &str) -> Option<Function> {
+        self.funcs.remove(name)
+    }
+
+    /// Looks up a variable
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).cloned()
+    }
+
+    /// Looks up a function
+    pub fn get_func(&self, name: &str) -> Option<&Function> {
+        self.funcs.get(name)
+    }
+
+    /// Names of all variables
+    pub fn var_names(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    /// Names of all functions
+    pub fn func_names(&self) -> impl Iterator<Item = &str> {
+        self.funcs.keys().map(String::as_str)
+    }
+}
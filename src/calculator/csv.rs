@@ -0,0 +1,396 @@
+//! Numeric columns read from CSV files
+//!
+//! The delimiter and whether the first row is a header are detected unless given. Empty cells and
+//! markers such as `NA` are missing values, which are left out of their column. A column whose
+//! first value is text, e.g. a column of names, is not numeric; any other cell that is not a number
+//! is an error reported with its row and column.
+
+use std::fs;
+use std::path::Path;
+
+use num_traits::ToPrimitive;
+use thiserror::Error;
+
+use super::rational;
+use super::value::Value;
+
+/// Delimiters recognised when none is given, preferred in this order on ties; the comma comes last
+/// since it doubles as a decimal comma, e.g. in `1;2,5`
+pub const DELIMITERS: [char; 4] = [';', '\t', '|', ','];
+
+/// Cell contents treated as missing values, compared ignoring case
+const MISSING: [&str; 6] = ["", "na", "n/a", "nan", "null", "-"];
+
+/// How to read a file; the defaults detect everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CsvOptions {
+    /// Field separator; `None` picks the most frequent of [`DELIMITERS`] in the first row
+    pub delimiter: Option<char>,
+
+    /// Whether the first row names the columns; `None` assumes so when it contains text
+    pub header: Option<bool>,
+}
+
+/// Why a file or a column could not be read
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CsvError {
+    #[error("cannot read '{path}': {message}")]
+    Io { path: String, message: String },
+
+    #[error("the file has no rows")]
+    Empty,
+
+    #[error("no column '{0}'")]
+    NoColumn(String),
+
+    /// Rows are line numbers and columns count from 1
+    #[error("row {row}, column {column}: {message}")]
+    Cell { row: usize, column: usize, message: String },
+}
+
+/// One column of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    /// Header text, or `col1`, `col2`, ... without a header
+    pub name: String,
+
+    /// Values with missing cells left out, or the first cell that is not a number
+    pub values: Result<Vec<f64>, CsvError>,
+
+    /// Whether the first value is text, which makes the column non-numeric
+    pub is_text: bool,
+}
+
+impl Column {
+    /// The values as a list, whole numbers exact like typed literals
+    pub fn to_list(&self) -> Result<Value, CsvError> {
+        let values = self.values.clone()?;
+        Ok(Value::List(
+            values
+                .into_iter()
+                .map(|x| rational::from_f64(x).map_or(Value::Real(x), Value::from_rational))
+                .collect(),
+        ))
+    }
+
+    /// The header as a variable name, e.g. `Height_cm` for `Height (cm)`
+    pub fn variable_name(&self) -> String {
+        let mut name = String::new();
+        for c in self.name.chars() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
+            } else if !name.is_empty() && !name.ends_with('_') {
+                name.push('_');
+            }
+        }
+        let name = name.trim_end_matches('_');
+        match name.chars().next() {
+            None => "column".to_string(),
+            Some(c) if c.is_ascii_digit() => format!("col{}", name),
+            Some(_) => name.to_string(),
+        }
+    }
+}
+
+/// Columns of a parsed file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub columns: Vec<Column>,
+}
+
+/// How to pick a column: by header or by number counting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKey<'a> {
+    Name(&'a str),
+    Number(usize),
+}
+
+impl Table {
+    /// Reads and parses a file
+    pub fn read(path: &Path, options: &CsvOptions) -> Result<Self, CsvError> {
+        let text = fs::read_to_string(path).map_err(|e| CsvError::Io {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        Self::parse(&text, options)
+    }
+
+    /// Parses the contents of a file
+    pub fn parse(text: &str, options: &CsvOptions) -> Result<Self, CsvError> {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let delimiter = options.delimiter.unwrap_or_else(|| detect_delimiter(text));
+        let records = records(text, delimiter)?;
+        let (first, _) = records.first().ok_or(CsvError::Empty)?;
+        let has_header = options.header.unwrap_or_else(|| {
+            first
+                .iter()
+                .any(|cell| !is_missing(cell) && parse_number(cell, delimiter).is_none())
+        });
+
+        let (names, rows) = if has_header {
+            let names: Vec<String> = first
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| match cell.trim() {
+                    "" => format!("col{}", i + 1),
+                    name => name.to_string(),
+                })
+                .collect();
+            if let Some((cells, row)) = records[1..].iter().find(|(cells, _)| cells.len() > names.len()) {
+                return Err(CsvError::Cell {
+                    row: *row,
+                    column: names.len() + 1,
+                    message: format!("the row has {} fields, but the header has {}", cells.len(), names.len()),
+                });
+            }
+            (names, &records[1..])
+        } else {
+            let width = records.iter().map(|(cells, _)| cells.len()).max().unwrap_or(0);
+            ((1..=width).map(|i| format!("col{}", i)).collect(), &records[..])
+        };
+
+        let columns = names
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let cells = rows
+                    .iter()
+                    .filter_map(|(cells, row)| cells.get(index).map(|cell| (cell.trim(), *row)))
+                    .filter(|(cell, _)| !is_missing(cell));
+                let is_text = cells
+                    .clone()
+                    .next()
+                    .is_some_and(|(cell, _)| parse_number(cell, delimiter).is_none());
+                let values = cells
+                    .map(|(cell, row)| {
+                        parse_number(cell, delimiter).ok_or_else(|| CsvError::Cell {
+                            row,
+                            column: index + 1,
+                            message: format!("'{}' is not a number", cell),
+                        })
+                    })
+                    .collect();
+                Column { name, values, is_text }
+            })
+            .collect();
+        Ok(Table { columns })
+    }
+
+    /// The column with the given header, ignoring case if no header matches exactly, or number
+    pub fn column(&self, key: ColumnKey) -> Result<&Column, CsvError> {
+        let found = match key {
+            ColumnKey::Name(name) => self.columns.iter().find(|column| column.name == name).or_else(|| {
+                self.columns
+                    .iter()
+                    .find(|column| column.name.eq_ignore_ascii_case(name))
+            }),
+            ColumnKey::Number(number) => number.checked_sub(1).and_then(|index| self.columns.get(index)),
+        };
+        found.ok_or_else(|| match key {
+            ColumnKey::Name(name) => CsvError::NoColumn(name.to_string()),
+            ColumnKey::Number(number) => CsvError::NoColumn(number.to_string()),
+        })
+    }
+}
+
+/// `import_csv(path, column, delimiter)`: a numeric column as a list
+///
+/// The column is a header in quotes or a number counting from 1; the optional delimiter is detected
+/// when left out.
+pub fn import_column(args: &[Value]) -> Result<Value, String> {
+    let Value::Text(path) = &args[0] else {
+        return Err(format!("the file name must be text in quotes, got {}", args[0]));
+    };
+    let key = match &args[1] {
+        Value::Text(name) => ColumnKey::Name(name),
+        value => match value.to_integer().and_then(|n| n.to_usize()) {
+            Some(number) => ColumnKey::Number(number),
+            None => {
+                return Err(format!(
+                    "the column must be a header in quotes or a number, got {}",
+                    value
+                ))
+            }
+        },
+    };
+    let delimiter = match args.get(2) {
+        None => None,
+        Some(Value::Text(text)) if text.chars().count() == 1 => text.chars().next(),
+        Some(value) => {
+            return Err(format!(
+                "the delimiter must be a single character in quotes, got {}",
+                value
+            ))
+        }
+    };
+    let options = CsvOptions {
+        delimiter,
+        header: None,
+    };
+    let table = Table::read(Path::new(path), &options).map_err(|e| e.to_string())?;
+    table.column(key).and_then(Column::to_list).map_err(|e| e.to_string())
+}
+
+/// The delimiter occurring most often outside quotes in the first row, `,` if there is none
+fn detect_delimiter(text: &str) -> char {
+    let mut counts = [0usize; DELIMITERS.len()];
+    let mut quoted = false;
+    for c in text.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if quoted {
+            continue;
+        } else if c == '\n' {
+            break;
+        } else if let Some(i) = DELIMITERS.iter().position(|&d| d == c) {
+            counts[i] += 1;
+        }
+    }
+    // `max_by_key` keeps the last maximum, so search in reverse to prefer earlier delimiters
+    match (0..DELIMITERS.len()).rev().max_by_key(|&i| counts[i]) {
+        Some(i) if counts[i] > 0 => DELIMITERS[i],
+        _ => ',',
+    }
+}
+
+/// Splits the text into rows of cells, each with its row number; blank lines are skipped
+///
+/// Cells may be quoted to contain the delimiter, line breaks or `""` for a quote.
+fn records(text: &str, delimiter: char) -> Result<Vec<(Vec<String>, usize)>, CsvError> {
+    let mut records = Vec::new();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.next_if_eq(&'"').is_some() => cell.push('"'),
+                '"' => quoted = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    cell.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if cell.trim().is_empty() => {
+                cell.clear();
+                quoted = true;
+            }
+            c if c == delimiter => cells.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                cells.push(std::mem::take(&mut cell));
+                let cells = std::mem::take(&mut cells);
+                if !(cells.len() == 1 && cells[0].trim().is_empty()) {
+                    records.push((cells, start_line));
+                }
+                line += 1;
+                start_line = line;
+            }
+            c => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(CsvError::Cell {
+            row: start_line,
+            column: cells.len() + 1,
+            message: "the quoted cell is never closed".to_string(),
+        });
+    }
+    cells.push(cell);
+    if !(cells.len() == 1 && cells[0].trim().is_empty()) {
+        records.push((cells, start_line));
+    }
+    Ok(records)
+}
+
+fn is_missing(cell: &str) -> bool {
+    let cell = cell.trim();
+    MISSING.iter().any(|marker| marker.eq_ignore_ascii_case(cell))
+}
+
+/// A number, also with a decimal comma when the delimiter is not a comma, e.g. `1,5` in `1,5;2`
+fn parse_number(cell: &str, delimiter: char) -> Option<f64> {
+    let cell = cell.trim();
+    let cell = cell.strip_prefix('+').unwrap_or(cell);
+    if delimiter != ',' && cell.matches(',').count() == 1 && !cell.contains('.') {
+        return cell.replace(',', ".").parse().ok();
+    }
+    cell.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(table: &Table, key: ColumnKey) -> Result<Vec<f64>, CsvError> {
+        table.column(key)?.values.clone()
+    }
+
+    #[test]
+    fn test_headers_and_missing_values() {
+        let text = "name,height,weight\r\nAda,1.7,NA\r\n\r\nBob,\"1,8\",80\r\nCy,1.6,\r\n";
+        let table = Table::parse(text, &CsvOptions::default()).unwrap();
+        assert_eq!(table.columns.len(), 3);
+        assert!(table.columns[0].is_text);
+        assert_eq!(values(&table, ColumnKey::Name("weight")), Ok(vec![80.0]));
+        assert_eq!(
+            values(&table, ColumnKey::Name("Height")),
+            Err(CsvError::Cell {
+                row: 4,
+                column: 2,
+                message: "'1,8' is not a number".to_string(),
+            })
+        );
+        assert_eq!(
+            values(&table, ColumnKey::Number(1)).unwrap_err().to_string(),
+            "row 2, column 1: 'Ada' is not a number"
+        );
+        assert_eq!(
+            table.column(ColumnKey::Number(4)).unwrap_err(),
+            CsvError::NoColumn("4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_delimiters() {
+        let table = Table::parse("1;2,5\n3;-\n", &CsvOptions::default()).unwrap();
+        assert_eq!(values(&table, ColumnKey::Name("col1")), Ok(vec![1.0, 3.0]));
+        assert_eq!(values(&table, ColumnKey::Number(2)), Ok(vec![2.5]));
+
+        let tabs = Table::parse("x\ty\n1\t2\n", &CsvOptions::default()).unwrap();
+        assert_eq!(values(&tabs, ColumnKey::Name("y")), Ok(vec![2.0]));
+
+        let forced = CsvOptions {
+            delimiter: Some('|'),
+            header: Some(false),
+        };
+        let table = Table::parse("1|2\n3\n", &forced).unwrap();
+        assert_eq!(values(&table, ColumnKey::Number(2)), Ok(vec![2.0]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Table::parse("\n\n", &CsvOptions::default()), Err(CsvError::Empty));
+        assert_eq!(
+            Table::parse("a,b\n1,2,3\n", &CsvOptions::default())
+                .unwrap_err()
+                .to_string(),
+            "row 2, column 3: the row has 3 fields, but the header has 2"
+        );
+        assert_eq!(
+            Table::parse("a\n\"1\n", &CsvOptions::default())
+                .unwrap_err()
+                .to_string(),
+            "row 2, column 1: the quoted cell is never closed"
+        );
+    }
+}
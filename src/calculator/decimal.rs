@@ -0,0 +1,440 @@
+//! Arbitrary-precision decimal arithmetic used in decimal mode
+//!
+//! Functions take the precision in significant digits, compute with guard digits and round the
+//! result. They return `None` where they have no decimal result, e.g. `ln(-1)` or an argument too
+//! large to handle; the interpreter then falls back to the `f64` version.
+
+use std::num::NonZeroU64;
+use std::str::FromStr;
+
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, Context, One, RoundingMode, Signed, ToPrimitive, Zero};
+
+/// Significant digits used when no precision is configured
+pub const DEFAULT_PRECISION: u64 = 30;
+
+/// Extra digits carried through intermediate steps
+pub const GUARD_DIGITS: u64 = 10;
+
+/// Integer powers and factorials with more digits than this are rounded instead of exact
+const MAX_EXACT_DIGITS: u64 = 100_000;
+
+/// Largest magnitude accepted by `exp`; beyond it the result over- or underflows anyway
+const MAX_EXP_ARGUMENT: f64 = 1e9;
+
+/// Largest number of integer digits of an angle that trigonometric functions reduce exactly
+const MAX_ANGLE_DIGITS: i64 = 1_000;
+
+/// Rounds to `precision` significant digits, half to even, and drops trailing zeros
+pub fn round(x: &BigDecimal, precision: u64) -> BigDecimal {
+    if x.digits() <= precision {
+        return x.normalized();
+    }
+    x.with_precision_round(nonzero(precision), RoundingMode::HalfEven)
+        .normalized()
+}
+
+/// Converts a float through its shortest round-trip representation, so `0.1` becomes exactly 0.1
+pub fn from_f64(x: f64) -> Option<BigDecimal> {
+    if !x.is_finite() {
+        return None;
+    }
+    BigDecimal::from_str(&x.to_string()).ok()
+}
+
+/// Nearest float, infinite when out of range
+pub fn to_f64(x: &BigDecimal) -> f64 {
+    x.to_f64().unwrap_or(f64::NAN)
+}
+
+/// `a / b` rounded to `precision` digits, `None` when dividing by zero
+pub fn div(a: &BigDecimal, b: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    if b.is_zero() {
+        return None;
+    }
+    let inverse = b.inverse_with_context(&context(precision + GUARD_DIGITS));
+    Some(round(&(a * inverse), precision))
+}
+
+/// `a ^ b`, exact for moderate non-negative integer exponents
+pub fn pow(a: &BigDecimal, b: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    if b.is_integer() {
+        if let Some(n) = b.to_i64() {
+            return powi(a, n, precision);
+        }
+    }
+    if a.is_zero() {
+        return b.is_positive().then(BigDecimal::zero);
+    }
+    if a.is_negative() {
+        return None;
+    }
+    // The exponent's integer digits are lost to exp's argument, so carry that many more
+    let estimate = (to_f64(b) * to_f64(a).ln()).abs();
+    let extra = if estimate.is_finite() && estimate >= 1.0 {
+        estimate.log10().ceil() as u64
+    } else {
+        0
+    };
+    let wp = precision + GUARD_DIGITS + extra;
+    exp(&(b * ln(a, wp)?), precision)
+}
+
+fn powi(a: &BigDecimal, n: i64, precision: u64) -> Option<BigDecimal> {
+    if a.is_zero() && n < 0 {
+        return None;
+    }
+    let (digits, scale) = a.as_bigint_and_exponent();
+    let exact_digits = a.digits().saturating_mul(n.unsigned_abs());
+    match u32::try_from(n.unsigned_abs()) {
+        Ok(exponent) if exact_digits <= MAX_EXACT_DIGITS => {
+            let power = BigDecimal::new(digits.pow(exponent), scale * i64::from(exponent));
+            if n >= 0 {
+                Some(power)
+            } else {
+                div(&BigDecimal::one(), &power, precision)
+            }
+        }
+        _ => Some(round(
+            &a.powi_with_context(n, &context(precision + GUARD_DIGITS)),
+            precision,
+        )),
+    }
+}
+
+/// `n!` for integers up to a size that can be computed exactly
+pub fn factorial(x: &BigDecimal) -> Option<BigDecimal> {
+    if !x.is_integer() || x.is_negative() {
+        return None;
+    }
+    let n = x.to_u64()?;
+    // log10(n!) < n log10(n), which bounds the number of digits
+    if n > 1 && n as f64 * (n as f64).log10() > MAX_EXACT_DIGITS as f64 {
+        return None;
+    }
+    let product = (2..=n).fold(BigInt::one(), |acc, k| acc * k);
+    Some(BigDecimal::from(product))
+}
+
+/// Square root, `None` for negative numbers
+pub fn sqrt(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    x.sqrt_with_context(&context(precision))
+        .map(|root| round(&root, precision))
+}
+
+/// π by Machin's formula, π = 16 atan(1/5) - 4 atan(1/239)
+pub fn pi(precision: u64) -> BigDecimal {
+    let wp = precision + GUARD_DIGITS;
+    let pi = atan_inverse(5, wp) * BigDecimal::from(16) - atan_inverse(239, wp) * BigDecimal::from(4);
+    round(&pi, precision)
+}
+
+/// Euler's number
+pub fn e(precision: u64) -> BigDecimal {
+    exp(&BigDecimal::one(), precision).unwrap_or_default()
+}
+
+/// atan(1/n) by its Taylor series
+fn atan_inverse(n: u32, wp: u64) -> BigDecimal {
+    let n = BigDecimal::from(n);
+    let n_squared = &n * &n;
+    let eps = epsilon(wp);
+    let mut power = div(&BigDecimal::one(), &n, wp).unwrap_or_default();
+    let mut sum = power.clone();
+    for k in 1u64.. {
+        power = div(&power, &n_squared, wp).unwrap_or_default();
+        let term = div(&power, &BigDecimal::from(2 * k + 1), wp).unwrap_or_default();
+        if term < eps {
+            break;
+        }
+        if k % 2 == 1 {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+    }
+    sum
+}
+
+/// e^x by the Taylor series of x / 2^k, squared k times
+pub fn exp(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    if to_f64(x).abs() > MAX_EXP_ARGUMENT {
+        return None;
+    }
+    let half = BigDecimal::new(5.into(), 1);
+    let mut r = x.clone();
+    let mut halvings = 0u64;
+    while r.abs() > half {
+        r = r.half();
+        halvings += 1;
+    }
+    // Each squaring doubles the relative error, costing about 0.3 digits
+    let wp = precision + GUARD_DIGITS + halvings * 3 / 10 + 1;
+    let r = round(&r, wp);
+    let eps = epsilon(wp);
+    let mut term = BigDecimal::one();
+    let mut sum = BigDecimal::one();
+    for n in 1u64.. {
+        term = div(&(&term * &r), &BigDecimal::from(n), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    for _ in 0..halvings {
+        sum = round(&(&sum * &sum), wp);
+    }
+    Some(round(&sum, precision))
+}
+
+/// Natural logarithm, `None` for non-positive numbers
+pub fn ln(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    if !x.is_positive() {
+        return None;
+    }
+    if x.is_one() {
+        return Some(BigDecimal::zero());
+    }
+    let wp = precision + GUARD_DIGITS;
+    // x = m * 10^k with 1 <= m < 10
+    let (digits, scale) = x.as_bigint_and_exponent();
+    let exponent = x.digits() as i64 - 1;
+    let m = BigDecimal::new(digits, exponent);
+    let k = exponent - scale;
+    let mut result = ln_reduced(&m, wp)?;
+    if k != 0 {
+        result += ln_reduced(&BigDecimal::from(10), wp + exponent_digits(k))? * BigDecimal::from(k);
+    }
+    Some(round(&result, precision))
+}
+
+/// ln(y) for y >= 1 by square roots and the atanh series
+fn ln_reduced(y: &BigDecimal, wp: u64) -> Option<BigDecimal> {
+    let sqrt_context = context(wp + GUARD_DIGITS);
+    let threshold = BigDecimal::new(101.into(), 2);
+    let mut y = y.clone();
+    let mut roots = 0u32;
+    while y > threshold {
+        y = y.sqrt_with_context(&sqrt_context)?;
+        roots += 1;
+    }
+    // ln y = 2 atanh(z) = 2 (z + z^3/3 + z^5/5 + ...) with z = (y - 1) / (y + 1)
+    let one = BigDecimal::one();
+    let z = div(&(&y - &one), &(&y + &one), wp + GUARD_DIGITS)?;
+    let z_squared = round(&(&z * &z), wp);
+    let eps = epsilon(wp + 2);
+    let mut power = z.clone();
+    let mut sum = z;
+    for k in 1u64.. {
+        power = round(&(&power * &z_squared), wp);
+        let term = div(&power, &BigDecimal::from(2 * k + 1), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += term;
+    }
+    Some(sum * BigDecimal::from(2u64 << roots))
+}
+
+/// Base 10 logarithm, exact for powers of ten
+pub fn log10(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let (digits, scale) = x.normalized().as_bigint_and_exponent();
+    if digits.is_one() {
+        return Some(BigDecimal::from(-scale));
+    }
+    let wp = precision + GUARD_DIGITS;
+    div(&ln(x, wp)?, &ln(&BigDecimal::from(10), wp)?, precision)
+}
+
+/// Base 2 logarithm
+pub fn log2(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    div(&ln(x, wp)?, &ln(&BigDecimal::from(2), wp)?, precision)
+}
+
+/// Sine, `None` for angles too large to reduce
+pub fn sin(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    let r = reduce_angle(x, wp)?;
+    let r_squared = round(&(&r * &r), wp);
+    let eps = epsilon(wp);
+    let mut term = r.clone();
+    let mut sum = r;
+    for n in 1u64.. {
+        term = -div(&(&term * &r_squared), &BigDecimal::from((2 * n) * (2 * n + 1)), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    Some(round(&sum, precision))
+}
+
+/// Cosine, `None` for angles too large to reduce
+pub fn cos(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    let r = reduce_angle(x, wp)?;
+    let r_squared = round(&(&r * &r), wp);
+    let eps = epsilon(wp);
+    let mut term = BigDecimal::one();
+    let mut sum = BigDecimal::one();
+    for n in 1u64.. {
+        term = -div(&(&term * &r_squared), &BigDecimal::from((2 * n - 1) * (2 * n)), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    Some(round(&sum, precision))
+}
+
+/// Tangent
+pub fn tan(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    div(&sin(x, wp)?, &cos(x, wp)?, precision)
+}
+
+/// Reduces an angle to [-π, π], carrying enough digits to keep the remainder accurate
+fn reduce_angle(x: &BigDecimal, wp: u64) -> Option<BigDecimal> {
+    let integer_digits = x.digits() as i64 - x.fractional_digit_count();
+    if integer_digits > MAX_ANGLE_DIGITS {
+        return None;
+    }
+    let wp = wp + integer_digits.max(0) as u64;
+    let two_pi = pi(wp) * BigDecimal::from(2);
+    let turns = div(x, &two_pi, wp)?.with_scale_round(0, RoundingMode::HalfEven);
+    Some(round(&(x - turns * two_pi), wp))
+}
+
+/// Arctangent
+pub fn atan(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    if x.is_negative() {
+        return atan(&-x, precision).map(|y| -y);
+    }
+    let one = BigDecimal::one();
+    let result = if *x > one {
+        // atan(x) = π/2 - atan(1/x)
+        pi(wp).half() - atan_reduced(&div(&one, x, wp)?, wp)?
+    } else {
+        atan_reduced(x, wp)?
+    };
+    Some(round(&result, precision))
+}
+
+/// atan(x) for 0 <= x <= 1, halving the angle twice before summing the series
+fn atan_reduced(x: &BigDecimal, wp: u64) -> Option<BigDecimal> {
+    let one = BigDecimal::one();
+    let mut x = x.clone();
+    for _ in 0..2 {
+        // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2)))
+        let root = sqrt(&(&one + &x * &x), wp)?;
+        x = div(&x, &(&one + root), wp)?;
+    }
+    let x_squared = round(&(&x * &x), wp);
+    let eps = epsilon(wp);
+    let mut power = x.clone();
+    let mut sum = x;
+    for k in 1u64.. {
+        power = -round(&(&power * &x_squared), wp);
+        let term = div(&power, &BigDecimal::from(2 * k + 1), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += term;
+    }
+    Some(sum * BigDecimal::from(4))
+}
+
+/// Arcsine, `None` outside [-1, 1]
+pub fn asin(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    let one = BigDecimal::one();
+    let magnitude = x.abs();
+    if magnitude > one {
+        return None;
+    }
+    if magnitude == one {
+        let right_angle = pi(precision).half();
+        return Some(if x.is_negative() { -right_angle } else { right_angle });
+    }
+    let cosine = sqrt(&(&one - x * x), wp)?;
+    atan(&div(x, &cosine, wp)?, precision)
+}
+
+/// Arccosine, `None` outside [-1, 1]
+pub fn acos(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    Some(round(&(pi(wp).half() - asin(x, wp)?), precision))
+}
+
+/// Hyperbolic sine, summing the series near zero where e^x - e^-x would cancel
+pub fn sinh(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    if x.abs() >= BigDecimal::one() {
+        let exp_x = exp(x, wp)?;
+        let difference = &exp_x - div(&BigDecimal::one(), &exp_x, wp)?;
+        return Some(round(&difference.half(), precision));
+    }
+    let x_squared = round(&(x * x), wp);
+    let eps = epsilon(wp);
+    let mut term = x.clone();
+    let mut sum = x.clone();
+    for n in 1u64.. {
+        term = div(&(&term * &x_squared), &BigDecimal::from((2 * n) * (2 * n + 1)), wp)?;
+        if term.abs() < eps {
+            break;
+        }
+        sum += &term;
+    }
+    Some(round(&sum, precision))
+}
+
+/// Hyperbolic cosine
+pub fn cosh(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    let exp_x = exp(x, wp)?;
+    let sum = &exp_x + div(&BigDecimal::one(), &exp_x, wp)?;
+    Some(round(&sum.half(), precision))
+}
+
+/// Hyperbolic tangent
+pub fn tanh(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    let wp = precision + GUARD_DIGITS;
+    div(&sinh(x, wp)?, &cosh(x, wp)?, precision)
+}
+
+/// Radians to degrees
+pub fn degrees(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    div(&(x * BigDecimal::from(180)), &pi(precision + GUARD_DIGITS), precision)
+}
+
+/// Degrees to radians
+pub fn radians(x: &BigDecimal, precision: u64) -> Option<BigDecimal> {
+    div(&(x * pi(precision + GUARD_DIGITS)), &BigDecimal::from(180), precision)
+}
+
+/// Rounds to an integer in the given direction
+pub fn to_integer(x: &BigDecimal, mode: RoundingMode) -> BigDecimal {
+    x.with_scale_round(0, mode)
+}
+
+fn context(precision: u64) -> Context {
+    Context::new(nonzero(precision), RoundingMode::HalfEven)
+}
+
+fn nonzero(precision: u64) -> NonZeroU64 {
+    NonZeroU64::new(precision.max(1)).unwrap_or(NonZeroU64::MIN)
+}
+
+/// 10^-digits
+fn epsilon(digits: u64) -> BigDecimal {
+    BigDecimal::new(BigInt::one(), digits as i64)
+}
+
+/// Digits needed to hold the integer `k`
+fn exponent_digits(k: i64) -> u64 {
+    k.unsigned_abs()
+        .checked_ilog10()
+        .map_or(0, |digits| u64::from(digits) + 1)
+}
@@ -0,0 +1,294 @@
+//! Probability distributions backed by `statrs`
+//!
+//! Each distribution has a density (`pdf`, or `pmf` for discrete ones), a cumulative
+//! distribution (`cdf`) and, for continuous ones, its inverse (`inv`), named after MATLAB, e.g.
+//! `normpdf(x, mu, sigma)`. Invalid parameters such as a negative standard deviation are reported
+//! with a message instead of returning NaN.
+
+use statrs::distribution::{
+    Binomial, ChiSquared, Continuous, ContinuousCDF, Discrete, DiscreteCDF, Exp, FisherSnedecor, Normal, Poisson,
+    StudentsT, Uniform,
+};
+
+use super::context::Arity;
+
+/// A distribution function callable from expressions
+pub struct DistributionFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result, or explains which argument is invalid
+    pub eval: fn(&[f64]) -> Result<f64, String>,
+}
+
+/// All distribution functions, registered by the evaluator
+pub const FUNCTIONS: &[DistributionFn] = &[
+    DistributionFn {
+        name: "normpdf",
+        params: "x, mu, sigma",
+        arity: Arity::Between(1, 3),
+        eval: |args| Ok(normal(args)?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "normcdf",
+        params: "x, mu, sigma",
+        arity: Arity::Between(1, 3),
+        eval: |args| Ok(normal(args)?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "norminv",
+        params: "p, mu, sigma",
+        arity: Arity::Between(1, 3),
+        eval: |args| Ok(normal(args)?.inverse_cdf(probability("p", args[0])?)),
+    },
+    DistributionFn {
+        name: "tpdf",
+        params: "x, nu",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(students_t(args[1])?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "tcdf",
+        params: "x, nu",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(students_t(args[1])?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "tinv",
+        params: "p, nu",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(students_t(args[1])?.inverse_cdf(probability("p", args[0])?)),
+    },
+    DistributionFn {
+        name: "chi2pdf",
+        params: "x, k",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(chi_squared(args[1])?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "chi2cdf",
+        params: "x, k",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(chi_squared(args[1])?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "chi2inv",
+        params: "p, k",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(chi_squared(args[1])?.inverse_cdf(probability("p", args[0])?)),
+    },
+    DistributionFn {
+        name: "fpdf",
+        params: "x, d1, d2",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(fisher_snedecor(args[1], args[2])?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "fcdf",
+        params: "x, d1, d2",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(fisher_snedecor(args[1], args[2])?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "finv",
+        params: "p, d1, d2",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(fisher_snedecor(args[1], args[2])?.inverse_cdf(probability("p", args[0])?)),
+    },
+    DistributionFn {
+        name: "binompmf",
+        params: "k, n, p",
+        arity: Arity::Exact(3),
+        eval: |args| {
+            let binomial = binomial(args[1], args[2])?;
+            Ok(outcome(args[0])?.map_or(0.0, |k| binomial.pmf(k)))
+        },
+    },
+    DistributionFn {
+        name: "binomcdf",
+        params: "k, n, p",
+        arity: Arity::Exact(3),
+        eval: |args| {
+            let binomial = binomial(args[1], args[2])?;
+            Ok(outcome(args[0])?.map_or(0.0, |k| binomial.cdf(k)))
+        },
+    },
+    DistributionFn {
+        name: "poisspmf",
+        params: "k, lambda",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            let poisson = poisson(args[1])?;
+            Ok(outcome(args[0])?.map_or(0.0, |k| poisson.pmf(k)))
+        },
+    },
+    DistributionFn {
+        name: "poisscdf",
+        params: "k, lambda",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            let poisson = poisson(args[1])?;
+            Ok(outcome(args[0])?.map_or(0.0, |k| poisson.cdf(k)))
+        },
+    },
+    DistributionFn {
+        name: "exppdf",
+        params: "x, lambda",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(exponential(args[1])?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "expcdf",
+        params: "x, lambda",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(exponential(args[1])?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "expinv",
+        params: "p, lambda",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(exponential(args[1])?.inverse_cdf(probability("p", args[0])?)),
+    },
+    DistributionFn {
+        name: "unifpdf",
+        params: "x, a, b",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(uniform(args[1], args[2])?.pdf(args[0])),
+    },
+    DistributionFn {
+        name: "unifcdf",
+        params: "x, a, b",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(uniform(args[1], args[2])?.cdf(args[0])),
+    },
+    DistributionFn {
+        name: "unifinv",
+        params: "p, a, b",
+        arity: Arity::Exact(3),
+        eval: |args| Ok(uniform(args[1], args[2])?.inverse_cdf(probability("p", args[0])?)),
+    },
+];
+
+/// Normal distribution from the optional `mu` and `sigma` after `x`, standard by default
+fn normal(args: &[f64]) -> Result<Normal, String> {
+    let mu = args.get(1).copied().unwrap_or(0.0);
+    let sigma = positive("sigma", args.get(2).copied().unwrap_or(1.0))?;
+    if !mu.is_finite() {
+        return Err("mu must be a finite number".to_string());
+    }
+    Normal::new(mu, sigma).map_err(|e| e.to_string())
+}
+
+fn students_t(nu: f64) -> Result<StudentsT, String> {
+    StudentsT::new(0.0, 1.0, positive("degrees of freedom", nu)?).map_err(|e| e.to_string())
+}
+
+fn chi_squared(k: f64) -> Result<ChiSquared, String> {
+    ChiSquared::new(positive("degrees of freedom", k)?).map_err(|e| e.to_string())
+}
+
+fn fisher_snedecor(d1: f64, d2: f64) -> Result<FisherSnedecor, String> {
+    FisherSnedecor::new(positive("d1", d1)?, positive("d2", d2)?).map_err(|e| e.to_string())
+}
+
+fn binomial(n: f64, p: f64) -> Result<Binomial, String> {
+    if n < 0.0 || n.fract() != 0.0 || n >= u64::MAX as f64 {
+        return Err("n must be a non-negative integer".to_string());
+    }
+    Binomial::new(probability("p", p)?, n as u64).map_err(|e| e.to_string())
+}
+
+fn poisson(lambda: f64) -> Result<Poisson, String> {
+    Poisson::new(positive("lambda", lambda)?).map_err(|e| e.to_string())
+}
+
+fn exponential(lambda: f64) -> Result<Exp, String> {
+    Exp::new(positive("lambda", lambda)?).map_err(|e| e.to_string())
+}
+
+fn uniform(a: f64, b: f64) -> Result<Uniform, String> {
+    if !(a.is_finite() && b.is_finite()) {
+        return Err("a and b must be finite numbers".to_string());
+    }
+    if a >= b {
+        return Err("a must be less than b".to_string());
+    }
+    Uniform::new(a, b).map_err(|e| e.to_string())
+}
+
+/// A parameter that must be a positive finite number
+fn positive(name: &str, x: f64) -> Result<f64, String> {
+    if x > 0.0 && x.is_finite() {
+        Ok(x)
+    } else {
+        Err(format!("{} must be positive, got {}", name, x))
+    }
+}
+
+/// A probability in [0, 1]
+fn probability(name: &str, p: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&p) {
+        Ok(p)
+    } else {
+        Err(format!("{} must be between 0 and 1, got {}", name, p))
+    }
+}
+
+/// The outcome `k` of a discrete distribution, `None` for negative outcomes which have probability 0
+fn outcome(k: f64) -> Result<Option<u64>, String> {
+    if k.fract() != 0.0 {
+        return Err(format!("k must be an integer, got {}", k));
+    }
+    Ok((k >= 0.0).then_some(k as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[f64]) -> Result<f64, String> {
+        let function = FUNCTIONS.iter().find(|f| f.name == name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.eval)(args)
+    }
+
+    #[test]
+    fn test_continuous() {
+        assert!((call("normpdf", &[0.0]).unwrap() - 0.398_942_280_401_432_7).abs() < 1e-15);
+        assert!((call("normcdf", &[1.96]).unwrap() - 0.975_002_104_851_780).abs() < 1e-9);
+        assert!((call("norminv", &[0.975, 100.0, 15.0]).unwrap() - 129.399_459_77).abs() < 1e-6);
+        assert_eq!(call("norminv", &[0.5]).unwrap(), 0.0);
+        assert!((call("tcdf", &[2.0, 10.0]).unwrap() - 0.963_305_982_614_8).abs() < 1e-9);
+        assert!((call("chi2cdf", &[3.841_458_820_694_124, 1.0]).unwrap() - 0.95).abs() < 1e-9);
+        assert!((call("expinv", &[0.5, 2.0]).unwrap() - std::f64::consts::LN_2 / 2.0).abs() < 1e-12);
+        assert_eq!(call("unifcdf", &[3.0, 2.0, 6.0]).unwrap(), 0.25);
+    }
+
+    #[test]
+    fn test_discrete() {
+        assert!((call("binompmf", &[3.0, 10.0, 0.5]).unwrap() - 120.0 / 1024.0).abs() < 1e-12);
+        assert!((call("binomcdf", &[10.0, 10.0, 0.3]).unwrap() - 1.0).abs() < 1e-12);
+        assert!((call("poisspmf", &[2.0, 3.0]).unwrap() - 4.5 * (-3.0f64).exp()).abs() < 1e-12);
+        assert_eq!(call("poisscdf", &[-1.0, 3.0]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            call("normpdf", &[0.0, 0.0, -1.0]).unwrap_err(),
+            "sigma must be positive, got -1"
+        );
+        assert_eq!(
+            call("norminv", &[1.5]).unwrap_err(),
+            "p must be between 0 and 1, got 1.5"
+        );
+        assert!(call("tcdf", &[1.0, 0.0]).is_err());
+        assert!(call("binompmf", &[1.0, 2.5, 0.5]).is_err());
+        assert!(call("binompmf", &[1.5, 3.0, 0.5]).is_err());
+        assert!(call("unifpdf", &[0.0, 2.0, 1.0]).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Structured evaluation errors with source spans
+
+use thiserror::Error;
+
+use super::ast::Span;
+use super::csv::CsvError;
+
+/// Result type used by the parser and evaluator
+pub type CalcResult<T> = std::result::Result<T, CalcError>;
+
+/// What went wrong while parsing or evaluating an expression
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ErrorKind {
+    #[error("unexpected character '{0}'")]
+    UnexpectedChar(char),
+
+    #[error("invalid number '{0}'")]
+    InvalidNumber(String),
+
+    #[error("text is never closed")]
+    UnterminatedText,
+
+    #[error("unexpected {0}")]
+    UnexpectedToken(String),
+
+    #[error("unbalanced parenthesis: {0}")]
+    UnbalancedParen(String),
+
+    #[error("unknown function '{0}'")]
+    UnknownFunction(String),
+
+    #[error("unknown variable '{0}'")]
+    UnknownVariable(String),
+
+    #[error("unknown unit '{0}'")]
+    UnknownUnit(String),
+
+    #[error("incompatible units: {0} and {1}")]
+    IncompatibleUnits(String, String),
+
+    #[error("cannot assign to '{0}'")]
+    InvalidAssignment(String),
+
+    #[error("'{0}' is a constant and cannot be reassigned")]
+    ReadOnly(String),
+
+    #[error("invalid definition of '{0}'")]
+    InvalidDefinition(String),
+
+    #[error("'{0}' is a built-in function and cannot be redefined")]
+    BuiltinFunction(String),
+
+    #[error("'{0}' defines a function and has no value")]
+    NoValue(String),
+
+    #[error("'{0}' is a function and must be called with arguments")]
+    NotAVariable(String),
+
+    #[error("'{name}' expects {expected} argument(s), got {found}")]
+    ArityMismatch {
+        name: String,
+        expected: String,
+        found: usize,
+    },
+
+    #[error("domain error in '{name}': {message}")]
+    DomainError { name: String, message: String },
+
+    #[error("division by zero")]
+    DivisionByZero,
+
+    #[error("complex numbers are disabled")]
+    ComplexDisabled,
+
+    #[error("result {0} is not a real number")]
+    NotReal(String),
+
+    #[error("in '{name}': {source}")]
+    InFunction { name: String, source: Box<ErrorKind> },
+
+    #[error("recursion limit exceeded while calling '{0}'")]
+    RecursionLimit(String),
+
+    #[error("'{name}' did not converge: {message}")]
+    NoConvergence { name: String, message: String },
+
+    #[error("an equation is only allowed as an argument, e.g. of solve")]
+    MisplacedEquation,
+
+    #[error("a range is only allowed as an argument, e.g. of ode")]
+    MisplacedRange,
+
+    #[error("{0}")]
+    Mod(String),
+
+    #[error("{0}")]
+    Csv(CsvError),
+}
+
+/// An error together with the byte range it refers to and an optional hint
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{kind}")]
+pub struct CalcError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub suggestion: Option<String>,
+}
+
+impl CalcError {
+    /// Creates an error without a suggestion
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            suggestion: None,
+        }
+    }
+
+    /// Attaches a suggestion shown below the diagnostic
+    pub fn with_suggestion<S: Into<String>>(mut self, suggestion: S) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// Replaces the span, e.g. when an error from a nested source is reported at the call site
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Renders the error as a multi-line diagnostic with a caret underline:
+    ///
+    /// ```text
+    /// error: unknown function 'sqr'
+    ///   sqr(4) + 1
+    ///   ^^^
+    /// help: did you mean 'sqrt'?
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("error: {}\n", self.kind);
+
+        let line = source.replace(['\n', '\r', '\t'], " ");
+        let start = clamp_to_char_boundary(source, self.span.start);
+        let end = clamp_to_char_boundary(source, self.span.end.max(start));
+        let column = source[..start].chars().count();
+        let width = source[start..end].chars().count().max(1);
+
+        out.push_str(&format!("  {}\n", line));
+        out.push_str(&format!("  {}{}\n", " ".repeat(column), "^".repeat(width)));
+
+        if let Some(suggestion) = &self.suggestion {
+            out.push_str(&format!("help: {}\n", suggestion));
+        }
+        out
+    }
+}
+
+fn clamp_to_char_boundary(source: &str, mut index: usize) -> usize {
+    index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Finds the candidate closest to `name` by edit distance, if any is close enough
+///
+/// Among equally close candidates, one that `name` is the start of wins, since names are more
+/// often cut short than mistyped, e.g. `sqr` suggests `sqrt` rather than `qr`.
+pub fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|&(distance, candidate)| (distance, !candidate.starts_with(name), candidate))
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_caret() {
+        let error = CalcError::new(ErrorKind::UnknownFunction("sqr".to_string()), Span::new(4, 7))
+            .with_suggestion("did you mean 'sqrt'?");
+        assert_eq!(
+            error.render("1 + sqr(4)"),
+            "error: unknown function 'sqr'\n  1 + sqr(4)\n      ^^^\nhelp: did you mean 'sqrt'?\n"
+        );
+    }
+
+    #[test]
+    fn test_render_empty_span_at_end() {
+        let error = CalcError::new(ErrorKind::UnexpectedToken("end of input".to_string()), Span::new(3, 3));
+        assert!(error.render("1 +").ends_with("  1 +\n     ^\n"));
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let names = ["sqrt", "sin", "sinh", "log10"];
+        assert_eq!(closest_match("sqr", names), Some("sqrt"));
+        assert_eq!(closest_match("log1", names), Some("log10"));
+        assert_eq!(closest_match("banana", names), None);
+    }
+}
@@ -1,13 +1,81 @@
 //! Expression evaluation module
 
-use anyhow::{bail, Result};
-use meval::{Context, Expr};
-use std::collections::HashMap;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
 use std::sync::Arc;
 
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_complex::Complex64;
+use num_traits::Signed;
+
+use super::ast::{Expr, ExprKind, Span, Stmt};
+use super::cache::{CacheStats, LruCache};
+use super::calculus;
+use super::context::{Context, FunctionBody};
+use super::csv::{self, CsvOptions, Table};
+use super::decimal;
+use super::distributions;
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::lexer::is_identifier;
 use super::math_functions::*;
-use super::mods::{Mod, ModManager};
+use super::matrix;
+use super::mods::{save_mod_file, Mod, ModManager, ModType, SimplifiedMod};
+use super::ode;
+use super::parser::{parse, parse_program};
+use super::poly;
+use super::programmer::WordSize;
 use super::random::*;
+use super::rational;
+use super::regression;
+use super::solver;
+use super::stats;
+use super::symbolic;
+use super::units::UnitRegistry;
+use super::value::Value;
+
+/// Built-in constants that user assignments may not replace
+const RESERVED_NAMES: [&str; 3] = ["pi", "e", "m"];
+
+/// Significant digits decimal mode carries beyond the displayed decimal places
+const DECIMAL_GUARD_DIGITS: u64 = 20;
+
+/// Default number of entries kept in each of the AST and result caches
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+/// A function defined from the expression line, e.g. `f(x, y) = x^2 + y`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub name: String,
+    pub params: Vec<String>,
+
+    /// Body as it was typed
+    pub body: String,
+}
+
+impl fmt::Display for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}) = {}", self.name, self.params.join(", "), self.body)
+    }
+}
+
+/// What running an input line produced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The last statement was an expression or assignment with this value
+    Value(Value),
+
+    /// The last statement defined the named function
+    Defined(String),
+}
+
+/// A memoised result together with the values of the variables it was computed from
+#[derive(Debug, Clone)]
+struct CachedResult {
+    value: Value,
+    dependencies: Vec<(String, Option<Value>)>,
+}
 
 /// Evaluates mathematical expressions
 pub struct Evaluator {
@@ -15,19 +83,28 @@ pub struct Evaluator {
     safe_mode: bool,
 
     /// Context with custom functions
-    context: Context<'static>,
+    context: Context,
 
     /// Mod manager for custom mod functions
     mod_manager: ModManager,
 
-    /// Expression cache for performance optimization
-    expr_cache: HashMap<String, Arc<Expr>>,
+    /// Names of mod functions and constants currently registered in the context
+    mod_bindings: Vec<String>,
+
+    /// User variables assigned with `name = value` or `set_variable`
+    variables: BTreeMap<String, Value>,
 
-    /// Result cache for performance optimization
-    result_cache: HashMap<String, f64>,
+    /// User functions defined with `name(params) = body` or `define_function`
+    functions: BTreeMap<String, UserFunction>,
+
+    /// Parsed programs by source text
+    expr_cache: LruCache<String, Arc<Vec<Stmt>>>,
+
+    /// Results of pure single-expression inputs, checked against their dependencies on lookup
+    result_cache: LruCache<String, CachedResult>,
 
     /// Last calculation result (for 'm' constant)
-    last_result: f64,
+    last_result: Value,
 }
 
 impl Evaluator {
@@ -77,6 +154,50 @@ impl Evaluator {
         ctx.func("erf", erf);
         ctx.func("erfc", erfc);
 
+        // Add power and aggregate functions
+        ctx.func2("pow", pow);
+        ctx.funcn("hypot", hypot, 1..);
+        ctx.funcn("sum", sum, 1..);
+        ctx.funcn("avg", avg, 1..);
+
+        // Add integer functions
+        ctx.funcn(
+            "gcd",
+            |args| {
+                args.iter()
+                    .copied()
+                    .reduce(|a, b| integer_pair(a, b).map_or(f64::NAN, |(a, b)| gcd(a, b) as f64))
+                    .unwrap_or(f64::NAN)
+            },
+            2..,
+        );
+        ctx.funcn(
+            "lcm",
+            |args| {
+                args.iter()
+                    .copied()
+                    .reduce(|a, b| {
+                        integer_pair(a, b).map_or(f64::NAN, |(a, b)| match lcm(a, b) {
+                            Some(lcm) => lcm as f64,
+                            None => (a / gcd(a, b)) as f64 * b as f64,
+                        })
+                    })
+                    .unwrap_or(f64::NAN)
+            },
+            2..,
+        );
+        ctx.func2("binomial", binomial);
+        ctx.funcn(
+            "modpow",
+            |args| match (integer_pair(args[0], args[1]), integer_pair(args[2], 0.0)) {
+                (Some((base, exponent)), Some((modulus, _))) if args[0] >= 0.0 && args[1] >= 0.0 && modulus > 0 => {
+                    modpow(base, exponent, modulus) as f64
+                }
+                _ => f64::NAN,
+            },
+            3,
+        );
+
         // Add angle conversion functions
         ctx.func("degrees", degrees);
         ctx.func("radians", radians);
@@ -87,21 +208,220 @@ impl Evaluator {
         // Add geometric functions (two argument versions)
         ctx.funcn("s_tri", |args| triangle_area(args[0], args[1]), 2);
         ctx.funcn("s_rect", |args| rectangle_area(args[0], args[1]), 2);
+        ctx.func2("pythagorean_theorem", pythagorean_theorem);
+
+        // Add geometric functions (three argument versions)
+        ctx.funcn("trapezoid_area", |args| trapezoid_area(args[0], args[1], args[2]), 3);
+        ctx.funcn(
+            "heron_triangle_area",
+            |args| heron_triangle_area(args[0], args[1], args[2]).unwrap_or(f64::NAN),
+            3,
+        );
+
+        // Add floating point checks, returning 1 for true and 0 for false
+        ctx.func("is_nan", |x| f64::from(u8::from(is_nan(x))));
+        ctx.func("is_inf", |x| f64::from(u8::from(is_inf(x))));
+        ctx.funcn(
+            "is_close",
+            |args| {
+                // Tolerances default to those of Python's math.isclose
+                let rel_tol = args.get(2).copied().unwrap_or(1e-9);
+                let abs_tol = args.get(3).copied().unwrap_or(0.0);
+                if rel_tol < 0.0 || abs_tol < 0.0 {
+                    return f64::NAN;
+                }
+                f64::from(u8::from(is_close(args[0], args[1], rel_tol, abs_tol)))
+            },
+            2..=4,
+        );
+
+        // Add probability distributions
+        for function in distributions::FUNCTIONS {
+            ctx.checked_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add statistics over lists
+        for function in stats::FUNCTIONS {
+            ctx.value_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add matrix and vector functions
+        for function in matrix::FUNCTIONS {
+            ctx.value_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add polynomial functions over coefficient lists
+        for function in poly::FUNCTIONS {
+            ctx.value_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add regression and curve fitting
+        for function in regression::FUNCTIONS {
+            ctx.value_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add equation solving, which binds the solved variable itself
+        for function in solver::FUNCTIONS {
+            ctx.lazy_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add calculus over a bound variable; sum and prod(expr, k, from, to) extend the variadic
+        // sum and the list product
+        for function in calculus::FUNCTIONS {
+            ctx.lazy_funcn(function.name, function.eval, function.arity);
+        }
+        ctx.unevaluated_funcn("sum", calculus::bounded_sum);
+        ctx.unevaluated_funcn("prod", calculus::bounded_prod);
+
+        // Add symbolic differentiation, also written d/dx(expr)
+        for function in symbolic::FUNCTIONS {
+            ctx.lazy_funcn(function.name, function.eval, function.arity);
+        }
+
+        // Add initial value problems, e.g. ode(dy/dt = -y, y0 = 1, t = 0..10)
+        for function in ode::FUNCTIONS {
+            ctx.lazy_funcn(function.name, function.eval, function.arity);
+        }
 
         // Add random functions
-        ctx.func("random", |_| random()); // Takes dummy parameter
+        ctx.func0("random", random);
+        ctx.func2("randint", |a, b| match (integer_bound(a), integer_bound(b)) {
+            (Some(a), Some(b)) if a <= b => randint(a, b) as f64,
+            _ => f64::NAN,
+        });
+        ctx.func2("uniform", |a, b| {
+            if a == b {
+                a
+            } else if a < b && (b - a).is_finite() {
+                uniform(a, b)
+            } else {
+                f64::NAN
+            }
+        });
+        for name in ["random", "randint", "uniform"] {
+            ctx.mark_impure(name);
+        }
+
+        // Add CSV import, impure since the file may change between calls
+        ctx.value_funcn("import_csv", csv::import_column, 2..=3);
+        ctx.mark_impure("import_csv");
+
+        // Add complex number helpers and complex versions of the functions above, used in complex mode
+        ctx.func("re", re);
+        ctx.func("im", im);
+        ctx.func("arg", arg);
+        ctx.func("conj", conj);
+        ctx.complex_func("re", complex_re);
+        ctx.complex_func("im", complex_im);
+        ctx.complex_func("arg", complex_arg);
+        ctx.complex_func("conj", complex_conj);
+        ctx.complex_funcn("pow", |args| complex_pow(args[0], args[1]));
+        ctx.complex_funcn("sum", |args| args.iter().sum());
+        ctx.complex_funcn("avg", |args| args.iter().sum::<Complex64>() / args.len() as f64);
+        ctx.complex_func("abs", complex_abs);
+        ctx.complex_func("fabs", complex_abs);
+        ctx.complex_func("sqrt", Complex64::sqrt);
+        ctx.complex_func("exp", Complex64::exp);
+        ctx.complex_func("ln", Complex64::ln);
+        ctx.complex_func("log", Complex64::ln);
+        ctx.complex_func("log10", complex_log10);
+        ctx.complex_func("log2", complex_log2);
+        ctx.complex_func("sin", Complex64::sin);
+        ctx.complex_func("cos", Complex64::cos);
+        ctx.complex_func("csin", complex_csin);
+        ctx.complex_func("tan", Complex64::tan);
+        ctx.complex_func("asin", Complex64::asin);
+        ctx.complex_func("acos", Complex64::acos);
+        ctx.complex_func("atan", Complex64::atan);
+        ctx.complex_func("sinh", Complex64::sinh);
+        ctx.complex_func("cosh", Complex64::cosh);
+        ctx.complex_func("tanh", Complex64::tanh);
+        ctx.complex_func("asinh", Complex64::asinh);
+        ctx.complex_func("acosh", Complex64::acosh);
+        ctx.complex_func("atanh", Complex64::atanh);
+
+        // Add exact versions, used when every argument is a fraction
+        ctx.rational_funcn("gcd", rational::gcd);
+        ctx.rational_funcn("lcm", rational::lcm);
+        ctx.rational_funcn("binomial", |args| rational::binomial(&args[0], &args[1]));
+        ctx.rational_funcn("modpow", rational::modpow);
+        ctx.rational_func("factorial", rational::factorial);
+        ctx.rational_funcn("max", rational::max);
+        ctx.rational_funcn("min", rational::min);
+        ctx.rational_funcn("sum", rational::sum);
+        ctx.rational_funcn("avg", rational::avg);
+        ctx.rational_funcn("pow", |args| rational::pow(&args[0], &args[1]));
+        ctx.rational_func("sqrt", rational::sqrt);
+        ctx.rational_func("abs", |r| Some(r.abs()));
+        ctx.rational_func("fabs", |r| Some(r.abs()));
+        ctx.rational_func("floor", |r| Some(r.floor()));
+        ctx.rational_func("ceil", |r| Some(r.ceil()));
+        ctx.rational_func("trunc", |r| Some(r.trunc()));
+        ctx.rational_func("round", |r| Some(r.round()));
+        ctx.rational_func("signum", |r| Some(rational::signum(r)));
+        ctx.rational_func("re", |r| Some(r.clone()));
+        ctx.rational_func("conj", |r| Some(r.clone()));
+
+        // Add arbitrary-precision versions, used in decimal mode
+        ctx.decimal_func("sqrt", decimal::sqrt);
+        ctx.decimal_func("exp", decimal::exp);
+        ctx.decimal_func("ln", decimal::ln);
+        ctx.decimal_func("log", decimal::ln);
+        ctx.decimal_func("log10", decimal::log10);
+        ctx.decimal_func("log2", decimal::log2);
+        ctx.decimal_func("sin", decimal::sin);
+        ctx.decimal_func("cos", decimal::cos);
+        ctx.decimal_func("tan", decimal::tan);
+        ctx.decimal_func("csin", |x, precision| {
+            decimal::cos(&decimal::sin(x, precision + 5)?, precision)
+        });
+        ctx.decimal_func("asin", decimal::asin);
+        ctx.decimal_func("acos", decimal::acos);
+        ctx.decimal_func("atan", decimal::atan);
+        ctx.decimal_func("sinh", decimal::sinh);
+        ctx.decimal_func("cosh", decimal::cosh);
+        ctx.decimal_func("tanh", decimal::tanh);
+        ctx.decimal_func("degrees", decimal::degrees);
+        ctx.decimal_func("radians", decimal::radians);
+        ctx.decimal_func("factorial", |x, _| decimal::factorial(x));
+        ctx.decimal_func("abs", |x, _| Some(x.abs()));
+        ctx.decimal_func("fabs", |x, _| Some(x.abs()));
+        ctx.decimal_func("floor", |x, _| Some(decimal::to_integer(x, RoundingMode::Floor)));
+        ctx.decimal_func("ceil", |x, _| Some(decimal::to_integer(x, RoundingMode::Ceiling)));
+        ctx.decimal_func("trunc", |x, _| Some(decimal::to_integer(x, RoundingMode::Down)));
+        ctx.decimal_func("round", |x, _| Some(decimal::to_integer(x, RoundingMode::HalfUp)));
+        ctx.decimal_func("s_circle", |r, precision| Some(decimal::pi(precision + 5) * r * r));
+        ctx.decimal_funcn("pow", |args, precision| decimal::pow(&args[0], &args[1], precision));
+        ctx.decimal_funcn("max", |args, _| args.iter().max().cloned());
+        ctx.decimal_funcn("min", |args, _| args.iter().min().cloned());
+        ctx.decimal_funcn("sum", |args, _| Some(args.iter().sum()));
+        ctx.decimal_funcn("avg", |args, precision| {
+            decimal::div(&args.iter().sum(), &BigDecimal::from(args.len() as u64), precision)
+        });
+        ctx.decimal_funcn("hypot", |args, precision| {
+            decimal::sqrt(&args.iter().map(|x| x * x).sum(), precision)
+        });
+
+        // Trigonometric functions take and return angles in the angle mode
+        ctx.mark_angles();
 
         let mut mod_manager = ModManager::new();
         let _ = mod_manager.load_mods(); // Silently ignore errors if mods dir doesn't exist
 
-        Self {
+        let mut evaluator = Self {
             safe_mode: true,
             context: ctx,
             mod_manager,
-            expr_cache: HashMap::new(),
-            result_cache: HashMap::new(),
-            last_result: 0.0,
-        }
+            mod_bindings: Vec::new(),
+            variables: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            expr_cache: LruCache::new(DEFAULT_CACHE_CAPACITY),
+            result_cache: LruCache::new(DEFAULT_CACHE_CAPACITY),
+            last_result: Value::Real(0.0),
+        };
+        evaluator.register_mods();
+        evaluator.load_units();
+        evaluator
     }
 
     /// Sets the evaluation mode
@@ -111,18 +431,164 @@ impl Evaluator {
 
     /// Sets the last result (updates 'm' constant)
     pub fn set_last_result(&mut self, result: f64) {
-        self.last_result = result;
+        self.set_last_value(Value::Real(result));
+    }
+
+    /// Sets the last result to a possibly complex value
+    pub fn set_last_value(&mut self, result: Value) {
+        self.last_result = result.clone();
         self.context.var("m", result);
     }
 
-    /// Gets the last result
+    /// Gets the last result; complex results give NaN, see `get_last_value`
     pub fn get_last_result(&self) -> f64 {
-        self.last_result
+        self.last_result.as_real().unwrap_or(f64::NAN)
+    }
+
+    /// Gets the last result, which may be complex
+    pub fn get_last_value(&self) -> Value {
+        self.last_result.clone()
+    }
+
+    /// Enables or disables complex mode
+    ///
+    /// In complex mode `i` is the imaginary unit, literals like `4i` are allowed and functions
+    /// such as `sqrt(-1)` return complex results instead of domain errors.
+    pub fn set_complex_mode(&mut self, enabled: bool) {
+        self.context.set_complex_mode(enabled);
+        if enabled {
+            self.context.var("i", Complex64::i());
+        } else if let Some(value) = self.variables.get("i") {
+            // A user variable named 'i' is shadowed only while complex mode is on
+            self.context.var("i", value.clone());
+        } else {
+            self.context.remove_var("i");
+        }
+        // Cached results may be errors or real values that now differ
+        self.result_cache.clear();
+    }
+
+    /// Whether complex mode is enabled
+    pub fn complex_mode(&self) -> bool {
+        self.context.complex_mode()
+    }
+
+    /// Enables decimal mode showing `decimal_places` digits after the point, or disables it
+    ///
+    /// Decimal mode computes with arbitrary-precision decimals instead of floats, so `0.1 + 0.2`
+    /// is exactly 0.3 and `factorial(30)` keeps every digit. Sums, differences, products and
+    /// integer powers are exact; other results carry `decimal_places` plus guard digits.
+    pub fn set_decimal_mode(&mut self, decimal_places: Option<usize>) {
+        let precision = decimal_places.map(|places| places as u64 + DECIMAL_GUARD_DIGITS);
+        self.context.set_decimal_precision(precision);
+        match precision {
+            Some(precision) => {
+                self.context.var("pi", Value::Decimal(decimal::pi(precision)));
+                self.context.var("e", Value::Decimal(decimal::e(precision)));
+            }
+            None => {
+                self.context.var("pi", pi());
+                self.context.var("e", e());
+            }
+        }
+        // Cached results were computed in the other mode or with another precision
+        self.result_cache.clear();
+    }
+
+    /// Significant digits decimal mode computes with, `None` when it is disabled
+    pub fn decimal_precision(&self) -> Option<u64> {
+        self.context.decimal_precision()
+    }
+
+    /// Sets the word size of programmer mode, or lets integers grow freely with `None`
+    ///
+    /// Every integer result then wraps around to the word size, so with 8-bit unsigned integers
+    /// `255 + 1` is 0 and `~0` is 255. Fractions and floats are not affected.
+    pub fn set_word_size(&mut self, word_size: Option<WordSize>) {
+        self.context.set_word_size(word_size);
+        // Cached integer results may have been wrapped to another width
+        self.result_cache.clear();
+    }
+
+    /// Word size of programmer mode, `None` when integers are unbounded
+    pub fn word_size(&self) -> Option<WordSize> {
+        self.context.word_size()
+    }
+
+    /// Sets the unit `sin`, `cos` and `tan` take and `asin`, `acos`, `atan` return angles in
+    ///
+    /// Literals with a suffix, e.g. `30°` or `1rad`, are converted to the active unit.
+    pub fn set_angle_mode(&mut self, mode: AngleMode) {
+        self.context.set_angle_mode(mode);
+        // Cached trigonometric results were computed in the old unit
+        self.result_cache.clear();
+    }
+
+    /// Unit trigonometric functions take and return angles in
+    pub fn angle_mode(&self) -> AngleMode {
+        self.context.angle_mode()
     }
 
     /// Reload all mods
     pub fn reload_mods(&mut self) -> Result<(), anyhow::Error> {
-        self.mod_manager.reload_mods()
+        let result = self.mod_manager.reload_mods();
+        self.register_mods();
+        self.load_units();
+        result
+    }
+
+    /// Extends the built-in units with the `.cmunit` files in the mods directory
+    fn load_units(&mut self) {
+        let mut units = UnitRegistry::builtin();
+        let warnings = Arc::make_mut(&mut units).load_dir(Path::new("mods"));
+        for warning in warnings {
+            self.mod_manager.push_warning(warning);
+        }
+        self.context.set_units(units);
+        // Cached results may use units that were redefined
+        self.result_cache.clear();
+    }
+
+    /// Registers every function mod as a callable and every constant mod as a variable
+    ///
+    /// Mods are parsed once here, so they compose with built-ins and each other anywhere in an
+    /// expression, e.g. `sqrt(a.b.c(max(1, 2), 3)) * b.c.d`.
+    fn register_mods(&mut self) {
+        for name in self.mod_bindings.drain(..) {
+            self.context.remove_func(&name);
+            self.context.remove_var(&name);
+        }
+        // Cached results may depend on the previous mod definitions
+        self.result_cache.clear();
+
+        let mut names = self.mod_manager.list_mods();
+        names.sort();
+        for name in names {
+            let Some(mod_def) = self.mod_manager.get_mod(&name) else {
+                continue;
+            };
+            if mod_def.mod_type == ModType::Num {
+                if let Some(value) = mod_def.res.res {
+                    self.context.var(name.clone(), value);
+                    self.mod_bindings.push(name);
+                }
+                continue;
+            }
+            let Some(method) = mod_def.calc.howto.clone() else {
+                continue;
+            };
+            let params = mod_def.var.needvars.clone();
+            match method.parse::<Expr>() {
+                Ok(body) => {
+                    self.context.define(name.clone(), params, body);
+                    self.mod_bindings.push(name);
+                }
+                Err(e) => {
+                    let warning = format!("Warning: Skipping mod '{}': invalid method \"{}\": {}", name, method, e);
+                    self.mod_manager.push_warning(warning);
+                }
+            }
+        }
     }
 
     /// List all available mods
@@ -150,141 +616,400 @@ impl Evaluator {
         self.mod_manager.get_mod(name)
     }
 
+    /// Lists user variables sorted by name
+    pub fn list_variables(&self) -> Vec<(String, Value)> {
+        self.variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Loads every numeric column of a CSV file into a list variable named after its header
+    ///
+    /// Text columns such as names are skipped. Returns the variable names in column order, or the
+    /// first cell of a numeric column that is not a number.
+    pub fn import_csv(&mut self, path: &Path, options: &CsvOptions) -> CalcResult<Vec<String>> {
+        let error = |e| CalcError::new(ErrorKind::Csv(e), Span::default());
+        let table = Table::read(path, options).map_err(error)?;
+        // Check every column before binding any, so a bad cell leaves the variables unchanged
+        let lists = table
+            .columns
+            .iter()
+            .filter(|column| !column.is_text)
+            .map(|column| Ok((column.variable_name(), column.to_list().map_err(error)?)))
+            .collect::<CalcResult<Vec<_>>>()?;
+        let mut names = Vec::new();
+        for (name, list) in lists {
+            self.set_variable(&name, list)?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    /// Gets a user variable
+    pub fn get_variable(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).cloned()
+    }
+
+    /// Sets a user variable, rejecting invalid names and built-in or mod constants
+    pub fn set_variable<V: Into<Value>>(&mut self, name: &str, value: V) -> CalcResult<()> {
+        if !is_identifier(name) || name.contains('.') {
+            return Err(CalcError::new(
+                ErrorKind::InvalidAssignment(name.to_string()),
+                Span::new(0, name.len()),
+            ));
+        }
+        self.check_assignable(name, Span::new(0, name.len()))?;
+        self.bind_variable(name, value.into());
+        Ok(())
+    }
+
+    /// Deletes a user variable, returning its value if it existed
+    pub fn delete_variable(&mut self, name: &str) -> Option<Value> {
+        let value = self.variables.remove(name)?;
+        if !(name == "i" && self.complex_mode()) {
+            self.context.remove_var(name);
+        }
+        Some(value)
+    }
+
+    fn check_assignable(&self, name: &str, span: Span) -> CalcResult<()> {
+        let is_imaginary_unit = name == "i" && self.complex_mode();
+        if RESERVED_NAMES.contains(&name)
+            || is_imaginary_unit
+            || self.mod_bindings.iter().any(|binding| binding == name)
+        {
+            return Err(CalcError::new(ErrorKind::ReadOnly(name.to_string()), span)
+                .with_suggestion("choose a different variable name"));
+        }
+        Ok(())
+    }
+
+    fn bind_variable(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_string(), value.clone());
+        self.context.var(name, value);
+    }
+
+    /// Lists user functions sorted by name
+    pub fn list_functions(&self) -> Vec<UserFunction> {
+        self.functions.values().cloned().collect()
+    }
+
+    /// Gets a user function
+    pub fn get_function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// Defines or replaces a user function, e.g. `define_function("f", &["x", "y"], "x^2 + y")`
+    pub fn define_function(&mut self, name: &str, params: &[&str], body: &str) -> CalcResult<()> {
+        let name_span = Span::new(0, name.len());
+        if !is_identifier(name) || name.contains('.') {
+            return Err(CalcError::new(
+                ErrorKind::InvalidDefinition(name.to_string()),
+                name_span,
+            ));
+        }
+        for (i, param) in params.iter().enumerate() {
+            if !is_identifier(param) || param.contains('.') || params[..i].contains(param) {
+                return Err(
+                    CalcError::new(ErrorKind::InvalidDefinition(name.to_string()), name_span)
+                        .with_suggestion(format!("invalid parameter '{}'", param)),
+                );
+            }
+        }
+        self.check_definable(name, name_span)?;
+        let params = params.iter().map(|param| param.to_string()).collect();
+        self.bind_function(name, params, parse(body)?, body);
+        Ok(())
+    }
+
+    /// Deletes a user function, returning it if it existed
+    pub fn delete_function(&mut self, name: &str) -> Option<UserFunction> {
+        let function = self.functions.remove(name)?;
+        self.context.remove_func(name);
+        self.result_cache.clear();
+        Some(function)
+    }
+
+    /// Converts a user function into a function mod definition
+    ///
+    /// Mods are loaded without the session state, so the body may only use its parameters,
+    /// built-ins and other mods.
+    pub fn function_as_mod(&self, name: &str) -> CalcResult<SimplifiedMod> {
+        let (Some(function), Some(FunctionBody::Expr { body, .. })) = (
+            self.functions.get(name),
+            self.context.get_func(name).map(|func| &func.body),
+        ) else {
+            return Err(CalcError::new(
+                ErrorKind::UnknownFunction(name.to_string()),
+                Span::new(0, name.len()),
+            ));
+        };
+
+        let mut dependency = None;
+        body.visit(&mut |expr| match &expr.kind {
+            ExprKind::Ident(ident) if !function.params.contains(ident) && self.variables.contains_key(ident) => {
+                dependency.get_or_insert(format!("the variable '{}'", ident));
+            }
+            ExprKind::Call { name: callee, .. } if self.functions.contains_key(callee) => {
+                dependency.get_or_insert(format!("the function '{}'", callee));
+            }
+            _ => {}
+        });
+        if let Some(dependency) = dependency {
+            return Err(CalcError::new(
+                ErrorKind::Mod(format!("'{}' uses {}, which mods cannot see", name, dependency)),
+                Span::new(0, name.len()),
+            )
+            .with_suggestion("replace it with a parameter or a constant mod"));
+        }
+
+        Ok(SimplifiedMod {
+            name: Some(function.name.clone()),
+            needs: Some(function.params.clone()),
+            method: Some(function.body.clone()),
+            res: None,
+            mod_type: Some("fun".to_string()),
+        })
+    }
+
+    /// Saves a user function as `mods/<filename>` under `mod_id` and reloads the mods
+    ///
+    /// Returns the file name that was written. Existing files are never overwritten.
+    pub fn promote_function(&mut self, name: &str, mod_id: &str, filename: &str) -> Result<String> {
+        if !is_identifier(mod_id) || !mod_id.contains('.') {
+            return Err(anyhow::anyhow!("Mod ID must be in a.b.c format"));
+        }
+        let simplified_mod = self.function_as_mod(name)?;
+        let path = std::path::Path::new("mods").join(filename);
+        if path.exists() || path.with_extension("cmfun").exists() {
+            return Err(anyhow::anyhow!("mods/{} already exists", filename));
+        }
+        let filename = save_mod_file(filename, mod_id, &simplified_mod)?;
+        self.reload_mods()?;
+        Ok(filename)
+    }
+
+    fn check_definable(&self, name: &str, span: Span) -> CalcResult<()> {
+        let is_builtin = self.context.get_func(name).is_some() && !self.functions.contains_key(name);
+        if is_builtin || self.mod_bindings.iter().any(|binding| binding == name) {
+            return Err(CalcError::new(ErrorKind::BuiltinFunction(name.to_string()), span)
+                .with_suggestion("choose a different function name"));
+        }
+        Ok(())
+    }
+
+    fn bind_function(&mut self, name: &str, params: Vec<String>, body: Expr, body_source: &str) {
+        self.context.define(name, params.clone(), body);
+        self.functions.insert(
+            name.to_string(),
+            UserFunction {
+                name: name.to_string(),
+                params,
+                body: body_source.trim().to_string(),
+            },
+        );
+        // Cached results may call the old definition
+        self.result_cache.clear();
+    }
+
     /// Evaluates a mathematical expression
-    pub fn evaluate(&mut self, expression: &str) -> Result<f64> {
-        // Check if expression is a mod function call (name(args))
-        if let Some(paren_pos) = expression.find('(') {
-            let func_name = expression[..paren_pos].trim();
-            // We only need to check if the mod exists, we don't need to use the mod_def value here
-            if self.mod_manager.get_mod(func_name).is_some() {
-                // This is a mod function call
-                return self.evaluate_mod(func_name, expression);
+    ///
+    /// The input may contain several statements separated by `;` or line breaks, including
+    /// assignments such as `rate = 0.07; 100 * (1 + rate)^2`. The value of the last statement is
+    /// returned; use [`Evaluator::execute`] for input that may end with a function definition
+    /// and [`Evaluator::evaluate_value`] for complex results.
+    pub fn evaluate(&mut self, expression: &str) -> CalcResult<f64> {
+        let value = self.evaluate_value(expression)?;
+        value.as_real().ok_or_else(|| {
+            CalcError::new(ErrorKind::NotReal(value.to_string()), Span::new(0, expression.len()))
+                .with_suggestion("use evaluate_value to get complex results")
+        })
+    }
+
+    /// Evaluates an expression whose result may be complex
+    pub fn evaluate_value(&mut self, expression: &str) -> CalcResult<Value> {
+        match self.execute(expression)? {
+            Outcome::Value(value) => Ok(value),
+            Outcome::Defined(name) => {
+                let function = &self.functions[&name];
+                Err(
+                    CalcError::new(ErrorKind::NoValue(name.clone()), Span::new(0, expression.len()))
+                        .with_suggestion(format!("call it, e.g. {}({})", name, function.params.join(", "))),
+                )
             }
         }
+    }
 
-        // Otherwise, evaluate normally
+    /// Runs an input line that may define functions as well as compute values
+    pub fn execute(&mut self, expression: &str) -> CalcResult<Outcome> {
         if self.safe_mode {
             // 使用缓存机制来优化性能
             let cache_key = expression.to_string();
             if let Some(cached_result) = self.get_cached_result(&cache_key) {
-                return Ok(cached_result);
+                // 更新最近结果 (m 常量)
+                self.set_last_value(cached_result.clone());
+                return Ok(Outcome::Value(cached_result));
             }
 
             // 使用缓存的表达式对象来优化性能
-            let expr = if let Some(cached_expr) = self.expr_cache.get(cache_key.as_str()) {
-                cached_expr.clone()
+            let program = if let Some(cached_program) = self.expr_cache.get(cache_key.as_str()) {
+                cached_program.clone()
             } else {
-                let parsed_expr = expression.parse::<Expr>()?;
-                let arc_expr = Arc::new(parsed_expr);
-                self.expr_cache.insert(cache_key.clone(), arc_expr.clone());
-                arc_expr
+                let parsed_program = Arc::new(parse_program(expression)?);
+                self.expr_cache.insert(cache_key.clone(), parsed_program.clone());
+                parsed_program
+            };
+
+            // Snapshot dependencies before running, since evaluation updates 'm'. Assignments and
+            // definitions have side effects and impure calls such as random() differ every time,
+            // so neither is memoised.
+            let dependencies = match program.as_slice() {
+                [Stmt::Expr(expr)] => expr.dependencies(&self.context).map(|names| {
+                    names
+                        .into_iter()
+                        .map(|name| {
+                            let value = self.context.get_var(&name);
+                            (name, value)
+                        })
+                        .collect()
+                }),
+                _ => None,
             };
 
             // 评估表达式
-            match expr.eval_with_context(&self.context) {
-                Ok(result) => {
-                    // 缓存结果
-                    self.result_cache.insert(cache_key, result);
-                    // 更新最近结果 (m 常量)
-                    self.set_last_result(result);
-                    Ok(result)
-                }
-                Err(e) => bail!("Evaluation error: {}", e),
+            let result = self.run(&program)?;
+            // 缓存结果
+            if let (Outcome::Value(value), Some(dependencies)) = (&result, dependencies) {
+                self.result_cache.insert(
+                    cache_key,
+                    CachedResult {
+                        value: value.clone(),
+                        dependencies,
+                    },
+                );
             }
+            Ok(result)
         } else {
             // In a real implementation, this would allow more complex expressions
             // For now, we'll just use the same safe evaluation
-            match expression.parse::<Expr>() {
-                Ok(expr) => match expr.eval_with_context(&self.context) {
-                    Ok(result) => {
-                        // 更新最近结果 (m 常量)
-                        self.set_last_result(result);
-                        Ok(result)
-                    }
-                    Err(e) => bail!("Evaluation error: {}", e),
-                },
-                Err(e) => bail!("Parse error: {}", e),
-            }
+            self.run(&parse_program(expression)?)
         }
     }
 
-    /// Evaluate a mod function call
-    fn evaluate_mod(&self, mod_name: &str, expression: &str) -> Result<f64> {
-        // Extract function name and arguments
-        let paren_start = expression
-            .find('(')
-            .ok_or_else(|| anyhow::anyhow!("Invalid mod call"))?;
-        let paren_end = expression
-            .rfind(')')
-            .ok_or_else(|| anyhow::anyhow!("Invalid mod call"))?;
-
-        if paren_end <= paren_start {
-            bail!("Invalid mod call: empty parentheses");
-        }
-
-        let args_str = &expression[paren_start + 1..paren_end];
-        let args: Vec<&str> = args_str.split(',').map(|s| s.trim()).collect();
-
-        // Get mod definition
-        let mod_def = self
-            .mod_manager
-            .get_mod(mod_name)
-            .ok_or_else(|| anyhow::anyhow!("Mod '{}' not found", mod_name))?;
-
-        // Check number of arguments
-        if args.len() != mod_def.var.needvars.len() {
-            bail!(
-                "Mod '{}' expects {} arguments, got {}",
-                mod_name,
-                mod_def.var.needvars.len(),
-                args.len()
-            );
-        }
-
-        // Build a new context with the provided arguments
-        let mut ctx = self.context.clone();
-
-        // Evaluate each argument and bind to variable
-        for (i, var_name) in mod_def.var.needvars.iter().enumerate() {
-            let arg_val: f64 = match args[i].parse::<f64>() {
-                Ok(v) => v,
-                Err(_) => {
-                    // Try to evaluate as an expression
-                    match args[i].parse::<Expr>() {
-                        Ok(expr) => match expr.eval_with_context(&self.context) {
-                            Ok(v) => v,
-                            Err(e) => bail!("Failed to evaluate argument '{}': {}", args[i], e),
-                        },
-                        Err(e) => bail!("Failed to parse argument '{}': {}", args[i], e),
+    /// Runs statements in order, returning the outcome of the last one
+    fn run(&mut self, program: &[Stmt]) -> CalcResult<Outcome> {
+        let mut outcome = Outcome::Value(Value::Real(0.0));
+        for stmt in program {
+            outcome = match stmt {
+                Stmt::Expr(expr) => Outcome::Value(expr.eval_value(&self.context)?),
+                Stmt::Assign { name, name_span, value } => match regression_model(value) {
+                    // `f = fit_poly(xs, ys, 2)` defines the fitted curve `f(x)`
+                    Some((fit, model)) => {
+                        self.check_assignable(name, *name_span)?;
+                        self.check_definable(name, *name_span)?;
+                        let coefficients = match value.eval_value(&self.context)? {
+                            Value::List(coefficients) => coefficients,
+                            other => {
+                                return Err(CalcError::new(
+                                    ErrorKind::DomainError {
+                                        name: fit.to_string(),
+                                        message: format!("expected a list of coefficients, got {}", other),
+                                    },
+                                    value.span,
+                                ))
+                            }
+                        };
+                        let body_source = model(&coefficients);
+                        self.bind_function(name, vec!["x".to_string()], parse(&body_source)?, &body_source);
+                        Outcome::Defined(name.clone())
                     }
+                    None => {
+                        self.check_assignable(name, *name_span)?;
+                        let value = value.eval_value(&self.context)?;
+                        self.bind_variable(name, value.clone());
+                        Outcome::Value(value)
+                    }
+                },
+                Stmt::Function {
+                    name,
+                    name_span,
+                    params,
+                    body,
+                    body_source,
+                } => {
+                    self.check_definable(name, *name_span)?;
+                    self.bind_function(name, params.clone(), body.clone(), body_source);
+                    Outcome::Defined(name.clone())
                 }
             };
-
-            ctx.var(var_name, arg_val);
         }
-
-        // Get the calculation expression
-        let calc_expr = mod_def
-            .calc
-            .howto
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Mod '{}' has no calculation defined", mod_name))?;
-
-        // Evaluate the calculation expression
-        match calc_expr.parse::<Expr>() {
-            Ok(expr) => match expr.eval_with_context(&ctx) {
-                Ok(result) => Ok(result),
-                Err(e) => bail!("Mod calculation error: {}", e),
-            },
-            Err(e) => bail!("Mod expression parse error: {}", e),
+        // 更新最近结果 (m 常量)
+        if let Outcome::Value(value) = &outcome {
+            self.set_last_value(value.clone());
         }
+        Ok(outcome)
     }
 
     // 添加缓存机制
-    fn get_cached_result(&self, cache_key: &str) -> Option<f64> {
-        // 实现缓存逻辑
-        self.result_cache.get(cache_key).copied()
+    fn get_cached_result(&mut self, cache_key: &str) -> Option<Value> {
+        // 实现缓存逻辑: only reuse a result while every variable it read still has the same value
+        let context = &self.context;
+        self.result_cache
+            .get_if(cache_key, |cached| {
+                cached
+                    .dependencies
+                    .iter()
+                    .all(|(name, value)| context.get_var(name) == *value)
+            })
+            .map(|cached| cached.value.clone())
     }
+
+    /// Statistics of the parsed-program cache
+    pub fn ast_cache_stats(&self) -> CacheStats {
+        self.expr_cache.stats()
+    }
+
+    /// Statistics of the result cache
+    pub fn result_cache_stats(&self) -> CacheStats {
+        self.result_cache.stats()
+    }
+
+    /// Sets how many entries each cache keeps; 0 disables caching
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.expr_cache.set_capacity(capacity);
+        self.result_cache.set_capacity(capacity);
+    }
+
+    /// Empties both caches and resets their statistics
+    pub fn clear_caches(&mut self) {
+        self.expr_cache.clear();
+        self.expr_cache.reset_stats();
+        self.result_cache.clear();
+        self.result_cache.reset_stats();
+    }
+}
+
+/// The name and the curve of a regression call such as `fit_poly(xs, ys, 2)`, if `expr` is one
+fn regression_model(expr: &Expr) -> Option<(&'static str, regression::Model)> {
+    let ExprKind::Call { name, .. } = &expr.kind else {
+        return None;
+    };
+    regression::FUNCTIONS
+        .iter()
+        .find(|function| function.name == name)
+        .and_then(|function| Some((function.name, function.model?)))
+}
+
+/// Magnitudes of two integral floats, for the `u64` integer functions
+fn integer_pair(a: f64, b: f64) -> Option<(u64, u64)> {
+    let integral = |x: f64| x.fract() == 0.0 && x.abs() < u64::MAX as f64;
+    (integral(a) && integral(b)).then(|| (a.abs() as u64, b.abs() as u64))
+}
+
+/// A bound of `randint`, which must be an integer within the range of `i64`
+fn integer_bound(x: f64) -> Option<i64> {
+    (x.fract() == 0.0 && x.abs() < i64::MAX as f64).then_some(x as i64)
 }
 
 impl Default for Evaluator {
@@ -3,6 +3,7 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
@@ -36,15 +37,6 @@ impl HistoryManager {
         self.history.clear();
     }
 
-    /// Gets history as a formatted string
-    pub fn to_string(&self) -> String {
-        let mut result = String::new();
-        for (expr, res) in &self.history {
-            result.push_str(&format!("{} = {}\n", expr, res));
-        }
-        result
-    }
-
     /// Saves history to a file
     pub fn save_to_file(&self, filename: &str) -> Result<()> {
         let file = File::create(filename)?;
@@ -58,6 +50,16 @@ impl HistoryManager {
     }
 }
 
+/// Formats history as one `expression = result` line per entry
+impl fmt::Display for HistoryManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (expr, res) in &self.history {
+            writeln!(f, "{} = {}", expr, res)?;
+        }
+        Ok(())
+    }
+}
+
 impl Default for HistoryManager {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,1047 @@
+//! Tree-walking evaluation of expression ASTs
+
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+use bigdecimal::{BigDecimal, Zero};
+use num_bigint::{BigInt, Sign};
+use num_complex::Complex64;
+use num_rational::BigRational;
+
+use super::ast::{BinaryOp, Expr, ExprKind, PostfixOp, Span, UnaryOp, UnitExpr};
+use super::context::{AngleUse, Arity, Context, Function, FunctionBody, NativeFn, Scope};
+use super::decimal;
+use super::error::{closest_match, CalcError, CalcResult, ErrorKind};
+use super::math_functions::{complex_pow, factorial, AngleMode};
+use super::matrix;
+use super::programmer::{self, MAX_SHIFT};
+use super::rational;
+use super::units::{DimensionMismatch, Quantity, Unit};
+use super::value::Value;
+
+/// Maximum nesting of expression-defined function calls, guards against runaway recursion
+pub const MAX_CALL_DEPTH: usize = 64;
+
+impl Expr {
+    /// Evaluates the expression with the standard context
+    pub fn eval(&self) -> CalcResult<f64> {
+        self.eval_with_context(&Context::new())
+    }
+
+    /// Evaluates the expression with the given context, requiring a real result
+    pub fn eval_with_context(&self, ctx: &Context) -> CalcResult<f64> {
+        let value = eval(self, ctx)?;
+        value
+            .as_real()
+            .ok_or_else(|| CalcError::new(ErrorKind::NotReal(value.to_string()), self.span))
+    }
+
+    /// Evaluates the expression with the given context, allowing complex results
+    pub fn eval_value(&self, ctx: &Context) -> CalcResult<Value> {
+        eval(self, ctx)
+    }
+
+    /// Variables the expression reads, following calls into expression-defined functions
+    ///
+    /// Returns `None` when an impure function such as `random` may be called, since the result
+    /// then cannot be reused even if every variable is unchanged.
+    pub fn dependencies(&self, ctx: &Context) -> Option<BTreeSet<String>> {
+        let mut names = BTreeSet::new();
+        let mut visited = HashSet::new();
+        collect_dependencies(self, &[], ctx, &mut visited, &mut names).then_some(names)
+    }
+}
+
+/// Adds the free variables of `expr` to `names`, returning false if it calls an impure function
+fn collect_dependencies(
+    expr: &Expr,
+    params: &[String],
+    ctx: &Context,
+    visited: &mut HashSet<String>,
+    names: &mut BTreeSet<String>,
+) -> bool {
+    let mut pure = true;
+    expr.visit(&mut |node| match &node.kind {
+        ExprKind::Ident(name) if !params.contains(name) => {
+            names.insert(name.clone());
+        }
+        ExprKind::Call { name, .. } => match ctx.get_func(name) {
+            Some(func) if !func.pure => pure = false,
+            Some(func) => {
+                // Each function body is scanned once, which also stops at recursive definitions
+                if let FunctionBody::Expr { params, body } = &func.body {
+                    if visited.insert(name.clone()) {
+                        pure &= collect_dependencies(body, params, ctx, visited, names);
+                    }
+                }
+            }
+            None => {}
+        },
+        _ => {}
+    });
+    pure
+}
+
+/// Evaluates an expression node recursively
+pub fn eval(expr: &Expr, ctx: &Context) -> CalcResult<Value> {
+    Frame {
+        ctx,
+        locals: &[],
+        depth: 0,
+    }
+    .eval(expr)
+}
+
+/// Evaluation state for one function body: globals plus bound parameters
+struct Frame<'a> {
+    ctx: &'a Context,
+    locals: &'a [(String, Value)],
+    depth: usize,
+}
+
+impl Frame<'_> {
+    fn lookup(&self, name: &str) -> Option<Value> {
+        self.locals
+            .iter()
+            .find(|(local, _)| local == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| self.ctx.get_var(name))
+    }
+
+    /// Evaluates a node, wrapping integer results to the word size in programmer mode
+    fn eval(&self, expr: &Expr) -> CalcResult<Value> {
+        let value = self.eval_node(expr)?;
+        Ok(self.wrap(value))
+    }
+
+    fn wrap(&self, value: Value) -> Value {
+        match (value, self.ctx.word_size()) {
+            (Value::Integer(n), Some(word_size)) => Value::Integer(word_size.wrap(&n)),
+            (Value::List(items), Some(_)) => Value::List(items.into_iter().map(|item| self.wrap(item)).collect()),
+            (Value::Matrix(rows), Some(_)) => Value::Matrix(
+                rows.into_iter()
+                    .map(|row| row.into_iter().map(|item| self.wrap(item)).collect())
+                    .collect(),
+            ),
+            (value, _) => value,
+        }
+    }
+
+    fn eval_node(&self, expr: &Expr) -> CalcResult<Value> {
+        match &expr.kind {
+            ExprKind::Number(value) => Ok(self.literal(*value)),
+            ExprKind::Angle(value, unit) => Ok(self.convert_angle(self.literal(*value), *unit, self.ctx.angle_mode())),
+            ExprKind::Integer(n) => Ok(Value::Integer(n.clone())),
+            ExprKind::Text(text) => Ok(Value::Text(text.clone())),
+            ExprKind::Imaginary(value) if self.ctx.complex_mode() => {
+                Ok(Value::from_complex(Complex64::new(0.0, *value)))
+            }
+            ExprKind::Imaginary(_) => Err(CalcError::new(ErrorKind::ComplexDisabled, expr.span)
+                .with_suggestion("enable complex mode in the settings to use imaginary numbers")),
+            ExprKind::Ident(name) => match self.lookup(name) {
+                Some(value) => Ok(value),
+                None if self.ctx.get_func(name).is_some() => {
+                    Err(CalcError::new(ErrorKind::NotAVariable(name.clone()), expr.span)
+                        .with_suggestion(format!("call it as {}(...)", name)))
+                }
+                None => {
+                    let error = CalcError::new(ErrorKind::UnknownVariable(name.clone()), expr.span);
+                    let candidates = self.locals.iter().map(|(local, _)| local.as_str());
+                    Err(match closest_match(name, candidates.chain(self.ctx.var_names())) {
+                        Some(candidate) => error.with_suggestion(format!("did you mean '{}'?", candidate)),
+                        None => error,
+                    })
+                }
+            },
+            ExprKind::Unary { op, expr } => {
+                let value = self.eval(expr)?;
+                self.unary(*op, value, expr)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let a = self.eval(lhs)?;
+                let b = self.eval(rhs)?;
+                self.binary(*op, a, b, expr, rhs)
+            }
+            ExprKind::Postfix { op, expr: operand } => {
+                let value = self.eval(operand)?;
+                self.postfix(*op, value, expr)
+            }
+            ExprKind::Call { name, args } => self.call(name, args, expr),
+            ExprKind::Equation { .. } => Err(CalcError::new(ErrorKind::MisplacedEquation, expr.span)
+                .with_suggestion("solve it for a variable, e.g. solve(x^2 = 2, x)")),
+            ExprKind::Range { .. } => Err(CalcError::new(ErrorKind::MisplacedRange, expr.span)
+                .with_suggestion("e.g. ode(dy/dt = -y, y0 = 1, t = 0..10)")),
+            ExprKind::List(items) => items
+                .iter()
+                .map(|item| self.eval(item))
+                .collect::<CalcResult<Vec<Value>>>()
+                .map(matrix::from_items),
+            ExprKind::Quantity { expr: operand, unit } => {
+                let value = self.eval(operand)?;
+                let unit = self.resolve_unit(unit)?;
+                let value = self.quantity_operand("unit", &value, operand)?;
+                // A plain number takes the unit as is, keeping the zero point of e.g. `20 degC`
+                let quantity = if value.unit.factors.is_empty() {
+                    Quantity::new(value.value, unit)
+                } else {
+                    value.combine(&Quantity::new(1.0, unit), 1)
+                };
+                Ok(quantity_value(quantity))
+            }
+            ExprKind::Convert { expr: operand, unit } => {
+                let value = self.eval(operand)?;
+                let target = self.resolve_unit(unit)?;
+                let quantity = self.quantity_operand("to", &value, operand)?;
+                quantity
+                    .convert(&target)
+                    .map(|converted| Value::Quantity(Box::new(converted)))
+                    .map_err(|mismatch| incompatible_units(mismatch, expr))
+            }
+        }
+    }
+
+    /// Prefix operators, applied to each element of a list or matrix
+    fn unary(&self, op: UnaryOp, value: Value, expr: &Expr) -> CalcResult<Value> {
+        Ok(match (op, value) {
+            (_, value @ Value::Matrix(_)) => matrix::from_list(self.unary(op, matrix::into_list(value), expr)?),
+            (_, Value::List(items)) => Value::List(
+                items
+                    .into_iter()
+                    .map(|item| self.unary(op, item, expr))
+                    .collect::<CalcResult<_>>()?,
+            ),
+            (_, Value::Text(_)) => return Err(text_unsupported(op.symbol(), expr)),
+            (UnaryOp::Neg, Value::Real(x)) => Value::Real(-x),
+            (UnaryOp::Neg, Value::Complex(z)) => Value::Complex(-z),
+            (UnaryOp::Neg, Value::Integer(n)) => Value::Integer(-n),
+            (UnaryOp::Neg, Value::Rational(r)) => Value::Rational(-r),
+            (UnaryOp::Neg, Value::Decimal(d)) => Value::Decimal(-d),
+            (UnaryOp::Neg, Value::Quantity(q)) => Value::Quantity(Box::new(Quantity::new(-q.value, q.unit))),
+            (UnaryOp::Plus, value) => value,
+            (UnaryOp::BitNot, value) => Value::Integer(programmer::not(&integer_operand(op.symbol(), &value, expr)?)),
+        })
+    }
+
+    /// Infix operators; lists combine element by element, or with each element of the other list
+    fn binary(&self, op: BinaryOp, a: Value, b: Value, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let (a, b) = match (a, b) {
+            (a @ Value::Matrix(_), b) | (a, b @ Value::Matrix(_)) => return self.binary_matrix(op, a, b, expr, rhs),
+            (Value::List(xs), Value::List(ys)) => {
+                check_lengths(op.symbol(), xs.len(), ys.len(), expr)?;
+                return xs
+                    .into_iter()
+                    .zip(ys)
+                    .map(|(x, y)| self.binary(op, x, y, expr, rhs))
+                    .collect::<CalcResult<Vec<Value>>>()
+                    .map(Value::List);
+            }
+            (Value::List(xs), b) => {
+                return xs
+                    .into_iter()
+                    .map(|x| self.binary(op, x, b.clone(), expr, rhs))
+                    .collect::<CalcResult<Vec<Value>>>()
+                    .map(Value::List);
+            }
+            (a, Value::List(ys)) => {
+                return ys
+                    .into_iter()
+                    .map(|y| self.binary(op, a.clone(), y, expr, rhs))
+                    .collect::<CalcResult<Vec<Value>>>()
+                    .map(Value::List);
+            }
+            (Value::Text(_), _) | (_, Value::Text(_)) => return Err(text_unsupported(op.symbol(), expr)),
+            operands => operands,
+        };
+        if op.is_bitwise() {
+            return self.binary_bitwise(op, &a, &b, expr, rhs);
+        }
+        if matches!(a, Value::Quantity(_)) || matches!(b, Value::Quantity(_)) {
+            return self.binary_quantity(op, &a, &b, expr, rhs);
+        }
+        match (&a, &b) {
+            (Value::Complex(_), _) | (_, Value::Complex(_)) => {
+                self.binary_complex(op, a.to_complex(), b.to_complex(), expr, rhs)
+            }
+            _ if self.ctx.decimal_precision().is_none() => match (a.to_rational(), b.to_rational()) {
+                (Some(x), Some(y)) => self.binary_rational(op, &x, &y, expr, rhs),
+                _ => {
+                    let (a, b) = (a.as_real().unwrap_or(f64::NAN), b.as_real().unwrap_or(f64::NAN));
+                    self.binary_real(op, a, b, expr, rhs)
+                }
+            },
+            _ => match (self.to_decimal(&a), self.to_decimal(&b)) {
+                (Some(x), Some(y)) => self.binary_decimal(op, &x, &y, expr, rhs),
+                _ => {
+                    let (a, b) = (a.as_real().unwrap_or(f64::NAN), b.as_real().unwrap_or(f64::NAN));
+                    self.binary_real(op, a, b, expr, rhs)
+                }
+            },
+        }
+    }
+
+    /// Matrix sums, differences, products and powers; other operators with a number apply to each
+    /// entry
+    fn binary_matrix(&self, op: BinaryOp, a: Value, b: Value, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let matrix_error = |message| {
+            CalcError::new(
+                ErrorKind::DomainError {
+                    name: op.symbol().to_string(),
+                    message,
+                },
+                expr.span,
+            )
+        };
+        match (op, &a, &b) {
+            (BinaryOp::Mul, Value::Matrix(_) | Value::List(_), Value::Matrix(_) | Value::List(_)) => {
+                matrix::product(&a, &b).map_err(matrix_error)
+            }
+            (BinaryOp::Pow, Value::Matrix(rows), exponent) if !matches!(exponent, Value::Matrix(_)) => {
+                matrix::power(rows, exponent).map_err(matrix_error)
+            }
+            (BinaryOp::Add | BinaryOp::Sub, Value::Matrix(x), Value::Matrix(y)) => {
+                matrix::check_sizes(x, y).map_err(matrix_error)?;
+                self.binary(op, matrix::into_list(a), matrix::into_list(b), expr, rhs)
+                    .map(matrix::from_list)
+            }
+            (BinaryOp::Div, Value::Matrix(_), Value::Matrix(_)) => {
+                Err(matrix_error("matrices cannot be divided".to_string())
+                    .with_suggestion("multiply by the inverse instead, e.g. A * inverse(B)"))
+            }
+            (_, Value::Matrix(_), Value::Matrix(_) | Value::List(_)) | (_, Value::List(_), Value::Matrix(_)) => {
+                Err(matrix_error(format!("'{}' is not defined for matrices", op.symbol())))
+            }
+            _ => self
+                .binary(op, matrix::into_list(a), matrix::into_list(b), expr, rhs)
+                .map(matrix::from_list),
+        }
+    }
+
+    /// Postfix operators, applied to each element of a list or matrix
+    fn postfix(&self, op: PostfixOp, value: Value, expr: &Expr) -> CalcResult<Value> {
+        if let Value::Matrix(_) = value {
+            return self.postfix(op, matrix::into_list(value), expr).map(matrix::from_list);
+        }
+        if let Value::List(items) = value {
+            return items
+                .into_iter()
+                .map(|item| self.postfix(op, item, expr))
+                .collect::<CalcResult<Vec<Value>>>()
+                .map(Value::List);
+        }
+        match value {
+            Value::Quantity(_) => return Err(units_unsupported(op.symbol(), expr)),
+            Value::Text(_) => return Err(text_unsupported(op.symbol(), expr)),
+            _ => {}
+        }
+        if let (Value::Decimal(d), PostfixOp::Factorial) = (&value, op) {
+            if let Some(result) = decimal::factorial(d) {
+                return Ok(Value::Decimal(result));
+            }
+        }
+        if let (Some(r), PostfixOp::Factorial) = (value.to_rational(), op) {
+            if let Some(result) = rational::factorial(&r) {
+                return Ok(Value::from_rational(result));
+            }
+        }
+        let value = match value.as_real() {
+            Some(value) => value,
+            None => return Err(complex_unsupported(op.symbol(), expr)),
+        };
+        let result = match op {
+            PostfixOp::Factorial => factorial(value),
+        };
+        check_domain(op.symbol(), &[value], result, expr).map(|result| self.in_mode(Value::Real(result)))
+    }
+
+    /// Arithmetic where at least one operand has a unit; sums need matching dimensions
+    fn binary_quantity(&self, op: BinaryOp, a: &Value, b: &Value, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let x = self.quantity_operand(op.symbol(), a, expr)?;
+        let y = self.quantity_operand(op.symbol(), b, expr)?;
+        let result = match op {
+            BinaryOp::Add => x.add(&y),
+            BinaryOp::Sub => x.sub(&y),
+            BinaryOp::Mul => Ok(x.combine(&y, 1)),
+            BinaryOp::Div | BinaryOp::Rem if y.value == 0.0 => {
+                return Err(CalcError::new(ErrorKind::DivisionByZero, rhs.span));
+            }
+            BinaryOp::Div => Ok(x.combine(&y, -1)),
+            BinaryOp::Rem => x.rem(&y),
+            BinaryOp::Pow => match b
+                .as_real()
+                .filter(|n| n.fract() == 0.0 && n.abs() <= f64::from(i32::MAX))
+            {
+                Some(n) => Ok(x.powi(n as i32)),
+                None => {
+                    return Err(CalcError::new(
+                        ErrorKind::DomainError {
+                            name: op.symbol().to_string(),
+                            message: "exponents of quantities must be whole numbers without units".to_string(),
+                        },
+                        rhs.span,
+                    ));
+                }
+            },
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise operators are evaluated by binary_bitwise")
+            }
+        };
+        result
+            .map(quantity_value)
+            .map_err(|mismatch| incompatible_units(mismatch, expr))
+    }
+
+    /// The value as a quantity; plain numbers have no unit
+    fn quantity_operand(&self, name: &str, value: &Value, expr: &Expr) -> CalcResult<Quantity> {
+        match value {
+            Value::Quantity(q) => Ok((**q).clone()),
+            Value::List(_) | Value::Matrix(_) => Err(list_unsupported(name, expr)),
+            Value::Text(_) => Err(text_unsupported(name, expr)),
+            value => match value.as_real() {
+                Some(x) => Ok(Quantity::new(x, Unit::default())),
+                None => Err(complex_unsupported(name, expr)),
+            },
+        }
+    }
+
+    fn resolve_unit(&self, unit: &UnitExpr) -> CalcResult<Unit> {
+        self.ctx.units().resolve(unit).map_err(|name| {
+            let error = CalcError::new(ErrorKind::UnknownUnit(name.clone()), unit.span);
+            if self.lookup(&name).is_some() {
+                return error.with_suggestion(format!("'{}' is a variable; use '*' to multiply by it", name));
+            }
+            if matches!(name.as_str(), "deg" | "rad" | "grad") {
+                return error.with_suggestion(format!("write angles without a space, e.g. 30{}", name));
+            }
+            match closest_match(&name, self.ctx.units().names()) {
+                Some(candidate) => error.with_suggestion(format!("did you mean '{}'?", candidate)),
+                None => error,
+            }
+        })
+    }
+
+    fn binary_real(&self, op: BinaryOp, a: f64, b: f64, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let result = match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div | BinaryOp::Rem if b == 0.0 => {
+                return Err(CalcError::new(ErrorKind::DivisionByZero, rhs.span));
+            }
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => a % b,
+            // Negative bases with fractional exponents have complex results, e.g. (-8)^(1/3)
+            BinaryOp::Pow if self.ctx.complex_mode() && a < 0.0 && b.fract() != 0.0 => {
+                return self.binary_complex(op, a.into(), b.into(), expr, rhs);
+            }
+            BinaryOp::Pow => a.powf(b),
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise operators are evaluated by binary_bitwise")
+            }
+        };
+        // Integer results too large to be exact as floats are promoted to exact integers
+        if result.abs() > rational::MAX_EXACT_FLOAT && self.ctx.decimal_precision().is_none() {
+            if let (Some(x), Some(y)) = (rational::from_f64(a), rational::from_f64(b)) {
+                let exact = match op {
+                    BinaryOp::Add => Some(x + y),
+                    BinaryOp::Sub => Some(x - y),
+                    BinaryOp::Mul => Some(x * y),
+                    BinaryOp::Pow => rational::pow(&x, &y),
+                    BinaryOp::Div | BinaryOp::Rem => None,
+                    BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                        unreachable!("bitwise operators are evaluated by binary_bitwise")
+                    }
+                };
+                if let Some(exact) = exact {
+                    return Ok(Value::from_rational(exact));
+                }
+            }
+        }
+        check_domain(op.symbol(), &[a, b], result, expr).map(Value::Real)
+    }
+
+    /// Exact except for powers that are not integers or too large, which use floats
+    fn binary_rational(
+        &self,
+        op: BinaryOp,
+        a: &BigRational,
+        b: &BigRational,
+        expr: &Expr,
+        rhs: &Expr,
+    ) -> CalcResult<Value> {
+        let result = match op {
+            BinaryOp::Add => Some(a + b),
+            BinaryOp::Sub => Some(a - b),
+            BinaryOp::Mul => Some(a * b),
+            BinaryOp::Div | BinaryOp::Rem if b.is_zero() => {
+                return Err(CalcError::new(ErrorKind::DivisionByZero, rhs.span));
+            }
+            BinaryOp::Div => Some(a / b),
+            BinaryOp::Rem => Some(rational::rem(a, b)),
+            BinaryOp::Pow => rational::pow(a, b),
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise operators are evaluated by binary_bitwise")
+            }
+        };
+        match result {
+            Some(result) => Ok(Value::from_rational(result)),
+            None => self.binary_real(op, rational::to_f64(a), rational::to_f64(b), expr, rhs),
+        }
+    }
+
+    /// Sums, differences and products are exact; other results are rounded to the precision
+    fn binary_decimal(
+        &self,
+        op: BinaryOp,
+        a: &BigDecimal,
+        b: &BigDecimal,
+        expr: &Expr,
+        rhs: &Expr,
+    ) -> CalcResult<Value> {
+        let precision = self.precision();
+        let result = match op {
+            BinaryOp::Add => Some(a + b),
+            BinaryOp::Sub => Some(a - b),
+            BinaryOp::Mul => Some(a * b),
+            BinaryOp::Div | BinaryOp::Rem if b.is_zero() => {
+                return Err(CalcError::new(ErrorKind::DivisionByZero, rhs.span));
+            }
+            BinaryOp::Div => decimal::div(a, b, precision),
+            BinaryOp::Rem => Some(a % b),
+            BinaryOp::Pow => decimal::pow(a, b, precision),
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise operators are evaluated by binary_bitwise")
+            }
+        };
+        match result {
+            Some(result) => Ok(self.settle(result)),
+            // Undefined or out of range: let the float version decide, e.g. (-8)^(1/3) in complex mode
+            None => self
+                .binary_real(op, decimal::to_f64(a), decimal::to_f64(b), expr, rhs)
+                .map(|value| self.in_mode(value)),
+        }
+    }
+
+    /// Bitwise operators and shifts, which need whole-number operands
+    fn binary_bitwise(&self, op: BinaryOp, a: &Value, b: &Value, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let x = integer_operand(op.symbol(), a, expr)?;
+        let y = integer_operand(op.symbol(), b, expr)?;
+        let word_size = self.ctx.word_size();
+        let result = match op {
+            BinaryOp::BitAnd => Some(&x & &y),
+            BinaryOp::BitOr => Some(&x | &y),
+            BinaryOp::BitXor => Some(&x ^ &y),
+            BinaryOp::Shl => programmer::shl(&x, &y, word_size),
+            BinaryOp::Shr => programmer::shr(&x, &y, word_size),
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem | BinaryOp::Pow => {
+                unreachable!("arithmetic operators are evaluated by binary_real")
+            }
+        };
+        result.map(Value::Integer).ok_or_else(|| {
+            let message = if y.sign() == Sign::Minus {
+                format!("shift amount {} is negative", y)
+            } else {
+                format!("shift amount {} is larger than {}", y, MAX_SHIFT)
+            };
+            CalcError::new(
+                ErrorKind::DomainError {
+                    name: op.symbol().to_string(),
+                    message,
+                },
+                rhs.span,
+            )
+        })
+    }
+
+    /// Rounds a decimal result that is not an integer to the precision; integers stay exact
+    fn settle(&self, result: BigDecimal) -> Value {
+        if result.is_integer() {
+            Value::Decimal(result.normalized())
+        } else {
+            Value::Decimal(decimal::round(&result, self.precision()))
+        }
+    }
+
+    fn precision(&self) -> u64 {
+        self.ctx.decimal_precision().unwrap_or(decimal::DEFAULT_PRECISION)
+    }
+
+    /// Integer literals are exact fractions, or decimals in decimal mode
+    fn literal(&self, value: f64) -> Value {
+        match rational::from_f64(value) {
+            Some(r) if self.ctx.decimal_precision().is_none() => Value::from_rational(r),
+            _ => self.in_mode(Value::Real(value)),
+        }
+    }
+
+    /// Converts finite real values and fractions, also in lists and matrices, to decimals in decimal mode
+    fn in_mode(&self, value: Value) -> Value {
+        match value {
+            Value::Real(x) if self.ctx.decimal_precision().is_some() => {
+                decimal::from_f64(x).map_or(value, Value::Decimal)
+            }
+            Value::Rational(r) if self.ctx.decimal_precision().is_some() => {
+                self.settle(rational::to_decimal(&r, self.precision()))
+            }
+            Value::List(items) if self.ctx.decimal_precision().is_some() => {
+                Value::List(items.into_iter().map(|item| self.in_mode(item)).collect())
+            }
+            Value::Matrix(rows) if self.ctx.decimal_precision().is_some() => Value::Matrix(
+                rows.into_iter()
+                    .map(|row| row.into_iter().map(|item| self.in_mode(item)).collect())
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    /// The value as a decimal if decimal mode is on and it is a finite real number
+    fn to_decimal(&self, value: &Value) -> Option<BigDecimal> {
+        self.ctx.decimal_precision()?;
+        match value {
+            Value::Real(x) => decimal::from_f64(*x),
+            Value::Complex(_) | Value::Quantity(_) | Value::List(_) | Value::Matrix(_) | Value::Text(_) => None,
+            Value::Integer(n) => Some(BigDecimal::from(n.clone())),
+            Value::Rational(r) => Some(rational::to_decimal(r, self.precision())),
+            Value::Decimal(d) => Some(d.clone()),
+        }
+    }
+
+    fn binary_complex(&self, op: BinaryOp, a: Complex64, b: Complex64, expr: &Expr, rhs: &Expr) -> CalcResult<Value> {
+        let result = match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div if b == Complex64::from(0.0) => {
+                return Err(CalcError::new(ErrorKind::DivisionByZero, rhs.span));
+            }
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => return Err(complex_unsupported(op.symbol(), expr)),
+            BinaryOp::Pow => complex_pow(a, b),
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise operators are evaluated by binary_bitwise")
+            }
+        };
+        check_complex_domain(op.symbol(), &[a, b], result, expr)
+    }
+
+    /// Calls a native function on numbers, spreading lists into the arguments of variadic functions
+    /// and calling other functions once for each element
+    fn call_elementwise(
+        &self,
+        name: &str,
+        func: &Function,
+        native: &NativeFn,
+        values: Vec<Value>,
+        expr: &Expr,
+    ) -> CalcResult<Value> {
+        // Functions apply to each entry of a matrix, as to a list of its rows
+        if values.iter().any(|value| matches!(value, Value::Matrix(_))) {
+            let values = values.into_iter().map(matrix::into_list).collect();
+            return self
+                .call_elementwise(name, func, native, values, expr)
+                .map(matrix::from_list);
+        }
+        if values.iter().any(|value| matches!(value, Value::List(_))) {
+            if let Arity::AtLeast(_) = func.arity {
+                let spread: Vec<Value> = values
+                    .into_iter()
+                    .flat_map(|value| match value {
+                        Value::List(items) => items,
+                        value => vec![value],
+                    })
+                    .collect();
+                if !func.arity.accepts(spread.len()) {
+                    return Err(CalcError::new(
+                        ErrorKind::DomainError {
+                            name: name.to_string(),
+                            message: "the list is empty".to_string(),
+                        },
+                        expr.span,
+                    ));
+                }
+                return self.call_elementwise(name, func, native, spread, expr);
+            }
+            let mut len = None;
+            for value in &values {
+                if let Value::List(items) = value {
+                    check_lengths(name, *len.get_or_insert(items.len()), items.len(), expr)?;
+                }
+            }
+            return (0..len.unwrap_or(0))
+                .map(|i| {
+                    let args = values
+                        .iter()
+                        .map(|value| match value {
+                            Value::List(items) => items[i].clone(),
+                            value => value.clone(),
+                        })
+                        .collect();
+                    self.call_elementwise(name, func, native, args, expr)
+                })
+                .collect::<CalcResult<Vec<Value>>>()
+                .map(Value::List);
+        }
+        if values.iter().any(|value| matches!(value, Value::Quantity(_))) {
+            return Err(units_unsupported(name, expr));
+        }
+        if values.iter().any(|value| matches!(value, Value::Text(_))) {
+            return Err(text_unsupported(name, expr));
+        }
+        let mode = self.ctx.angle_mode();
+        match func.angle {
+            Some(AngleUse::Arguments) => {
+                let quarter_turns = values
+                    .iter()
+                    .all(|value| value.as_real().is_some_and(|x| mode.is_quarter_turn(x)));
+                let values: Vec<Value> = values
+                    .into_iter()
+                    .map(|value| self.convert_angle(self.reduce_angle(value), mode, AngleMode::Radians))
+                    .collect();
+                let result = self.call_native(name, func, native, &values, expr)?;
+                // Sine and cosine of a whole number of quarter turns are exact, not 6e-17
+                Ok(if quarter_turns { snap_to_integer(result) } else { result })
+            }
+            Some(AngleUse::Result) if mode != AngleMode::Radians => self
+                .call_native(name, func, native, &values, expr)
+                .map(|result| self.convert_angle(result, AngleMode::Radians, mode)),
+            _ => self.call_native(name, func, native, &values, expr),
+        }
+    }
+
+    /// Calls a native function, preferring its decimal, exact and complex versions where they apply
+    fn call_native(
+        &self,
+        name: &str,
+        func: &Function,
+        native: &NativeFn,
+        values: &[Value],
+        expr: &Expr,
+    ) -> CalcResult<Value> {
+        if let (Some(decimal_fn), Some(precision)) = (&func.decimal, self.ctx.decimal_precision()) {
+            let decimals: Option<Vec<BigDecimal>> = values.iter().map(|value| self.to_decimal(value)).collect();
+            if let Some(result) = decimals.and_then(|args| decimal_fn(&args, precision)) {
+                return Ok(self.settle(result));
+            }
+        }
+        if let Some(rational_fn) = &func.rational {
+            let fractions: Option<Vec<BigRational>> = values.iter().map(Value::to_rational).collect();
+            if let Some(result) = fractions.and_then(|args| rational_fn(&args)) {
+                return Ok(Value::from_rational(result));
+            }
+        }
+        let reals: Option<Vec<f64>> = values.iter().map(Value::as_real).collect();
+        let complex_args = || values.iter().map(Value::to_complex).collect::<Vec<_>>();
+        match (reals, &func.complex) {
+            (Some(reals), complex) => {
+                let result = match &func.checked {
+                    Some(checked) => checked(&reals).map_err(|message| {
+                        CalcError::new(
+                            ErrorKind::DomainError {
+                                name: name.to_string(),
+                                message,
+                            },
+                            expr.span,
+                        )
+                    })?,
+                    None => native(&reals),
+                };
+                // Retry in the complex plane when the real version is undefined, e.g. sqrt(-1)
+                match complex {
+                    Some(complex)
+                        if self.ctx.complex_mode() && result.is_nan() && !reals.iter().any(|arg| arg.is_nan()) =>
+                    {
+                        let args = complex_args();
+                        check_complex_domain(name, &args, complex(&args), expr)
+                    }
+                    _ => check_domain(name, &reals, result, expr).map(|result| self.in_mode(Value::Real(result))),
+                }
+            }
+            (None, Some(complex)) => {
+                let args = complex_args();
+                check_complex_domain(name, &args, complex(&args), expr)
+            }
+            (None, None) => Err(complex_unsupported(name, expr)),
+        }
+    }
+
+    /// Reduces a real angle in the angle mode to within one turn, which is exact for degrees and
+    /// gradians and keeps `sin(36000030°)` precise
+    fn reduce_angle(&self, value: Value) -> Value {
+        let mode = self.ctx.angle_mode();
+        match value.as_real() {
+            Some(x) if mode != AngleMode::Radians && self.ctx.decimal_precision().is_none() => {
+                Value::Real(x % mode.full_turn())
+            }
+            _ => value,
+        }
+    }
+
+    /// Converts an angle between units, keeping decimals precise and complex numbers complex
+    fn convert_angle(&self, value: Value, from: AngleMode, to: AngleMode) -> Value {
+        if from == to {
+            return value;
+        }
+        if let Some(x) = self.to_decimal(&value) {
+            let precision = self.precision() + decimal::GUARD_DIGITS;
+            let full_turn = |mode| match mode {
+                AngleMode::Radians => decimal::pi(precision) * BigDecimal::from(2),
+                AngleMode::Degrees => BigDecimal::from(360),
+                AngleMode::Gradians => BigDecimal::from(400),
+            };
+            if let Some(result) = decimal::div(&(x * full_turn(to)), &full_turn(from), precision) {
+                return self.settle(result);
+            }
+        }
+        match value {
+            Value::Complex(z) => Value::from_complex(z * (to.full_turn() / from.full_turn())),
+            value => match value.as_real() {
+                Some(x) => Value::Real(from.convert(x, to)),
+                None => value,
+            },
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Expr], expr: &Expr) -> CalcResult<Value> {
+        let func = match self.ctx.get_func(name) {
+            Some(func) => func,
+            None if self.lookup(name).is_some() => {
+                return Err(CalcError::new(ErrorKind::UnknownFunction(name.to_string()), expr.span)
+                    .with_suggestion(format!("'{}' is a variable; use '*' to multiply", name)));
+            }
+            None => {
+                let error = CalcError::new(ErrorKind::UnknownFunction(name.to_string()), expr.span);
+                return Err(match closest_match(name, self.ctx.func_names()) {
+                    Some(candidate) => error.with_suggestion(format!("did you mean '{}'?", candidate)),
+                    None => error,
+                });
+            }
+        };
+        if !func.arity.accepts(args.len()) {
+            let error = CalcError::new(
+                ErrorKind::ArityMismatch {
+                    name: name.to_string(),
+                    expected: func.arity.to_string(),
+                    found: args.len(),
+                },
+                expr.span,
+            );
+            return Err(match &func.body {
+                FunctionBody::Expr { params, .. } => error.with_suggestion(format!("{}({})", name, params.join(", "))),
+                FunctionBody::Native(_) | FunctionBody::Values(_) | FunctionBody::Lazy(_) => error,
+            });
+        }
+        if let FunctionBody::Lazy(lazy) = &func.body {
+            return lazy(self, args, expr.span).map(|result| self.in_mode(result));
+        }
+        if let Some(result) = func.unevaluated.as_ref().and_then(|f| f(self, args, expr.span)) {
+            return result.map(|result| self.in_mode(result));
+        }
+
+        let values = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<CalcResult<Vec<Value>>>()?;
+
+        match &func.body {
+            FunctionBody::Native(native) => self.call_elementwise(name, func, native, values, expr),
+            FunctionBody::Values(value_fn) => value_fn(&values).map(|result| self.in_mode(result)).map_err(|message| {
+                CalcError::new(
+                    ErrorKind::DomainError {
+                        name: name.to_string(),
+                        message,
+                    },
+                    expr.span,
+                )
+            }),
+            FunctionBody::Lazy(_) => unreachable!("called with unevaluated arguments above"),
+            FunctionBody::Expr { params, body } => {
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Err(CalcError::new(ErrorKind::RecursionLimit(name.to_string()), expr.span));
+                }
+                let locals: Vec<(String, Value)> = params.iter().cloned().zip(values).collect();
+                let frame = Frame {
+                    ctx: self.ctx,
+                    locals: &locals,
+                    depth: self.depth + 1,
+                };
+                // Spans inside the body refer to the definition, so report at the call site instead
+                frame.eval(body).map_err(|e| match e.kind {
+                    ErrorKind::InFunction { .. } | ErrorKind::RecursionLimit(_) => e.with_span(expr.span),
+                    kind => CalcError::new(
+                        ErrorKind::InFunction {
+                            name: name.to_string(),
+                            source: Box::new(kind),
+                        },
+                        expr.span,
+                    )
+                    .with_suggestion(format!("{}({}) = {}", name, params.join(", "), body)),
+                })
+            }
+        }
+    }
+}
+
+impl Scope for Frame<'_> {
+    fn eval_with(&self, expr: &Expr, bindings: &[(String, Value)]) -> CalcResult<Value> {
+        let locals: Vec<(String, Value)> = bindings.iter().chain(self.locals).cloned().collect();
+        Frame {
+            ctx: self.ctx,
+            locals: &locals,
+            depth: self.depth,
+        }
+        .eval(expr)
+    }
+
+    fn combine(&self, op: BinaryOp, a: Value, b: Value, span: Span) -> CalcResult<Value> {
+        // Operator errors only use the spans of the expression and its right operand
+        let at = Expr::new(ExprKind::Ident(op.symbol().to_string()), span);
+        self.binary(op, a, b, &at, &at).map(|value| self.wrap(value))
+    }
+
+    fn angle_mode(&self) -> AngleMode {
+        self.ctx.angle_mode()
+    }
+
+    fn definition(&self, name: &str) -> Option<(Vec<String>, Arc<Expr>)> {
+        match &self.ctx.get_func(name)?.body {
+            FunctionBody::Expr { params, body } => Some((params.clone(), body.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Rounds a real result within rounding error of a whole number in [-1, 1], e.g. `cos(90°)`
+fn snap_to_integer(value: Value) -> Value {
+    match value {
+        Value::Real(x) if x.abs() <= 1.0 + 1e-9 && (x - x.round()).abs() < 1e-9 => Value::Real(x.round() + 0.0),
+        value => value,
+    }
+}
+
+/// A quantity, or a plain number when its units cancel, e.g. `1 km / 1 m` is 1000
+fn quantity_value(quantity: Quantity) -> Value {
+    match quantity.dimensionless_value() {
+        Some(x) => Value::Real(x),
+        None => Value::Quantity(Box::new(quantity)),
+    }
+}
+
+fn incompatible_units(mismatch: DimensionMismatch, expr: &Expr) -> CalcError {
+    let describe = |unit: String| if unit.is_empty() { "no unit".to_string() } else { unit };
+    CalcError::new(
+        ErrorKind::IncompatibleUnits(describe(mismatch.left), describe(mismatch.right)),
+        expr.span,
+    )
+    .with_suggestion("only quantities of the same dimension can be added or converted")
+}
+
+/// Error for a built-in function or operator that has no version for quantities
+fn units_unsupported(name: &str, expr: &Expr) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: name.to_string(),
+            message: "arguments with units are not supported".to_string(),
+        },
+        expr.span,
+    )
+    .with_suggestion("divide by a unit to get a plain number, e.g. x / (1 m)")
+}
+
+/// Error for a unit applied to a list, which has no quantity version
+fn list_unsupported(name: &str, expr: &Expr) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: name.to_string(),
+            message: "lists are not supported".to_string(),
+        },
+        expr.span,
+    )
+}
+
+/// Error for text used as a number, e.g. a file name in arithmetic
+fn text_unsupported(name: &str, expr: &Expr) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: name.to_string(),
+            message: "text arguments are not supported".to_string(),
+        },
+        expr.span,
+    )
+}
+
+/// Checks that two lists combined element by element have the same length
+fn check_lengths(name: &str, a: usize, b: usize, expr: &Expr) -> CalcResult<()> {
+    if a == b {
+        return Ok(());
+    }
+    Err(CalcError::new(
+        ErrorKind::DomainError {
+            name: name.to_string(),
+            message: format!("lists have different lengths ({} and {})", a, b),
+        },
+        expr.span,
+    ))
+}
+
+/// Error for an operation or function that has no complex version
+fn complex_unsupported(name: &str, expr: &Expr) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: name.to_string(),
+            message: "complex arguments are not supported".to_string(),
+        },
+        expr.span,
+    )
+}
+
+/// The operand of a bitwise operator as an integer, or an error for fractions and complex numbers
+fn integer_operand(name: &str, value: &Value, expr: &Expr) -> CalcResult<BigInt> {
+    if let Value::Complex(_) = value {
+        return Err(complex_unsupported(name, expr));
+    }
+    value.to_integer().ok_or_else(|| {
+        CalcError::new(
+            ErrorKind::DomainError {
+                name: name.to_string(),
+                message: format!("{} is not an integer", value),
+            },
+            expr.span,
+        )
+        .with_suggestion("bitwise operators work on whole numbers; round first, e.g. round(x)")
+    })
+}
+
+/// Like `check_domain`, for complex arguments and results
+fn check_complex_domain(name: &str, args: &[Complex64], result: Complex64, expr: &Expr) -> CalcResult<Value> {
+    let is_nan = |z: &Complex64| z.re.is_nan() || z.im.is_nan();
+    if is_nan(&result) && !args.iter().any(is_nan) {
+        let args = args
+            .iter()
+            .map(|arg| Value::from_complex(*arg).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(CalcError::new(
+            ErrorKind::DomainError {
+                name: name.to_string(),
+                message: format!("result is undefined for ({})", args),
+            },
+            expr.span,
+        ));
+    }
+    Ok(Value::from_complex(result))
+}
+
+/// Turns a NaN produced from well-defined inputs into a domain error
+fn check_domain(name: &str, args: &[f64], result: f64, expr: &Expr) -> CalcResult<f64> {
+    if result.is_nan() && !args.iter().any(|arg| arg.is_nan()) {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ");
+        return Err(CalcError::new(
+            ErrorKind::DomainError {
+                name: name.to_string(),
+                message: format!("result is undefined for ({})", args),
+            },
+            expr.span,
+        ));
+    }
+    Ok(result)
+}
@@ -0,0 +1,322 @@
+//! Tokenizer for calculator expressions
+
+use num_bigint::BigInt;
+
+use super::ast::Span;
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::math_functions::AngleMode;
+use super::programmer::Radix;
+
+/// Kind of a lexical token
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Integer(BigInt),
+    Imaginary(f64),
+    Angle(f64, AngleMode),
+    Ident(String),
+    Text(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Bang,
+    Amp,
+    Pipe,
+    Tilde,
+    Shl,
+    Shr,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Equals,
+    DotDot,
+    Semicolon,
+    Newline,
+    Eof,
+}
+
+impl TokenKind {
+    /// Human readable description used in error messages
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Number(n) => format!("number '{}'", n),
+            TokenKind::Integer(n) => format!("number '{}'", n),
+            TokenKind::Imaginary(n) => format!("imaginary number '{}i'", n),
+            TokenKind::Angle(n, unit) => format!("angle '{}{}'", n, unit.suffix()),
+            TokenKind::Ident(name) => format!("identifier '{}'", name),
+            TokenKind::Text(text) => format!("text \"{}\"", text),
+            TokenKind::Plus => "'+'".to_string(),
+            TokenKind::Minus => "'-'".to_string(),
+            TokenKind::Star => "'*'".to_string(),
+            TokenKind::Slash => "'/'".to_string(),
+            TokenKind::Percent => "'%'".to_string(),
+            TokenKind::Caret => "'^'".to_string(),
+            TokenKind::Bang => "'!'".to_string(),
+            TokenKind::Amp => "'&'".to_string(),
+            TokenKind::Pipe => "'|'".to_string(),
+            TokenKind::Tilde => "'~'".to_string(),
+            TokenKind::Shl => "'<<'".to_string(),
+            TokenKind::Shr => "'>>'".to_string(),
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+            TokenKind::LBracket => "'['".to_string(),
+            TokenKind::RBracket => "']'".to_string(),
+            TokenKind::Comma => "','".to_string(),
+            TokenKind::Equals => "'='".to_string(),
+            TokenKind::DotDot => "'..'".to_string(),
+            TokenKind::Semicolon => "';'".to_string(),
+            TokenKind::Newline => "line break".to_string(),
+            TokenKind::Eof => "end of input".to_string(),
+        }
+    }
+}
+
+/// A token together with its byte span in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Splits an expression into tokens, always ending with `TokenKind::Eof`
+///
+/// Line breaks become `TokenKind::Newline` statement separators, except inside parentheses and
+/// brackets where they are ordinary whitespace.
+pub fn tokenize(input: &str) -> CalcResult<Vec<Token>> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut depth = 0usize;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+
+        if c == b'\n' && depth == 0 {
+            tokens.push(Token {
+                kind: TokenKind::Newline,
+                span: Span::new(pos, pos + 1),
+            });
+            pos += 1;
+            continue;
+        }
+
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos;
+
+        // Prefixed integer literals such as `0xFF`, `0o17` and `0b1010`
+        let radix = bytes
+            .get(pos + 1)
+            .copied()
+            .filter(|_| c == b'0')
+            .and_then(Radix::from_prefix);
+        if let Some(radix) = radix.filter(|_| bytes.get(pos + 2).is_some_and(u8::is_ascii_alphanumeric)) {
+            pos += 2;
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+            let text = &input[start..pos];
+            let digits: Vec<u8> = text.bytes().skip(2).filter(|&b| b != b'_').collect();
+            let value = BigInt::parse_bytes(&digits, radix.base())
+                .ok_or_else(|| CalcError::new(ErrorKind::InvalidNumber(text.to_string()), Span::new(start, pos)))?;
+            tokens.push(Token {
+                kind: TokenKind::Integer(value),
+                span: Span::new(start, pos),
+            });
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == b'.' && bytes.get(pos + 1).is_some_and(|b| b.is_ascii_digit())) {
+            pos = scan_number(bytes, pos);
+            let text = &input[start..pos];
+            let value: f64 = text
+                .parse()
+                .map_err(|_| CalcError::new(ErrorKind::InvalidNumber(text.to_string()), Span::new(start, pos)))?;
+            // A trailing `i` that does not start a longer identifier makes the literal imaginary, e.g. `4i`
+            let imaginary = bytes.get(pos) == Some(&b'i')
+                && !bytes
+                    .get(pos + 1)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+            let angle = angle_suffix(&input[pos..]);
+            let kind = if imaginary {
+                pos += 1;
+                TokenKind::Imaginary(value)
+            } else if let Some((unit, len)) = angle {
+                pos += len;
+                TokenKind::Angle(value, unit)
+            } else {
+                TokenKind::Number(value)
+            };
+            tokens.push(Token {
+                kind,
+                span: Span::new(start, pos),
+            });
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == b'_' {
+            pos = scan_ident(bytes, pos);
+            tokens.push(Token {
+                kind: TokenKind::Ident(input[start..pos].to_string()),
+                span: Span::new(start, pos),
+            });
+            continue;
+        }
+
+        // Text in double quotes, e.g. a file name; there are no escapes, so `\` stays as typed
+        if c == b'"' {
+            let end = input[pos + 1..]
+                .find('"')
+                .map(|offset| pos + 1 + offset)
+                .ok_or_else(|| {
+                    CalcError::new(ErrorKind::UnterminatedText, Span::new(start, bytes.len()))
+                        .with_suggestion("add '\"' at the end of the text")
+                })?;
+            pos = end + 1;
+            tokens.push(Token {
+                kind: TokenKind::Text(input[start + 1..end].to_string()),
+                span: Span::new(start, pos),
+            });
+            continue;
+        }
+
+        // Two-character shift operators
+        let shift = match (c, bytes.get(pos + 1)) {
+            (b'<', Some(b'<')) => Some(TokenKind::Shl),
+            (b'>', Some(b'>')) => Some(TokenKind::Shr),
+            (b'.', Some(b'.')) => Some(TokenKind::DotDot),
+            _ => None,
+        };
+        if let Some(kind) = shift {
+            pos += 2;
+            tokens.push(Token {
+                kind,
+                span: Span::new(start, pos),
+            });
+            continue;
+        }
+
+        let kind = match c {
+            b'+' => TokenKind::Plus,
+            b'-' => TokenKind::Minus,
+            b'*' => TokenKind::Star,
+            b'/' => TokenKind::Slash,
+            b'%' => TokenKind::Percent,
+            b'^' => TokenKind::Caret,
+            b'!' => TokenKind::Bang,
+            b'&' => TokenKind::Amp,
+            b'|' => TokenKind::Pipe,
+            b'~' => TokenKind::Tilde,
+            b'(' => {
+                depth += 1;
+                TokenKind::LParen
+            }
+            b')' => {
+                depth = depth.saturating_sub(1);
+                TokenKind::RParen
+            }
+            b'[' => {
+                depth += 1;
+                TokenKind::LBracket
+            }
+            b']' => {
+                depth = depth.saturating_sub(1);
+                TokenKind::RBracket
+            }
+            b',' => TokenKind::Comma,
+            b'=' => TokenKind::Equals,
+            b';' => TokenKind::Semicolon,
+            _ => {
+                let ch = input[start..].chars().next().unwrap_or('?');
+                return Err(CalcError::new(
+                    ErrorKind::UnexpectedChar(ch),
+                    Span::new(start, start + ch.len_utf8()),
+                ));
+            }
+        };
+        pos += 1;
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, pos),
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        span: Span::new(bytes.len(), bytes.len()),
+    });
+    Ok(tokens)
+}
+
+/// Whether `name` is a single identifier token, e.g. a valid variable name
+pub fn is_identifier(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    !bytes.is_empty() && (bytes[0].is_ascii_alphabetic() || bytes[0] == b'_') && scan_ident(bytes, 0) == bytes.len()
+}
+
+/// Angle unit and length of a suffix directly after a number, e.g. `°` in `30°` or `grad` in `50grad`
+fn angle_suffix(rest: &str) -> Option<(AngleMode, usize)> {
+    if rest.starts_with('°') {
+        return Some((AngleMode::Degrees, '°'.len_utf8()));
+    }
+    let len = scan_ident(rest.as_bytes(), 0);
+    let unit = match &rest[..len] {
+        "deg" => AngleMode::Degrees,
+        "rad" => AngleMode::Radians,
+        "grad" => AngleMode::Gradians,
+        _ => return None,
+    };
+    Some((unit, len))
+}
+
+/// Scans a decimal literal with an optional fraction and exponent
+fn scan_number(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    // A second dot starts a range, as in `0..10`
+    if pos < bytes.len() && bytes[pos] == b'.' && bytes.get(pos + 1) != Some(&b'.') {
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    // Only treat `e` as an exponent when digits follow, so `2e` still reads as `2` then `e`
+    if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+        let mut exp = pos + 1;
+        if exp < bytes.len() && (bytes[exp] == b'+' || bytes[exp] == b'-') {
+            exp += 1;
+        }
+        if exp < bytes.len() && bytes[exp].is_ascii_digit() {
+            pos = exp;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    pos
+}
+
+/// Scans an identifier; dots are allowed between segments so mod ids like `a.b.c` stay whole
+fn scan_ident(bytes: &[u8], mut pos: usize) -> usize {
+    loop {
+        while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+            pos += 1;
+        }
+        let continues = pos + 1 < bytes.len()
+            && bytes[pos] == b'.'
+            && (bytes[pos + 1].is_ascii_alphabetic() || bytes[pos + 1] == b'_');
+        if !continues {
+            return pos;
+        }
+        pos += 1;
+    }
+}
@@ -1,6 +1,10 @@
 //! Mathematical functions implementation
 
 use std::f64::consts::{E, PI};
+use std::fmt;
+
+use num_complex::Complex64;
+use serde::{Deserialize, Serialize};
 
 /// Calculates the area of a triangle
 #[inline]
@@ -45,6 +49,23 @@ pub fn pythagorean_theorem(a: f64, b: f64) -> f64 {
     (a * a + b * b).sqrt()
 }
 
+/// Euclidean length of a vector with the arguments as components, e.g. `hypot(3, 4)` is 5
+///
+/// Folding with `f64::hypot` avoids overflow when squaring large components.
+pub fn hypot(args: &[f64]) -> f64 {
+    args.iter().fold(0.0, |acc, &x| acc.hypot(x))
+}
+
+/// Sum of the arguments
+pub fn sum(args: &[f64]) -> f64 {
+    args.iter().sum()
+}
+
+/// Arithmetic mean of the arguments
+pub fn avg(args: &[f64]) -> f64 {
+    sum(args) / args.len() as f64
+}
+
 /// Provides access to mathematical constants
 #[inline]
 pub fn pi() -> f64 {
@@ -187,14 +208,53 @@ pub fn gcd(mut a: u64, mut b: u64) -> u64 {
     a
 }
 
-pub fn lcm(a: u64, b: u64) -> u64 {
+/// Least common multiple, `None` when it does not fit in a `u64`
+pub fn lcm(a: u64, b: u64) -> Option<u64> {
     if a == 0 || b == 0 {
-        0
+        Some(0)
     } else {
-        a * b / gcd(a, b)
+        // Dividing first keeps the intermediate result no larger than the answer
+        (a / gcd(a, b)).checked_mul(b)
     }
 }
 
+// Binomial coefficient, exact up to 2^53 and approximate beyond
+pub fn binomial(n: f64, k: f64) -> f64 {
+    if n.fract() != 0.0 || k.fract() != 0.0 || n < 0.0 || k < 0.0 {
+        return f64::NAN;
+    }
+    if k > n {
+        return 0.0;
+    }
+    let k = k.min(n - k);
+    let mut result = 1.0;
+    let mut i = 1.0;
+    while i <= k {
+        // Multiplying before dividing keeps every partial result an integer
+        result = result * (n - k + i) / i;
+        i += 1.0;
+    }
+    result.round()
+}
+
+// Modular exponentiation
+pub fn modpow(base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    if modulus == 1 {
+        return 0;
+    }
+    let modulus = u128::from(modulus);
+    let mut base = u128::from(base) % modulus;
+    let mut result = 1u128;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent >>= 1;
+    }
+    result as u64
+}
+
 // Angle conversion
 pub fn degrees(x: f64) -> f64 {
     x * 180.0 / PI
@@ -204,6 +264,81 @@ pub fn radians(x: f64) -> f64 {
     x * PI / 180.0
 }
 
+/// Unit that trigonometric functions take and return angles in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum AngleMode {
+    /// A full turn is 2π
+    #[default]
+    Radians,
+
+    /// A full turn is 360
+    Degrees,
+
+    /// A full turn is 400
+    Gradians,
+}
+
+impl AngleMode {
+    pub const ALL: [AngleMode; 3] = [AngleMode::Degrees, AngleMode::Radians, AngleMode::Gradians];
+
+    /// Size of a full turn in this unit
+    pub fn full_turn(&self) -> f64 {
+        match self {
+            AngleMode::Radians => 2.0 * PI,
+            AngleMode::Degrees => 360.0,
+            AngleMode::Gradians => 400.0,
+        }
+    }
+
+    /// Converts an angle in this unit to `unit`
+    pub fn convert(&self, x: f64, unit: AngleMode) -> f64 {
+        if *self == unit {
+            x
+        } else {
+            x / self.full_turn() * unit.full_turn()
+        }
+    }
+
+    /// Whether `x` in this unit is a whole number of quarter turns, where sine and cosine are
+    /// exactly 0 or ±1; in radians this holds for the nearest floats, e.g. `pi / 2`
+    pub fn is_quarter_turn(&self, x: f64) -> bool {
+        x.is_finite() && self.convert(x, AngleMode::Degrees) % 90.0 == 0.0
+    }
+
+    /// Indicator shown in the GUI
+    pub fn label(&self) -> &'static str {
+        match self {
+            AngleMode::Radians => "RAD",
+            AngleMode::Degrees => "DEG",
+            AngleMode::Gradians => "GRAD",
+        }
+    }
+
+    /// Suffix of literals in this unit, e.g. `30°`
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            AngleMode::Radians => "rad",
+            AngleMode::Degrees => "°",
+            AngleMode::Gradians => "grad",
+        }
+    }
+
+    /// The next mode of the GUI toggle
+    pub fn next(&self) -> AngleMode {
+        match self {
+            AngleMode::Degrees => AngleMode::Radians,
+            AngleMode::Radians => AngleMode::Gradians,
+            AngleMode::Gradians => AngleMode::Degrees,
+        }
+    }
+}
+
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
 // Floating point utilities
 pub fn is_inf(x: f64) -> bool {
     x.is_infinite()
@@ -216,3 +351,72 @@ pub fn is_nan(x: f64) -> bool {
 pub fn is_close(a: f64, b: f64, rel_tol: f64, abs_tol: f64) -> bool {
     (a - b).abs() <= (rel_tol * b.abs()).max(abs_tol)
 }
+
+// Complex number parts; the real versions treat x as x + 0i
+pub fn re(x: f64) -> f64 {
+    x
+}
+
+pub fn im(_x: f64) -> f64 {
+    0.0
+}
+
+pub fn arg(x: f64) -> f64 {
+    if x.is_sign_negative() {
+        PI
+    } else {
+        0.0
+    }
+}
+
+pub fn conj(x: f64) -> f64 {
+    x
+}
+
+pub fn complex_re(z: Complex64) -> Complex64 {
+    Complex64::from(z.re)
+}
+
+pub fn complex_im(z: Complex64) -> Complex64 {
+    Complex64::from(z.im)
+}
+
+pub fn complex_arg(z: Complex64) -> Complex64 {
+    Complex64::from(z.arg())
+}
+
+pub fn complex_abs(z: Complex64) -> Complex64 {
+    Complex64::from(z.norm())
+}
+
+pub fn complex_conj(z: Complex64) -> Complex64 {
+    z.conj()
+}
+
+// Complex versions of the functions above, used in complex mode
+pub fn complex_csin(z: Complex64) -> Complex64 {
+    z.sin().cos()
+}
+
+pub fn complex_log10(z: Complex64) -> Complex64 {
+    z.ln() / std::f64::consts::LN_10
+}
+
+pub fn complex_log2(z: Complex64) -> Complex64 {
+    z.ln() / std::f64::consts::LN_2
+}
+
+pub fn complex_pow(z: Complex64, w: Complex64) -> Complex64 {
+    if w.im == 0.0 && w.re.fract() == 0.0 && w.re.abs() <= i32::MAX as f64 {
+        // Integer powers by repeated multiplication stay exact, e.g. i^2 = -1
+        z.powi(w.re as i32)
+    } else if z == Complex64::from(0.0) {
+        if w.re > 0.0 {
+            Complex64::from(0.0)
+        } else {
+            Complex64::new(f64::NAN, f64::NAN)
+        }
+    } else {
+        z.powc(w)
+    }
+}
@@ -0,0 +1,808 @@
+//! Matrices and vectors
+//!
+//! A list whose items are lists of numbers, all of the same length, is a matrix, e.g.
+//! `[[1, 2], [3, 4]]`; other lists are vectors. `+` and `-` combine matrices of the same size entry
+//! by entry and `*` is the matrix product, taking a list on the left as a row vector and on the
+//! right as a column vector. `^` takes whole powers, negative ones of the inverse. Operations with
+//! a number apply to every entry, as they do for lists.
+//!
+//! Exact matrices give exact determinants, inverses and solutions, e.g. `inverse([[2, 1], [1, 1]])`
+//! is `[[1, -1], [-1, 2]]`. QR decompositions and eigenvalues always use floats.
+
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Num, Signed, ToPrimitive, Zero};
+
+use super::context::Arity;
+use super::poly::roots;
+use super::rational;
+use super::value::Value;
+
+/// Evaluates `$body` with `$m` bound to the exact or float entries of the matrix `$rows`
+macro_rules! with_entries {
+    ($rows:expr, |$m:ident| $body:expr) => {
+        match exact($rows) {
+            Some($m) => $body,
+            None => {
+                let $m = reals($rows)?;
+                $body
+            }
+        }
+    };
+}
+
+/// A matrix or vector function callable from expressions
+pub struct MatrixFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result, or explains why the arguments are unsuitable
+    pub eval: fn(&[Value]) -> Result<Value, String>,
+}
+
+/// All matrix and vector functions, registered by the evaluator
+pub const FUNCTIONS: &[MatrixFn] = &[
+    MatrixFn {
+        name: "transpose",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| transpose(&args[0]),
+    },
+    MatrixFn {
+        name: "det",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| Ok(with_entries!(square(&args[0])?, |m| determinant(m).into_value())),
+    },
+    MatrixFn {
+        name: "inverse",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| with_entries!(square(&args[0])?, |m| inverse(m).map(grid).ok_or_else(singular)),
+    },
+    MatrixFn {
+        name: "rank",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| {
+            let rank = with_entries!(matrix(&args[0])?, |m| {
+                let columns = m[0].len();
+                reduce(&mut { m }, columns).rank
+            });
+            Ok(Value::Integer(rank.into()))
+        },
+    },
+    MatrixFn {
+        name: "trace",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| Ok(with_entries!(square(&args[0])?, |m| trace(&m).into_value())),
+    },
+    MatrixFn {
+        name: "linsolve",
+        params: "A, b",
+        arity: Arity::Exact(2),
+        eval: |args| linsolve(&args[0], &args[1]),
+    },
+    MatrixFn {
+        name: "lu",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| {
+            Ok(with_entries!(square(&args[0])?, |m| Value::List(
+                lu(m).map(grid).to_vec()
+            )))
+        },
+    },
+    MatrixFn {
+        name: "qr",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| {
+            let (q, r) = qr(reals(matrix(&args[0])?)?);
+            Ok(Value::List(vec![grid(q), grid(r)]))
+        },
+    },
+    MatrixFn {
+        name: "eigenvalues",
+        params: "A",
+        arity: Arity::Exact(1),
+        eval: |args| eigenvalues(square(&args[0])?),
+    },
+    MatrixFn {
+        name: "dot",
+        params: "u, v",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            let pair = vectors(&args[0], &args[1], None)?;
+            Ok(with_entries!(&pair, |m| dot(&m[0], &m[1]).into_value()))
+        },
+    },
+    MatrixFn {
+        name: "cross",
+        params: "u, v",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            let pair = vectors(&args[0], &args[1], Some(3))?;
+            Ok(with_entries!(&pair, |m| vector(cross(&m[0], &m[1]).to_vec())))
+        },
+    },
+    MatrixFn {
+        name: "norm",
+        params: "v",
+        arity: Arity::Exact(1),
+        eval: |args| {
+            let rows = match &args[0] {
+                Value::Matrix(rows) => rows.clone(),
+                Value::List(items) if !items.is_empty() => vec![items.clone()],
+                value => return Err(format!("{} is not a vector or matrix", value)),
+            };
+            // Euclidean for vectors and Frobenius for matrices, exact when the square is a perfect square
+            Ok(match exact(&rows) {
+                Some(m) => {
+                    let squares = m.iter().flatten().fold(BigRational::zero(), |sum, x| sum + x * x);
+                    match rational::sqrt(&squares) {
+                        Some(root) => Value::from_rational(root),
+                        None => Value::Real(rational::to_f64(&squares).sqrt()),
+                    }
+                }
+                None => Value::Real(reals(&rows)?.iter().flatten().map(|x| x * x).sum::<f64>().sqrt()),
+            })
+        },
+    },
+];
+
+/// The value of a list literal: a matrix when every item is a list of numbers of the same length
+pub fn from_items(items: Vec<Value>) -> Value {
+    let width = match items.first() {
+        Some(Value::List(row)) if !row.is_empty() => row.len(),
+        _ => return Value::List(items),
+    };
+    let rectangular = items
+        .iter()
+        .all(|item| matches!(item, Value::List(row) if row.len() == width && row.iter().all(is_number)));
+    if !rectangular {
+        return Value::List(items);
+    }
+    Value::Matrix(
+        items
+            .into_iter()
+            .map(|item| match item {
+                Value::List(row) => row,
+                _ => unreachable!("checked to be a list"),
+            })
+            .collect(),
+    )
+}
+
+/// A list of rows as a matrix again after an operation on each entry; other values are unchanged
+pub fn from_list(value: Value) -> Value {
+    match value {
+        Value::List(items) => from_items(items),
+        value => value,
+    }
+}
+
+/// A matrix as the list of its rows, so list operations apply to each entry; other values are
+/// unchanged
+pub fn into_list(value: Value) -> Value {
+    match value {
+        Value::Matrix(rows) => Value::List(rows.into_iter().map(Value::List).collect()),
+        value => value,
+    }
+}
+
+/// Checks that two matrices combined entry by entry have the same size
+pub fn check_sizes(a: &[Vec<Value>], b: &[Vec<Value>]) -> Result<(), String> {
+    if size(a) == size(b) {
+        return Ok(());
+    }
+    Err(format!("matrices have different sizes ({} and {})", size(a), size(b)))
+}
+
+/// Matrix product of two matrices, or of a matrix and a vector
+pub fn product(a: &Value, b: &Value) -> Result<Value, String> {
+    let left = match a {
+        Value::Matrix(rows) => rows.clone(),
+        Value::List(items) if !items.is_empty() => vec![items.clone()],
+        value => return Err(format!("{} is not a vector or matrix", value)),
+    };
+    let right = match b {
+        Value::Matrix(rows) => rows.clone(),
+        Value::List(items) if !items.is_empty() => items.iter().map(|item| vec![item.clone()]).collect(),
+        value => return Err(format!("{} is not a vector or matrix", value)),
+    };
+    if left[0].len() != right.len() {
+        return Err(format!(
+            "cannot multiply {} by {}; the columns of the first must match the rows of the second",
+            size(&left),
+            size(&right)
+        ));
+    }
+    let result = match (exact(&left), exact(&right)) {
+        (Some(x), Some(y)) => grid(multiply(&x, &y)),
+        _ => grid(multiply(&reals(&left)?, &reals(&right)?)),
+    };
+    // A row vector times a matrix is a row, and a matrix times a column vector a column
+    Ok(match (a, b, result) {
+        (Value::List(_), _, Value::Matrix(mut rows)) => Value::List(rows.remove(0)),
+        (_, Value::List(_), Value::Matrix(rows)) => Value::List(rows.into_iter().flatten().collect()),
+        (_, _, result) => result,
+    })
+}
+
+/// A square matrix to a whole power; negative powers are powers of the inverse
+pub fn power(rows: &[Vec<Value>], exponent: &Value) -> Result<Value, String> {
+    check_square(rows)?;
+    let n = exponent
+        .to_integer()
+        .and_then(|n| n.to_i32())
+        .ok_or_else(|| format!("matrix powers must be whole numbers, got {}", exponent))?;
+    with_entries!(rows, |m| {
+        let base = if n < 0 { inverse(m).ok_or_else(singular)? } else { m };
+        Ok(grid(pow(base, n.unsigned_abs())))
+    })
+}
+
+/// Numbers matrices are computed with: exact fractions or floats
+pub(super) trait Number: Num + Signed + PartialOrd + Clone {
+    fn count(n: usize) -> Self;
+
+    fn into_value(self) -> Value;
+
+    /// Whether the number is zero, or lost in rounding next to numbers of size `scale`
+    fn negligible(&self, scale: &Self) -> bool;
+}
+
+impl Number for BigRational {
+    fn count(n: usize) -> Self {
+        BigRational::from_integer(BigInt::from(n))
+    }
+
+    fn into_value(self) -> Value {
+        Value::from_rational(self)
+    }
+
+    fn negligible(&self, _scale: &Self) -> bool {
+        self.is_zero()
+    }
+}
+
+impl Number for f64 {
+    fn count(n: usize) -> Self {
+        n as f64
+    }
+
+    fn into_value(self) -> Value {
+        Value::Real(self)
+    }
+
+    fn negligible(&self, scale: &Self) -> bool {
+        self.abs() <= scale * 1e-12
+    }
+}
+
+/// Solves `matrix * x = rhs`, or `None` if the matrix is singular
+pub(super) fn solve<T: Number>(matrix: Vec<Vec<T>>, rhs: Vec<T>) -> Option<Vec<T>> {
+    let columns = rhs.into_iter().map(|x| vec![x]).collect();
+    solve_columns(matrix, columns).map(|solution| solution.into_iter().flatten().collect())
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Real(_) | Value::Complex(_) | Value::Integer(_) | Value::Rational(_) | Value::Decimal(_)
+    )
+}
+
+fn size<T>(m: &[Vec<T>]) -> String {
+    format!("{}×{}", m.len(), m.first().map_or(0, Vec::len))
+}
+
+fn singular() -> String {
+    "the matrix is singular".to_string()
+}
+
+fn matrix(value: &Value) -> Result<&[Vec<Value>], String> {
+    match value {
+        Value::Matrix(rows) => Ok(rows),
+        value => Err(format!("{} is not a matrix", value)),
+    }
+}
+
+fn square(value: &Value) -> Result<&[Vec<Value>], String> {
+    let rows = matrix(value)?;
+    check_square(rows)?;
+    Ok(rows)
+}
+
+fn check_square(rows: &[Vec<Value>]) -> Result<(), String> {
+    if rows.len() != rows[0].len() {
+        return Err(format!("the matrix must be square, got {}", size(rows)));
+    }
+    Ok(())
+}
+
+/// Two vectors of the same length as the rows of a matrix, checking the length if one is required
+fn vectors(u: &Value, v: &Value, length: Option<usize>) -> Result<Vec<Vec<Value>>, String> {
+    let (Value::List(u), Value::List(v)) = (u, v) else {
+        return Err("the arguments must be vectors, e.g. [1, 2, 3]".to_string());
+    };
+    if u.len() != v.len() {
+        return Err(format!("vectors have different lengths ({} and {})", u.len(), v.len()));
+    }
+    match length {
+        Some(length) if u.len() != length => Err(format!("the vectors must have {} entries, got {}", length, u.len())),
+        _ if u.is_empty() => Err("the vectors are empty".to_string()),
+        _ => Ok(vec![u.clone(), v.clone()]),
+    }
+}
+
+fn exact(rows: &[Vec<Value>]) -> Option<Vec<Vec<BigRational>>> {
+    rows.iter()
+        .map(|row| row.iter().map(Value::to_rational).collect())
+        .collect()
+}
+
+fn reals(rows: &[Vec<Value>]) -> Result<Vec<Vec<f64>>, String> {
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|value| value.as_real().ok_or_else(|| format!("{} is not a real number", value)))
+                .collect()
+        })
+        .collect()
+}
+
+fn grid<T: Number>(m: Vec<Vec<T>>) -> Value {
+    Value::Matrix(
+        m.into_iter()
+            .map(|row| row.into_iter().map(Number::into_value).collect())
+            .collect(),
+    )
+}
+
+fn vector<T: Number>(v: Vec<T>) -> Value {
+    Value::List(v.into_iter().map(Number::into_value).collect())
+}
+
+fn transpose(value: &Value) -> Result<Value, String> {
+    match value {
+        Value::Matrix(rows) => Ok(Value::Matrix(
+            (0..rows[0].len())
+                .map(|j| rows.iter().map(|row| row[j].clone()).collect())
+                .collect(),
+        )),
+        // A vector becomes a column
+        Value::List(items) if !items.is_empty() && items.iter().all(is_number) => {
+            Ok(Value::Matrix(items.iter().map(|item| vec![item.clone()]).collect()))
+        }
+        value => Err(format!("{} is not a vector or matrix", value)),
+    }
+}
+
+fn linsolve(a: &Value, b: &Value) -> Result<Value, String> {
+    let a = square(a)?;
+    let columns = match b {
+        Value::Matrix(rows) => rows.clone(),
+        Value::List(items) => items.iter().map(|item| vec![item.clone()]).collect(),
+        value => return Err(format!("{} is not a vector or matrix", value)),
+    };
+    if columns.len() != a.len() {
+        return Err(format!(
+            "b must have {} rows to match the matrix, got {}",
+            a.len(),
+            columns.len()
+        ));
+    }
+    let solution = match (exact(a), exact(&columns)) {
+        (Some(a), Some(b)) => solve_columns(a, b).map(grid),
+        _ => solve_columns(reals(a)?, reals(&columns)?).map(grid),
+    }
+    .ok_or_else(singular)?;
+    Ok(match (b, solution) {
+        (Value::List(_), Value::Matrix(rows)) => Value::List(rows.into_iter().flatten().collect()),
+        (_, solution) => solution,
+    })
+}
+
+fn identity<T: Number>(n: usize) -> Vec<Vec<T>> {
+    (0..n)
+        .map(|i| (0..n).map(|j| if i == j { T::one() } else { T::zero() }).collect())
+        .collect()
+}
+
+fn multiply<T: Number>(a: &[Vec<T>], b: &[Vec<T>]) -> Vec<Vec<T>> {
+    a.iter()
+        .map(|row| {
+            (0..b[0].len())
+                .map(|j| {
+                    row.iter()
+                        .zip(b)
+                        .fold(T::zero(), |sum, (x, b_row)| sum + x.clone() * b_row[j].clone())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn pow<T: Number>(mut base: Vec<Vec<T>>, mut n: u32) -> Vec<Vec<T>> {
+    let mut result = identity(base.len());
+    while n > 0 {
+        if n & 1 == 1 {
+            result = multiply(&result, &base);
+        }
+        n >>= 1;
+        if n > 0 {
+            base = multiply(&base, &base);
+        }
+    }
+    result
+}
+
+fn trace<T: Number>(m: &[Vec<T>]) -> T {
+    m.iter()
+        .enumerate()
+        .fold(T::zero(), |sum, (i, row)| sum + row[i].clone())
+}
+
+fn dot<T: Number>(u: &[T], v: &[T]) -> T {
+    u.iter()
+        .zip(v)
+        .fold(T::zero(), |sum, (x, y)| sum + x.clone() * y.clone())
+}
+
+fn cross<T: Number>(u: &[T], v: &[T]) -> [T; 3] {
+    let term = |i: usize, j: usize| u[i].clone() * v[j].clone() - u[j].clone() * v[i].clone();
+    [term(1, 2), term(2, 0), term(0, 1)]
+}
+
+fn compare_magnitudes<T: Number>(a: &T, b: &T) -> Ordering {
+    a.abs().partial_cmp(&b.abs()).unwrap_or(Ordering::Equal)
+}
+
+/// Outcome of Gauss-Jordan elimination
+struct Reduction<T> {
+    rank: usize,
+
+    /// Determinant of the reduced columns if they are square, zero when singular
+    determinant: T,
+}
+
+/// Brings the first `columns` columns to reduced row echelon form with partial pivoting, applying
+/// the same row operations to any further columns
+fn reduce<T: Number>(m: &mut [Vec<T>], columns: usize) -> Reduction<T> {
+    let scale = m
+        .iter()
+        .flat_map(|row| &row[..columns])
+        .map(|x| x.abs())
+        .fold(T::zero(), |max, x| if x > max { x } else { max });
+    let mut determinant = T::one();
+    let mut rank = 0;
+    for col in 0..columns {
+        if rank == m.len() {
+            break;
+        }
+        let pivot = (rank..m.len())
+            .max_by(|&i, &j| compare_magnitudes(&m[i][col], &m[j][col]))
+            .expect("rows remain below the rank");
+        if m[pivot][col].negligible(&scale) {
+            determinant = T::zero();
+            continue;
+        }
+        if pivot != rank {
+            m.swap(pivot, rank);
+            determinant = -determinant;
+        }
+        let value = m[rank][col].clone();
+        determinant = determinant * value.clone();
+        for x in m[rank].iter_mut() {
+            *x = x.clone() / value.clone();
+        }
+        let pivot_row = m[rank].clone();
+        for (i, row) in m.iter_mut().enumerate() {
+            if i == rank || row[col].is_zero() {
+                continue;
+            }
+            let factor = row[col].clone();
+            for (x, p) in row.iter_mut().zip(&pivot_row) {
+                *x = x.clone() - factor.clone() * p.clone();
+            }
+        }
+        rank += 1;
+    }
+    if rank < columns {
+        determinant = T::zero();
+    }
+    Reduction { rank, determinant }
+}
+
+fn determinant<T: Number>(mut m: Vec<Vec<T>>) -> T {
+    let n = m.len();
+    reduce(&mut m, n).determinant
+}
+
+/// Solves `matrix * x = b` for each column of `b`, or `None` if the matrix is singular
+fn solve_columns<T: Number>(matrix: Vec<Vec<T>>, b: Vec<Vec<T>>) -> Option<Vec<Vec<T>>> {
+    let n = matrix.len();
+    let mut augmented: Vec<Vec<T>> = matrix
+        .into_iter()
+        .zip(b)
+        .map(|(mut row, rhs)| {
+            row.extend(rhs);
+            row
+        })
+        .collect();
+    if reduce(&mut augmented, n).rank < n {
+        return None;
+    }
+    Some(augmented.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+fn inverse<T: Number>(m: Vec<Vec<T>>) -> Option<Vec<Vec<T>>> {
+    let n = m.len();
+    solve_columns(m, identity(n))
+}
+
+/// `[L, U, P]` with `P A = L U`, where `L` is unit lower triangular and `U` upper triangular
+fn lu<T: Number>(mut u: Vec<Vec<T>>) -> [Vec<Vec<T>>; 3] {
+    let n = u.len();
+    let mut l: Vec<Vec<T>> = vec![vec![T::zero(); n]; n];
+    let mut order: Vec<usize> = (0..n).collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| compare_magnitudes(&u[i][col], &u[j][col]))
+            .expect("rows remain below the column");
+        // Rows of L below the column only hold multipliers from earlier columns, so they swap too
+        u.swap(col, pivot);
+        l.swap(col, pivot);
+        order.swap(col, pivot);
+        if u[col][col].is_zero() {
+            continue;
+        }
+        let pivot_row = u[col].clone();
+        for (row, l_row) in u.iter_mut().zip(l.iter_mut()).skip(col + 1) {
+            let factor = row[col].clone() / pivot_row[col].clone();
+            for (x, p) in row.iter_mut().zip(&pivot_row).skip(col) {
+                *x = x.clone() - factor.clone() * p.clone();
+            }
+            row[col] = T::zero();
+            l_row[col] = factor;
+        }
+    }
+    for (i, row) in l.iter_mut().enumerate() {
+        row[i] = T::one();
+    }
+    let permutation = order
+        .iter()
+        .map(|&j| (0..n).map(|k| if k == j { T::one() } else { T::zero() }).collect())
+        .collect();
+    [l, u, permutation]
+}
+
+/// `(Q, R)` with `A = Q R`, `Q` orthogonal and `R` upper triangular with a non-negative diagonal,
+/// by Householder reflections
+fn qr(mut r: Vec<Vec<f64>>) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    let (rows, columns) = (r.len(), r[0].len());
+    let mut q: Vec<Vec<f64>> = identity(rows);
+    for k in 0..columns.min(rows.saturating_sub(1)) {
+        let norm = r[k..].iter().map(|row| row[k] * row[k]).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            continue;
+        }
+        // Reflect column k onto a multiple of the k-th unit vector
+        let mut v: Vec<f64> = r
+            .iter()
+            .enumerate()
+            .map(|(i, row)| if i < k { 0.0 } else { row[k] })
+            .collect();
+        v[k] += if r[k][k] >= 0.0 { norm } else { -norm };
+        let length = v.iter().map(|x| x * x).sum::<f64>();
+        for j in 0..columns {
+            let s = 2.0 * r.iter().zip(&v).map(|(row, vi)| row[j] * vi).sum::<f64>() / length;
+            for (row, vi) in r.iter_mut().zip(&v) {
+                row[j] -= s * vi;
+            }
+        }
+        for row in q.iter_mut() {
+            let s = 2.0 * row.iter().zip(&v).map(|(x, vi)| x * vi).sum::<f64>() / length;
+            for (x, vi) in row.iter_mut().zip(&v) {
+                *x -= s * vi;
+            }
+        }
+    }
+    for (i, row) in r.iter_mut().enumerate() {
+        for x in row.iter_mut().take(i.min(columns)) {
+            *x = 0.0;
+        }
+    }
+    // Flip signs so the diagonal of R is non-negative, which makes the decomposition unique
+    for i in 0..rows.min(columns) {
+        if r[i][i] < 0.0 {
+            for x in r[i].iter_mut() {
+                *x = -*x;
+            }
+            for row in q.iter_mut() {
+                row[i] = -row[i];
+            }
+        }
+    }
+    (q, r)
+}
+
+/// Coefficients of `det(x I - A)`, highest power first, by the Faddeev–LeVerrier recurrence
+fn characteristic<T: Number>(a: &[Vec<T>]) -> Vec<T> {
+    let n = a.len();
+    let mut coefficients = vec![T::one()];
+    let mut m: Vec<Vec<T>> = vec![vec![T::zero(); n]; n];
+    for k in 1..=n {
+        // M_k = A M_(k-1) + c I, then the next coefficient is -tr(A M_k) / k
+        m = multiply(a, &m);
+        let c = coefficients[k - 1].clone();
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = row[i].clone() + c.clone();
+        }
+        coefficients.push(-trace(&multiply(a, &m)) / T::count(k));
+    }
+    coefficients
+}
+
+/// Eigenvalues as the roots of the characteristic polynomial, largest real part first
+///
+/// Whole-number eigenvalues of exact matrices are exact, and symmetric matrices, whose
+/// eigenvalues are all real, have rounding residue in the imaginary parts removed.
+fn eigenvalues(rows: &[Vec<Value>]) -> Result<Value, String> {
+    let a = reals(rows)?;
+    let symmetric = (0..a.len()).all(|i| (0..i).all(|j| a[i][j] == a[j][i]));
+    let polynomial = with_entries!(rows, |m| {
+        characteristic(&m)
+            .into_iter()
+            .map(|c| c.into_value().as_real().unwrap_or(f64::NAN))
+            .collect::<Vec<f64>>()
+    });
+    let exact_polynomial = exact(rows).map(|m| characteristic(&m));
+    let mut values: Vec<Complex64> = roots(&polynomial);
+    // Conjugate pairs differ in the real part only by rounding, so compare it to nine places
+    let real_part = |z: &Complex64| (z.re * 1e9).round();
+    values.sort_by(|a, b| real_part(b).total_cmp(&real_part(a)).then(b.im.total_cmp(&a.im)));
+    Ok(Value::List(
+        values
+            .into_iter()
+            .map(|z| {
+                let near_real = z.im.abs() <= 1e-6 * (1.0 + z.norm());
+                if let (true, Some(p)) = (near_real, &exact_polynomial) {
+                    let candidate = BigRational::from_integer(BigInt::from(z.re.round() as i64));
+                    let residue = p.iter().fold(BigRational::zero(), |acc, c| acc * &candidate + c);
+                    if residue.is_zero() {
+                        return Value::from_rational(candidate);
+                    }
+                }
+                if symmetric || z.im.abs() <= 1e-12 * z.norm() {
+                    Value::Real(z.re)
+                } else {
+                    Value::Complex(z)
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+        let function = FUNCTIONS.iter().find(|f| f.name == name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.eval)(args)
+    }
+
+    fn ints(xs: &[i64]) -> Vec<Value> {
+        xs.iter().map(|&x| Value::Integer(x.into())).collect()
+    }
+
+    fn int_matrix(rows: &[&[i64]]) -> Value {
+        Value::Matrix(rows.iter().map(|row| ints(row)).collect())
+    }
+
+    fn show(result: Result<Value, String>) -> String {
+        result.unwrap().to_string()
+    }
+
+    #[test]
+    fn test_exact() {
+        let a = int_matrix(&[&[2, 1], &[1, 1]]);
+        let b = Value::List(ints(&[3, 2]));
+        assert_eq!(show(call("det", &[int_matrix(&[&[1, 2], &[3, 4]])])), "-2");
+        assert_eq!(show(call("inverse", std::slice::from_ref(&a))), "[[1, -1], [-1, 2]]");
+        assert_eq!(
+            show(call("inverse", &[int_matrix(&[&[1, 2], &[3, 4]])])),
+            "[[-2, 1], [3/2, -1/2]]"
+        );
+        assert_eq!(show(call("rank", &[int_matrix(&[&[1, 2, 3], &[2, 4, 6]])])), "1");
+        assert_eq!(show(call("trace", std::slice::from_ref(&a))), "3");
+        assert_eq!(show(call("transpose", &[int_matrix(&[&[1, 2, 3]])])), "[[1], [2], [3]]");
+        assert_eq!(show(call("linsolve", &[a.clone(), b.clone()])), "[1, 1]");
+        assert_eq!(show(product(&a, &b)), "[8, 5]");
+        assert_eq!(show(product(&b, &a)), "[8, 5]");
+
+        let fibonacci = [ints(&[1, 1]), ints(&[1, 0])];
+        assert_eq!(
+            show(power(&fibonacci, &Value::Integer(10.into()))),
+            "[[89, 55], [55, 34]]"
+        );
+        assert_eq!(
+            show(power(&[ints(&[2, 1]), ints(&[1, 1])], &Value::Integer((-1).into()))),
+            "[[1, -1], [-1, 2]]"
+        );
+    }
+
+    #[test]
+    fn test_decompositions() {
+        assert_eq!(
+            show(call("lu", &[int_matrix(&[&[1, 2], &[3, 4]])])),
+            "[[[1, 0], [1/3, 1]], [[3, 4], [0, 2/3]], [[0, 1], [1, 0]]]"
+        );
+
+        let Value::List(qr) = call("qr", &[int_matrix(&[&[3, 0], &[4, 5]])]).unwrap() else {
+            panic!("qr returns a list")
+        };
+        let assert_close = |value: &Value, expected: [[f64; 2]; 2]| {
+            let Value::Matrix(rows) = value else {
+                panic!("not a matrix: {}", value)
+            };
+            for (row, expected) in rows.iter().zip(expected) {
+                for (x, e) in row.iter().zip(expected) {
+                    assert!((x.as_real().unwrap() - e).abs() < 1e-12, "{} != {:?}", value, expected);
+                }
+            }
+        };
+        assert_close(&qr[0], [[0.6, -0.8], [0.8, 0.6]]);
+        assert_close(&qr[1], [[5.0, 4.0], [0.0, 3.0]]);
+    }
+
+    #[test]
+    fn test_eigenvalues_and_vectors() {
+        assert_eq!(show(call("eigenvalues", &[int_matrix(&[&[2, 1], &[1, 2]])])), "[3, 1]");
+        assert_eq!(show(call("eigenvalues", &[int_matrix(&[&[1, 0], &[0, 1]])])), "[1, 1]");
+        let Value::List(rotation) = call("eigenvalues", &[int_matrix(&[&[0, -1], &[1, 0]])]).unwrap() else {
+            panic!("eigenvalues returns a list")
+        };
+        let z = rotation[0].to_complex();
+        assert!(z.re.abs() < 1e-12 && (z.im - 1.0).abs() < 1e-12, "{}", z);
+
+        let (u, v) = (Value::List(ints(&[1, 0, 0])), Value::List(ints(&[0, 1, 0])));
+        assert_eq!(show(call("cross", &[u.clone(), v.clone()])), "[0, 0, 1]");
+        assert_eq!(show(call("dot", &[u, v])), "0");
+        assert_eq!(show(call("norm", &[Value::List(ints(&[3, 4]))])), "5");
+        assert_eq!(show(call("norm", &[int_matrix(&[&[1, 1], &[1, 1]])])), "2");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            call("inverse", &[int_matrix(&[&[1, 2], &[2, 4]])]).unwrap_err(),
+            "the matrix is singular"
+        );
+        assert_eq!(
+            call("det", &[int_matrix(&[&[1, 2, 3]])]).unwrap_err(),
+            "the matrix must be square, got 1×3"
+        );
+        assert_eq!(
+            product(&int_matrix(&[&[1, 2]]), &int_matrix(&[&[1, 2]])).unwrap_err(),
+            "cannot multiply 1×2 by 1×2; the columns of the first must match the rows of the second"
+        );
+        let one = Value::List(ints(&[1]));
+        assert_eq!(
+            call("cross", &[one.clone(), one]).unwrap_err(),
+            "the vectors must have 3 entries, got 1"
+        );
+    }
+}
@@ -1,11 +1,38 @@
 //! Core calculator module
 
+pub mod ast;
+pub mod cache;
+pub mod calculus;
+pub mod context;
+pub mod csv;
+pub mod decimal;
+pub mod distributions;
+pub mod error;
 pub mod evaluator;
 pub mod history;
+pub mod interpreter;
+pub mod lexer;
 pub mod math_functions;
+pub mod matrix;
 pub mod mods;
+pub mod ode;
+pub mod parser;
+pub mod poly;
+pub mod programmer;
 pub mod random;
+pub mod rational;
+pub mod regression;
+pub mod solver;
+pub mod stats;
+pub mod symbolic;
+pub mod units;
+pub mod value;
 
-pub use evaluator::Evaluator;
+pub use error::{CalcError, ErrorKind};
+pub use evaluator::{Evaluator, Outcome, UserFunction};
 pub use history::HistoryManager;
+pub use math_functions::AngleMode;
 pub use mods::ModManager;
+pub use programmer::{Radix, WordSize};
+pub use units::{Quantity, UnitRegistry};
+pub use value::{ComplexDisplay, FormatOptions, FractionDisplay, Value};
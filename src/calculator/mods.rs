@@ -5,9 +5,23 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+/// Kind of value a mod provides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModType {
+    /// Function evaluated from `method` with the `needs` arguments
+    #[default]
+    Fun,
+
+    /// Constant bound to the `res` value
+    Num,
+}
+
 /// Represents a single mod loaded from a .cmfun file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Mod {
+    #[serde(default, rename = "type")]
+    pub mod_type: ModType,
     #[serde(default)]
     pub desc: ModDesc,
     #[serde(default)]
@@ -18,17 +32,6 @@ pub struct Mod {
     pub res: ModRes,
 }
 
-impl Default for Mod {
-    fn default() -> Self {
-        Mod {
-            desc: ModDesc::default(),
-            var: ModVar::default(),
-            calc: ModCalc::default(),
-            res: ModRes::default(),
-        }
-    }
-}
-
 /// New flat mod structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlatMod {
@@ -74,61 +77,126 @@ pub struct SimplifiedMod {
     pub mod_type: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModDesc {
-    pub name: Option<String>,
-}
+impl SimplifiedMod {
+    /// Validates the section and converts it to the legacy `Mod` structure
+    ///
+    /// `type` decides which fields are required; when it is missing it is inferred from whether a
+    /// `method` or a `res` is present.
+    pub fn into_mod(self) -> Result<Mod, String> {
+        let mod_type = match self.mod_type.as_deref() {
+            Some("fun") => ModType::Fun,
+            Some("num") => ModType::Num,
+            Some(other) => return Err(format!("unknown type \"{}\" (expected \"fun\" or \"num\")", other)),
+            None if self.method.is_some() => ModType::Fun,
+            None if self.res.is_some() => ModType::Num,
+            None => return Err("neither a method nor a res value is defined".to_string()),
+        };
+
+        match mod_type {
+            ModType::Fun => {
+                if self.method.as_deref().is_none_or(|m| m.trim().is_empty()) {
+                    return Err("function mods require a method".to_string());
+                }
+            }
+            ModType::Num => match self.res {
+                Some(value) if value.is_finite() => {}
+                Some(value) => return Err(format!("constant value {} is not a finite number", value)),
+                None => return Err("constant mods require a res value".to_string()),
+            },
+        }
 
-impl Default for ModDesc {
-    fn default() -> Self {
-        ModDesc { name: None }
+        Ok(Mod {
+            mod_type,
+            desc: ModDesc { name: self.name },
+            var: ModVar {
+                needvars: match mod_type {
+                    ModType::Fun => self.needs.unwrap_or_default(),
+                    ModType::Num => Vec::new(),
+                },
+            },
+            calc: ModCalc {
+                howto: match mod_type {
+                    ModType::Fun => self.method,
+                    ModType::Num => None,
+                },
+            },
+            res: ModRes {
+                res: match mod_type {
+                    ModType::Fun => None,
+                    ModType::Num => self.res,
+                },
+            },
+        })
+    }
+
+    /// Serialises the mod as a `[mod_id]` section in the `.cmfun` format
+    pub fn to_toml_section(&self, mod_id: &str) -> String {
+        let mut toml_content = format!("[{}]\n", mod_id);
+        if let Some(name) = &self.name {
+            toml_content.push_str(&format!("name = \"{}\"\n", name));
+        }
+        if let Some(mod_type) = &self.mod_type {
+            toml_content.push_str(&format!("type = \"{}\"\n", mod_type));
+        }
+        if let Some(needs) = &self.needs {
+            let needs: Vec<String> = needs.iter().map(|need| format!("\"{}\"", need)).collect();
+            toml_content.push_str(&format!("needs = [{}]\n", needs.join(", ")));
+        }
+        if let Some(method) = &self.method {
+            toml_content.push_str(&format!("method = \"{}\"\n", method));
+        }
+        if let Some(res) = self.res {
+            toml_content.push_str(&format!("res = {}\n", res));
+        }
+        toml_content.push('\n');
+        toml_content
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModVar {
-    pub needvars: Vec<String>,
+/// Writes a single mod to `mods/<filename>`, adding the `.cmfun` extension if missing
+///
+/// Returns the file name that was written.
+pub fn save_mod_file(filename: &str, mod_id: &str, simplified_mod: &SimplifiedMod) -> Result<String, anyhow::Error> {
+    let filename = if filename.ends_with(".cmfun") {
+        filename.to_string()
+    } else {
+        format!("{}.cmfun", filename)
+    };
+
+    // Create mods directory if it doesn't exist
+    let mods_dir = Path::new("mods");
+    fs::create_dir_all(mods_dir).map_err(|e| anyhow::anyhow!("Failed to create mods directory: {}", e))?;
+
+    fs::write(mods_dir.join(&filename), simplified_mod.to_toml_section(mod_id))
+        .map_err(|e| anyhow::anyhow!("Failed to save mod: {}", e))?;
+    Ok(filename)
 }
 
-impl Default for ModVar {
-    fn default() -> Self {
-        ModVar { needvars: Vec::new() }
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModDesc {
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModCalc {
-    pub howto: Option<String>,
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModVar {
+    pub needvars: Vec<String>,
 }
 
-impl Default for ModCalc {
-    fn default() -> Self {
-        ModCalc { howto: None }
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModCalc {
+    pub howto: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModRes {
     pub res: Option<f64>,
 }
 
-impl Default for ModRes {
-    fn default() -> Self {
-        ModRes { res: None }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModVars {
     pub needs: Option<std::collections::HashMap<String, String>>,
 }
 
-impl Default for ModVars {
-    fn default() -> Self {
-        ModVars { needs: None }
-    }
-}
-
 /// Mod manager that loads and stores mods
 #[derive(Debug, Clone)]
 pub struct ModManager {
@@ -234,22 +302,15 @@ impl ModManager {
                                         let mod_name = mod_id.clone();
 
                                         // Convert to legacy Mod structure for compatibility
-                                        let legacy_mod = Mod {
-                                            desc: ModDesc {
-                                                name: simplified_mod.name.clone(),
-                                            },
-                                            var: ModVar {
-                                                needvars: simplified_mod.needs.unwrap_or_else(Vec::new),
-                                            },
-                                            calc: ModCalc {
-                                                howto: simplified_mod.method,
-                                            },
-                                            res: ModRes {
-                                                res: simplified_mod.res,
-                                            },
-                                        };
-
-                                        self.mods.insert(mod_name, legacy_mod);
+                                        match simplified_mod.into_mod() {
+                                            Ok(legacy_mod) => {
+                                                self.mods.insert(mod_name, legacy_mod);
+                                            }
+                                            Err(reason) => self.warnings.push(format!(
+                                                "Warning: Skipping mod '{}' in file {:?}: {}",
+                                                mod_name, path, reason
+                                            )),
+                                        }
                                     } else {
                                         eprintln!(
                                             "Warning: Failed to parse mod section in file {:?}: Invalid format",
@@ -319,6 +380,11 @@ impl ModManager {
     pub fn clear_warnings(&mut self) {
         self.warnings.clear();
     }
+
+    /// Record a warning found while validating loaded mods
+    pub fn push_warning(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
 }
 
 #[cfg(test)]
@@ -343,4 +409,39 @@ howto = "a + b"
         assert_eq!(mod_def.var.needvars, vec!["a", "b"]);
         assert_eq!(mod_def.calc.howto, Some("a + b".to_string()));
     }
+
+    #[test]
+    fn test_simplified_mod_type_validation() {
+        let parse = |content: &str| toml::from_str::<SimplifiedMod>(content).unwrap().into_mod();
+
+        let constant = parse("name = \"test_num\"\ntype = \"num\"\nres = 1.5").unwrap();
+        assert_eq!(constant.mod_type, ModType::Num);
+        assert_eq!(constant.res.res, Some(1.5));
+        assert_eq!(constant.calc.howto, None);
+
+        let function = parse("needs = [\"x\"]\nmethod = \"x + 1\"").unwrap();
+        assert_eq!(function.mod_type, ModType::Fun);
+
+        assert!(parse("type = \"num\"\nmethod = \"1 + 1\"").is_err());
+        assert!(parse("type = \"fun\"\nres = 2.0").is_err());
+        assert!(parse("type = \"str\"\nres = 2.0").is_err());
+    }
+
+    #[test]
+    fn test_simplified_mod_round_trip() {
+        let simplified = SimplifiedMod {
+            name: Some("f".to_string()),
+            needs: Some(vec!["x".to_string(), "y".to_string()]),
+            method: Some("x^2 + y".to_string()),
+            res: None,
+            mod_type: Some("fun".to_string()),
+        };
+        let section = simplified.to_toml_section("user.fn.f");
+        assert!(section.starts_with("[user.fn.f]\n"));
+
+        let body = section.lines().skip(1).collect::<Vec<_>>().join("\n");
+        let parsed = toml::from_str::<SimplifiedMod>(&body).unwrap().into_mod().unwrap();
+        assert_eq!(parsed.var.needvars, ["x", "y"]);
+        assert_eq!(parsed.calc.howto.as_deref(), Some("x^2 + y"));
+    }
 }
@@ -0,0 +1,458 @@
+//! Initial value problems for systems of first-order ordinary differential equations
+//!
+//! `ode(dy/dt = -k*y, y0 = 1, t = 0..10, 0.5)` integrates each `dy/dt` equation from its initial
+//! value over the time range and returns a table as a matrix: a row per time, holding `t` and then
+//! each variable in the order of the equations. Right-hand sides are ordinary expressions, so they
+//! may use variables, user functions and mods. Initial values are written `y0 = 1` or `y = 1`.
+//!
+//! With a step, the classic fourth-order Runge–Kutta method takes equal steps, shortening the last
+//! one to end on the range. Without one, the Dormand–Prince 5(4) pair adapts the step to keep the
+//! estimated error within tolerance, and every accepted step is a row.
+
+use std::cell::RefCell;
+
+use super::ast::{BinaryOp, Expr, ExprKind, Span};
+use super::calculus::{invalid_argument, no_convergence, real_argument};
+use super::context::{Arity, Scope};
+use super::error::{CalcError, CalcResult};
+use super::value::Value;
+
+/// Most steps a solution may take
+pub const MAX_STEPS: usize = 100_000;
+
+/// Error per step the adaptive method accepts, relative to the size of each variable
+const RELATIVE_TOLERANCE: f64 = 1e-8;
+
+/// Error per step the adaptive method accepts for variables near zero
+const ABSOLUTE_TOLERANCE: f64 = 1e-10;
+
+/// An ODE function callable from expressions
+pub struct OdeFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result from the unevaluated arguments and the span of the call
+    pub eval: fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value>,
+}
+
+/// All ODE functions, registered by the evaluator
+pub const FUNCTIONS: &[OdeFn] = &[OdeFn {
+    name: "ode",
+    params: "dy/dt = f, y0 = a, t = from..to, step",
+    arity: Arity::AtLeast(2),
+    eval: ode,
+}];
+
+/// `ode(dy/dt = f, ..., y0 = a, ..., t = from..to, step)`
+fn ode(scope: &dyn Scope, args: &[Expr], span: Span) -> CalcResult<Value> {
+    let problem = Problem::read(args, span)?;
+    let t0 = real_argument(scope, "ode", problem.start, "the start time")?;
+    let t1 = real_argument(scope, "ode", problem.end, "the end time")?;
+    let y0 = problem
+        .initial
+        .iter()
+        .map(|value| real_argument(scope, "ode", value, "an initial value"))
+        .collect::<CalcResult<Vec<f64>>>()?;
+
+    let system = System {
+        scope,
+        time: problem.time,
+        names: &problem.names,
+        rates: &problem.rates,
+        error: RefCell::new(None),
+    };
+    let mut rates = |t: f64, y: &[f64]| system.rates(t, y);
+    let rows =
+        match problem.step {
+            Some(step) => {
+                let h = real_argument(scope, "ode", step, "the step")?;
+                if h <= 0.0 {
+                    return Err(invalid_argument("ode", "the step must be positive", step.span));
+                }
+                if (t1 - t0).abs() / h > MAX_STEPS as f64 {
+                    return Err(invalid_argument(
+                        "ode",
+                        &format!("at most {} steps are allowed", MAX_STEPS),
+                        step.span,
+                    ));
+                }
+                runge_kutta(&mut rates, t0, &y0, t1, h)
+            }
+            None => dormand_prince(&mut rates, t0, &y0, t1).map_err(|t| {
+                system.error.borrow_mut().take().unwrap_or_else(|| {
+                    no_convergence("ode", format!("the step size became too small at t = {}", t), span)
+                })
+            })?,
+        };
+
+    if let Some((t, _)) = rows.last().filter(|(_, y)| y.iter().any(|x| !x.is_finite())) {
+        return Err(system
+            .error
+            .into_inner()
+            .unwrap_or_else(|| invalid_argument("ode", &format!("the solution is not finite at t = {}", t), span)));
+    }
+    Ok(Value::Matrix(
+        rows.into_iter()
+            .map(|(t, y)| std::iter::once(t).chain(y).map(Value::Real).collect())
+            .collect(),
+    ))
+}
+
+/// The arguments of `ode`, sorted out but not evaluated
+struct Problem<'a> {
+    /// Variables in the order of their equations
+    names: Vec<String>,
+    rates: Vec<&'a Expr>,
+    initial: Vec<&'a Expr>,
+    time: &'a str,
+    start: &'a Expr,
+    end: &'a Expr,
+    step: Option<&'a Expr>,
+}
+
+impl<'a> Problem<'a> {
+    fn read(args: &'a [Expr], span: Span) -> CalcResult<Self> {
+        let mut equations: Vec<(String, &str, &Expr)> = Vec::new();
+        let mut values: Vec<(&str, &Expr, Span)> = Vec::new();
+        let mut range = None;
+        let mut step = None;
+
+        for arg in args {
+            let ExprKind::Equation { lhs, rhs } = &arg.kind else {
+                if step.replace(arg).is_some() {
+                    return Err(
+                        invalid_argument("ode", "only the step may be given without a name", arg.span)
+                            .with_suggestion("e.g. ode(dy/dt = -y, y0 = 1, t = 0..10, 0.1)"),
+                    );
+                }
+                continue;
+            };
+            match (&lhs.kind, &rhs.kind) {
+                (ExprKind::Ident(time), ExprKind::Range { start, end }) if range.is_none() => {
+                    range = Some((time.as_str(), &**start, &**end));
+                }
+                (
+                    ExprKind::Binary {
+                        op: BinaryOp::Div,
+                        lhs: dy,
+                        rhs: dt,
+                    },
+                    _,
+                ) => match (differential(dy), differential(dt)) {
+                    (Some(name), Some(time)) => equations.push((name.to_string(), time, rhs)),
+                    _ => {
+                        return Err(invalid_argument(
+                            "ode",
+                            &format!("'{}' is not a derivative such as dy/dt", lhs),
+                            lhs.span,
+                        ))
+                    }
+                },
+                (ExprKind::Ident(name), _) => values.push((name.as_str(), &**rhs, lhs.span)),
+                _ => {
+                    return Err(invalid_argument(
+                        "ode",
+                        &format!("'{}' is neither a derivative such as dy/dt nor a variable", lhs),
+                        lhs.span,
+                    ))
+                }
+            }
+        }
+
+        let Some((time, start, end)) = range else {
+            return Err(invalid_argument("ode", "the time range is missing", span).with_suggestion("e.g. t = 0..10"));
+        };
+        if equations.is_empty() {
+            return Err(invalid_argument("ode", "there is no equation", span)
+                .with_suggestion("e.g. ode(dy/dt = -y, y0 = 1, t = 0..10)"));
+        }
+
+        let mut problem = Problem {
+            names: Vec::new(),
+            rates: Vec::new(),
+            initial: Vec::new(),
+            time,
+            start,
+            end,
+            step,
+        };
+        for (name, by, rate) in equations {
+            if by != time {
+                return Err(invalid_argument(
+                    "ode",
+                    &format!("d{}/d{} is not a derivative by the time {}", name, by, time),
+                    rate.span,
+                ));
+            }
+            if problem.names.contains(&name) {
+                return Err(invalid_argument(
+                    "ode",
+                    &format!("{} has more than one equation", name),
+                    rate.span,
+                ));
+            }
+            let start_name = format!("{}0", name);
+            let Some(&(_, value, _)) = values
+                .iter()
+                .find(|(given, _, _)| *given == name || *given == start_name)
+            else {
+                return Err(
+                    invalid_argument("ode", &format!("the initial value of {} is missing", name), span)
+                        .with_suggestion(format!("e.g. {} = 1", start_name)),
+                );
+            };
+            problem.names.push(name);
+            problem.rates.push(rate);
+            problem.initial.push(value);
+        }
+        if let Some((given, _, span)) = values.iter().find(|(given, _, _)| {
+            !problem
+                .names
+                .iter()
+                .any(|name| *given == name.as_str() || given.strip_suffix('0') == Some(name.as_str()))
+        }) {
+            return Err(invalid_argument(
+                "ode",
+                &format!("{} is not the initial value of a variable with an equation", given),
+                *span,
+            ));
+        }
+        Ok(problem)
+    }
+}
+
+/// `y` for the differential `dy`
+fn differential(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Ident(name) if !name.contains('.') => name.strip_prefix('d').filter(|name| !name.is_empty()),
+        _ => None,
+    }
+}
+
+/// Right-hand sides of the equations as a function of the time and the variables
+struct System<'a> {
+    scope: &'a dyn Scope,
+    time: &'a str,
+    names: &'a [String],
+    rates: &'a [&'a Expr],
+
+    /// First evaluation error, reported if the solution stops being finite
+    error: RefCell<Option<CalcError>>,
+}
+
+impl System<'_> {
+    /// Rates of change at time `t`, NaN where a right-hand side is undefined or not real
+    fn rates(&self, t: f64, y: &[f64]) -> Vec<f64> {
+        let bindings: Vec<(String, Value)> = std::iter::once((self.time.to_string(), Value::Real(t)))
+            .chain(self.names.iter().cloned().zip(y.iter().map(|&x| Value::Real(x))))
+            .collect();
+        self.rates
+            .iter()
+            .map(|rate| match self.scope.eval_with(rate, &bindings) {
+                Ok(value) => value.as_real().unwrap_or(f64::NAN),
+                Err(error) => {
+                    self.error.borrow_mut().get_or_insert(error);
+                    f64::NAN
+                }
+            })
+            .collect()
+    }
+}
+
+/// Solution of `y' = f(t, y)` from `y(t0) = y0` to `t1` in equal steps of the classic fourth-order
+/// Runge–Kutta method, the last shortened to end on `t1`
+///
+/// Stops after the first state that is not finite.
+pub fn runge_kutta(
+    f: &mut impl FnMut(f64, &[f64]) -> Vec<f64>,
+    t0: f64,
+    y0: &[f64],
+    t1: f64,
+    step: f64,
+) -> Vec<(f64, Vec<f64>)> {
+    // The tolerance keeps rounding, as in 1 / 0.1, from adding a tiny last step
+    let steps = ((t1 - t0).abs() / step - 1e-9).ceil().max(0.0) as usize;
+    let h = step.copysign(t1 - t0);
+    let mut rows = vec![(t0, y0.to_vec())];
+    let (mut t, mut y) = (t0, y0.to_vec());
+    for i in 1..=steps {
+        let next = if i == steps { t1 } else { t0 + i as f64 * h };
+        let h = next - t;
+        let k1 = f(t, &y);
+        let k2 = f(t + h / 2.0, &advance(&y, h / 2.0, &[(1.0, &k1)]));
+        let k3 = f(t + h / 2.0, &advance(&y, h / 2.0, &[(1.0, &k2)]));
+        let k4 = f(t + h, &advance(&y, h, &[(1.0, &k3)]));
+        y = advance(&y, h / 6.0, &[(1.0, &k1), (2.0, &k2), (2.0, &k3), (1.0, &k4)]);
+        t = next;
+        rows.push((t, y.clone()));
+        if y.iter().any(|x| !x.is_finite()) {
+            break;
+        }
+    }
+    rows
+}
+
+/// Solution of `y' = f(t, y)` from `y(t0) = y0` to `t1` by the Dormand–Prince 5(4) pair with
+/// adaptive steps, a row per accepted step
+///
+/// Fails with the time reached if the step size underflows or `MAX_STEPS` is exceeded, e.g. near
+/// a singularity or where `f` is not finite.
+pub fn dormand_prince(
+    f: &mut impl FnMut(f64, &[f64]) -> Vec<f64>,
+    t0: f64,
+    y0: &[f64],
+    t1: f64,
+) -> Result<Vec<(f64, Vec<f64>)>, f64> {
+    const C: [f64; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+    const A2: [f64; 1] = [1.0 / 5.0];
+    const A3: [f64; 2] = [3.0 / 40.0, 9.0 / 40.0];
+    const A4: [f64; 3] = [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0];
+    const A5: [f64; 4] = [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0];
+    const A6: [f64; 5] = [
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ];
+    // Weights of the fifth-order solution; k2 has none
+    const B: [f64; 5] = [
+        35.0 / 384.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ];
+    // Fifth- minus fourth-order weights for k1 and k3 to k7
+    const E: [f64; 6] = [
+        71.0 / 57600.0,
+        -71.0 / 16695.0,
+        71.0 / 1920.0,
+        -17253.0 / 339200.0,
+        22.0 / 525.0,
+        -1.0 / 40.0,
+    ];
+
+    let direction = (t1 - t0).signum();
+    let mut rows = vec![(t0, y0.to_vec())];
+    let (mut t, mut y) = (t0, y0.to_vec());
+    let mut k1 = f(t, &y);
+    if k1.iter().any(|k| !k.is_finite()) {
+        return Err(t);
+    }
+    let mut h = (t1 - t0) / 100.0;
+
+    while (t1 - t) * direction > 0.0 {
+        if rows.len() > MAX_STEPS || h.abs() < 1e-12 * t.abs().max(1.0) {
+            return Err(t);
+        }
+        let last = (t + h - t1) * direction >= 0.0;
+        if last {
+            h = t1 - t;
+        }
+
+        let k2 = f(t + C[0] * h, &advance(&y, h, &[(A2[0], &k1)]));
+        let k3 = f(t + C[1] * h, &advance(&y, h, &[(A3[0], &k1), (A3[1], &k2)]));
+        let k4 = f(
+            t + C[2] * h,
+            &advance(&y, h, &[(A4[0], &k1), (A4[1], &k2), (A4[2], &k3)]),
+        );
+        let k5 = f(
+            t + C[3] * h,
+            &advance(&y, h, &[(A5[0], &k1), (A5[1], &k2), (A5[2], &k3), (A5[3], &k4)]),
+        );
+        let k6 = f(
+            t + C[4] * h,
+            &advance(
+                &y,
+                h,
+                &[(A6[0], &k1), (A6[1], &k2), (A6[2], &k3), (A6[3], &k4), (A6[4], &k5)],
+            ),
+        );
+        let next = advance(
+            &y,
+            h,
+            &[(B[0], &k1), (B[1], &k3), (B[2], &k4), (B[3], &k5), (B[4], &k6)],
+        );
+        let k7 = f(t + C[5] * h, &next);
+        let error = advance(
+            &vec![0.0; y.len()],
+            h,
+            &[
+                (E[0], &k1),
+                (E[1], &k3),
+                (E[2], &k4),
+                (E[3], &k5),
+                (E[4], &k6),
+                (E[5], &k7),
+            ],
+        );
+
+        // Root mean square of the errors relative to the tolerance, at most 1 to accept the step
+        let norm = (error
+            .iter()
+            .zip(y.iter().zip(&next))
+            .map(|(e, (a, b))| (e / (ABSOLUTE_TOLERANCE + RELATIVE_TOLERANCE * a.abs().max(b.abs()))).powi(2))
+            .sum::<f64>()
+            / y.len() as f64)
+            .sqrt();
+        if norm <= 1.0 {
+            t = if last { t1 } else { t + h };
+            y = next;
+            k1 = k7;
+            rows.push((t, y.clone()));
+        }
+        let factor = if norm.is_nan() {
+            0.2
+        } else {
+            (0.9 * norm.powf(-0.2)).clamp(0.2, 5.0)
+        };
+        h *= factor;
+    }
+    Ok(rows)
+}
+
+/// `y + h * sum(weight * k)`
+fn advance(y: &[f64], h: f64, terms: &[(f64, &Vec<f64>)]) -> Vec<f64> {
+    (0..y.len())
+        .map(|i| y[i] + h * terms.iter().map(|(weight, k)| weight * k[i]).sum::<f64>())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runge_kutta() {
+        let rows = runge_kutta(&mut |_, y| vec![-y[0]], 0.0, &[1.0], 1.0, 0.1);
+        assert_eq!(rows.len(), 11);
+        let (t, y) = rows.last().unwrap();
+        assert_eq!(*t, 1.0);
+        assert!((y[0] - (-1f64).exp()).abs() < 1e-6);
+
+        // The last step is shortened to end on the range, also backwards
+        let rows = runge_kutta(&mut |t, _| vec![2.0 * t], 1.0, &[1.0], -0.5, 0.4);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows.last().unwrap().0, -0.5);
+        assert!((rows.last().unwrap().1[0] - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_dormand_prince() {
+        // A harmonic oscillator returns to its start after a full period
+        let period = 2.0 * std::f64::consts::PI;
+        let rows = dormand_prince(&mut |_, y| vec![y[1], -y[0]], 0.0, &[1.0, 0.0], period).unwrap();
+        let (t, y) = rows.last().unwrap();
+        assert_eq!(*t, period);
+        assert!((y[0] - 1.0).abs() < 1e-7 && y[1].abs() < 1e-7);
+        assert!(rows.len() < 200);
+
+        // y' = y^2 from 1 blows up at t = 1
+        assert!(dormand_prince(&mut |_, y| vec![y[0] * y[0]], 0.0, &[1.0], 2.0).is_err());
+    }
+}
@@ -0,0 +1,759 @@
+//! Recursive descent parser producing an expression AST
+//!
+//! Grammar, from lowest to highest precedence:
+//!
+//! ```text
+//! program  := sep* (stmt (sep+ stmt)*)? sep*        sep := ';' | line break
+//! stmt     := ident '=' expr | ident '(' params? ')' '=' expr | expr
+//! expr     := bitor (('to' | 'in') unit)*
+//! bitor    := bitxor ('|' bitxor)*
+//! bitxor   := bitand ('xor' bitand)*
+//! bitand   := shift ('&' shift)*
+//! shift    := additive (('<<' | '>>') additive)*
+//! additive := term (('+' | '-') term)*
+//! term     := quantity (('*' | '/' | '%') quantity)*
+//! quantity := unary unit?
+//! unary    := ('-' | '+' | '~') unary | power
+//! power    := postfix ('^' unary)?
+//! postfix  := primary '!'*
+//! primary  := number | number 'i' | number angle | text | ident | ident '(' args? ')' | '(' expr ')' | list
+//! args     := arg (',' arg)*                             arg := range ('=' range)?
+//! range    := expr ('..' expr)?
+//! list     := '[' (expr (',' expr)*)? ']'                text := '"' any character but '"' '"'
+//! angle    := '°' | 'deg' | 'rad' | 'grad'
+//! unit     := factor (('*' | '/') factor)*                factor := ident ('^' '-'? number)?
+//! ```
+//!
+//! `^` is right associative and binds tighter than unary minus, so `-2^2` is `-4`. The bitwise
+//! operators bind looser than arithmetic, as in Python, so `1 << 2 + 1` is `8`.
+//!
+//! A unit directly after a value makes a quantity, so `9.81 m/s^2 * 80 kg` multiplies two
+//! quantities. Inside a unit, `*` and `/` only continue the unit when a name follows them.
+//!
+//! Like `i`, an angle suffix must directly follow its number: `30°` is an angle in degrees
+//! whatever the angle mode, while `30 deg` is a number followed by an unknown unit.
+//!
+//! An argument may be an equation, as in `solve(cos(x) = x, x)`, or a range, as in
+//! `ode(dy/dt = -y, y0 = 1, t = 0..10)`; evaluating either anywhere else is an error.
+//!
+//! `d/dx(expr)` is short for `diff(expr, x)`, for any variable name after the `d`. It is only read
+//! this way when written exactly so, a `d` divided by a call of a name starting with `d`.
+
+use std::str::FromStr;
+
+use super::ast::{BinaryOp, Expr, ExprKind, PostfixOp, Span, Stmt, UnaryOp, UnitExpr};
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::lexer::{tokenize, Token, TokenKind};
+
+/// Parses a single expression into an AST; line breaks are treated as whitespace
+pub fn parse(input: &str) -> CalcResult<Expr> {
+    let mut tokens = tokenize(input)?;
+    tokens.retain(|token| token.kind != TokenKind::Newline);
+    let mut parser = Parser {
+        source: input,
+        tokens,
+        pos: 0,
+    };
+    let expr = parser.parse_expr()?;
+    parser.expect_eof()?;
+    Ok(expr)
+}
+
+/// Parses one or more statements separated by `;` or line breaks
+pub fn parse_program(input: &str) -> CalcResult<Vec<Stmt>> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        source: input,
+        tokens,
+        pos: 0,
+    };
+    let mut statements = Vec::new();
+
+    loop {
+        while parser.eat(&TokenKind::Semicolon) || parser.eat(&TokenKind::Newline) {}
+        if parser.peek().kind == TokenKind::Eof {
+            break;
+        }
+        statements.push(parser.parse_statement()?);
+        match parser.peek().kind {
+            TokenKind::Semicolon | TokenKind::Newline | TokenKind::Eof => {}
+            _ => parser.expect_eof()?,
+        }
+    }
+
+    if statements.is_empty() {
+        return Err(parser.unexpected());
+    }
+    Ok(statements)
+}
+
+/// Parses a unit expression such as `kg*m/s^2`
+pub fn parse_unit(input: &str) -> CalcResult<UnitExpr> {
+    let mut parser = Parser {
+        source: input,
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let unit = parser.parse_unit()?;
+    parser.expect_eof()?;
+    Ok(unit)
+}
+
+impl FromStr for Expr {
+    type Err = CalcError;
+
+    fn from_str(s: &str) -> CalcResult<Self> {
+        parse(s)
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    /// The token `offset` places after the current one
+    fn peek_nth(&self, offset: usize) -> &Token {
+        &self.tokens[(self.pos + offset).min(self.tokens.len() - 1)]
+    }
+
+    /// Whether the current token is the identifier `word`
+    fn at_word(&self, word: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(name) if name == word)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if &self.peek().kind == kind {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self) -> CalcError {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::RParen => CalcError::new(ErrorKind::UnbalancedParen("unexpected ')'".to_string()), token.span)
+                .with_suggestion("remove this ')' or add a matching '(' before it"),
+            TokenKind::RBracket => CalcError::new(ErrorKind::UnbalancedParen("unexpected ']'".to_string()), token.span)
+                .with_suggestion("remove this ']' or add a matching '[' before it"),
+            TokenKind::Eof => CalcError::new(ErrorKind::UnexpectedToken(token.kind.describe()), token.span)
+                .with_suggestion("the expression is incomplete"),
+            _ => CalcError::new(ErrorKind::UnexpectedToken(token.kind.describe()), token.span),
+        }
+    }
+
+    fn expect_eof(&self) -> CalcResult<()> {
+        match self.peek().kind {
+            TokenKind::Eof => Ok(()),
+            TokenKind::RParen | TokenKind::RBracket => Err(self.unexpected()),
+            _ => Err(self
+                .unexpected()
+                .with_suggestion("an operator may be missing before this")),
+        }
+    }
+
+    /// Consumes the `)` or `]` closing the bracket opened at `open`
+    fn expect_close(&mut self, open: Span, kind: TokenKind) -> CalcResult<Span> {
+        let close = self.peek().clone();
+        if close.kind != kind {
+            let (opening, closing) = if kind == TokenKind::RBracket {
+                ('[', ']')
+            } else {
+                ('(', ')')
+            };
+            return Err(CalcError::new(
+                ErrorKind::UnbalancedParen(format!("'{}' is never closed", opening)),
+                open,
+            )
+            .with_suggestion(format!("add '{}' before {}", closing, close.kind.describe())));
+        }
+        self.advance();
+        Ok(close.span)
+    }
+
+    fn parse_statement(&mut self) -> CalcResult<Stmt> {
+        let target = self.parse_expr()?;
+        if !self.eat(&TokenKind::Equals) {
+            return Ok(Stmt::Expr(target));
+        }
+        let value = self.parse_expr()?;
+        match target.kind {
+            ExprKind::Ident(name) if !name.contains('.') => Ok(Stmt::Assign {
+                name,
+                name_span: target.span,
+                value,
+            }),
+            ExprKind::Ident(name) => Err(CalcError::new(ErrorKind::InvalidAssignment(name), target.span)
+                .with_suggestion("dotted names are reserved for mods; pick a plain variable name")),
+            ExprKind::Call { name, args } => {
+                if name.contains('.') {
+                    return Err(CalcError::new(ErrorKind::InvalidDefinition(name), target.span)
+                        .with_suggestion("dotted names are reserved for mods; pick a plain function name"));
+                }
+                let mut params: Vec<String> = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg.kind {
+                        ExprKind::Ident(param) if !param.contains('.') && !params.contains(&param) => {
+                            params.push(param)
+                        }
+                        ExprKind::Ident(param) if params.contains(&param) => {
+                            return Err(CalcError::new(ErrorKind::InvalidDefinition(name), arg.span)
+                                .with_suggestion(format!("parameter '{}' is listed twice", param)));
+                        }
+                        _ => {
+                            return Err(CalcError::new(ErrorKind::InvalidDefinition(name), arg.span)
+                                .with_suggestion("parameters must be plain names, e.g. f(x, y) = x^2 + y"));
+                        }
+                    }
+                }
+                Ok(Stmt::Function {
+                    name,
+                    name_span: target.span,
+                    params,
+                    body_source: self.source[value.span.start..value.span.end].to_string(),
+                    body: value,
+                })
+            }
+            _ => Err(
+                CalcError::new(ErrorKind::InvalidAssignment(target.to_string()), target.span)
+                    .with_suggestion("only variables and functions can be assigned, e.g. x = 3 or f(x) = x^2"),
+            ),
+        }
+    }
+
+    fn parse_expr(&mut self) -> CalcResult<Expr> {
+        let mut expr = self.parse_bitor()?;
+        while self.at_word("to") || self.at_word("in") {
+            self.advance();
+            let unit = self.parse_unit()?;
+            let span = expr.span.to(unit.span);
+            expr = Expr::new(
+                ExprKind::Convert {
+                    expr: Box::new(expr),
+                    unit,
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_bitor(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_bitxor()?;
+        while self.eat(&TokenKind::Pipe) {
+            let rhs = self.parse_bitxor()?;
+            lhs = binary(BinaryOp::BitOr, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_bitxor(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_bitand()?;
+        // `xor` is a word rather than `^`, which already means power
+        while self.at_word("xor") {
+            self.advance();
+            let rhs = self.parse_bitand()?;
+            lhs = binary(BinaryOp::BitXor, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_bitand(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_shift()?;
+        while self.eat(&TokenKind::Amp) {
+            let rhs = self.parse_shift()?;
+            lhs = binary(BinaryOp::BitAnd, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_shift(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_additive()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Shl => BinaryOp::Shl,
+                TokenKind::Shr => BinaryOp::Shr,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.parse_additive()?;
+            lhs = binary(op, lhs, rhs);
+        }
+    }
+
+    fn parse_additive(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Plus => BinaryOp::Add,
+                TokenKind::Minus => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.parse_term()?;
+            lhs = binary(op, lhs, rhs);
+        }
+    }
+
+    fn parse_term(&mut self) -> CalcResult<Expr> {
+        let mut lhs = self.parse_quantity()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Star => BinaryOp::Mul,
+                TokenKind::Slash => BinaryOp::Div,
+                TokenKind::Percent => BinaryOp::Rem,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.parse_quantity()?;
+            lhs = binary(op, lhs, rhs);
+        }
+    }
+
+    fn parse_quantity(&mut self) -> CalcResult<Expr> {
+        let expr = self.parse_unary()?;
+        let is_unit =
+            matches!(&self.peek().kind, TokenKind::Ident(name) if !matches!(name.as_str(), "to" | "in" | "xor"));
+        if !is_unit {
+            return Ok(expr);
+        }
+        let unit = self.parse_unit()?;
+        let span = expr.span.to(unit.span);
+        Ok(Expr::new(
+            ExprKind::Quantity {
+                expr: Box::new(expr),
+                unit,
+            },
+            span,
+        ))
+    }
+
+    fn parse_unit(&mut self) -> CalcResult<UnitExpr> {
+        let mut factors = Vec::new();
+        let mut sign = 1;
+        let mut span = self.peek().span;
+        loop {
+            let token = self.peek().clone();
+            let TokenKind::Ident(name) = token.kind else {
+                return Err(self
+                    .unexpected()
+                    .with_suggestion("expected a unit such as m, km/h or kg*m/s^2"));
+            };
+            self.advance();
+            span = span.to(token.span);
+            let mut power = 1;
+            if self.peek().kind == TokenKind::Caret {
+                self.advance();
+                let negative = self.eat(&TokenKind::Minus);
+                let token = self.peek().clone();
+                power = match token.kind {
+                    TokenKind::Number(n) if n.fract() == 0.0 && n.abs() <= 64.0 => n as i32,
+                    _ => {
+                        return Err(self
+                            .unexpected()
+                            .with_suggestion("unit powers must be whole numbers, e.g. m^2 or s^-1"))
+                    }
+                };
+                self.advance();
+                span = span.to(token.span);
+                if negative {
+                    power = -power;
+                }
+            }
+            factors.push((name, sign * power));
+
+            // `*` and `/` continue the unit only when another unit name follows, so `2 m * 3` works
+            sign = match (&self.peek().kind, &self.peek_nth(1).kind) {
+                (TokenKind::Star, TokenKind::Ident(_)) => 1,
+                (TokenKind::Slash, TokenKind::Ident(_)) => -1,
+                _ => return Ok(UnitExpr { factors, span }),
+            };
+            self.advance();
+        }
+    }
+
+    fn parse_unary(&mut self) -> CalcResult<Expr> {
+        let op = match self.peek().kind {
+            TokenKind::Minus => UnaryOp::Neg,
+            TokenKind::Plus => UnaryOp::Plus,
+            TokenKind::Tilde => UnaryOp::BitNot,
+            _ => return self.parse_power(),
+        };
+        let start = self.advance().span;
+        let expr = self.parse_unary()?;
+        let span = start.to(expr.span);
+        Ok(Expr::new(
+            ExprKind::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            span,
+        ))
+    }
+
+    fn parse_power(&mut self) -> CalcResult<Expr> {
+        let base = self.parse_postfix()?;
+        if self.eat(&TokenKind::Caret) {
+            let exponent = self.parse_unary()?;
+            return Ok(binary(BinaryOp::Pow, base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn parse_postfix(&mut self) -> CalcResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        while self.peek().kind == TokenKind::Bang {
+            let end = self.advance().span;
+            let span = expr.span.to(end);
+            expr = Expr::new(
+                ExprKind::Postfix {
+                    op: PostfixOp::Factorial,
+                    expr: Box::new(expr),
+                },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> CalcResult<Expr> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(value) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Number(value), token.span))
+            }
+            TokenKind::Integer(value) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Integer(value), token.span))
+            }
+            TokenKind::Imaginary(value) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Imaginary(value), token.span))
+            }
+            TokenKind::Angle(value, unit) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Angle(value, unit), token.span))
+            }
+            TokenKind::Text(text) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Text(text), token.span))
+            }
+            TokenKind::Ident(name) => {
+                self.advance();
+                if let Some(variable) = self.derivative_operator(&name) {
+                    return self.parse_derivative(variable, token.span);
+                }
+                if self.peek().kind == TokenKind::LParen {
+                    self.parse_call(name, token.span)
+                } else {
+                    Ok(Expr::new(ExprKind::Ident(name), token.span))
+                }
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let mut inner = self.parse_expr()?;
+                let close = self.expect_close(token.span, TokenKind::RParen)?;
+                // Widen the span to include the parentheses
+                inner.span = token.span.to(close);
+                Ok(inner)
+            }
+            TokenKind::LBracket => self.parse_list(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_call(&mut self, name: String, name_span: Span) -> CalcResult<Expr> {
+        let open = self.advance().span;
+        let mut args = Vec::new();
+
+        if self.peek().kind != TokenKind::RParen {
+            loop {
+                let arg = self.parse_range()?;
+                if self.eat(&TokenKind::Equals) {
+                    let rhs = self.parse_range()?;
+                    let span = arg.span.to(rhs.span);
+                    args.push(Expr::new(
+                        ExprKind::Equation {
+                            lhs: Box::new(arg),
+                            rhs: Box::new(rhs),
+                        },
+                        span,
+                    ));
+                } else {
+                    args.push(arg);
+                }
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+
+        let close = self.expect_close(open, TokenKind::RParen)?;
+        Ok(Expr::new(ExprKind::Call { name, args }, name_span.to(close)))
+    }
+
+    fn parse_range(&mut self) -> CalcResult<Expr> {
+        let start = self.parse_expr()?;
+        if !self.eat(&TokenKind::DotDot) {
+            return Ok(start);
+        }
+        let end = self.parse_expr()?;
+        let span = start.span.to(end.span);
+        Ok(Expr::new(
+            ExprKind::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+            },
+            span,
+        ))
+    }
+
+    /// The variable of `d/dx(` after an identifier `name`, if that is what follows
+    fn derivative_operator(&self, name: &str) -> Option<String> {
+        if name != "d" || self.peek().kind != TokenKind::Slash || self.peek_nth(2).kind != TokenKind::LParen {
+            return None;
+        }
+        match &self.peek_nth(1).kind {
+            TokenKind::Ident(operator) if operator.len() > 1 && !operator.contains('.') => {
+                operator.strip_prefix('d').map(str::to_string)
+            }
+            _ => None,
+        }
+    }
+
+    /// `d/dx(expr)` as the call `diff(expr, x)`
+    fn parse_derivative(&mut self, variable: String, start: Span) -> CalcResult<Expr> {
+        self.advance();
+        let operator = self.advance().span;
+        let mut expr = self.parse_call("diff".to_string(), start)?;
+        if let ExprKind::Call { args, .. } = &mut expr.kind {
+            let span = Span::new(operator.start + 1, operator.end);
+            args.push(Expr::new(ExprKind::Ident(variable), span));
+        }
+        Ok(expr)
+    }
+
+    fn parse_list(&mut self) -> CalcResult<Expr> {
+        let open = self.advance().span;
+        let mut items = Vec::new();
+
+        if self.peek().kind != TokenKind::RBracket {
+            loop {
+                items.push(self.parse_expr()?);
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+
+        let close = self.expect_close(open, TokenKind::RBracket)?;
+        Ok(Expr::new(ExprKind::List(items), open.to(close)))
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span.to(rhs.span);
+    Expr::new(
+        ExprKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(input: &str) -> String {
+        parse(input).expect("Failed to parse").to_string()
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(shape("1 + 2 * 3"), "(1 + (2 * 3))");
+        assert_eq!(shape("-2^2"), "(-(2 ^ 2))");
+        assert_eq!(shape("2^3^2"), "(2 ^ (3 ^ 2))");
+        assert_eq!(shape("2^-1"), "(2 ^ (-1))");
+        assert_eq!(shape("3!^2"), "((3!) ^ 2)");
+        assert_eq!(shape("1 << 2 + 1"), "(1 << (2 + 1))");
+        assert_eq!(shape("a | b xor c & d"), "(a | (b xor (c & d)))");
+        assert_eq!(shape("~x & 0xF0 >> 4"), "((~x) & (240 >> 4))");
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(shape("5 km + 300 m"), "((5 km) + (300 m))");
+        assert_eq!(shape("9.81 m/s^2 * 80 kg"), "((9.81 m/s^2) * (80 kg))");
+        assert_eq!(shape("60 mph to m/s"), "((60 mph) to m/s)");
+        assert_eq!(shape("x kg*m/s^-2 in N"), "((x kg*m*s^2) to N)");
+        assert_eq!(shape("2 m * 3 / x"), "(((2 m) * 3) / x)");
+        assert_eq!(shape("-3 s^-1"), "((-3) s^-1)");
+        assert_eq!(parse_unit("kg*m/s^2").unwrap().to_string(), "kg*m/s^2");
+        assert!(parse("5 m^x").is_err());
+        assert!(parse("5 to").is_err());
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        assert_eq!(shape("0xFF + 0o17 + 0b1010"), "((255 + 15) + 10)");
+        assert_eq!(shape("0xFFFF_FFFF_FFFF_FFFF"), "18446744073709551615");
+        assert_eq!(shape("0x10 * x"), "(16 * x)");
+        assert!(matches!(parse("0b102").unwrap_err().kind, ErrorKind::InvalidNumber(_)));
+        assert!(parse("1 < 2").is_err());
+    }
+
+    #[test]
+    fn test_angle_literals() {
+        assert_eq!(shape("sin(30°) + 30deg"), "(sin(30°) + 30°)");
+        assert_eq!(shape("2rad * 50grad"), "(2rad * 50grad)");
+        assert_eq!(shape("20degC"), "(20 degC)");
+        assert_eq!(shape("-90°^2"), "(-(90° ^ 2))");
+        assert!(parse("°").is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(shape("[1, 2, 3.5]"), "[1, 2, 3.5]");
+        assert_eq!(shape("[]"), "[]");
+        assert_eq!(shape("2 * [x, -1] + sum([1])"), "((2 * [x, (-1)]) + sum([1]))");
+        assert_eq!(parse("[1,\n 2]").unwrap().span, Span::new(0, 7));
+        assert!(matches!(
+            parse("[1, 2").unwrap_err().kind,
+            ErrorKind::UnbalancedParen(_)
+        ));
+        assert!(parse("[1, 2)").is_err());
+        assert!(parse("1]").is_err());
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            shape(r#"import_csv("C:\data\a b.csv", 2)"#),
+            r#"import_csv("C:\data\a b.csv", 2)"#
+        );
+        assert_eq!(parse(r#""""#).unwrap().kind, ExprKind::Text(String::new()));
+        assert_eq!(parse(r#"f("x"#).unwrap_err().kind, ErrorKind::UnterminatedText);
+    }
+
+    #[test]
+    fn test_calls_and_idents() {
+        assert_eq!(shape("max(1, 2, 3)"), "max(1, 2, 3)");
+        assert_eq!(shape("random()"), "random()");
+        assert_eq!(shape("a.b.c(x, 2) + b.c.d"), "(a.b.c(x, 2) + b.c.d)");
+        assert_eq!(shape("2e3 + e"), "(2000 + e)");
+        assert_eq!(shape("3 + 4i - i"), "((3 + 4i) - i)");
+        assert_eq!(shape("2.5e1i * int(1)"), "(25i * int(1))");
+        assert_eq!(shape("solve(x^2 - 2 = 0, x)"), "solve(((x ^ 2) - 2) = 0, x)");
+        assert_eq!(
+            shape("ode(dy/dt = -k*y, y0 = 1, t = 0..2.5*4, 0.1)"),
+            "ode((dy / dt) = ((-k) * y), y0 = 1, t = 0..(2.5 * 4), 0.1)"
+        );
+        assert_eq!(shape("f(.5..1.)"), "f(0.5..1)");
+    }
+
+    #[test]
+    fn test_derivative_operator() {
+        assert_eq!(shape("d/dx(x^2)"), "diff((x ^ 2), x)");
+        assert_eq!(shape("2 * d/dt(sin(t))"), "(2 * diff(sin(t), t))");
+        assert_eq!(shape("d / dx (x)"), "diff(x, x)");
+
+        // Anything else is an ordinary division
+        assert_eq!(shape("d/dx"), "(d / dx)");
+        assert_eq!(shape("d/x(2)"), "(d / x(2))");
+        assert_eq!(shape("e/dx(2)"), "(e / dx(2))");
+    }
+
+    #[test]
+    fn test_spans() {
+        let expr = parse("1 + sqrt(4)").unwrap();
+        assert_eq!(expr.span, Span::new(0, 11));
+        if let ExprKind::Binary { rhs, .. } = expr.kind {
+            assert_eq!(rhs.span, Span::new(4, 11));
+        } else {
+            panic!("expected binary expression");
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        let program = parse_program("rate = 0.07; principal * (1 + rate)^n\n\n x").unwrap();
+        let shapes: Vec<String> = program.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(shapes, ["rate = 0.07", "(principal * ((1 + rate) ^ n))", "x"]);
+
+        // Line breaks inside parentheses do not end the statement
+        assert_eq!(parse_program("max(1,\n 2)").unwrap().len(), 1);
+
+        assert!(matches!(
+            parse_program("2 = 3").unwrap_err().kind,
+            ErrorKind::InvalidAssignment(_)
+        ));
+        assert!(parse_program(" ; ").is_err());
+
+        let program = parse_program("f(x, y) = x^2 + y; f(1, 2)").unwrap();
+        match &program[0] {
+            Stmt::Function {
+                name,
+                params,
+                body_source,
+                ..
+            } => {
+                assert_eq!(name, "f");
+                assert_eq!(params, &["x", "y"]);
+                assert_eq!(body_source, "x^2 + y");
+            }
+            other => panic!("expected a function definition, got {:?}", other),
+        }
+        assert_eq!(program[0].to_string(), "f(x, y) = ((x ^ 2) + y)");
+        assert_eq!(parse_program("zero() = 0").unwrap().len(), 1);
+
+        for input in ["f(x, x) = x", "f(2) = 1", "a.b(x) = x", "f(x = 1) = x"] {
+            assert!(matches!(
+                parse_program(input).unwrap_err().kind,
+                ErrorKind::InvalidDefinition(_)
+            ));
+        }
+        assert!(parse("x = 1").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse("2 * (1 + 2").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnbalancedParen(_)));
+        assert_eq!(err.span, Span::new(4, 5));
+
+        let err = parse("1 + 2)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnbalancedParen(_)));
+        assert_eq!(err.span, Span::new(5, 6));
+
+        let err = parse("1 +").unwrap_err();
+        assert_eq!(err.span, Span::new(3, 3));
+
+        let err = parse("1 $ 2").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnexpectedChar('$'));
+        assert_eq!(err.span, Span::new(2, 3));
+
+        assert!(parse("1 2").is_err());
+        assert!(parse("sqrt(1,").is_err());
+        assert!(parse("0..10").is_err());
+    }
+}
@@ -0,0 +1,407 @@
+//! Polynomials as lists of coefficients, highest power first
+//!
+//! `[1, -3, 2]` is `x^2 - 3*x + 2`, the order `fit_poly` returns, so fitted coefficients can be
+//! passed on directly. A single number is a constant polynomial. Exact and decimal coefficients give
+//! exact results, e.g. `polyint([3, 2])` is `[3/2, 2, 0]`; a single float makes the whole
+//! computation use floats.
+//!
+//! Roots are found numerically, all of them at once, complex ones included. Whole and rational
+//! roots of exact polynomials are exact, e.g. `polyroots([2, -3, 1])` is `[1/2, 1]`.
+
+use std::f64::consts::PI;
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use super::context::Arity;
+use super::matrix::Number;
+use super::value::Value;
+
+/// Evaluates `$body` with `$ps` bound to the exact or float coefficients of the polynomials `$args`
+macro_rules! with_polynomials {
+    ($args:expr, |$ps:ident| $body:expr) => {
+        match Polynomials::new($args)? {
+            Polynomials::Exact($ps) => $body,
+            Polynomials::Real($ps) => $body,
+        }
+    };
+}
+
+/// A polynomial function callable from expressions
+pub struct PolyFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result, or explains why the arguments are unsuitable
+    pub eval: fn(&[Value]) -> Result<Value, String>,
+}
+
+/// All polynomial functions, registered by the evaluator
+pub const FUNCTIONS: &[PolyFn] = &[
+    PolyFn {
+        name: "polyval",
+        params: "p, x",
+        arity: Arity::Exact(2),
+        eval: |args| evaluate(&args[0], &args[1]),
+    },
+    PolyFn {
+        name: "polyroots",
+        params: "p",
+        arity: Arity::Exact(1),
+        eval: |args| polyroots(&args[0]),
+    },
+    PolyFn {
+        name: "polymul",
+        params: "p, q",
+        arity: Arity::Exact(2),
+        eval: |args| Ok(with_polynomials!(args, |ps| coefficients(multiply(&ps[0], &ps[1])))),
+    },
+    PolyFn {
+        name: "polydiv",
+        params: "p, q",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            with_polynomials!(args, |ps| {
+                let (quotient, remainder) =
+                    divide(&ps[0], &ps[1]).ok_or_else(|| "division by the zero polynomial".to_string())?;
+                Ok(Value::List(vec![coefficients(quotient), coefficients(remainder)]))
+            })
+        },
+    },
+    PolyFn {
+        name: "polyder",
+        params: "p",
+        arity: Arity::Exact(1),
+        eval: |args| Ok(with_polynomials!(args, |ps| coefficients(derivative(&ps[0])))),
+    },
+    PolyFn {
+        name: "polyint",
+        params: "p, c",
+        arity: Arity::Between(1, 2),
+        eval: |args| {
+            Ok(with_polynomials!(args, |ps| {
+                let constant = ps.get(1).and_then(|c| c.first()).cloned().unwrap_or_else(Zero::zero);
+                coefficients(integral(&ps[0], constant))
+            }))
+        },
+    },
+];
+
+/// Coefficients of one or more polynomials, exact only if every coefficient is exact or a decimal
+enum Polynomials {
+    Exact(Vec<Vec<BigRational>>),
+    Real(Vec<Vec<f64>>),
+}
+
+impl Polynomials {
+    fn new(args: &[Value]) -> Result<Self, String> {
+        let lists = args.iter().map(coefficient_list).collect::<Result<Vec<_>, _>>()?;
+        let exact = lists
+            .iter()
+            .map(|list| list.iter().map(Value::to_exact).collect::<Option<Vec<_>>>())
+            .collect::<Option<Vec<_>>>();
+        if let Some(exact) = exact {
+            return Ok(Polynomials::Exact(exact.into_iter().map(trim).collect()));
+        }
+        lists
+            .iter()
+            .map(|list| {
+                list.iter()
+                    .map(|value| value.as_real().ok_or_else(|| format!("{} is not a real number", value)))
+                    .collect::<Result<Vec<f64>, _>>()
+                    .map(trim)
+            })
+            .collect::<Result<_, _>>()
+            .map(Polynomials::Real)
+    }
+}
+
+fn coefficient_list(value: &Value) -> Result<&[Value], String> {
+    match value {
+        Value::List(items) if items.is_empty() => Err("the list of coefficients is empty".to_string()),
+        Value::List(items) => Ok(items),
+        value if value.as_real().is_some() => Ok(std::slice::from_ref(value)),
+        value => Err(format!("{} is not a list of coefficients", value)),
+    }
+}
+
+/// Drops leading zero coefficients, keeping a single zero for the zero polynomial
+fn trim<T: Number>(mut p: Vec<T>) -> Vec<T> {
+    let leading = p.iter().take_while(|c| c.is_zero()).count().min(p.len() - 1);
+    p.drain(..leading);
+    p
+}
+
+fn coefficients<T: Number>(p: Vec<T>) -> Value {
+    Value::List(p.into_iter().map(Number::into_value).collect())
+}
+
+fn horner<T: Number>(p: &[T], x: &T) -> T {
+    p.iter().fold(T::zero(), |acc, c| acc * x.clone() + c.clone())
+}
+
+fn multiply<T: Number>(p: &[T], q: &[T]) -> Vec<T> {
+    let mut product = vec![T::zero(); p.len() + q.len() - 1];
+    for (i, a) in p.iter().enumerate() {
+        for (j, b) in q.iter().enumerate() {
+            product[i + j] = product[i + j].clone() + a.clone() * b.clone();
+        }
+    }
+    trim(product)
+}
+
+/// Quotient and remainder of long division, or `None` when dividing by the zero polynomial
+fn divide<T: Number>(p: &[T], q: &[T]) -> Option<(Vec<T>, Vec<T>)> {
+    if q[0].is_zero() {
+        return None;
+    }
+    if p.len() < q.len() {
+        return Some((vec![T::zero()], p.to_vec()));
+    }
+    let steps = p.len() - q.len() + 1;
+    let mut remainder = p.to_vec();
+    let mut quotient = Vec::with_capacity(steps);
+    for i in 0..steps {
+        let factor = remainder[i].clone() / q[0].clone();
+        for (j, c) in q.iter().enumerate() {
+            remainder[i + j] = remainder[i + j].clone() - factor.clone() * c.clone();
+        }
+        quotient.push(factor);
+    }
+    // The leading terms cancel by construction; floats may leave rounding residue there
+    let remainder = remainder.split_off(steps);
+    Some((
+        quotient,
+        trim(if remainder.is_empty() {
+            vec![T::zero()]
+        } else {
+            remainder
+        }),
+    ))
+}
+
+fn derivative<T: Number>(p: &[T]) -> Vec<T> {
+    let degree = p.len() - 1;
+    if degree == 0 {
+        return vec![T::zero()];
+    }
+    p[..degree]
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c.clone() * T::count(degree - i))
+        .collect()
+}
+
+fn integral<T: Number>(p: &[T], constant: T) -> Vec<T> {
+    let degree = p.len() - 1;
+    let mut integral: Vec<T> = p
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c.clone() / T::count(degree - i + 1))
+        .collect();
+    integral.push(constant);
+    trim(integral)
+}
+
+/// `polyval(p, x)`, at each element of `x` if it is a list
+///
+/// A complex `x` evaluates a real polynomial at a complex point, e.g. to check complex roots.
+fn evaluate(p: &Value, x: &Value) -> Result<Value, String> {
+    match x {
+        Value::List(items) => items
+            .iter()
+            .map(|x| evaluate(p, x))
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        Value::Complex(z) => {
+            let Polynomials::Real(ps) = Polynomials::new(&[p.clone(), Value::Real(0.0)])? else {
+                unreachable!("a float makes every coefficient a float")
+            };
+            let value = ps[0].iter().fold(Complex64::zero(), |acc, &c| acc * z + c);
+            Ok(Value::from_complex(value))
+        }
+        x if x.as_real().is_none() => Err(format!("{} is not a number", x)),
+        x => Ok(with_polynomials!(&[p.clone(), x.clone()], |ps| {
+            horner(&ps[0], &ps[1][0]).into_value()
+        })),
+    }
+}
+
+/// Every root, complex ones included, with repeated roots listed as often as they repeat
+///
+/// Real roots come first in ascending order, then complex roots by real part, each conjugate pair
+/// with the positive imaginary part first.
+fn polyroots(p: &Value) -> Result<Value, String> {
+    let polynomials = Polynomials::new(std::slice::from_ref(p))?;
+    let (reals, exact) = match polynomials {
+        Polynomials::Exact(mut ps) => {
+            let p = ps.remove(0);
+            (
+                p.iter().map(|c| c.to_f64().unwrap_or(f64::NAN)).collect::<Vec<f64>>(),
+                Some(p),
+            )
+        }
+        Polynomials::Real(mut ps) => (ps.remove(0), None),
+    };
+    if reals.len() == 1 && reals[0] == 0.0 {
+        return Err("every number is a root of the zero polynomial".to_string());
+    }
+    if reals.iter().any(|c| !c.is_finite()) {
+        return Err("the coefficients must be finite".to_string());
+    }
+
+    let mut values: Vec<(Complex64, Value)> = roots(&reals)
+        .into_iter()
+        .map(|z| {
+            let near_real = z.im.abs() <= 1e-7 * (1.0 + z.norm());
+            let value = match (near_real, &exact) {
+                (true, Some(p)) => rational_root(p, z.re).map_or(Value::Real(z.re), Value::from_rational),
+                (true, None) => Value::Real(z.re),
+                (false, _) => Value::Complex(z),
+            };
+            (if near_real { Complex64::new(z.re, 0.0) } else { z }, value)
+        })
+        .collect();
+    values.sort_by(|(a, _), (b, _)| {
+        (a.im != 0.0)
+            .cmp(&(b.im != 0.0))
+            .then(a.re.total_cmp(&b.re))
+            .then(b.im.total_cmp(&a.im))
+    });
+    Ok(Value::List(values.into_iter().map(|(_, value)| value).collect()))
+}
+
+/// The rational number near `x` that is an exact root of `p`, if there is one
+///
+/// By the rational root theorem, the denominator of a root in lowest terms divides the leading
+/// coefficient once the coefficients are scaled to whole numbers.
+fn rational_root(p: &[BigRational], x: f64) -> Option<BigRational> {
+    let scale = p.iter().fold(BigInt::one(), |scale, c| scale.lcm(c.denom()));
+    let leading = (&p[0] * BigRational::from_integer(scale)).to_integer().abs().to_f64()?;
+    if leading > 1e9 {
+        return None;
+    }
+    let numerator = (x * leading).round();
+    let candidate = BigRational::new(BigInt::from(numerator.to_i64()?), BigInt::from(leading as i64));
+    horner(p, &candidate).is_zero().then_some(candidate)
+}
+
+/// All complex roots of a polynomial, highest power first, by the Durand–Kerner method
+///
+/// The leading coefficient must not be zero.
+pub(super) fn roots(coefficients: &[f64]) -> Vec<Complex64> {
+    let monic: Vec<f64> = coefficients.iter().map(|c| c / coefficients[0]).collect();
+    let degree = monic.len() - 1;
+    let eval = |z: Complex64| monic.iter().fold(Complex64::zero(), |acc, &c| acc * z + c);
+    // Start on a circle enclosing every root, off the real axis so conjugate pairs can separate
+    let radius = 1.0 + monic[1..].iter().fold(0.0, |max: f64, c| max.max(c.abs()));
+    let mut zs: Vec<Complex64> = (0..degree)
+        .map(|k| Complex64::from_polar(radius, 2.0 * PI * k as f64 / degree as f64 + 0.4))
+        .collect();
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for k in 0..degree {
+            let denominator = zs
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != k)
+                .fold(Complex64::one(), |product, (_, &z)| product * (zs[k] - z));
+            let step = eval(zs[k]) / denominator;
+            if step.is_finite() {
+                zs[k] -= step;
+                change = change.max(step.norm() / (1.0 + zs[k].norm()));
+            }
+        }
+        if change < 1e-15 {
+            break;
+        }
+    }
+    zs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+        let function = FUNCTIONS.iter().find(|f| f.name == name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.eval)(args)
+    }
+
+    fn ints(xs: &[i64]) -> Value {
+        Value::List(xs.iter().map(|&x| Value::Integer(x.into())).collect())
+    }
+
+    fn show(result: Result<Value, String>) -> String {
+        result.unwrap().to_string()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(show(call("polymul", &[ints(&[1, -1]), ints(&[1, 1])])), "[1, 0, -1]");
+        assert_eq!(show(call("polymul", &[ints(&[1, 2]), ints(&[0])])), "[0]");
+        assert_eq!(
+            show(call("polydiv", &[ints(&[1, 0, -3, 5]), ints(&[1, -2])])),
+            "[[1, 2, 1], [7]]"
+        );
+        assert_eq!(
+            show(call("polydiv", &[ints(&[2, 1]), ints(&[3, 0, 1])])),
+            "[[0], [2, 1]]"
+        );
+        assert_eq!(show(call("polydiv", &[ints(&[1, 1]), ints(&[2])])), "[[1/2, 1/2], [0]]");
+        assert!(call("polydiv", &[ints(&[1, 1]), ints(&[0, 0])]).is_err());
+        assert_eq!(show(call("polyder", &[ints(&[1, -3, 2])])), "[2, -3]");
+        assert_eq!(show(call("polyder", &[ints(&[5])])), "[0]");
+        assert_eq!(show(call("polyint", &[ints(&[3, 2])])), "[3/2, 2, 0]");
+        assert_eq!(show(call("polyint", &[ints(&[1]), Value::Real(2.5)])), "[1, 2.5]");
+        assert_eq!(
+            show(call("polyval", &[ints(&[1, -3, 2]), ints(&[0, 1, 5])])),
+            "[2, 0, 12]"
+        );
+        assert_eq!(show(call("polyval", &[ints(&[2, 1]), Value::Real(0.25)])), "1.5");
+        assert!(call("polyval", &[ints(&[1]), Value::Text("x".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_roots() {
+        assert_eq!(show(call("polyroots", &[ints(&[1, -3, 2])])), "[1, 2]");
+        assert_eq!(show(call("polyroots", &[ints(&[2, -3, 1])])), "[1/2, 1]");
+        assert_eq!(show(call("polyroots", &[ints(&[1, -2, 1])])), "[1, 1]");
+        assert_eq!(show(call("polyroots", &[ints(&[4])])), "[]");
+        assert!(call("polyroots", &[ints(&[0, 0])]).is_err());
+
+        let Value::List(roots) = call("polyroots", &[ints(&[1, 2, 5])]).unwrap() else {
+            panic!("expected a list");
+        };
+        let z = |value: &Value| match value {
+            Value::Complex(z) => *z,
+            other => panic!("expected a complex root, got {}", other),
+        };
+        assert!((z(&roots[0]) - Complex64::new(-1.0, 2.0)).norm() < 1e-12);
+        assert!((z(&roots[1]) - Complex64::new(-1.0, -2.0)).norm() < 1e-12);
+
+        // x^3 - 2 has one real root and a conjugate pair
+        let Value::List(roots) = call(
+            "polyroots",
+            &[Value::List(vec![
+                Value::Real(1.0),
+                Value::Real(0.0),
+                Value::Real(0.0),
+                Value::Real(-2.0),
+            ])],
+        )
+        .unwrap() else {
+            panic!("expected a list");
+        };
+        assert!((roots[0].as_real().unwrap() - 2f64.cbrt()).abs() < 1e-12);
+        assert!(matches!(roots[1], Value::Complex(_)) && matches!(roots[2], Value::Complex(_)));
+    }
+}
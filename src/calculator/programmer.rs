@@ -0,0 +1,257 @@
+//! Programmer mode: fixed-width integers, bitwise operators and radix formatting
+//!
+//! Without a word size, bitwise operators work on integers of any size as if they were written in
+//! infinite two's complement, so `~5` is `-6`. With a word size, every integer result wraps around
+//! to that many bits, e.g. `255 + 1` is `0` for 8-bit unsigned and `127 + 1` is `-128` for 8-bit signed.
+
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+
+/// Word sizes offered by the programmer panel
+pub const WORD_SIZES: [u32; 4] = [8, 16, 32, 64];
+
+/// Shifts by more than this many bits are rejected when there is no word size
+pub const MAX_SHIFT: u64 = 1_000_000;
+
+/// Width and signedness of the integers programmer mode computes with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WordSize {
+    pub bits: u32,
+    pub signed: bool,
+}
+
+impl Default for WordSize {
+    fn default() -> Self {
+        Self { bits: 64, signed: true }
+    }
+}
+
+impl WordSize {
+    /// Creates a word size of `bits` bits, at least one
+    pub fn new(bits: u32, signed: bool) -> Self {
+        Self {
+            bits: bits.max(1),
+            signed,
+        }
+    }
+
+    /// Smallest representable value
+    pub fn min(&self) -> BigInt {
+        if self.signed {
+            -(BigInt::one() << (self.bits - 1))
+        } else {
+            BigInt::zero()
+        }
+    }
+
+    /// Largest representable value
+    pub fn max(&self) -> BigInt {
+        if self.signed {
+            (BigInt::one() << (self.bits - 1)) - 1
+        } else {
+            (BigInt::one() << self.bits) - 1
+        }
+    }
+
+    /// Wraps `n` around to the representable range, keeping its lowest `bits` bits
+    pub fn wrap(&self, n: &BigInt) -> BigInt {
+        let pattern = self.pattern(n);
+        if self.signed && pattern.bit(u64::from(self.bits) - 1) {
+            pattern - (BigInt::one() << self.bits)
+        } else {
+            pattern
+        }
+    }
+
+    /// The lowest `bits` bits of `n` as a non-negative number, i.e. its two's complement bit pattern
+    pub fn pattern(&self, n: &BigInt) -> BigInt {
+        n & self.mask()
+    }
+
+    fn mask(&self) -> BigInt {
+        (BigInt::one() << self.bits) - 1
+    }
+}
+
+impl std::fmt::Display for WordSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", if self.signed { "i" } else { "u" }, self.bits)
+    }
+}
+
+/// Number base of a formatted integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hexadecimal,
+    Octal,
+    Binary,
+}
+
+impl Radix {
+    /// All bases in the order the programmer panel shows them
+    pub const ALL: [Radix; 4] = [Radix::Decimal, Radix::Hexadecimal, Radix::Octal, Radix::Binary];
+
+    /// Numeric base
+    pub fn base(&self) -> u32 {
+        match self {
+            Radix::Decimal => 10,
+            Radix::Hexadecimal => 16,
+            Radix::Octal => 8,
+            Radix::Binary => 2,
+        }
+    }
+
+    /// Literal prefix, e.g. `0x`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Radix::Decimal => "",
+            Radix::Hexadecimal => "0x",
+            Radix::Octal => "0o",
+            Radix::Binary => "0b",
+        }
+    }
+
+    /// Short label shown next to the value
+    pub fn label(&self) -> &'static str {
+        match self {
+            Radix::Decimal => "DEC",
+            Radix::Hexadecimal => "HEX",
+            Radix::Octal => "OCT",
+            Radix::Binary => "BIN",
+        }
+    }
+
+    /// The base a literal prefix letter selects, e.g. `x` for hexadecimal
+    pub fn from_prefix(letter: u8) -> Option<Radix> {
+        match letter.to_ascii_lowercase() {
+            b'x' => Some(Radix::Hexadecimal),
+            b'o' => Some(Radix::Octal),
+            b'b' => Some(Radix::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Formats `n` in `radix` with its literal prefix
+///
+/// With a word size, negative numbers show their two's complement bit pattern in the non-decimal
+/// bases, e.g. `-1` is `0xFF` for 8 bits; otherwise they keep a minus sign, e.g. `-0xFF`.
+pub fn format_radix(n: &BigInt, radix: Radix, word_size: Option<WordSize>) -> String {
+    if radix == Radix::Decimal {
+        return n.to_string();
+    }
+    let n = match word_size {
+        Some(word_size) => word_size.pattern(n),
+        None => n.clone(),
+    };
+    let digits = n.abs().to_str_radix(radix.base()).to_uppercase();
+    let sign = if n.is_negative() { "-" } else { "" };
+    format!("{}{}{}", sign, radix.prefix(), digits)
+}
+
+/// Bitwise not, which wraps like every other result when there is a word size
+pub fn not(n: &BigInt) -> BigInt {
+    !n
+}
+
+/// `n << amount`, or `None` for negative or huge shifts
+///
+/// Shifting past the word size gives zero, which avoids building huge intermediate numbers.
+pub fn shl(n: &BigInt, amount: &BigInt, word_size: Option<WordSize>) -> Option<BigInt> {
+    let amount = shift_amount(amount, word_size)?;
+    match word_size {
+        Some(word_size) if amount >= u64::from(word_size.bits) => Some(BigInt::zero()),
+        _ => Some(n << amount),
+    }
+}
+
+/// Arithmetic `n >> amount`, rounding towards negative infinity, or `None` for negative shifts
+///
+/// Unsigned word sizes shift the bit pattern, so zeros move in from the left.
+pub fn shr(n: &BigInt, amount: &BigInt, word_size: Option<WordSize>) -> Option<BigInt> {
+    if amount.is_negative() {
+        return None;
+    }
+    // Shifting further than the number is wide always gives 0 or -1
+    let amount = amount.to_u64().unwrap_or(u64::MAX).min(n.bits() + 1);
+    match word_size {
+        Some(word_size) if !word_size.signed => Some(word_size.pattern(n) >> amount),
+        _ => Some(n >> amount),
+    }
+}
+
+/// Whether bit `index` of `n` is set, counting from the least significant bit
+pub fn bit(n: &BigInt, index: u32, word_size: Option<WordSize>) -> bool {
+    match word_size {
+        Some(word_size) => word_size.pattern(n).bit(u64::from(index)),
+        None => n.bit(u64::from(index)),
+    }
+}
+
+/// `n` with bit `index` flipped, wrapped to the word size
+pub fn toggle_bit(n: &BigInt, index: u32, word_size: Option<WordSize>) -> BigInt {
+    let toggled = n ^ (BigInt::one() << index);
+    match word_size {
+        Some(word_size) => word_size.wrap(&toggled),
+        None => toggled,
+    }
+}
+
+fn shift_amount(amount: &BigInt, word_size: Option<WordSize>) -> Option<u64> {
+    if amount.is_negative() {
+        return None;
+    }
+    match word_size {
+        Some(word_size) => Some(amount.to_u64().unwrap_or(u64::MAX).min(u64::from(word_size.bits))),
+        None => amount.to_u64().filter(|&amount| amount <= MAX_SHIFT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+
+    #[test]
+    fn test_wrap() {
+        let u8 = WordSize::new(8, false);
+        let i8 = WordSize::new(8, true);
+        assert_eq!(u8.wrap(&int(256)), int(0));
+        assert_eq!(u8.wrap(&int(-1)), int(255));
+        assert_eq!(i8.wrap(&int(128)), int(-128));
+        assert_eq!(i8.wrap(&int(-129)), int(127));
+        assert_eq!(i8.min(), int(-128));
+        assert_eq!(u8.max(), int(255));
+        assert_eq!(WordSize::default().max(), int(i64::MAX));
+    }
+
+    #[test]
+    fn test_format_radix() {
+        let i8 = Some(WordSize::new(8, true));
+        assert_eq!(format_radix(&int(255), Radix::Hexadecimal, None), "0xFF");
+        assert_eq!(format_radix(&int(-255), Radix::Hexadecimal, None), "-0xFF");
+        assert_eq!(format_radix(&int(-1), Radix::Hexadecimal, i8), "0xFF");
+        assert_eq!(format_radix(&int(-1), Radix::Decimal, i8), "-1");
+        assert_eq!(format_radix(&int(10), Radix::Binary, None), "0b1010");
+        assert_eq!(format_radix(&int(15), Radix::Octal, None), "0o17");
+    }
+
+    #[test]
+    fn test_shifts_and_bits() {
+        let u8 = Some(WordSize::new(8, false));
+        assert_eq!(shl(&int(1), &int(4), None), Some(int(16)));
+        assert_eq!(shl(&int(1), &int(100), u8), Some(int(0)));
+        assert_eq!(shl(&int(1), &int(-1), None), None);
+        assert_eq!(shr(&int(-16), &int(2), None), Some(int(-4)));
+        assert_eq!(shr(&int(-16), &int(2), u8), Some(int(60)));
+        assert_eq!(shr(&int(-1), &int(1000), None), Some(int(-1)));
+        assert!(bit(&int(-1), 7, u8));
+        assert_eq!(toggle_bit(&int(0), 7, Some(WordSize::new(8, true))), int(-128));
+        assert_eq!(not(&int(5)), int(-6));
+    }
+}
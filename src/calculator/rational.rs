@@ -0,0 +1,186 @@
+//! Exact fraction arithmetic
+//!
+//! Integer literals evaluate to exact integers of any size, which stay exact as fractions through
+//! `+ - * /`, integer powers and the functions here. Functions return `None` where the result is not a fraction, e.g.
+//! `sqrt(2)`; the interpreter then falls back to floats.
+
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use super::decimal;
+
+/// Largest float that is still an exact integer, 2^53
+pub const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+/// Powers whose numerator and denominator would exceed this many bits fall back to floats
+const MAX_EXACT_BITS: u64 = 332_200;
+
+/// Largest argument of the exact factorial, whose result has about 456,000 digits
+const MAX_EXACT_FACTORIAL: u64 = 100_000;
+
+/// The fraction equal to an integral float; other floats are not exact
+pub fn from_f64(x: f64) -> Option<BigRational> {
+    if x.fract() != 0.0 || x.abs() > MAX_EXACT_FLOAT {
+        return None;
+    }
+    Some(BigRational::from_integer(BigInt::from(x as i64)))
+}
+
+/// Nearest float
+pub fn to_f64(r: &BigRational) -> f64 {
+    r.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Decimal with `precision` significant digits
+pub fn to_decimal(r: &BigRational, precision: u64) -> BigDecimal {
+    let numer = BigDecimal::from(r.numer().clone());
+    if r.is_integer() {
+        return numer;
+    }
+    decimal::div(&numer, &BigDecimal::from(r.denom().clone()), precision).unwrap_or_default()
+}
+
+/// The fraction exactly equal to a decimal, e.g. 0.25 is 1/4
+pub fn from_decimal(d: &BigDecimal) -> BigRational {
+    let (digits, scale) = d.as_bigint_and_exponent();
+    let power = num_traits::Pow::pow(BigInt::from(10), scale.unsigned_abs());
+    if scale < 0 {
+        BigRational::from_integer(digits * power)
+    } else {
+        BigRational::new(digits, power)
+    }
+}
+
+/// Whether the fraction is an integer that fits in an `i64`
+pub fn to_i64(r: &BigRational) -> Option<i64> {
+    r.is_integer().then(|| r.numer().to_i64()).flatten()
+}
+
+/// `a ^ b` for integer exponents whose result is not too large
+pub fn pow(a: &BigRational, b: &BigRational) -> Option<BigRational> {
+    let n = i32::try_from(to_i64(b)?).ok()?;
+    if a.is_zero() && n < 0 {
+        return None;
+    }
+    let bits = (a.numer().bits() + a.denom().bits()).saturating_mul(u64::from(n.unsigned_abs()));
+    (bits <= MAX_EXACT_BITS).then(|| num_traits::Pow::pow(a, n))
+}
+
+/// Remainder with the sign of `a`, like `%` on floats
+pub fn rem(a: &BigRational, b: &BigRational) -> BigRational {
+    a - b * (a / b).trunc()
+}
+
+/// Square root if both numerator and denominator are perfect squares
+pub fn sqrt(r: &BigRational) -> Option<BigRational> {
+    if r.is_negative() {
+        return None;
+    }
+    let exact_root = |n: &BigInt| Some(n.sqrt()).filter(|root| &(root * root) == n);
+    Some(BigRational::new(exact_root(r.numer())?, exact_root(r.denom())?))
+}
+
+/// Greatest common divisor of integers, always non-negative
+pub fn gcd(args: &[BigRational]) -> Option<BigRational> {
+    integers(args)?
+        .into_iter()
+        .reduce(|a, b| a.gcd(&b))
+        .map(BigRational::from_integer)
+}
+
+/// Least common multiple of integers, always non-negative
+pub fn lcm(args: &[BigRational]) -> Option<BigRational> {
+    integers(args)?
+        .into_iter()
+        .reduce(|a, b| a.lcm(&b))
+        .map(BigRational::from_integer)
+}
+
+/// Largest argument
+pub fn max(args: &[BigRational]) -> Option<BigRational> {
+    args.iter().max().cloned()
+}
+
+/// Smallest argument
+pub fn min(args: &[BigRational]) -> Option<BigRational> {
+    args.iter().min().cloned()
+}
+
+/// Sum of the arguments
+pub fn sum(args: &[BigRational]) -> Option<BigRational> {
+    Some(args.iter().sum())
+}
+
+/// Arithmetic mean of the arguments
+pub fn avg(args: &[BigRational]) -> Option<BigRational> {
+    let count = BigRational::from_integer(BigInt::from(args.len()));
+    sum(args).map(|total| total / count)
+}
+
+/// -1, 0 or 1
+pub fn signum(r: &BigRational) -> BigRational {
+    if r.is_zero() {
+        BigRational::zero()
+    } else if r.is_negative() {
+        -BigRational::one()
+    } else {
+        BigRational::one()
+    }
+}
+
+/// `n!` for non-negative integers up to a size that can be computed exactly
+pub fn factorial(n: &BigRational) -> Option<BigRational> {
+    let n = to_u64(n).filter(|&n| n <= MAX_EXACT_FACTORIAL)?;
+    Some(BigRational::from_integer(product(1, n)))
+}
+
+/// Number of ways to choose `k` of `n` items, for non-negative integers
+pub fn binomial(n: &BigRational, k: &BigRational) -> Option<BigRational> {
+    let (n, k) = (to_u64(n)?, to_u64(k)?);
+    if k > n {
+        return Some(BigRational::zero());
+    }
+    // n! / (k! (n - k)!) = (n - k + 1) ... n / k!, using the smaller of k and n - k
+    let k = k.min(n - k);
+    if k > MAX_EXACT_FACTORIAL {
+        return None;
+    }
+    Some(BigRational::from_integer(product(n - k + 1, n) / product(1, k)))
+}
+
+/// `base ^ exponent mod modulus` for integers, with a non-negative exponent and non-zero modulus
+///
+/// The result lies between zero and the modulus.
+pub fn modpow(args: &[BigRational]) -> Option<BigRational> {
+    let [base, exponent, modulus] = integers(args)?.try_into().ok()?;
+    if exponent.is_negative() || modulus.is_zero() {
+        return None;
+    }
+    Some(BigRational::from_integer(base.modpow(&exponent, &modulus)))
+}
+
+/// Product of the integers from `low` to `high`, split in halves to keep the operands balanced
+fn product(low: u64, high: u64) -> BigInt {
+    match high.checked_sub(low) {
+        None => BigInt::one(),
+        Some(0) => BigInt::from(low),
+        Some(span) if span < 16 => (low..=high).map(BigInt::from).product(),
+        Some(span) => {
+            let middle = low + span / 2;
+            product(low, middle) * product(middle + 1, high)
+        }
+    }
+}
+
+fn to_u64(r: &BigRational) -> Option<u64> {
+    r.is_integer().then(|| r.numer().to_u64()).flatten()
+}
+
+fn integers(args: &[BigRational]) -> Option<Vec<BigInt>> {
+    args.iter()
+        .map(|arg| arg.is_integer().then(|| arg.numer().clone()))
+        .collect()
+}
@@ -0,0 +1,397 @@
+//! Least-squares regression and curve fitting
+//!
+//! `linreg(xs, ys)` gives the slope, intercept and r² of the best straight line. The `fit_`
+//! functions return the coefficients of a model, highest power first for polynomials; assigning
+//! one, as in `f = fit_poly(xs, ys, 2)`, defines the fitted curve `f(x)` instead. Exact data give
+//! exact linear and polynomial fits, e.g. `linreg([1, 2, 3], [1, 3, 4])` is `[3/2, -1/3, 27/28]`.
+
+use std::cmp::Ordering;
+
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use super::context::Arity;
+use super::matrix::{solve, Number};
+use super::value::Value;
+
+/// Body of a fitted curve in `x`, given the coefficients of the fit
+pub type Model = fn(&[Value]) -> String;
+
+/// A regression function callable from expressions
+pub struct RegressionFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result, or explains why the data is unsuitable
+    pub eval: fn(&[Value]) -> Result<Value, String>,
+
+    /// Curve of the coefficients `eval` returned, for fits that define a function when assigned
+    pub model: Option<Model>,
+}
+
+/// All regression functions, registered by the evaluator
+pub const FUNCTIONS: &[RegressionFn] = &[
+    RegressionFn {
+        name: "linreg",
+        params: "xs, ys",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            Ok(match Points::new(&args[0], &args[1])? {
+                Points::Exact(xs, ys) => linreg(&xs, &ys)?,
+                Points::Real(xs, ys) => linreg(&xs, &ys)?,
+            })
+        },
+        model: None,
+    },
+    RegressionFn {
+        name: "fit_linear",
+        params: "xs, ys",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            Ok(match Points::new(&args[0], &args[1])? {
+                Points::Exact(xs, ys) => coefficients(polynomial(&xs, &ys, 1)?),
+                Points::Real(xs, ys) => coefficients(polynomial(&xs, &ys, 1)?),
+            })
+        },
+        model: Some(polynomial_model),
+    },
+    RegressionFn {
+        name: "fit_poly",
+        params: "xs, ys, degree",
+        arity: Arity::Exact(3),
+        eval: |args| {
+            let degree = match args[2].to_integer().and_then(|n| n.to_usize()) {
+                Some(degree) => degree,
+                None => return Err(format!("the degree must be a non-negative integer, got {}", args[2])),
+            };
+            Ok(match Points::new(&args[0], &args[1])? {
+                Points::Exact(xs, ys) => coefficients(polynomial(&xs, &ys, degree)?),
+                Points::Real(xs, ys) => coefficients(polynomial(&xs, &ys, degree)?),
+            })
+        },
+        model: Some(polynomial_model),
+    },
+    RegressionFn {
+        name: "fit_exp",
+        params: "xs, ys",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            // ln(y) = ln(a) + b*x
+            let (xs, ys) = Points::new(&args[0], &args[1])?.into_reals();
+            let ys = logarithms(&ys, "y")?;
+            let line = polynomial(&xs, &ys, 1)?;
+            Ok(coefficients(vec![line[1].exp(), line[0]]))
+        },
+        model: Some(|c| format!("{}*exp({}*x)", c[0], c[1])),
+    },
+    RegressionFn {
+        name: "fit_log",
+        params: "xs, ys",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            // y = a + b*ln(x)
+            let (xs, ys) = Points::new(&args[0], &args[1])?.into_reals();
+            let xs = logarithms(&xs, "x")?;
+            let line = polynomial(&xs, &ys, 1)?;
+            Ok(coefficients(vec![line[1], line[0]]))
+        },
+        model: Some(|c| format!("{}{}*log(x)", c[0], signed(&c[1]))),
+    },
+    RegressionFn {
+        name: "fit_power",
+        params: "xs, ys",
+        arity: Arity::Exact(2),
+        eval: |args| {
+            // ln(y) = ln(a) + b*ln(x)
+            let (xs, ys) = Points::new(&args[0], &args[1])?.into_reals();
+            let (xs, ys) = (logarithms(&xs, "x")?, logarithms(&ys, "y")?);
+            let line = polynomial(&xs, &ys, 1)?;
+            Ok(coefficients(vec![line[1].exp(), line[0]]))
+        },
+        model: Some(|c| format!("{}*x^({})", c[0], c[1])),
+    },
+];
+
+/// The x and y values of the data points
+enum Points {
+    Exact(Vec<BigRational>, Vec<BigRational>),
+    Real(Vec<f64>, Vec<f64>),
+}
+
+impl Points {
+    fn new(xs: &Value, ys: &Value) -> Result<Self, String> {
+        let (Value::List(xs), Value::List(ys)) = (xs, ys) else {
+            return Err("the x and y values must be lists".to_string());
+        };
+        if xs.len() != ys.len() {
+            return Err(format!(
+                "the x and y lists have different lengths ({} and {})",
+                xs.len(),
+                ys.len()
+            ));
+        }
+        let exact = |values: &[Value]| values.iter().map(Value::to_rational).collect::<Option<Vec<_>>>();
+        if let (Some(xs), Some(ys)) = (exact(xs), exact(ys)) {
+            return Ok(Points::Exact(xs, ys));
+        }
+        let reals = |values: &[Value]| {
+            values
+                .iter()
+                .map(|value| value.as_real().ok_or_else(|| format!("{} is not a real number", value)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Points::Real(reals(xs)?, reals(ys)?))
+    }
+
+    fn into_reals(self) -> (Vec<f64>, Vec<f64>) {
+        match self {
+            Points::Exact(xs, ys) => {
+                let reals = |values: Vec<BigRational>| values.iter().map(super::rational::to_f64).collect();
+                (reals(xs), reals(ys))
+            }
+            Points::Real(xs, ys) => (xs, ys),
+        }
+    }
+}
+
+fn coefficients<T: Number>(values: Vec<T>) -> Value {
+    Value::List(values.into_iter().map(Number::into_value).collect())
+}
+
+/// Slope, intercept and coefficient of determination of the least-squares line
+fn linreg<T: Number>(xs: &[T], ys: &[T]) -> Result<Value, String> {
+    let line = polynomial(xs, ys, 1)?;
+    let r_squared = r_squared(xs, ys, &line);
+    let mut values = line;
+    values.push(r_squared);
+    Ok(coefficients(values))
+}
+
+/// Coefficients of the least-squares polynomial, highest power first
+///
+/// Solves the normal equations, which have a unique solution once there are more distinct x
+/// values than the degree.
+fn polynomial<T: Number>(xs: &[T], ys: &[T], degree: usize) -> Result<Vec<T>, String> {
+    let mut distinct = xs.to_vec();
+    distinct.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    distinct.dedup();
+    if distinct.len() <= degree {
+        return Err(format!(
+            "at least {} points with different x values are needed, got {}",
+            degree + 1,
+            distinct.len()
+        ));
+    }
+
+    // Powers of each x, highest first
+    let rows: Vec<Vec<T>> = xs
+        .iter()
+        .map(|x| {
+            let mut powers = vec![T::one()];
+            for _ in 0..degree {
+                powers.push(powers[powers.len() - 1].clone() * x.clone());
+            }
+            powers.reverse();
+            powers
+        })
+        .collect();
+    let size = degree + 1;
+    let mut matrix = vec![vec![T::zero(); size]; size];
+    let mut rhs = vec![T::zero(); size];
+    for (row, y) in rows.iter().zip(ys) {
+        for i in 0..size {
+            for j in 0..size {
+                matrix[i][j] = matrix[i][j].clone() + row[i].clone() * row[j].clone();
+            }
+            rhs[i] = rhs[i].clone() + row[i].clone() * y.clone();
+        }
+    }
+    solve(matrix, rhs).ok_or_else(|| "the points do not determine a fit".to_string())
+}
+
+/// Fraction of the variance in `ys` the polynomial explains; 1 when every y is the same
+fn r_squared<T: Number>(xs: &[T], ys: &[T], polynomial: &[T]) -> T {
+    let mean = ys.iter().cloned().fold(T::zero(), |sum, y| sum + y) / T::count(ys.len());
+    let square = |d: T| d.clone() * d;
+    let total = ys
+        .iter()
+        .fold(T::zero(), |sum, y| sum + square(y.clone() - mean.clone()));
+    if total.is_zero() {
+        return T::one();
+    }
+    let residual = xs.iter().zip(ys).fold(T::zero(), |sum, (x, y)| {
+        let predicted = polynomial.iter().fold(T::zero(), |acc, c| acc * x.clone() + c.clone());
+        sum + square(y.clone() - predicted)
+    });
+    T::one() - residual / total
+}
+
+/// Natural logarithms of values that must all be positive for the fit
+fn logarithms(values: &[f64], axis: &str) -> Result<Vec<f64>, String> {
+    values
+        .iter()
+        .map(|&v| {
+            if v > 0.0 {
+                Ok(v.ln())
+            } else {
+                Err(format!("the {} values must be positive, got {}", axis, v))
+            }
+        })
+        .collect()
+}
+
+/// A coefficient after another term, e.g. ` - 2` or ` + 1/3`
+fn signed(value: &Value) -> String {
+    let text = value.to_string();
+    match text.strip_prefix('-') {
+        Some(magnitude) => format!(" - {}", magnitude),
+        None => format!(" + {}", text),
+    }
+}
+
+/// `c0*x^n + … + cn`, leaving out terms with an exact zero coefficient and writing `x` for `1*x`
+fn polynomial_model(coefficients: &[Value]) -> String {
+    let degree = coefficients.len() - 1;
+    let mut body = String::new();
+    for (i, c) in coefficients.iter().enumerate() {
+        let power = degree - i;
+        let exact = c.to_rational();
+        if exact.as_ref().is_some_and(Zero::is_zero) && !(power == 0 && body.is_empty()) {
+            continue;
+        }
+        let text = c.to_string();
+        let (negative, magnitude) = match text.strip_prefix('-') {
+            Some(magnitude) => (true, magnitude),
+            None => (false, text.as_str()),
+        };
+        let unit = exact.is_some_and(|r| r.abs().is_one());
+        body.push_str(match (body.is_empty(), negative) {
+            (true, false) => "",
+            (true, true) => "-",
+            (false, false) => " + ",
+            (false, true) => " - ",
+        });
+        body.push_str(&match (power, unit) {
+            (0, _) => magnitude.to_string(),
+            (1, true) => "x".to_string(),
+            (1, false) => format!("{}*x", magnitude),
+            (power, true) => format!("x^{}", power),
+            (power, false) => format!("{}*x^{}", magnitude, power),
+        });
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+        let function = FUNCTIONS.iter().find(|f| f.name == name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.eval)(args)
+    }
+
+    fn list(xs: &[f64]) -> Value {
+        Value::List(
+            xs.iter()
+                .map(|&x| match x.fract() {
+                    0.0 => Value::Integer((x as i64).into()),
+                    _ => Value::Real(x),
+                })
+                .collect(),
+        )
+    }
+
+    fn reals(value: Value) -> Vec<f64> {
+        match value {
+            Value::List(items) => items.iter().map(|item| item.as_real().unwrap()).collect(),
+            value => panic!("not a list: {}", value),
+        }
+    }
+
+    fn assert_close(actual: Vec<f64>, expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < tolerance, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_linear() {
+        let (xs, ys) = (list(&[1.0, 2.0, 3.0, 4.0, 5.0]), list(&[2.0, 4.0, 5.0, 4.0, 5.0]));
+        assert_eq!(
+            call("linreg", &[xs.clone(), ys.clone()]).unwrap().to_string(),
+            "[3/5, 11/5, 3/5]"
+        );
+        assert_eq!(call("fit_linear", &[xs, ys]).unwrap().to_string(), "[3/5, 11/5]");
+
+        // Anscombe's first data set
+        let xs = list(&[10.0, 8.0, 13.0, 9.0, 11.0, 14.0, 6.0, 4.0, 12.0, 7.0, 5.0]);
+        let ys = list(&[8.04, 6.95, 7.58, 8.81, 8.33, 9.96, 7.24, 4.26, 10.84, 4.82, 5.68]);
+        assert_close(
+            reals(call("linreg", &[xs, ys]).unwrap()),
+            &[0.50009, 3.00009, 0.66654],
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn test_curves() {
+        let xs: [f64; 5] = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let ys: Vec<f64> = xs.iter().map(|x| x * x - 2.0 * x + 3.0).collect();
+        let fit = call("fit_poly", &[list(&xs), list(&ys), Value::Integer(2.into())]).unwrap();
+        assert_eq!(fit.to_string(), "[1, -2, 3]");
+        let Value::List(coefficients) = fit else { unreachable!() };
+        assert_eq!(polynomial_model(&coefficients), "x^2 - 2*x + 3");
+        assert_eq!(polynomial_model(&[Value::Integer(0.into())]), "0");
+        let negatives = [-1, 0, 0, -5].map(|c| Value::Integer(c.into()));
+        assert_eq!(polynomial_model(&negatives), "-x^3 - 5");
+
+        let xs: [f64; 4] = [1.0, 2.0, 3.0, 4.0];
+        let exp: Vec<f64> = xs.iter().map(|x| 2.0 * (0.5 * x).exp()).collect();
+        assert_close(
+            reals(call("fit_exp", &[list(&xs), list(&exp)]).unwrap()),
+            &[2.0, 0.5],
+            1e-9,
+        );
+        let log: Vec<f64> = xs.iter().map(|x| 1.0 - 3.0 * x.ln()).collect();
+        assert_close(
+            reals(call("fit_log", &[list(&xs), list(&log)]).unwrap()),
+            &[1.0, -3.0],
+            1e-9,
+        );
+        let power: Vec<f64> = xs.iter().map(|x| 3.0 * x.powf(-1.5)).collect();
+        assert_close(
+            reals(call("fit_power", &[list(&xs), list(&power)]).unwrap()),
+            &[3.0, -1.5],
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let xs = list(&[1.0, 1.0, 2.0]);
+        let ys = list(&[1.0, 2.0, 3.0]);
+        assert_eq!(
+            call("fit_poly", &[xs.clone(), ys.clone(), Value::Integer(2.into())]).unwrap_err(),
+            "at least 3 points with different x values are needed, got 2"
+        );
+        assert_eq!(
+            call("linreg", &[xs.clone(), list(&[1.0])]).unwrap_err(),
+            "the x and y lists have different lengths (3 and 1)"
+        );
+        assert_eq!(
+            call("fit_exp", &[xs, list(&[1.0, 0.0, 2.0])]).unwrap_err(),
+            "the y values must be positive, got 0"
+        );
+        assert_eq!(
+            call("fit_poly", &[list(&[1.0]), list(&[1.0]), Value::Real(0.5)]).unwrap_err(),
+            "the degree must be a non-negative integer, got 0.5"
+        );
+    }
+}
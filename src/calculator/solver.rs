@@ -0,0 +1,446 @@
+//! Numeric equation solving: `solve(x^2 = 2, x)`
+//!
+//! Without a starting guess, `solve` scans an interval for sign changes and refines each with
+//! Brent's method, returning every root it finds. With a guess, Newton's method looks for the
+//! root nearest to it, falling back to Brent's method on the first sign change around the guess.
+//! The variable is only bound while solving, so any function, including mods, can appear in the
+//! equation, e.g. `solve(a.b.c(x, 2) = 30, x)`.
+
+use std::cell::{Cell, RefCell};
+
+use num_bigint::BigInt;
+
+use super::ast::{Expr, ExprKind, Span};
+use super::context::{Arity, Scope};
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::value::Value;
+
+/// Interval searched when neither a guess nor an interval is given
+pub const SEARCH_INTERVAL: (f64, f64) = (-100.0, 100.0);
+
+/// Number of steps the search interval is divided into when looking for sign changes
+const SUBINTERVALS: usize = 2000;
+
+/// Iterations of Brent's or Newton's method before giving up
+const MAX_ITERATIONS: usize = 200;
+
+/// Largest distance from a guess searched for a sign change when Newton's method fails
+const MAX_REACH: f64 = 1e6;
+
+/// A solver function callable from expressions
+pub struct SolverFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result from the unevaluated arguments and the span of the call
+    pub eval: fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value>,
+}
+
+/// All solver functions, registered by the evaluator
+pub const FUNCTIONS: &[SolverFn] = &[SolverFn {
+    name: "solve",
+    params: "equation, x",
+    arity: Arity::Between(2, 4),
+    eval: solve,
+}];
+
+/// `solve(equation, x)` over the default interval, `solve(equation, x, guess)` near a guess, or
+/// `solve(equation, x, from, to)` over an interval
+///
+/// An expression without `=` is solved for zero. One root is returned as a number, several as a
+/// sorted list; roots at whole numbers are exact.
+fn solve(scope: &dyn Scope, args: &[Expr], span: Span) -> CalcResult<Value> {
+    let (lhs, rhs) = match &args[0].kind {
+        ExprKind::Equation { lhs, rhs } => (lhs.as_ref(), Some(rhs.as_ref())),
+        _ => (&args[0], None),
+    };
+    let name = match &args[1].kind {
+        ExprKind::Ident(name) if !name.contains('.') => name,
+        _ => {
+            return Err(
+                invalid_argument("the second argument must be the variable to solve for", &args[1])
+                    .with_suggestion("e.g. solve(x^2 = 2, x)"),
+            )
+        }
+    };
+    let residual = Residual {
+        scope,
+        name,
+        lhs,
+        rhs,
+        defined: Cell::new(false),
+        error: RefCell::new(None),
+    };
+    let mut f = |x: f64| residual.at_real(x);
+
+    let roots = match args {
+        [_, _, guess] => {
+            let guess = real_argument(scope, guess, "the starting guess")?;
+            let root = newton(&mut f, guess).or_else(|| search_near(&mut f, guess));
+            residual.check()?;
+            match root {
+                Some(root) => vec![root],
+                None => {
+                    return Err(no_convergence(format!("no root found near {}", guess), span)
+                        .with_suggestion("try another guess, or search an interval, e.g. solve(x^2 = 2, x, 0, 10)"))
+                }
+            }
+        }
+        _ => {
+            let (from, to) = match args {
+                [_, _, from, to] => (
+                    real_argument(scope, from, "the start of the interval")?,
+                    real_argument(scope, to, "the end of the interval")?,
+                ),
+                _ => SEARCH_INTERVAL,
+            };
+            if from >= to {
+                return Err(invalid_argument("the interval must start below its end", &args[2]));
+            }
+            let roots = roots_in(&mut f, from, to);
+            residual.check()?;
+            if roots.is_empty() {
+                return Err(no_convergence(format!("no root found in [{}, {}]", from, to), span)
+                    .with_suggestion("try a starting guess, e.g. solve(cos(x) = x, x, 0.5)"));
+            }
+            roots
+        }
+    };
+
+    let mut values: Vec<Value> = roots.into_iter().map(|root| residual.settle(root)).collect();
+    Ok(if values.len() == 1 {
+        values.remove(0)
+    } else {
+        Value::List(values)
+    })
+}
+
+/// Difference between the two sides of an equation as a function of the variable
+struct Residual<'a> {
+    scope: &'a dyn Scope,
+    name: &'a str,
+    lhs: &'a Expr,
+    rhs: Option<&'a Expr>,
+
+    /// Whether any evaluation had a real result
+    defined: Cell<bool>,
+
+    /// First evaluation error, reported if no evaluation succeeded
+    error: RefCell<Option<CalcError>>,
+}
+
+impl Residual<'_> {
+    /// The difference with the variable bound to `x`, or `None` if either side is not real
+    fn at(&self, x: Value) -> CalcResult<Option<f64>> {
+        let bindings = [(self.name.to_string(), x)];
+        let lhs = self.scope.eval_with(self.lhs, &bindings)?.as_real();
+        let rhs = match self.rhs {
+            Some(rhs) => self.scope.eval_with(rhs, &bindings)?.as_real(),
+            None => Some(0.0),
+        };
+        Ok(lhs.zip(rhs).map(|(lhs, rhs)| lhs - rhs))
+    }
+
+    /// The difference at a real `x`, NaN where the equation is undefined, e.g. `sqrt(x)` for
+    /// negative `x`
+    fn at_real(&self, x: f64) -> f64 {
+        match self.at(Value::Real(x)) {
+            Ok(Some(y)) if y.is_finite() => {
+                self.defined.set(true);
+                y
+            }
+            Ok(_) => f64::NAN,
+            Err(error) => {
+                self.error.borrow_mut().get_or_insert(error);
+                f64::NAN
+            }
+        }
+    }
+
+    /// Reports the first evaluation error if the equation was never defined, e.g. because it
+    /// uses an unknown variable
+    fn check(&self) -> CalcResult<()> {
+        match self.error.borrow_mut().take() {
+            Some(error) if !self.defined.get() => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// The root as a value, exact if a nearby whole number solves the equation exactly
+    fn settle(&self, root: f64) -> Value {
+        let whole = root.round();
+        if (root - whole).abs() <= 1e-6 && whole.abs() < 1e15 {
+            let whole = Value::Integer(BigInt::from(whole as i64));
+            if self.at(whole.clone()).ok().flatten() == Some(0.0) {
+                return whole;
+            }
+        }
+        Value::Real(root)
+    }
+}
+
+/// Every root of `f` in `[from, to]`, in ascending order
+///
+/// Sign changes between neighbouring samples are refined with Brent's method; roots where `f`
+/// only touches zero, such as that of `x^2`, are found with Newton's method from local minima
+/// of `|f|`. Sign changes across poles, e.g. of `tan(x)`, are discarded.
+pub fn roots_in(f: &mut impl FnMut(f64) -> f64, from: f64, to: f64) -> Vec<f64> {
+    let step = (to - from) / SUBINTERVALS as f64;
+    let xs: Vec<f64> = (0..=SUBINTERVALS).map(|i| from + step * i as f64).collect();
+    let ys: Vec<f64> = xs.iter().map(|&x| f(x)).collect();
+
+    let mut roots = Vec::new();
+    for i in 0..xs.len() {
+        if ys[i] == 0.0 {
+            roots.push(xs[i]);
+            continue;
+        }
+        let changes_sign = i + 1 < xs.len()
+            && ys[i].is_finite()
+            && ys[i + 1].is_finite()
+            && ys[i + 1] != 0.0
+            && ys[i].signum() != ys[i + 1].signum();
+        if changes_sign {
+            if let Some(root) = brent(f, xs[i], xs[i + 1], ys[i], ys[i + 1]) {
+                let scale = ys[i].abs().max(ys[i + 1].abs()).max(1.0);
+                if f(root).abs() <= 1e-6 * scale {
+                    roots.push(root);
+                }
+            }
+        } else if (1..xs.len() - 1).contains(&i)
+            && ys[i - 1].signum() == ys[i].signum()
+            && ys[i].signum() == ys[i + 1].signum()
+            && ys[i].abs() < ys[i - 1].abs()
+            && ys[i].abs() <= ys[i + 1].abs()
+        {
+            if let Some(root) = newton(f, xs[i]).filter(|root| (xs[i - 1]..=xs[i + 1]).contains(root)) {
+                roots.push(root);
+            }
+        }
+    }
+
+    roots.sort_by(f64::total_cmp);
+    roots.dedup_by(|a, b| (*a - *b).abs() <= 1e-9 * (1.0 + b.abs()));
+    roots
+}
+
+/// Brent's method on a bracket `[a, b]` whose ends `fa` and `fb` have opposite signs
+///
+/// Combines bisection with secant steps and inverse quadratic interpolation, following
+/// Numerical Recipes' `zbrent`. Returns `None` if `f` becomes undefined inside the bracket.
+pub fn brent(f: &mut impl FnMut(f64) -> f64, mut a: f64, mut b: f64, mut fa: f64, mut fb: f64) -> Option<f64> {
+    if fa.signum() == fb.signum() && fa != 0.0 {
+        return None;
+    }
+    let (mut c, mut fc) = (b, fb);
+    let mut d = b - a;
+    let mut e = d;
+
+    for _ in 0..MAX_ITERATIONS {
+        if (fb > 0.0 && fc > 0.0) || (fb < 0.0 && fc < 0.0) {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+        let tolerance = 2.0 * f64::EPSILON * b.abs() + 1e-300;
+        let middle = 0.5 * (c - b);
+        if middle.abs() <= tolerance || fb == 0.0 {
+            return Some(b);
+        }
+
+        if e.abs() >= tolerance && fa.abs() > fb.abs() {
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2.0 * middle * s, 1.0 - s)
+            } else {
+                let q = fa / fc;
+                let r = fb / fc;
+                (
+                    s * (2.0 * middle * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            if 2.0 * p < (3.0 * middle * q - (tolerance * q).abs()).min((e * q).abs()) {
+                // Interpolation is converging
+                e = d;
+                d = p / q;
+            } else {
+                d = middle;
+                e = d;
+            }
+        } else {
+            d = middle;
+            e = d;
+        }
+
+        a = b;
+        fa = fb;
+        b += if d.abs() > tolerance {
+            d
+        } else {
+            tolerance.copysign(middle)
+        };
+        fb = f(b);
+        if fb.is_nan() {
+            return None;
+        }
+    }
+    None
+}
+
+/// Newton's method from `guess`, with the derivative estimated by central differences
+///
+/// Returns `None` if an iterate is undefined, the derivative vanishes or the steps do not
+/// settle on a root within the iteration limit.
+pub fn newton(f: &mut impl FnMut(f64) -> f64, guess: f64) -> Option<f64> {
+    let scale = f(guess).abs().max(1.0);
+    let mut x = guess;
+    for _ in 0..MAX_ITERATIONS {
+        let y = f(x);
+        if y == 0.0 {
+            return Some(x);
+        }
+        let h = 1e-7 * (1.0 + x.abs());
+        let slope = (f(x + h) - f(x - h)) / (2.0 * h);
+        let step = y / slope;
+        if !step.is_finite() {
+            return None;
+        }
+        x -= step;
+        if step.abs() <= 1e-12 * (1.0 + x.abs()) {
+            // Tiny steps also happen across jumps, where the estimated slope is huge
+            return (f(x).abs() <= 1e-8 * scale).then_some(x);
+        }
+    }
+    None
+}
+
+/// Brent's method on the first sign change found by stepping away from `guess` in both
+/// directions with growing steps
+fn search_near(f: &mut impl FnMut(f64) -> f64, guess: f64) -> Option<f64> {
+    let y = f(guess);
+    let (mut left, mut fleft) = (guess, y);
+    let (mut right, mut fright) = (guess, y);
+    let mut reach = 0.01 * (1.0 + guess.abs());
+
+    while reach <= MAX_REACH * (1.0 + guess.abs()) {
+        for (end, fend, x) in [
+            (&mut left, &mut fleft, guess - reach),
+            (&mut right, &mut fright, guess + reach),
+        ] {
+            let fx = f(x);
+            if fx.is_finite() && fend.is_finite() && fx.signum() != fend.signum() {
+                let (a, b, fa, fb) = if x < *end {
+                    (x, *end, fx, *fend)
+                } else {
+                    (*end, x, *fend, fx)
+                };
+                if let Some(root) = brent(f, a, b, fa, fb) {
+                    let scale = fa.abs().max(fb.abs()).max(1.0);
+                    if f(root).abs() <= 1e-6 * scale {
+                        return Some(root);
+                    }
+                }
+            }
+            *end = x;
+            *fend = fx;
+        }
+        reach *= 1.6;
+    }
+    None
+}
+
+/// Evaluates an argument that must be a real number
+fn real_argument(scope: &dyn Scope, arg: &Expr, what: &str) -> CalcResult<f64> {
+    let value = scope.eval_with(arg, &[])?;
+    value
+        .as_real()
+        .filter(|x| x.is_finite())
+        .ok_or_else(|| invalid_argument(&format!("{} must be a real number, got {}", what, value), arg))
+}
+
+fn invalid_argument(message: &str, arg: &Expr) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: "solve".to_string(),
+            message: message.to_string(),
+        },
+        arg.span,
+    )
+}
+
+fn no_convergence(message: String, span: Span) -> CalcError {
+    CalcError::new(
+        ErrorKind::NoConvergence {
+            name: "solve".to_string(),
+            message,
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brent() {
+        let mut f = |x: f64| x * x - 2.0;
+        let root = brent(&mut f, 0.0, 2.0, -2.0, 2.0).unwrap();
+        assert!((root - std::f64::consts::SQRT_2).abs() < 1e-14);
+        assert_eq!(brent(&mut f, 2.0, 3.0, 2.0, 7.0), None);
+
+        let mut f = |x: f64| x.cos() - x;
+        let root = brent(&mut f, 0.0, 1.0, 1.0, 1f64.cos() - 1.0).unwrap();
+        assert!((root - 0.739_085_133_215_160_6).abs() < 1e-14);
+    }
+
+    #[test]
+    fn test_newton() {
+        let root = newton(&mut |x: f64| x.cos() - x, 0.5).unwrap();
+        assert!((root - 0.739_085_133_215_160_6).abs() < 1e-14);
+        // The derivative of x^2 + 1 vanishes at 0 and it has no real root anyway
+        assert_eq!(newton(&mut |x: f64| x * x + 1.0, 0.0), None);
+        assert_eq!(newton(&mut |x: f64| x.sqrt() + 1.0, 4.0), None);
+        assert_eq!(newton(&mut |x: f64| x.floor() - 0.5, 0.3), None);
+    }
+
+    #[test]
+    fn test_roots_in() {
+        let roots = roots_in(&mut |x: f64| x * x - 2.0, -100.0, 100.0);
+        assert_eq!(roots.len(), 2);
+        assert!((roots[0] + std::f64::consts::SQRT_2).abs() < 1e-12);
+        assert!((roots[1] - std::f64::consts::SQRT_2).abs() < 1e-12);
+
+        // Double roots touch zero without changing sign
+        let roots = roots_in(&mut |x: f64| (x - 1.5).powi(2), -10.0, 10.0);
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0] - 1.5).abs() < 1e-6);
+
+        // The poles of tan change its sign but are no roots
+        let roots = roots_in(&mut |x: f64| x.tan(), -4.0, 4.0);
+        assert_eq!(roots.len(), 3);
+        assert!((roots[0] + std::f64::consts::PI).abs() < 1e-12);
+        assert!(roots[1].abs() < 1e-12);
+
+        assert!(roots_in(&mut |x: f64| x * x + 1.0, -100.0, 100.0).is_empty());
+    }
+}
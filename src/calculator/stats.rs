@@ -0,0 +1,374 @@
+//! Descriptive statistics over lists
+//!
+//! Every function takes its data as lists, separate arguments or a mix of both, so `mean([1, 2], 3)`
+//! is the mean of three values. Exact data gives exact results, e.g. `mean([1, 2])` is `3/2`, and so
+//! do decimals; a single float makes the whole computation use floats.
+
+use std::iter::{Product, Sum};
+use std::ops::{Add, Div, Mul, Sub};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use super::context::Arity;
+use super::rational;
+use super::value::Value;
+
+/// Evaluates `$body` with `$xs` bound to the exact or float samples of `$data`
+macro_rules! with_samples {
+    ($data:expr, |$xs:ident| $body:expr) => {
+        match $data {
+            Data::Exact($xs) => $body,
+            Data::Real($xs) => $body,
+        }
+    };
+}
+
+/// A statistics function callable from expressions
+pub struct StatsFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result, or explains why the data is unsuitable
+    pub eval: fn(&[Value]) -> Result<Value, String>,
+}
+
+/// All statistics functions, registered by the evaluator
+///
+/// `sum`, `min`, `max` and `avg` are ordinary variadic functions, which accept lists as well.
+pub const FUNCTIONS: &[StatsFn] = &[
+    StatsFn {
+        name: "mean",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| Ok(with_samples!(Data::new(args)?, |xs| mean(&xs).into_value())),
+    },
+    StatsFn {
+        name: "median",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| Ok(with_samples!(Data::new(args)?.sorted(), |xs| median(&xs).into_value())),
+    },
+    StatsFn {
+        name: "mode",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| Ok(with_samples!(Data::new(args)?.sorted(), |xs| mode(&xs).into_value())),
+    },
+    StatsFn {
+        name: "var",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| Ok(with_samples!(Data::new(args)?.at_least_two()?, |xs| variance(&xs).into_value())),
+    },
+    StatsFn {
+        name: "stdev",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| {
+            Ok(match Data::new(args)?.at_least_two()? {
+                // Exact when the variance is a perfect square, e.g. stdev([2, 4, 4, 4, 5, 5, 7, 9])
+                Data::Exact(xs) => {
+                    let variance = variance(&xs);
+                    match rational::sqrt(&variance) {
+                        Some(root) => Value::from_rational(root),
+                        None => Value::Real(rational::to_f64(&variance).sqrt()),
+                    }
+                }
+                Data::Real(xs) => Value::Real(variance(&xs).sqrt()),
+            })
+        },
+    },
+    StatsFn {
+        name: "prod",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| Ok(with_samples!(Data::new(args)?, |xs| product(xs).into_value())),
+    },
+    StatsFn {
+        name: "sort",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| {
+            Ok(with_samples!(Data::new(args)?.sorted(), |xs| {
+                Value::List(xs.into_iter().map(Sample::into_value).collect())
+            }))
+        },
+    },
+    StatsFn {
+        name: "percentile",
+        params: "values, p",
+        arity: Arity::AtLeast(2),
+        eval: |args| {
+            let (p, values) = args.split_last().expect("arity is at least 2");
+            let data = Data::new(values)?.sorted();
+            let p = match p.as_real() {
+                Some(x) if (0.0..=100.0).contains(&x) => p,
+                _ => return Err(format!("p must be between 0 and 100, got {}", p)),
+            };
+            let hundred = BigRational::from_integer(BigInt::from(100));
+            Ok(match (data, p.to_exact()) {
+                (Data::Exact(xs), Some(p)) => percentile(&xs, p / hundred).into_value(),
+                (data, _) => {
+                    let fraction = p.as_real().unwrap_or(f64::NAN) / 100.0;
+                    percentile(&data.into_reals(), fraction).into_value()
+                }
+            })
+        },
+    },
+    StatsFn {
+        name: "quartiles",
+        params: "values",
+        arity: Arity::AtLeast(1),
+        eval: |args| {
+            Ok(with_samples!(Data::new(args)?.sorted(), |xs| {
+                let quarters = [(1, 4), (1, 2), (3, 4)];
+                Value::List(
+                    quarters
+                        .into_iter()
+                        .map(|(numer, denom)| percentile(&xs, Sample::ratio(numer, denom)).into_value())
+                        .collect(),
+                )
+            }))
+        },
+    },
+];
+
+/// Numbers the statistics are computed with: exact fractions or floats
+trait Sample:
+    Clone + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Sum + Product
+{
+    /// `numer / denom` as a sample
+    fn ratio(numer: usize, denom: usize) -> Self;
+
+    /// The whole and fractional parts of a non-negative number
+    fn split(&self) -> (usize, Self);
+
+    fn into_value(self) -> Value;
+}
+
+impl Sample for BigRational {
+    fn ratio(numer: usize, denom: usize) -> Self {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    fn split(&self) -> (usize, Self) {
+        (self.trunc().to_integer().to_usize().unwrap_or(usize::MAX), self.fract())
+    }
+
+    fn into_value(self) -> Value {
+        Value::from_rational(self)
+    }
+}
+
+impl Sample for f64 {
+    fn ratio(numer: usize, denom: usize) -> Self {
+        numer as f64 / denom as f64
+    }
+
+    fn split(&self) -> (usize, Self) {
+        (self.trunc() as usize, self.fract())
+    }
+
+    fn into_value(self) -> Value {
+        Value::Real(self)
+    }
+}
+
+/// The values of all arguments, with lists flattened
+enum Data {
+    Exact(Vec<BigRational>),
+    Real(Vec<f64>),
+}
+
+impl Data {
+    fn new(args: &[Value]) -> Result<Self, String> {
+        let mut values = Vec::new();
+        flatten(args, &mut values);
+        if values.is_empty() {
+            return Err("the list is empty".to_string());
+        }
+        // Decimals convert to fractions without loss, so decimal mode stays exact as well
+        if let Some(exact) = values.iter().map(Value::to_exact).collect::<Option<Vec<_>>>() {
+            return Ok(Data::Exact(exact));
+        }
+        values
+            .iter()
+            .map(|value| value.as_real().ok_or_else(|| format!("{} is not a real number", value)))
+            .collect::<Result<_, _>>()
+            .map(Data::Real)
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Data::Exact(xs) => xs.len(),
+            Data::Real(xs) => xs.len(),
+        }
+    }
+
+    /// Sorted in ascending order, NaN last
+    fn sorted(self) -> Self {
+        match self {
+            Data::Exact(mut xs) => {
+                xs.sort();
+                Data::Exact(xs)
+            }
+            Data::Real(mut xs) => {
+                xs.sort_by(f64::total_cmp);
+                Data::Real(xs)
+            }
+        }
+    }
+
+    /// The sample variance needs two values
+    fn at_least_two(self) -> Result<Self, String> {
+        if self.len() < 2 {
+            return Err("at least two values are needed".to_string());
+        }
+        Ok(self)
+    }
+
+    fn into_reals(self) -> Vec<f64> {
+        match self {
+            Data::Exact(xs) => xs.iter().map(rational::to_f64).collect(),
+            Data::Real(xs) => xs,
+        }
+    }
+}
+
+fn flatten(args: &[Value], values: &mut Vec<Value>) {
+    for arg in args {
+        match arg {
+            Value::List(items) => flatten(items, values),
+            Value::Matrix(rows) => rows.iter().for_each(|row| flatten(row, values)),
+            value => values.push(value.clone()),
+        }
+    }
+}
+
+fn mean<T: Sample>(xs: &[T]) -> T {
+    xs.iter().cloned().sum::<T>() / T::ratio(xs.len(), 1)
+}
+
+fn product<T: Sample>(xs: Vec<T>) -> T {
+    xs.into_iter().product()
+}
+
+fn median<T: Sample>(sorted: &[T]) -> T {
+    percentile(sorted, T::ratio(1, 2))
+}
+
+/// Most common value, the smallest one when several are equally common
+fn mode<T: Sample>(sorted: &[T]) -> T {
+    let mut best = (&sorted[0], 0);
+    let mut start = 0;
+    for end in 1..=sorted.len() {
+        if end == sorted.len() || sorted[end] != sorted[start] {
+            if end - start > best.1 {
+                best = (&sorted[start], end - start);
+            }
+            start = end;
+        }
+    }
+    best.0.clone()
+}
+
+/// Sample variance, dividing by one less than the count
+fn variance<T: Sample>(xs: &[T]) -> T {
+    let mean = mean(xs);
+    let squares: T = xs
+        .iter()
+        .map(|x| (x.clone() - mean.clone()) * (x.clone() - mean.clone()))
+        .sum();
+    squares / T::ratio(xs.len() - 1, 1)
+}
+
+/// Interpolates linearly between the closest ranks; `fraction` is in [0, 1]
+fn percentile<T: Sample>(sorted: &[T], fraction: T) -> T {
+    let rank = fraction * T::ratio(sorted.len() - 1, 1);
+    let (index, offset) = rank.split();
+    match sorted.get(index + 1) {
+        Some(next) => sorted[index].clone() + offset * (next.clone() - sorted[index].clone()),
+        None => sorted[sorted.len() - 1].clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+        let function = FUNCTIONS.iter().find(|f| f.name == name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.eval)(args)
+    }
+
+    fn list(xs: &[i64]) -> Value {
+        Value::List(xs.iter().map(|&x| Value::Integer(x.into())).collect())
+    }
+
+    fn show(result: Result<Value, String>) -> String {
+        result.unwrap().to_string()
+    }
+
+    #[test]
+    fn test_exact() {
+        let data = [list(&[2, 4, 4, 4, 5, 5, 7, 9])];
+        assert_eq!(show(call("mean", &data)), "5");
+        assert_eq!(show(call("median", &data)), "9/2");
+        assert_eq!(show(call("mode", &data)), "4");
+        assert_eq!(show(call("var", &data)), "32/7");
+        assert_eq!(show(call("stdev", &[list(&[1, 3])])), "1.4142135623730951");
+        assert_eq!(show(call("stdev", &[list(&[0, 2, 4])])), "2");
+        assert_eq!(show(call("prod", &[list(&[1, 2]), Value::Integer(3.into())])), "6");
+        assert_eq!(show(call("sort", &[list(&[3, -1, 2])])), "[-1, 2, 3]");
+        assert_eq!(show(call("mode", &[list(&[3, 1, 3, 1])])), "1");
+    }
+
+    #[test]
+    fn test_decimals() {
+        let decimals = |xs: &[&str]| Value::List(xs.iter().map(|x| Value::Decimal(x.parse().unwrap())).collect());
+        let data = [decimals(&["0.1", "0.2", "0.3"])];
+        assert_eq!(show(call("mean", &data)), "1/5");
+        assert_eq!(show(call("var", &data)), "1/100");
+        assert_eq!(show(call("median", &[decimals(&["0.4", "0.1", "0.2", "0.3"])])), "1/4");
+        assert_eq!(
+            show(call(
+                "percentile",
+                &[data[0].clone(), Value::Decimal("50".parse().unwrap())]
+            )),
+            "1/5"
+        );
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let data = list(&[1, 2, 3, 4]);
+        assert_eq!(
+            show(call("percentile", &[data.clone(), Value::Integer(50.into())])),
+            "5/2"
+        );
+        assert_eq!(show(call("percentile", &[data.clone(), Value::Real(100.0)])), "4");
+        assert_eq!(show(call("quartiles", std::slice::from_ref(&data))), "[7/4, 5/2, 13/4]");
+        assert_eq!(
+            call("percentile", &[data, Value::Integer(101.into())]).unwrap_err(),
+            "p must be between 0 and 100, got 101"
+        );
+    }
+
+    #[test]
+    fn test_floats_and_errors() {
+        let data = Value::List(vec![Value::Real(1.5), Value::Integer(2.into())]);
+        assert_eq!(show(call("mean", &[data])), "1.75");
+        assert_eq!(call("mean", &[Value::List(vec![])]).unwrap_err(), "the list is empty");
+        assert_eq!(
+            call("var", &[list(&[1])]).unwrap_err(),
+            "at least two values are needed"
+        );
+    }
+}
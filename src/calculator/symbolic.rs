@@ -0,0 +1,882 @@
+//! Symbolic differentiation and simplification
+//!
+//! `d/dx(x^2 * sin(x))`, short for `diff(x^2 * sin(x), x)`, differentiates an expression by the
+//! rules of calculus and returns the simplified result as text, here `2*x*sin(x) + x^2*cos(x)`.
+//! Other names stay symbols, user functions and mods are expanded into their bodies, and every
+//! built-in function with an elementary derivative is known. Trigonometric functions follow the
+//! angle mode, so in degrees the derivative of `sin(x)` is `pi*cos(x)/180`.
+//!
+//! Simplification brings an expression into a sum of terms, each a fraction times a product of
+//! powers of distinct bases. This folds constants and collects like terms and equal bases, but
+//! leaves products of sums unexpanded.
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use super::ast::{BinaryOp, Expr, ExprKind, Span, UnaryOp};
+use super::calculus;
+use super::context::{Arity, Scope};
+use super::decimal;
+use super::error::{CalcError, CalcResult, ErrorKind};
+use super::math_functions::AngleMode;
+use super::value::Value;
+
+/// Deepest nesting of user functions expanded while differentiating, which stops recursion
+const MAX_EXPANSIONS: usize = 32;
+
+/// Largest whole exponent a power of a product is multiplied out with, e.g. `(2*x)^3` to `8*x^3`
+const MAX_EXPANDED_POWER: i32 = 64;
+
+/// A symbolic function callable from expressions
+pub struct SymbolicFn {
+    pub name: &'static str,
+
+    /// Parameter names, shown in suggestions
+    pub params: &'static str,
+
+    pub arity: Arity,
+
+    /// Computes the result from the unevaluated arguments and the span of the call
+    pub eval: fn(&dyn Scope, &[Expr], Span) -> CalcResult<Value>,
+}
+
+/// All symbolic functions, registered by the evaluator
+pub const FUNCTIONS: &[SymbolicFn] = &[SymbolicFn {
+    name: "diff",
+    params: "expr, x",
+    arity: Arity::Exact(2),
+    eval: diff,
+}];
+
+/// `diff(expr, x)` or `d/dx(expr)`, the simplified derivative as text
+fn diff(scope: &dyn Scope, args: &[Expr], _span: Span) -> CalcResult<Value> {
+    let variable = match &args[1].kind {
+        ExprKind::Ident(name) if !name.contains('.') => name,
+        _ => {
+            return Err(unsupported("the second argument must be a variable name", args[1].span)
+                .with_suggestion("e.g. diff(x^2, x), or d/dx(x^2)"))
+        }
+    };
+    let differentiator = Differentiator {
+        scope,
+        variable,
+        expansions: 0,
+        within: None,
+    };
+    let derivative = differentiator.derive(&args[0])?;
+    Ok(Value::Text(format(&simplify(&derivative))))
+}
+
+/// Differentiates expressions with respect to one variable
+#[derive(Clone, Copy)]
+struct Differentiator<'a> {
+    scope: &'a dyn Scope,
+    variable: &'a str,
+
+    /// Number of user functions being expanded
+    expansions: usize,
+
+    /// Call of the outermost expanded user function, which errors inside its body refer to
+    within: Option<Span>,
+}
+
+impl Differentiator<'_> {
+    fn derive(&self, expr: &Expr) -> CalcResult<Expr> {
+        if !self.depends(expr) {
+            return Ok(integer(0));
+        }
+        match &expr.kind {
+            // Only the variable itself depends on the variable
+            ExprKind::Ident(_) => Ok(integer(1)),
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                expr: inner,
+            } => Ok(negate(self.derive(inner)?)),
+            ExprKind::Unary {
+                op: UnaryOp::Plus,
+                expr: inner,
+            } => self.derive(inner),
+            ExprKind::Binary {
+                op: op @ (BinaryOp::Add | BinaryOp::Sub),
+                lhs,
+                rhs,
+            } => Ok(binary(*op, self.derive(lhs)?, self.derive(rhs)?)),
+            ExprKind::Binary {
+                op: BinaryOp::Mul,
+                lhs,
+                rhs,
+            } => Ok(add(
+                multiply(self.derive(lhs)?, (**rhs).clone()),
+                multiply((**lhs).clone(), self.derive(rhs)?),
+            )),
+            ExprKind::Binary {
+                op: BinaryOp::Div,
+                lhs,
+                rhs,
+            } => Ok(divide(
+                binary(
+                    BinaryOp::Sub,
+                    multiply(self.derive(lhs)?, (**rhs).clone()),
+                    multiply((**lhs).clone(), self.derive(rhs)?),
+                ),
+                power((**rhs).clone(), integer(2)),
+            )),
+            ExprKind::Binary {
+                op: BinaryOp::Pow,
+                lhs,
+                rhs,
+            } => self.derive_power(lhs, rhs),
+            ExprKind::Call { name, args } => self.derive_call(name, args, expr.span),
+            _ => Err(self.no_derivative(&format!("'{}'", format(expr)), expr.span)),
+        }
+    }
+
+    /// Whether the expression mentions the variable
+    fn depends(&self, expr: &Expr) -> bool {
+        let mut found = false;
+        expr.visit(&mut |node| {
+            found |= matches!(&node.kind, ExprKind::Ident(name) if name == self.variable);
+        });
+        found
+    }
+
+    fn derive_power(&self, base: &Expr, exponent: &Expr) -> CalcResult<Expr> {
+        let (u, v) = (base.clone(), exponent.clone());
+        if !self.depends(exponent) {
+            // d(u^n) = n*u^(n-1)*du
+            let lowered = binary(BinaryOp::Sub, v.clone(), integer(1));
+            return Ok(multiply(multiply(v, power(u, lowered)), self.derive(base)?));
+        }
+        let log = call("ln", vec![u.clone()]);
+        let whole = power(u.clone(), v.clone());
+        if !self.depends(base) {
+            // d(a^v) = a^v*ln(a)*dv
+            return Ok(multiply(multiply(whole, log), self.derive(exponent)?));
+        }
+        // d(u^v) = u^v*(dv*ln(u) + v*du/u)
+        let inner = add(
+            multiply(self.derive(exponent)?, log),
+            divide(multiply(v, self.derive(base)?), u),
+        );
+        Ok(multiply(whole, inner))
+    }
+
+    fn derive_call(&self, name: &str, args: &[Expr], span: Span) -> CalcResult<Expr> {
+        if let Some((params, body)) = self.scope.definition(name) {
+            if params.len() == args.len() {
+                if self.expansions >= MAX_EXPANSIONS {
+                    return Err(self.no_derivative(&format!("'{}', which is nested too deeply", name), span));
+                }
+                let inner = Differentiator {
+                    expansions: self.expansions + 1,
+                    within: Some(self.within.unwrap_or(span)),
+                    ..*self
+                };
+                return inner.derive(&substitute(&body, &params, args));
+            }
+        }
+        if (name == "sum" || name == "prod") && calculus::is_bounded(args) {
+            return Err(self.no_derivative(&format!("'{}' over a bound variable", name), span));
+        }
+        if let Some(formula) = formula(name, args) {
+            return self.derive(&formula);
+        }
+
+        match args {
+            [u] => {
+                let outer = self.outer(name, u).ok_or_else(|| self.no_derivative(name, span))?;
+                Ok(multiply(outer, self.derive(u)?))
+            }
+            [y, x] if name == "atan2" => {
+                // d atan2(y, x) = (x*dy - y*dx)/(x^2 + y^2)
+                let numerator = binary(
+                    BinaryOp::Sub,
+                    multiply(x.clone(), self.derive(y)?),
+                    multiply(y.clone(), self.derive(x)?),
+                );
+                let denominator = add(power(x.clone(), integer(2)), power(y.clone(), integer(2)));
+                Ok(self.angle_result(divide(numerator, denominator)))
+            }
+            _ => Err(self.no_derivative(name, span)),
+        }
+    }
+
+    /// Derivative of a built-in function of one argument, at `u`
+    fn outer(&self, name: &str, u: &Expr) -> Option<Expr> {
+        let u = || u.clone();
+        let square = || power(u(), integer(2));
+        let reciprocal = |expr| divide(integer(1), expr);
+        Some(match name {
+            "sin" => self.angle_argument(call("cos", vec![u()])),
+            "cos" => negate(self.angle_argument(call("sin", vec![u()]))),
+            "tan" => self.angle_argument(reciprocal(power(call("cos", vec![u()]), integer(2)))),
+            // csin(u) is cos(sin(u)) in radians, so the outer sine takes a number of radians
+            "csin" => negate(self.angle_argument(multiply(
+                call("sin", vec![self.angle_result(call("sin", vec![u()]))]),
+                call("cos", vec![u()]),
+            ))),
+            "asin" => self.angle_result(reciprocal(sqrt(subtract(integer(1), square())))),
+            "acos" => negate(self.angle_result(reciprocal(sqrt(subtract(integer(1), square()))))),
+            "atan" => self.angle_result(reciprocal(add(integer(1), square()))),
+            "sinh" => call("cosh", vec![u()]),
+            "cosh" => call("sinh", vec![u()]),
+            "tanh" => reciprocal(power(call("cosh", vec![u()]), integer(2))),
+            "asinh" => reciprocal(sqrt(add(square(), integer(1)))),
+            "acosh" => reciprocal(sqrt(subtract(square(), integer(1)))),
+            "atanh" => reciprocal(subtract(integer(1), square())),
+            "exp" => call("exp", vec![u()]),
+            "ln" | "log" => reciprocal(u()),
+            "log10" => reciprocal(multiply(u(), call("ln", vec![integer(10)]))),
+            "log2" => reciprocal(multiply(u(), call("ln", vec![integer(2)]))),
+            "sqrt" => reciprocal(multiply(integer(2), sqrt(u()))),
+            "abs" | "fabs" => call("signum", vec![u()]),
+            "erf" | "erfc" => {
+                let slope = multiply(
+                    divide(integer(2), sqrt(ident("pi"))),
+                    call("exp", vec![negate(square())]),
+                );
+                if name == "erf" {
+                    slope
+                } else {
+                    negate(slope)
+                }
+            }
+            // Piecewise constant, or constant for real arguments
+            "floor" | "ceil" | "round" | "trunc" | "signum" | "im" | "arg" | "is_nan" | "is_inf" => integer(0),
+            _ => return None,
+        })
+    }
+
+    /// Scales the derivative of a function of an angle in the angle mode, e.g. by `pi/180`
+    fn angle_argument(&self, expr: Expr) -> Expr {
+        match self.scope.angle_mode() {
+            AngleMode::Radians => expr,
+            mode => multiply(expr, divide(ident("pi"), integer(half_turn(mode)))),
+        }
+    }
+
+    /// Scales the derivative of a function returning an angle in the angle mode, e.g. by `180/pi`
+    fn angle_result(&self, expr: Expr) -> Expr {
+        match self.scope.angle_mode() {
+            AngleMode::Radians => expr,
+            mode => multiply(expr, divide(integer(half_turn(mode)), ident("pi"))),
+        }
+    }
+
+    fn no_derivative(&self, what: &str, span: Span) -> CalcError {
+        unsupported(
+            &format!("no symbolic derivative of {}", what),
+            self.within.unwrap_or(span),
+        )
+        .with_suggestion("use deriv(expr, x, at) for a numeric derivative")
+    }
+}
+
+/// Half a turn in the angle mode, e.g. 180 for degrees
+fn half_turn(mode: AngleMode) -> i64 {
+    (mode.full_turn() / 2.0).round() as i64
+}
+
+/// Built-in function as the expression it computes, for functions whose derivative follows from it
+fn formula(name: &str, args: &[Expr]) -> Option<Expr> {
+    let sum = |exprs: Vec<Expr>| exprs.into_iter().reduce(add);
+    let squares = || sum(args.iter().map(|arg| power(arg.clone(), integer(2))).collect());
+    let half = |expr| divide(expr, integer(2));
+    Some(match (name, args) {
+        ("pow", [base, exponent]) => power(base.clone(), exponent.clone()),
+        ("hypot", _) | ("pythagorean_theorem", [_, _]) => sqrt(squares()?),
+        ("sum", _) => sum(args.to_vec())?,
+        ("prod", _) => args.iter().cloned().reduce(multiply)?,
+        ("avg", _) => divide(sum(args.to_vec())?, integer(args.len() as i64)),
+        ("s_circle", [r]) => multiply(ident("pi"), power(r.clone(), integer(2))),
+        ("s_tri", [base, height]) => half(multiply(base.clone(), height.clone())),
+        ("s_rect", [width, height]) => multiply(width.clone(), height.clone()),
+        ("trapezoid_area", [a, b, height]) => half(multiply(add(a.clone(), b.clone()), height.clone())),
+        ("degrees", [x]) => divide(multiply(x.clone(), integer(180)), ident("pi")),
+        ("radians", [x]) => divide(multiply(x.clone(), ident("pi")), integer(180)),
+        ("re" | "conj", [x]) => x.clone(),
+        _ => return None,
+    })
+}
+
+/// Replaces the parameters of a function body by the arguments of a call
+fn substitute(expr: &Expr, params: &[String], args: &[Expr]) -> Expr {
+    let sub = |inner: &Expr| Box::new(substitute(inner, params, args));
+    let kind = match &expr.kind {
+        ExprKind::Ident(name) => match params.iter().position(|param| param == name) {
+            Some(i) => return args[i].clone(),
+            None => ExprKind::Ident(name.clone()),
+        },
+        ExprKind::Unary { op, expr } => ExprKind::Unary {
+            op: *op,
+            expr: sub(expr),
+        },
+        ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+            op: *op,
+            lhs: sub(lhs),
+            rhs: sub(rhs),
+        },
+        ExprKind::Postfix { op, expr } => ExprKind::Postfix {
+            op: *op,
+            expr: sub(expr),
+        },
+        ExprKind::Call { name, args: call_args } => ExprKind::Call {
+            name: name.clone(),
+            args: call_args.iter().map(|arg| substitute(arg, params, args)).collect(),
+        },
+        ExprKind::List(items) => ExprKind::List(items.iter().map(|item| substitute(item, params, args)).collect()),
+        ExprKind::Quantity { expr, unit } => ExprKind::Quantity {
+            expr: sub(expr),
+            unit: unit.clone(),
+        },
+        ExprKind::Convert { expr, unit } => ExprKind::Convert {
+            expr: sub(expr),
+            unit: unit.clone(),
+        },
+        ExprKind::Equation { lhs, rhs } => ExprKind::Equation {
+            lhs: sub(lhs),
+            rhs: sub(rhs),
+        },
+        ExprKind::Range { start, end } => ExprKind::Range {
+            start: sub(start),
+            end: sub(end),
+        },
+        kind => kind.clone(),
+    };
+    Expr::new(kind, expr.span)
+}
+
+/// A product `coefficient * base^exponent * ...` with distinct bases in a fixed order
+#[derive(Debug, Clone)]
+struct Term {
+    coefficient: BigRational,
+    factors: Vec<(Expr, Expr)>,
+}
+
+impl Term {
+    fn constant(coefficient: BigRational) -> Self {
+        Self {
+            coefficient,
+            factors: Vec::new(),
+        }
+    }
+
+    fn factor(base: Expr, exponent: Expr) -> Self {
+        Self {
+            coefficient: BigRational::one(),
+            factors: vec![(base, exponent)],
+        }
+    }
+
+    fn times(mut self, other: Term) -> Term {
+        self.coefficient *= other.coefficient;
+        for (base, exponent) in other.factors {
+            match self.factors.iter().position(|(existing, _)| *existing == base) {
+                Some(i) => {
+                    let exponent = simplify(&add(self.factors[i].1.clone(), exponent));
+                    if constant(&exponent).is_some_and(|n| n.is_zero()) {
+                        self.factors.remove(i);
+                    } else {
+                        self.factors[i].1 = exponent;
+                    }
+                }
+                None => self.factors.push((base, exponent)),
+            }
+        }
+        self.factors.sort_by_cached_key(|(base, _)| order(base));
+        self
+    }
+
+    /// `1/term`, unless the coefficient is zero
+    fn reciprocal(self) -> Option<Term> {
+        if self.coefficient.is_zero() {
+            return None;
+        }
+        Some(Term {
+            coefficient: self.coefficient.recip(),
+            factors: self
+                .factors
+                .into_iter()
+                .map(|(base, exponent)| (base, simplify(&negate(exponent))))
+                .collect(),
+        })
+    }
+}
+
+/// Order of factors within a term: numbers, then variables, then calls, then the rest
+fn order(base: &Expr) -> (u8, String) {
+    let rank = match &base.kind {
+        ExprKind::Number(_) | ExprKind::Integer(_) => 0,
+        ExprKind::Ident(_) => 1,
+        ExprKind::Call { .. } => 2,
+        _ => 3,
+    };
+    (rank, format(base))
+}
+
+/// Simplifies an expression into a sum of terms, folding constants and collecting like terms
+pub fn simplify(expr: &Expr) -> Expr {
+    build(&collect(expr))
+}
+
+/// Terms of the sum an expression simplifies to, with like terms collected and none zero
+fn collect(expr: &Expr) -> Vec<Term> {
+    match &expr.kind {
+        ExprKind::Binary {
+            op: BinaryOp::Add,
+            lhs,
+            rhs,
+        } => combine(collect(lhs), collect(rhs)),
+        ExprKind::Binary {
+            op: BinaryOp::Sub,
+            lhs,
+            rhs,
+        } => combine(collect(lhs), negated(collect(rhs))),
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            expr: inner,
+        } => negated(collect(inner)),
+        ExprKind::Unary {
+            op: UnaryOp::Plus,
+            expr: inner,
+        } => collect(inner),
+        _ => {
+            let term = term(expr);
+            if term.coefficient.is_zero() {
+                Vec::new()
+            } else {
+                vec![term]
+            }
+        }
+    }
+}
+
+fn combine(mut terms: Vec<Term>, more: Vec<Term>) -> Vec<Term> {
+    for term in more {
+        match terms.iter_mut().find(|existing| existing.factors == term.factors) {
+            Some(existing) => existing.coefficient += term.coefficient,
+            None => terms.push(term),
+        }
+    }
+    terms.retain(|term| !term.coefficient.is_zero());
+    terms
+}
+
+fn negated(mut terms: Vec<Term>) -> Vec<Term> {
+    for term in &mut terms {
+        term.coefficient = -term.coefficient.clone();
+    }
+    terms
+}
+
+/// A product or single factor as one term
+fn term(expr: &Expr) -> Term {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Integer(_) => match constant(expr) {
+            Some(n) => Term::constant(n),
+            None => Term::factor(expr.clone(), integer(1)),
+        },
+        ExprKind::Ident(name) => Term::factor(ident(name), integer(1)),
+        ExprKind::Binary {
+            op: BinaryOp::Mul,
+            lhs,
+            rhs,
+        } => operand(lhs).times(operand(rhs)),
+        ExprKind::Binary {
+            op: BinaryOp::Div,
+            lhs,
+            rhs,
+        } => match operand(rhs).reciprocal() {
+            Some(reciprocal) => operand(lhs).times(reciprocal),
+            None => Term::factor(divide(simplify(lhs), simplify(rhs)), integer(1)),
+        },
+        ExprKind::Binary {
+            op: BinaryOp::Pow,
+            lhs,
+            rhs,
+        } => raise(simplify(lhs), simplify(rhs)),
+        ExprKind::Call { name, args } if name == "sqrt" && args.len() == 1 => raise(simplify(&args[0]), half()),
+        ExprKind::Call { name, args } => {
+            let args: Vec<Expr> = args.iter().map(simplify).collect();
+            fold(name, &args)
+                .map(Term::constant)
+                .unwrap_or_else(|| Term::factor(call(name, args), integer(1)))
+        }
+        _ => Term::factor(expr.clone(), integer(1)),
+    }
+}
+
+/// An operand of a product as one term; a sum of several terms becomes a single factor
+fn operand(expr: &Expr) -> Term {
+    let mut terms = collect(expr);
+    match terms.len() {
+        0 => Term::constant(BigRational::zero()),
+        1 => terms.pop().expect("there is one term"),
+        _ => Term::factor(build(&terms), integer(1)),
+    }
+}
+
+/// `base^exponent` of simplified expressions as one term
+fn raise(base: Expr, exponent: Expr) -> Term {
+    let n = constant(&exponent);
+    if n.as_ref().is_some_and(Zero::is_zero) {
+        return Term::constant(BigRational::one());
+    }
+    if n.as_ref().is_some_and(One::is_one) {
+        return operand(&base);
+    }
+
+    let mut terms = collect(&base);
+    let positive = n.as_ref().is_some_and(Signed::is_positive);
+    match terms.len() {
+        0 if positive => return Term::constant(BigRational::zero()),
+        1 => {
+            let term = terms.pop().expect("there is one term");
+            let whole = n
+                .as_ref()
+                .filter(|n| n.is_integer())
+                .and_then(|n| n.to_integer().to_i32())
+                .filter(|k| k.abs() <= MAX_EXPANDED_POWER);
+            match whole {
+                Some(k) if k > 0 || !term.coefficient.is_zero() => {
+                    return Term {
+                        coefficient: num_traits::Pow::pow(&term.coefficient, k),
+                        factors: term
+                            .factors
+                            .into_iter()
+                            .map(|(base, exponent)| (base, simplify(&multiply(exponent, integer(i64::from(k))))))
+                            .collect(),
+                    }
+                }
+                _ => {}
+            }
+            // A single plain factor takes any exponent, e.g. x^(1/2) or x^y
+            if term.coefficient.is_one()
+                && term.factors.len() == 1
+                && constant(&term.factors[0].1).is_some_and(|n| n.is_one())
+            {
+                return Term::factor(term.factors[0].0.clone(), exponent);
+            }
+            if term.factors.is_empty() && positive && term.coefficient.is_one() {
+                return Term::constant(BigRational::one());
+            }
+        }
+        _ => {}
+    }
+    Term::factor(base, exponent)
+}
+
+/// Exact values of built-in functions at simple arguments, e.g. `cos(0)` is 1
+fn fold(name: &str, args: &[Expr]) -> Option<BigRational> {
+    let [arg] = args else {
+        return None;
+    };
+    if matches!(&arg.kind, ExprKind::Ident(e) if e == "e") && matches!(name, "ln" | "log") {
+        return Some(BigRational::one());
+    }
+    let x = constant(arg)?;
+    match name {
+        "sin" | "tan" | "asin" | "atan" | "sinh" | "tanh" | "asinh" | "atanh" | "erf" if x.is_zero() => {
+            Some(BigRational::zero())
+        }
+        "cos" | "cosh" | "exp" | "erfc" if x.is_zero() => Some(BigRational::one()),
+        "ln" | "log" if x.is_one() => Some(BigRational::zero()),
+        "abs" | "fabs" => Some(x.abs()),
+        "signum" if !x.is_zero() => Some(x.signum()),
+        _ => None,
+    }
+}
+
+/// Exact value of an expression made of numbers and arithmetic only
+fn constant(expr: &Expr) -> Option<BigRational> {
+    match &expr.kind {
+        ExprKind::Number(x) => exact(*x),
+        ExprKind::Integer(n) => Some(BigRational::from_integer(n.clone())),
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            expr: inner,
+        } => constant(inner).map(|n| -n),
+        ExprKind::Unary {
+            op: UnaryOp::Plus,
+            expr: inner,
+        } => constant(inner),
+        ExprKind::Binary { op, lhs, rhs } => {
+            let (a, b) = (constant(lhs)?, constant(rhs)?);
+            match op {
+                BinaryOp::Add => Some(a + b),
+                BinaryOp::Sub => Some(a - b),
+                BinaryOp::Mul => Some(a * b),
+                BinaryOp::Div if !b.is_zero() => Some(a / b),
+                BinaryOp::Pow if b.is_integer() && !(a.is_zero() && b.is_negative()) => {
+                    let k = b.to_integer().to_i32().filter(|k| k.abs() <= MAX_EXPANDED_POWER)?;
+                    Some(num_traits::Pow::pow(&a, k))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A finite literal as the fraction its decimal digits spell, e.g. 0.1 as 1/10
+fn exact(x: f64) -> Option<BigRational> {
+    let (digits, scale) = decimal::from_f64(x)?.as_bigint_and_exponent();
+    let power = BigInt::from(10).pow(scale.unsigned_abs() as u32);
+    Some(if scale >= 0 {
+        BigRational::new(digits, power)
+    } else {
+        BigRational::from_integer(digits * power)
+    })
+}
+
+/// The sum of terms as an expression, e.g. `2*x - y/3`
+fn build(terms: &[Term]) -> Expr {
+    let mut sum: Option<Expr> = None;
+    for term in terms {
+        let negative = term.coefficient.is_negative();
+        let magnitude = product(&term.coefficient.abs(), &term.factors);
+        sum = Some(match sum {
+            None if negative => negate(magnitude),
+            None => magnitude,
+            Some(sum) if negative => subtract(sum, magnitude),
+            Some(sum) => add(sum, magnitude),
+        });
+    }
+    sum.unwrap_or_else(|| integer(0))
+}
+
+/// A positive coefficient times factors as an expression, negative powers as a denominator
+fn product(coefficient: &BigRational, factors: &[(Expr, Expr)]) -> Expr {
+    let mut numerator = Vec::new();
+    let mut denominator = Vec::new();
+    for (base, exponent) in factors {
+        match constant(exponent) {
+            Some(n) if n.is_negative() => denominator.push(power_of(base, &number(&-n))),
+            _ => numerator.push(power_of(base, exponent)),
+        }
+    }
+    if !coefficient.numer().is_one() || numerator.is_empty() {
+        numerator.insert(
+            0,
+            Expr::new(ExprKind::Integer(coefficient.numer().clone()), Span::default()),
+        );
+    }
+    if !coefficient.denom().is_one() {
+        denominator.insert(
+            0,
+            Expr::new(ExprKind::Integer(coefficient.denom().clone()), Span::default()),
+        );
+    }
+
+    let numerator = numerator
+        .into_iter()
+        .reduce(multiply)
+        .expect("the numerator is not empty");
+    match denominator.into_iter().reduce(multiply) {
+        Some(denominator) => divide(numerator, denominator),
+        None => numerator,
+    }
+}
+
+/// `base^exponent`, written as `base` for 1 and `sqrt(base)` for 1/2
+fn power_of(base: &Expr, exponent: &Expr) -> Expr {
+    match constant(exponent) {
+        Some(n) if n.is_one() => base.clone(),
+        Some(n) if n == BigRational::new(1.into(), 2.into()) => sqrt(base.clone()),
+        _ => power(base.clone(), exponent.clone()),
+    }
+}
+
+/// A fraction as an expression such as `-1/2`
+fn number(n: &BigRational) -> Expr {
+    let magnitude = Expr::new(ExprKind::Integer(n.numer().abs()), Span::default());
+    let magnitude = if n.is_integer() {
+        magnitude
+    } else {
+        divide(
+            magnitude,
+            Expr::new(ExprKind::Integer(n.denom().clone()), Span::default()),
+        )
+    };
+    if n.is_negative() {
+        negate(magnitude)
+    } else {
+        magnitude
+    }
+}
+
+/// Writes an expression with only the parentheses it needs, e.g. `2*x*sin(x) + x^2*cos(x)`
+pub fn format(expr: &Expr) -> String {
+    let wrap = |inner: &Expr, parenthesize: bool| {
+        if parenthesize {
+            format!("({})", format(inner))
+        } else {
+            format(inner)
+        }
+    };
+    match &expr.kind {
+        ExprKind::Binary { op, lhs, rhs } if precedence(expr) > 0 => {
+            let level = precedence(expr);
+            if *op == BinaryOp::Pow {
+                // Right associative
+                return format!(
+                    "{}^{}",
+                    wrap(lhs, precedence(lhs) <= level),
+                    wrap(rhs, precedence(rhs) < level)
+                );
+            }
+            let left = wrap(lhs, precedence(lhs) < level);
+            let right = wrap(
+                rhs,
+                precedence(rhs) < level
+                    || (precedence(rhs) == level && matches!(op, BinaryOp::Sub | BinaryOp::Div | BinaryOp::Rem)),
+            );
+            if level == 1 {
+                format!("{} {} {}", left, op.symbol(), right)
+            } else {
+                format!("{}{}{}", left, op.symbol(), right)
+            }
+        }
+        ExprKind::Unary { op, expr: inner } => format!(
+            "{}{}",
+            op.symbol(),
+            wrap(
+                inner,
+                precedence(inner) < 2 || matches!(inner.kind, ExprKind::Unary { .. })
+            )
+        ),
+        ExprKind::Call { name, args } => {
+            let args: Vec<String> = args.iter().map(format).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        _ => expr.to_string(),
+    }
+}
+
+/// How tightly an expression binds in `format`; 0 for nodes always written in parentheses
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Binary { op, .. } => match op {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 2,
+            BinaryOp::Pow => 4,
+            _ => 0,
+        },
+        ExprKind::Unary { .. } => 3,
+        ExprKind::Number(x) if x.is_sign_negative() => 3,
+        ExprKind::Integer(n) if n.is_negative() => 3,
+        _ => 5,
+    }
+}
+
+fn integer(n: i64) -> Expr {
+    Expr::new(ExprKind::Integer(BigInt::from(n)), Span::default())
+}
+
+fn half() -> Expr {
+    divide(integer(1), integer(2))
+}
+
+fn ident(name: &str) -> Expr {
+    Expr::new(ExprKind::Ident(name.to_string()), Span::default())
+}
+
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::new(
+        ExprKind::Call {
+            name: name.to_string(),
+            args,
+        },
+        Span::default(),
+    )
+}
+
+fn sqrt(expr: Expr) -> Expr {
+    call("sqrt", vec![expr])
+}
+
+fn negate(expr: Expr) -> Expr {
+    Expr::new(
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            expr: Box::new(expr),
+        },
+        Span::default(),
+    )
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::new(
+        ExprKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        Span::default(),
+    )
+}
+
+fn add(lhs: Expr, rhs: Expr) -> Expr {
+    binary(BinaryOp::Add, lhs, rhs)
+}
+
+fn subtract(lhs: Expr, rhs: Expr) -> Expr {
+    binary(BinaryOp::Sub, lhs, rhs)
+}
+
+fn multiply(lhs: Expr, rhs: Expr) -> Expr {
+    binary(BinaryOp::Mul, lhs, rhs)
+}
+
+fn divide(lhs: Expr, rhs: Expr) -> Expr {
+    binary(BinaryOp::Div, lhs, rhs)
+}
+
+fn power(base: Expr, exponent: Expr) -> Expr {
+    binary(BinaryOp::Pow, base, exponent)
+}
+
+fn unsupported(message: &str, span: Span) -> CalcError {
+    CalcError::new(
+        ErrorKind::DomainError {
+            name: "diff".to_string(),
+            message: message.to_string(),
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::parser::parse;
+
+    fn simplified(input: &str) -> String {
+        format(&simplify(&parse(input).unwrap()))
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(simplified("x*x + 2*x - x"), "x^2 + x");
+        assert_eq!(simplified("2*3 + x/x - 0*sin(x)"), "7");
+        assert_eq!(simplified("(x^2)^3 * x / 1"), "x^7");
+        assert_eq!(simplified("sqrt(x) / x"), "1/sqrt(x)");
+        assert_eq!(simplified("0.5*x + y^1*1"), "x/2 + y");
+        assert_eq!(simplified("cos(0)*exp(x) + ln(e)"), "exp(x) + 1");
+        assert_eq!(simplified("-(x - y) + 3*y"), "-x + 4*y");
+
+        // Square roots of squares are not plain values, and sums are not multiplied out
+        assert_eq!(simplified("sqrt(x^2)"), "sqrt(x^2)");
+        assert_eq!(simplified("(x + 1)^2 * (x + 1)"), "(x + 1)^3");
+    }
+
+    #[test]
+    fn test_format() {
+        let formatted = |input: &str| format(&parse(input).unwrap());
+        assert_eq!(formatted("((a - (b - c)) / (d * e))"), "(a - (b - c))/(d*e)");
+        assert_eq!(formatted("(x^y)^z + x^(y^z)"), "(x^y)^z + x^y^z");
+        assert_eq!(formatted("-(x + 1) * (-2)^2"), "-(x + 1)*(-2)^2");
+        assert_eq!(formatted("atan2(y, (x))"), "atan2(y, x)");
+    }
+}
@@ -0,0 +1,542 @@
+//! Physical units and quantities with dimensional analysis
+//!
+//! A quantity stores its number in the unit it was written in, e.g. `5 km`, together with the
+//! unit's scale to SI base units and its dimension. Sums convert the right operand to the unit of
+//! the left one and require equal dimensions; products and powers combine units, so `9.81 m/s^2 *
+//! 80 kg` is `784.8 m*kg/s^2`. Products without a dimension, like `1 km / 1 m`, become plain numbers.
+//!
+//! Units come from a registry in TOML format: the built-in `units.toml` plus any `.cmunit` files in
+//! the mods directory, which use the same format.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::ast::UnitExpr;
+use super::parser::parse_unit;
+
+/// Names of the SI base dimensions, in the order of `Dimension` exponents
+pub const BASE_DIMENSIONS: [&str; 7] = [
+    "length",
+    "mass",
+    "time",
+    "current",
+    "temperature",
+    "amount",
+    "luminosity",
+];
+
+/// Unit files in the mods directory use this extension
+pub const UNIT_FILE_EXTENSION: &str = "cmunit";
+
+const BUILTIN_UNITS: &str = include_str!("units.toml");
+
+/// Exponents of the base dimensions, e.g. `[1, 0, -2, 0, 0, 0, 0]` for acceleration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Dimension(pub [i32; 7]);
+
+impl Dimension {
+    /// Dimension of a single base quantity
+    pub fn base(name: &str) -> Option<Dimension> {
+        let index = BASE_DIMENSIONS.iter().position(|base| *base == name)?;
+        let mut exponents = [0; 7];
+        exponents[index] = 1;
+        Some(Dimension(exponents))
+    }
+
+    /// Whether every exponent is zero, i.e. the value is a plain number
+    pub fn is_dimensionless(&self) -> bool {
+        self.0.iter().all(|&exponent| exponent == 0)
+    }
+
+    fn combine(&self, other: &Dimension, power: i32) -> Dimension {
+        let mut exponents = self.0;
+        for (exponent, other) in exponents.iter_mut().zip(other.0) {
+            *exponent += other * power;
+        }
+        Dimension(exponents)
+    }
+}
+
+/// A resolved unit expression such as `km/h`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unit {
+    /// Unit names with their powers, as written
+    pub factors: Vec<(String, i32)>,
+
+    /// Size of the unit in SI base units, e.g. 1000 for `km`
+    pub scale: f64,
+
+    /// Zero point in SI base units; only non-zero for a lone temperature scale such as `degC`
+    pub offset: f64,
+
+    pub dimension: Dimension,
+}
+
+impl Default for Unit {
+    /// No unit, the unit of plain numbers
+    fn default() -> Self {
+        Self {
+            factors: Vec::new(),
+            scale: 1.0,
+            offset: 0.0,
+            dimension: Dimension::default(),
+        }
+    }
+}
+
+impl Unit {
+    /// Product of two units, `power` being -1 for a quotient
+    ///
+    /// Offsets are dropped, so products of temperature scales count degrees as differences.
+    pub fn combine(&self, other: &Unit, power: i32) -> Unit {
+        let mut factors = self.factors.clone();
+        for (name, exponent) in &other.factors {
+            match factors.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => *existing += exponent * power,
+                None => factors.push((name.clone(), exponent * power)),
+            }
+        }
+        factors.retain(|(_, exponent)| *exponent != 0);
+        Unit {
+            factors,
+            scale: self.scale * other.scale.powi(power),
+            offset: 0.0,
+            dimension: self.dimension.combine(&other.dimension, power),
+        }
+    }
+
+    /// The unit raised to an integer power
+    pub fn powi(&self, power: i32) -> Unit {
+        Unit {
+            factors: self
+                .factors
+                .iter()
+                .map(|(name, exponent)| (name.clone(), exponent * power))
+                .filter(|(_, exponent)| *exponent != 0)
+                .collect(),
+            scale: self.scale.powi(power),
+            offset: 0.0,
+            dimension: Dimension::default().combine(&self.dimension, power),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_factors(&self.factors))
+    }
+}
+
+/// A number with a unit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+/// Error from combining or converting quantities whose dimensions differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct DimensionMismatch {
+    pub left: String,
+    pub right: String,
+}
+
+impl Quantity {
+    /// Creates a quantity of `value` times `unit`
+    pub fn new(value: f64, unit: Unit) -> Self {
+        Self { value, unit }
+    }
+
+    /// The same amount expressed in another unit of the same dimension
+    pub fn convert(&self, unit: &Unit) -> Result<Quantity, DimensionMismatch> {
+        self.check_dimension(&unit.dimension, unit.to_string())?;
+        let base = self.value * self.unit.scale + self.unit.offset;
+        Ok(Quantity::new((base - unit.offset) / unit.scale, unit.clone()))
+    }
+
+    /// Sum in the unit of `self`; offsets are ignored, so `30 degC + 5 K` is `35 degC`
+    pub fn add(&self, other: &Quantity) -> Result<Quantity, DimensionMismatch> {
+        Ok(Quantity::new(self.value + self.delta(other)?, self.unit.clone()))
+    }
+
+    /// Difference in the unit of `self`
+    pub fn sub(&self, other: &Quantity) -> Result<Quantity, DimensionMismatch> {
+        Ok(Quantity::new(self.value - self.delta(other)?, self.unit.clone()))
+    }
+
+    /// Remainder in the unit of `self`
+    pub fn rem(&self, other: &Quantity) -> Result<Quantity, DimensionMismatch> {
+        Ok(Quantity::new(self.value % self.delta(other)?, self.unit.clone()))
+    }
+
+    /// Product, `power` being -1 for a quotient
+    pub fn combine(&self, other: &Quantity, power: i32) -> Quantity {
+        let value = if power < 0 {
+            self.value / other.value
+        } else {
+            self.value * other.value
+        };
+        Quantity::new(value, self.unit.combine(&other.unit, power))
+    }
+
+    /// The quantity raised to an integer power
+    pub fn powi(&self, power: i32) -> Quantity {
+        Quantity::new(self.value.powi(power), self.unit.powi(power))
+    }
+
+    /// The value in SI base units when the quantity has no dimension, e.g. 1000 for `km/m`
+    pub fn dimensionless_value(&self) -> Option<f64> {
+        self.unit
+            .dimension
+            .is_dimensionless()
+            .then_some(self.value * self.unit.scale)
+    }
+
+    /// `other` measured in the unit of `self`, as a difference
+    fn delta(&self, other: &Quantity) -> Result<f64, DimensionMismatch> {
+        self.check_dimension(&other.unit.dimension, other.unit.to_string())?;
+        Ok(other.value * other.unit.scale / self.unit.scale)
+    }
+
+    fn check_dimension(&self, dimension: &Dimension, unit: String) -> Result<(), DimensionMismatch> {
+        if self.unit.dimension == *dimension {
+            Ok(())
+        } else {
+            Err(DimensionMismatch {
+                left: self.unit.to_string(),
+                right: unit,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+/// A named unit in the registry
+#[derive(Debug, Clone, PartialEq)]
+struct UnitDef {
+    scale: f64,
+    offset: f64,
+    dimension: Dimension,
+
+    /// Whether SI prefixes may be attached, e.g. `k` in `km`
+    prefix: bool,
+}
+
+/// How a unit is written in a registry file
+#[derive(Debug, Clone, Deserialize)]
+struct UnitSpec {
+    /// Base dimension this unit measures, for base units
+    base: Option<String>,
+
+    #[serde(default = "one")]
+    scale: f64,
+
+    /// Unit expression the unit is a multiple of, e.g. `"kg*m/s^2"`
+    unit: Option<String>,
+
+    #[serde(default)]
+    offset: f64,
+
+    #[serde(default)]
+    prefix: bool,
+
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    prefixes: HashMap<String, f64>,
+
+    #[serde(default)]
+    units: HashMap<String, UnitSpec>,
+}
+
+/// Known units and SI prefixes
+#[derive(Debug, Clone, Default)]
+pub struct UnitRegistry {
+    units: HashMap<String, UnitDef>,
+
+    /// Prefixes with their factors, longest first so `da` is tried before `d`
+    prefixes: Vec<(String, f64)>,
+}
+
+impl UnitRegistry {
+    /// The built-in registry, parsed once and shared
+    pub fn builtin() -> Arc<UnitRegistry> {
+        static BUILTIN: OnceLock<Arc<UnitRegistry>> = OnceLock::new();
+        BUILTIN
+            .get_or_init(|| {
+                let mut registry = UnitRegistry::default();
+                registry
+                    .extend_from_toml(BUILTIN_UNITS)
+                    .expect("built-in unit registry is valid");
+                Arc::new(registry)
+            })
+            .clone()
+    }
+
+    /// Adds the prefixes and units of a registry file
+    ///
+    /// Units may refer to units of the registry and to each other in any order. Nothing is added
+    /// when the file is invalid.
+    pub fn extend_from_toml(&mut self, source: &str) -> Result<(), String> {
+        let file: RegistryFile = toml::from_str(source).map_err(|e| e.to_string())?;
+        let mut extended = self.clone();
+        for (prefix, factor) in file.prefixes {
+            if !(factor.is_finite() && factor > 0.0) {
+                return Err(format!("prefix '{}' must have a positive factor", prefix));
+            }
+            extended.prefixes.retain(|(existing, _)| *existing != prefix);
+            extended.prefixes.push((prefix, factor));
+        }
+        extended
+            .prefixes
+            .sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+        let mut names: Vec<&String> = file.units.keys().collect();
+        names.sort();
+        let mut done = HashSet::new();
+        for name in names {
+            extended.define(name, &file.units, &mut done, &mut Vec::new())?;
+        }
+        for (name, spec) in &file.units {
+            let def = extended.units[name].clone();
+            for alias in &spec.aliases {
+                extended.units.insert(alias.clone(), def.clone());
+            }
+        }
+        *self = extended;
+        Ok(())
+    }
+
+    /// Adds every `.cmunit` file in `dir`, returning a warning for each file that is skipped
+    pub fn load_dir(&mut self, dir: &Path) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return warnings;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|s| s.to_str()) == Some(UNIT_FILE_EXTENSION))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let result = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| self.extend_from_toml(&source));
+            if let Err(reason) = result {
+                warnings.push(format!("Warning: Skipping unit file {:?}: {}", path, reason));
+            }
+        }
+        warnings
+    }
+
+    /// Resolves a unit expression, or returns the first name that is not a known unit
+    pub fn resolve(&self, expr: &UnitExpr) -> Result<Unit, String> {
+        let mut unit = Unit::default();
+        for (name, power) in &expr.factors {
+            let def = self.lookup(name).ok_or_else(|| name.clone())?;
+            let factor = Unit {
+                factors: vec![(name.clone(), 1)],
+                scale: def.scale,
+                offset: def.offset,
+                dimension: def.dimension,
+            };
+            unit = unit.combine(&factor, *power);
+        }
+        // Only a lone temperature scale keeps its zero point
+        if let [(name, 1)] = expr.factors.as_slice() {
+            unit.offset = self.lookup(name).map_or(0.0, |def| def.offset);
+        }
+        Ok(unit)
+    }
+
+    /// Names of every unit without prefixes, for suggestions
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.units.keys().map(String::as_str)
+    }
+
+    fn lookup(&self, name: &str) -> Option<UnitDef> {
+        if let Some(def) = self.units.get(name) {
+            return Some(def.clone());
+        }
+        self.prefixes.iter().find_map(|(prefix, factor)| {
+            let def = self.units.get(name.strip_prefix(prefix.as_str())?)?;
+            def.prefix.then(|| UnitDef {
+                scale: def.scale * factor,
+                ..def.clone()
+            })
+        })
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        specs: &HashMap<String, UnitSpec>,
+        done: &mut HashSet<String>,
+        resolving: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if resolving.iter().any(|pending| pending == name) {
+            return Err(format!("unit '{}' is defined in terms of itself", name));
+        }
+        let Some(spec) = specs.get(name).filter(|_| !done.contains(name)) else {
+            return Ok(());
+        };
+        if !(spec.scale.is_finite() && spec.scale > 0.0) {
+            return Err(format!("unit '{}' must have a positive scale", name));
+        }
+
+        let (scale, dimension) = match (&spec.base, &spec.unit) {
+            (Some(base), None) => {
+                let dimension = Dimension::base(base).ok_or_else(|| {
+                    format!(
+                        "unit '{}' has unknown base \"{}\" (expected one of {})",
+                        name,
+                        base,
+                        BASE_DIMENSIONS.join(", ")
+                    )
+                })?;
+                (spec.scale, dimension)
+            }
+            (None, Some(unit)) => {
+                let expr =
+                    parse_unit(unit).map_err(|e| format!("unit '{}': invalid unit \"{}\": {}", name, unit, e))?;
+                resolving.push(name.to_string());
+                // Units of the same file may be used before they are defined
+                for (factor, _) in &expr.factors {
+                    let unprefixed: Vec<String> = self
+                        .prefixes
+                        .iter()
+                        .filter_map(|(prefix, _)| factor.strip_prefix(prefix.as_str()))
+                        .map(str::to_string)
+                        .collect();
+                    self.define(factor, specs, done, resolving)?;
+                    for unit in unprefixed {
+                        self.define(&unit, specs, done, resolving)?;
+                    }
+                }
+                resolving.pop();
+                let unit = self
+                    .resolve(&expr)
+                    .map_err(|unknown| format!("unit '{}' uses unknown unit '{}'", name, unknown))?;
+                (spec.scale * unit.scale, unit.dimension)
+            }
+            _ => return Err(format!("unit '{}' needs either a base or a unit", name)),
+        };
+        self.units.insert(
+            name.to_string(),
+            UnitDef {
+                scale,
+                offset: spec.offset,
+                dimension,
+                prefix: spec.prefix,
+            },
+        );
+        done.insert(name.to_string());
+        Ok(())
+    }
+}
+
+/// Writes unit factors as `m*kg/s^2`, or `s^-1` when every power is negative
+pub fn format_factors(factors: &[(String, i32)]) -> String {
+    let power = |name: &str, exponent: i32| {
+        if exponent == 1 {
+            name.to_string()
+        } else {
+            format!("{}^{}", name, exponent)
+        }
+    };
+    let numerator: Vec<String> = factors
+        .iter()
+        .filter(|(_, exponent)| *exponent > 0)
+        .map(|(name, exponent)| power(name, *exponent))
+        .collect();
+    let denominator: Vec<String> = factors
+        .iter()
+        .filter(|(_, exponent)| *exponent < 0)
+        .map(|(name, exponent)| power(name, -exponent))
+        .collect();
+    if numerator.is_empty() {
+        let inverse: Vec<String> = factors.iter().map(|(name, exponent)| power(name, *exponent)).collect();
+        return inverse.join("*");
+    }
+    if denominator.is_empty() {
+        numerator.join("*")
+    } else {
+        format!("{}/{}", numerator.join("*"), denominator.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(registry: &UnitRegistry, source: &str) -> Unit {
+        registry.resolve(&parse_unit(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_units() {
+        let registry = UnitRegistry::builtin();
+        assert_eq!(unit(&registry, "km").scale, 1000.0);
+        assert_eq!(unit(&registry, "kg").dimension, Dimension::base("mass").unwrap());
+        assert!((unit(&registry, "mph").scale - 0.44704).abs() < 1e-12);
+        assert_eq!(unit(&registry, "N").dimension, unit(&registry, "kg*m/s^2").dimension);
+        assert_eq!(unit(&registry, "min").scale, 60.0);
+        assert_eq!(unit(&registry, "ms").scale, 1e-3);
+        assert_eq!(unit(&registry, "degC").offset, 273.15);
+        assert_eq!(unit(&registry, "degC^2").offset, 0.0);
+        assert_eq!(registry.resolve(&parse_unit("kft").unwrap()), Err("kft".to_string()));
+    }
+
+    #[test]
+    fn test_quantities() {
+        let registry = UnitRegistry::builtin();
+        let km = Quantity::new(5.0, unit(&registry, "km"));
+        let m = Quantity::new(300.0, unit(&registry, "m"));
+        assert_eq!(km.add(&m).unwrap().value, 5.3);
+        assert_eq!(m.convert(&unit(&registry, "km")).unwrap().value, 0.3);
+        assert_eq!(km.combine(&m, -1).dimensionless_value(), Some(5000.0 / 300.0));
+
+        let boiling = Quantity::new(100.0, unit(&registry, "degC"));
+        assert!((boiling.convert(&unit(&registry, "degF")).unwrap().value - 212.0).abs() < 1e-9);
+
+        let second = Quantity::new(1.0, unit(&registry, "s"));
+        let mismatch = km.add(&second).unwrap_err();
+        assert_eq!((mismatch.left.as_str(), mismatch.right.as_str()), ("km", "s"));
+        assert_eq!(second.powi(-2).unit.to_string(), "s^-2");
+    }
+
+    #[test]
+    fn test_registry_files() {
+        let mut registry = (*UnitRegistry::builtin()).clone();
+        registry
+            .extend_from_toml("[units.furlong]\nscale = 220\nunit = \"yd\"\n\n[units.fpf]\nunit = \"furlong/fortnight\"\n\n[units.fortnight]\nscale = 14\nunit = \"day\"\n")
+            .unwrap();
+        assert!((unit(&registry, "furlong").scale - 201.168).abs() < 1e-9);
+        assert_eq!(unit(&registry, "fpf").dimension, unit(&registry, "m/s").dimension);
+
+        assert!(registry
+            .extend_from_toml("[units.a1]\nunit = \"a2\"\n[units.a2]\nunit = \"a1\"\n")
+            .is_err());
+        assert!(registry.extend_from_toml("[units.x1]\nbase = \"colour\"\n").is_err());
+        assert!(registry.extend_from_toml("[units.x2]\nunit = \"bogus\"\n").is_err());
+        assert!(registry.lookup("a1").is_none());
+    }
+}
@@ -0,0 +1,619 @@
+//! Values produced by evaluation
+
+use std::fmt;
+
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::{BigInt, ToBigInt};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use super::decimal;
+use super::rational;
+use super::units::Quantity;
+
+/// How complex results are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ComplexDisplay {
+    /// `3 + 4i`
+    #[default]
+    Rectangular,
+
+    /// `5 ∠ 0.927295218`, magnitude and angle in radians
+    Polar,
+}
+
+/// How exact fractions are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FractionDisplay {
+    /// `7/2`
+    #[default]
+    Improper,
+
+    /// `3 1/2`
+    Mixed,
+
+    /// `3.5`, rounded to the decimal places
+    Decimal,
+}
+
+/// Display settings for [`Value::format_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub complex_display: ComplexDisplay,
+    pub fraction_display: FractionDisplay,
+    pub decimal_places: usize,
+
+    /// Whether to separate thousands with commas, e.g. `1,000,000`
+    pub group_digits: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            complex_display: ComplexDisplay::default(),
+            fraction_display: FractionDisplay::default(),
+            decimal_places: 10,
+            group_digits: false,
+        }
+    }
+}
+
+/// Result of evaluating an expression
+///
+/// Complex values only appear in complex mode; a complex result whose imaginary part is exactly
+/// zero is always stored as `Real`. Exact results are `Integer` when whole and `Rational`
+/// otherwise. Decimal values only appear in decimal mode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Real(f64),
+    Complex(Complex64),
+
+    /// Exact integer of any size, stored as a string when serialised
+    Integer(#[serde(with = "string")] BigInt),
+
+    /// Exact fraction that is not an integer, stored as a string such as `"1/3"` when serialised
+    Rational(#[serde(with = "string")] BigRational),
+
+    /// Arbitrary-precision decimal, stored as a string when serialised
+    Decimal(BigDecimal),
+
+    /// Number with a unit, e.g. `5 km`
+    Quantity(Box<Quantity>),
+
+    /// List such as `[1, 2, 3.5]`, stored as `{ list = [...] }` so it is not read back as complex
+    List(#[serde(with = "list")] Vec<Value>),
+
+    /// Matrix of numbers given by its rows, all of the same non-zero length, e.g. `[[1, 2], [3, 4]]`;
+    /// stored as `{ matrix = [[...], ...] }`
+    Matrix(#[serde(with = "matrix")] Vec<Vec<Value>>),
+
+    /// Text such as a file name, stored as `{ text = "..." }` so it is not read back as a number
+    Text(#[serde(with = "text")] String),
+}
+
+impl Value {
+    /// Wraps a complex number, collapsing it to `Real` when the imaginary part is zero
+    pub fn from_complex(z: Complex64) -> Self {
+        if z.im == 0.0 {
+            Value::Real(z.re)
+        } else {
+            Value::Complex(z)
+        }
+    }
+
+    /// Wraps an exact result, as `Integer` when it is whole
+    pub fn from_rational(r: BigRational) -> Self {
+        if r.is_integer() {
+            Value::Integer(r.to_integer())
+        } else {
+            Value::Rational(r)
+        }
+    }
+
+    /// The value as a fraction if it is exact
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::Integer(n) => Some(BigRational::from_integer(n.clone())),
+            Value::Rational(r) => Some(r.clone()),
+            _ => None,
+        }
+    }
+
+    /// The value as a fraction if it is exact or a decimal, which converts without loss
+    pub fn to_exact(&self) -> Option<BigRational> {
+        match self {
+            Value::Decimal(d) => Some(rational::from_decimal(d)),
+            value => value.to_rational(),
+        }
+    }
+
+    /// The value as an exact integer if it is a whole real number
+    pub fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Value::Real(x) if x.is_finite() && x.fract() == 0.0 => x.to_bigint(),
+            Value::Integer(n) => Some(n.clone()),
+            Value::Rational(r) if r.is_integer() => Some(r.numer().clone()),
+            Value::Decimal(d) if d.is_integer() => d.to_bigint(),
+            _ => None,
+        }
+    }
+
+    /// The value as a float if it is real; exact and decimal values are rounded to the nearest float
+    pub fn as_real(&self) -> Option<f64> {
+        match self {
+            Value::Real(x) => Some(*x),
+            Value::Complex(_) => None,
+            Value::Integer(n) => Some(n.to_f64().unwrap_or(f64::NAN)),
+            Value::Rational(r) => Some(r.to_f64().unwrap_or(f64::NAN)),
+            Value::Decimal(d) => Some(decimal::to_f64(d)),
+            Value::Quantity(_) | Value::List(_) | Value::Matrix(_) | Value::Text(_) => None,
+        }
+    }
+
+    /// The value as a complex number
+    pub fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Real(x) => Complex64::new(*x, 0.0),
+            Value::Complex(z) => *z,
+            Value::Integer(_)
+            | Value::Rational(_)
+            | Value::Decimal(_)
+            | Value::Quantity(_)
+            | Value::List(_)
+            | Value::Matrix(_)
+            | Value::Text(_) => Complex64::new(self.as_real().unwrap_or(f64::NAN), 0.0),
+        }
+    }
+
+    /// Whether the value is an exact fraction that is not an integer, e.g. `1/3`
+    pub fn is_fraction(&self) -> bool {
+        matches!(self, Value::Rational(_))
+    }
+
+    /// Whether either component, or any element of a list or matrix, is NaN
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Value::Real(x) => x.is_nan(),
+            Value::Complex(z) => z.re.is_nan() || z.im.is_nan(),
+            Value::Integer(_) | Value::Rational(_) | Value::Decimal(_) | Value::Text(_) => false,
+            Value::Quantity(q) => q.value.is_nan(),
+            Value::List(items) => items.iter().any(Value::is_nan),
+            Value::Matrix(rows) => rows.iter().flatten().any(Value::is_nan),
+        }
+    }
+
+    /// Formats the value rounded to `decimal_places`, dropping trailing zeros
+    ///
+    /// Fractions are shown as improper fractions; see [`Value::format_with`].
+    pub fn format(&self, display: ComplexDisplay, decimal_places: usize) -> String {
+        self.format_with(&FormatOptions {
+            complex_display: display,
+            decimal_places,
+            ..FormatOptions::default()
+        })
+    }
+
+    /// Like [`Value::format`], with every display setting chosen
+    pub fn format_with(&self, options: &FormatOptions) -> String {
+        let FormatOptions {
+            complex_display: display,
+            fraction_display: fraction,
+            decimal_places,
+            group_digits,
+        } = *options;
+        let text = match self {
+            Value::Real(x) => format_real(*x, decimal_places),
+            Value::Complex(z) => match display {
+                ComplexDisplay::Rectangular => format_rectangular(*z, decimal_places),
+                ComplexDisplay::Polar => format!(
+                    "{} ∠ {}",
+                    format_real(z.norm(), decimal_places),
+                    format_real(z.arg(), decimal_places)
+                ),
+            },
+            Value::Integer(n) => n.to_string(),
+            Value::Rational(r) => format_rational(r, fraction, decimal_places),
+            Value::Decimal(d) => format_decimal(d, decimal_places),
+            Value::Quantity(q) => return format!("{} {}", Value::Real(q.value).format_with(options), q.unit),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.format_with(options)).collect();
+                return format!("[{}]", items.join(", "));
+            }
+            Value::Matrix(rows) => {
+                let rows: Vec<String> = rows
+                    .iter()
+                    .map(|row| Value::List(row.clone()).format_with(options))
+                    .collect();
+                return format!("[{}]", rows.join(", "));
+            }
+            Value::Text(_) => return self.to_string(),
+        };
+        if group_digits {
+            group_thousands(&text)
+        } else {
+            text
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Real(x)
+    }
+}
+
+impl From<Complex64> for Value {
+    fn from(z: Complex64) -> Self {
+        Value::from_complex(z)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Real(x) => write!(f, "{}", x),
+            Value::Complex(z) if z.im < 0.0 => write!(f, "{} - {}i", z.re, -z.im),
+            Value::Complex(z) => write!(f, "{} + {}i", z.re, z.im),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Rational(r) => write!(f, "{}", r),
+            Value::Decimal(d) => write!(f, "{}", d.normalized().to_plain_string()),
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Matrix(rows) => {
+                write!(f, "[")?;
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", Value::List(row.clone()))?;
+                }
+                write!(f, "]")
+            }
+            Value::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+/// Rounds to `decimal_places` and trims trailing zeros; very large or tiny values keep full precision
+fn format_real(x: f64, decimal_places: usize) -> String {
+    let tiny = x != 0.0 && x.abs() < 10f64.powi(-(decimal_places.min(300) as i32));
+    if !x.is_finite() || x.abs() >= 1e16 || tiny {
+        return x.to_string();
+    }
+    let rounded = format!("{:.*}", decimal_places, x);
+    let trimmed = if rounded.contains('.') {
+        rounded.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        rounded.as_str()
+    };
+    match trimmed {
+        "-0" => "0".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Rounds to `decimal_places` and trims trailing zeros, never switching to exponent notation
+fn format_decimal(d: &BigDecimal, decimal_places: usize) -> String {
+    let rounded = d.with_scale_round(decimal_places as i64, RoundingMode::HalfEven);
+    rounded.normalized().to_plain_string()
+}
+
+/// Fractions as chosen
+fn format_rational(r: &BigRational, fraction: FractionDisplay, decimal_places: usize) -> String {
+    match fraction {
+        FractionDisplay::Improper => r.to_string(),
+        FractionDisplay::Mixed if r.abs() < BigRational::from_integer(1.into()) => r.to_string(),
+        FractionDisplay::Mixed => {
+            let whole = r.trunc();
+            let rest = (r - &whole).abs();
+            format!("{} {}", whole.numer(), rest)
+        }
+        FractionDisplay::Decimal => {
+            // Round half away from zero at the last place by exact integer division
+            let scale = BigInt::from(10).pow(decimal_places as u32);
+            let numer: BigInt = r.numer().abs() * scale * 2 + r.denom();
+            let denom: BigInt = r.denom() * 2;
+            let digits = format!("{:0>width$}", (numer / denom).to_string(), width = decimal_places + 1);
+            let (whole, fractional) = digits.split_at(digits.len() - decimal_places);
+            let fractional = fractional.trim_end_matches('0');
+            let sign = if r.is_negative() && !(whole == "0" && fractional.is_empty()) {
+                "-"
+            } else {
+                ""
+            };
+            if fractional.is_empty() {
+                format!("{}{}", sign, whole)
+            } else {
+                format!("{}{}.{}", sign, whole, fractional)
+            }
+        }
+    }
+}
+
+/// Separates thousands in every run of integer digits, leaving digits after a point alone
+fn group_thousands(text: &str) -> String {
+    let mut grouped = String::with_capacity(text.len() + text.len() / 3);
+    let mut chars = text.char_indices().peekable();
+    let mut after_point = false;
+    while let Some((start, c)) = chars.next() {
+        if !c.is_ascii_digit() {
+            after_point = c == '.';
+            grouped.push(c);
+            continue;
+        }
+        let mut end = start + 1;
+        while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            end = i + 1;
+        }
+        let digits = &text[start..end];
+        if after_point {
+            grouped.push_str(digits);
+        } else {
+            for (i, digit) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                    grouped.push(',');
+                }
+                grouped.push(digit);
+            }
+        }
+        after_point = false;
+    }
+    grouped
+}
+
+/// `a + bi`, omitting a part that rounds to zero or is negligible next to the other one
+fn format_rectangular(z: Complex64, decimal_places: usize) -> String {
+    // Rounding residue such as the 1.2e-16i in exp(i * pi) is not worth showing
+    let half_unit = 0.5 * 10f64.powi(-(decimal_places.min(300) as i32));
+    let negligible = |part: f64, other: f64| part.abs() < half_unit || part.abs() <= 1e-12 * other.abs();
+    let re = format_real(z.re, decimal_places);
+    let im = match format_real(z.im.abs(), decimal_places) {
+        one if one == "1" => "i".to_string(),
+        other => format!("{}i", other),
+    };
+    let sign = if z.im < 0.0 { "-" } else { "+" };
+    if negligible(z.im, z.re) {
+        re
+    } else if negligible(z.re, z.im) {
+        if z.im < 0.0 {
+            format!("-{}", im)
+        } else {
+            im
+        }
+    } else {
+        format!("{} {} {}", re, sign, im)
+    }
+}
+
+/// Serialises exact numbers as strings such as `"1/3"`, which read back unambiguously
+mod string {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Serialises lists as a table with a single `list` key
+mod list {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Value;
+
+    #[derive(Serialize)]
+    struct ListRef<'a> {
+        list: &'a [Value],
+    }
+
+    #[derive(Deserialize)]
+    struct List {
+        list: Vec<Value>,
+    }
+
+    pub fn serialize<S: Serializer>(items: &[Value], serializer: S) -> Result<S::Ok, S::Error> {
+        ListRef { list: items }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Value>, D::Error> {
+        List::deserialize(deserializer).map(|list| list.list)
+    }
+}
+
+/// Serialises matrices as a table with a single `matrix` key
+mod matrix {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Value;
+
+    #[derive(Serialize)]
+    struct MatrixRef<'a> {
+        matrix: &'a [Vec<Value>],
+    }
+
+    #[derive(Deserialize)]
+    struct Matrix {
+        matrix: Vec<Vec<Value>>,
+    }
+
+    pub fn serialize<S: Serializer>(rows: &[Vec<Value>], serializer: S) -> Result<S::Ok, S::Error> {
+        MatrixRef { matrix: rows }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<Value>>, D::Error> {
+        Matrix::deserialize(deserializer).map(|matrix| matrix.matrix)
+    }
+}
+
+/// Serialises text as a table with a single `text` key
+mod text {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    struct TextRef<'a> {
+        text: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    struct Text {
+        text: String,
+    }
+
+    pub fn serialize<S: Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        TextRef { text }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Text::deserialize(deserializer).map(|text| text.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Value::Real(0.1 + 0.2).format(ComplexDisplay::Rectangular, 10), "0.3");
+        assert_eq!(Value::Real(-0.0).format(ComplexDisplay::Rectangular, 10), "0");
+        assert_eq!(
+            Value::Real(1e-12).format(ComplexDisplay::Rectangular, 10),
+            "0.000000000001"
+        );
+
+        let z = Value::from_complex(Complex64::new(3.0, -4.0));
+        assert_eq!(z.format(ComplexDisplay::Rectangular, 10), "3 - 4i");
+        assert_eq!(z.format(ComplexDisplay::Polar, 3), "5 ∠ -0.927");
+        assert_eq!(z.to_string(), "3 - 4i");
+        assert_eq!(
+            Value::from_complex(Complex64::new(0.0, -1.0)).format(ComplexDisplay::Rectangular, 10),
+            "-i"
+        );
+        assert_eq!(Value::from_complex(Complex64::new(2.0, 0.0)), Value::Real(2.0));
+
+        let seven_halves = Value::Rational(BigRational::new((-7).into(), 2.into()));
+        let show = |fraction_display, decimal_places| {
+            seven_halves.format_with(&FormatOptions {
+                fraction_display,
+                decimal_places,
+                ..FormatOptions::default()
+            })
+        };
+        assert_eq!(show(FractionDisplay::Improper, 10), "-7/2");
+        assert_eq!(show(FractionDisplay::Mixed, 10), "-3 1/2");
+        assert_eq!(show(FractionDisplay::Decimal, 10), "-3.5");
+        let two_thirds = Value::from_rational(BigRational::new(2.into(), 3.into()));
+        let options = |fraction_display| FormatOptions {
+            fraction_display,
+            decimal_places: 4,
+            ..FormatOptions::default()
+        };
+        assert_eq!(two_thirds.format_with(&options(FractionDisplay::Decimal)), "0.6667");
+        assert_eq!(two_thirds.format_with(&options(FractionDisplay::Mixed)), "2/3");
+        assert_eq!(
+            Value::from_rational(BigRational::new(6.into(), 3.into())),
+            Value::Integer(2.into())
+        );
+
+        let grouped = FormatOptions {
+            group_digits: true,
+            ..FormatOptions::default()
+        };
+        let million = Value::Integer(BigInt::from(-1_234_567));
+        assert_eq!(million.format_with(&grouped), "-1,234,567");
+        assert_eq!(Value::Real(12345.678901).format_with(&grouped), "12,345.678901");
+        assert_eq!(
+            Value::Rational(BigRational::new(1_000_001.into(), 1000.into())).format_with(&grouped),
+            "1,000,001/1,000"
+        );
+
+        let third = "0.333333333333333333333333".parse().unwrap();
+        assert_eq!(
+            Value::Decimal(third).format(ComplexDisplay::Rectangular, 20),
+            "0.33333333333333333333"
+        );
+        let big = "265252859812191058636308480000000".parse().unwrap();
+        assert_eq!(
+            Value::Decimal(big).format(ComplexDisplay::Rectangular, 10),
+            "265252859812191058636308480000000"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        let list = Value::List(vec![
+            Value::Integer(1.into()),
+            Value::Rational(BigRational::new(1.into(), 3.into())),
+            Value::Real(2.5),
+        ]);
+        assert_eq!(list.to_string(), "[1, 1/3, 2.5]");
+        let options = FormatOptions {
+            fraction_display: FractionDisplay::Decimal,
+            decimal_places: 3,
+            ..FormatOptions::default()
+        };
+        assert_eq!(list.format_with(&options), "[1, 0.333, 2.5]");
+        assert_eq!(list.as_real(), None);
+        assert!(Value::List(vec![Value::Real(f64::NAN)]).is_nan());
+
+        // Two reals must not read back as a complex number
+        let pair = Value::List(vec![Value::Real(1.5), Value::Real(2.0)]);
+        let json = serde_json::to_string(&pair).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), pair);
+        assert_eq!(
+            serde_json::from_str::<Value>(&serde_json::to_string(&list).unwrap()).unwrap(),
+            list
+        );
+
+        let text = Value::Text("12".to_string());
+        assert_eq!(text.to_string(), "\"12\"");
+        assert_eq!(
+            serde_json::from_str::<Value>(&serde_json::to_string(&text).unwrap()).unwrap(),
+            text
+        );
+
+        let matrix = Value::Matrix(vec![
+            vec![
+                Value::Integer(1.into()),
+                Value::Rational(BigRational::new(1.into(), 3.into())),
+                Value::Real(2.5),
+            ],
+            vec![Value::Real(4.0), Value::Real(f64::NAN), Value::Integer(6.into())],
+        ]);
+        assert_eq!(matrix.to_string(), "[[1, 1/3, 2.5], [4, NaN, 6]]");
+        assert_eq!(matrix.format_with(&options), "[[1, 0.333, 2.5], [4, NaN, 6]]");
+        assert!(matrix.is_nan());
+        let exact = Value::Matrix(vec![
+            vec![Value::Integer(1.into())],
+            vec![Value::Rational(BigRational::new(1.into(), 3.into()))],
+        ]);
+        assert_eq!(
+            serde_json::from_str::<Value>(&serde_json::to_string(&exact).unwrap()).unwrap(),
+            exact
+        );
+    }
+}
@@ -1,7 +1,20 @@
 //! Configuration module
+//!
+//! Settings are saved as `settings.toml` next to the memory file, so modes such as the angle unit
+//! survive restarts.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::calculator::{AngleMode, ComplexDisplay, FractionDisplay, WordSize};
 
 /// Application settings
-#[derive(Debug)]
+///
+/// Fields missing from the settings file keep their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     /// Whether to use safe evaluation mode
     pub safe_mode: bool,
@@ -11,6 +24,30 @@ pub struct Settings {
 
     /// Number of decimal places to display
     pub decimal_places: usize,
+
+    /// Whether `i` and complex results are enabled
+    pub complex_mode: bool,
+
+    /// How complex results are displayed
+    pub complex_display: ComplexDisplay,
+
+    /// How exact fractions such as `1/3 + 1/6` are displayed
+    pub fraction_display: FractionDisplay,
+
+    /// Whether to separate thousands in results, e.g. `1,000,000`
+    pub group_digits: bool,
+
+    /// Whether to compute with arbitrary-precision decimals, precise to `decimal_places`
+    pub decimal_mode: bool,
+
+    /// Whether the programmer panel is shown and integer results wrap to `word_size`
+    pub programmer_mode: bool,
+
+    /// Width and signedness of integers in programmer mode
+    pub word_size: WordSize,
+
+    /// Unit trigonometric functions take and return angles in
+    pub angle_mode: AngleMode,
 }
 
 impl Settings {
@@ -20,8 +57,43 @@ impl Settings {
             safe_mode: true,
             auto_save_history: true,
             decimal_places: 10,
+            complex_mode: false,
+            complex_display: ComplexDisplay::Rectangular,
+            fraction_display: FractionDisplay::Improper,
+            group_digits: true,
+            decimal_mode: false,
+            programmer_mode: false,
+            word_size: WordSize::default(),
+            angle_mode: AngleMode::default(),
         }
     }
+
+    /// Gets the settings file path in system-specific application data directory
+    pub fn get_settings_file_path() -> Result<PathBuf> {
+        let data_dir = dirs::data_local_dir().ok_or_else(|| anyhow::anyhow!("Failed to get system data directory"))?;
+        let app_dir = data_dir.join("calculatorMaxRs");
+        fs::create_dir_all(&app_dir)?;
+        Ok(app_dir.join("settings.toml"))
+    }
+
+    /// Loads settings from the system-specific settings file, or the defaults if there is none
+    pub fn load() -> Result<Self> {
+        let file_path = Self::get_settings_file_path()?;
+
+        if !file_path.exists() {
+            return Ok(Self::new());
+        }
+
+        let content = fs::read_to_string(&file_path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Saves settings to the system-specific settings file
+    pub fn save(&self) -> Result<()> {
+        let file_path = Self::get_settings_file_path()?;
+        fs::write(&file_path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
 }
 
 impl Default for Settings {
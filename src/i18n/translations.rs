@@ -116,7 +116,7 @@ impl Translations {
                         if let Some(text) = val.as_str() {
                             self.strings
                                 .entry(key.clone())
-                                .or_insert_with(HashMap::new)
+                                .or_default()
                                 .insert(*lang, text.to_string());
                         }
                     }
@@ -1,24 +1,41 @@
 //! Memory management module
 //!
-//! Handles saving and loading application memory (memory value and history)
+//! Handles saving and loading application memory (memory value, user variables and history)
 //! to system-specific application data directories.
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::calculator::Value;
+
 /// Application memory structure
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Memory {
-    /// Memory value (like 'm' in the calculator)
-    pub memory_value: f64,
+    /// Memory value (like 'm' in the calculator); files from before lists and exact values hold a
+    /// plain float, which reads back as a real value
+    pub memory_value: Value,
+
+    /// User variables defined in the calculator; complex values are stored as `[re, im]`
+    #[serde(default)]
+    pub variables: BTreeMap<String, Value>,
 
     /// Calculation history
     pub history: HashMap<String, String>,
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            memory_value: Value::Real(0.0),
+            variables: BTreeMap::new(),
+            history: HashMap::new(),
+        }
+    }
+}
+
 impl Memory {
     /// Creates a new empty memory
     pub fn new() -> Self {
@@ -76,13 +93,23 @@ impl Memory {
     }
 
     /// Sets the memory value
-    pub fn set_memory_value(&mut self, value: f64) {
+    pub fn set_memory_value(&mut self, value: Value) {
         self.memory_value = value;
     }
 
     /// Gets the memory value
-    pub fn get_memory_value(&self) -> f64 {
-        self.memory_value
+    pub fn get_memory_value(&self) -> &Value {
+        &self.memory_value
+    }
+
+    /// Replaces the stored user variables
+    pub fn set_variables(&mut self, variables: BTreeMap<String, Value>) {
+        self.variables = variables;
+    }
+
+    /// Gets the stored user variables
+    pub fn get_variables(&self) -> &BTreeMap<String, Value> {
+        &self.variables
     }
 
     /// Gets all history entries
@@ -1,20 +1,30 @@
 //! GUI implementation using egui
 
+use std::fs;
+use std::path::{Path, PathBuf};
+
 use anyhow::Result;
 use eframe::egui;
 
-use crate::calculator::mods::SimplifiedMod;
-use crate::calculator::{Evaluator, HistoryManager};
+use crate::calculator::ast::Span;
+use crate::calculator::csv::{CsvOptions, DELIMITERS};
+use crate::calculator::mods::{save_mod_file, ModType, SimplifiedMod};
+use crate::calculator::programmer::{self, WORD_SIZES};
+use crate::calculator::{calculus, distributions, matrix, ode, poly, regression, solver, stats, symbolic};
+use crate::calculator::{
+    ComplexDisplay, Evaluator, FormatOptions, FractionDisplay, HistoryManager, Outcome, Radix, Value,
+};
 use crate::config::Settings;
 use crate::i18n::translations::{Language, Translations};
 use crate::memory::Memory;
 
 /// Runs the GUI application
 pub fn run_gui() -> Result<()> {
-    let mut options = eframe::NativeOptions::default();
-
-    // Enable OpenGL renderer which generally has better font support
-    options.renderer = eframe::Renderer::Glow;
+    let options = eframe::NativeOptions {
+        // Enable OpenGL renderer which generally has better font support
+        renderer: eframe::Renderer::Glow,
+        ..Default::default()
+    };
 
     eframe::run_native(
         "Calculator Max",
@@ -42,7 +52,7 @@ fn setup_chinese_fonts(ctx: &egui::Context) {
         // Add the embedded Harmony OS Sans SC font
         fonts.font_data.insert(
             "HarmonyOS_Sans_SC".to_owned(),
-            egui::FontData::from_static(include_bytes!("../../assets/fonts/font.ttf")).into(),
+            egui::FontData::from_static(include_bytes!("../../assets/fonts/font.ttf")),
         );
 
         // Use it for both proportional and monospace text
@@ -84,6 +94,48 @@ fn setup_chinese_fonts(ctx: &egui::Context) {
     ctx.set_fonts(fonts);
 }
 
+/// Characters that can appear in a function, constant or mod name (e.g. `a.b.c`)
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Builds the layout for the expression field with the error range underlined in red
+fn error_layout_job(ui: &egui::Ui, text: &str, error_span: Option<Span>) -> egui::text::LayoutJob {
+    let normal = egui::TextFormat {
+        font_id: egui::TextStyle::Body.resolve(ui.style()),
+        color: ui.visuals().text_color(),
+        ..Default::default()
+    };
+    let mut job = egui::text::LayoutJob::default();
+
+    let range = error_span.and_then(|span| {
+        let end = span.end.min(text.len());
+        // Errors at the end of input have an empty span; underline the last character instead
+        let start = if span.start >= end {
+            text[..end].char_indices().last()?.0
+        } else {
+            span.start
+        };
+        let end = if start == end { text.len() } else { end };
+        (text.is_char_boundary(start) && text.is_char_boundary(end)).then_some(start..end)
+    });
+
+    match range {
+        Some(range) => {
+            let highlighted = egui::TextFormat {
+                color: egui::Color32::RED,
+                underline: egui::Stroke::new(2.0, egui::Color32::RED),
+                ..normal.clone()
+            };
+            job.append(&text[..range.start], 0.0, normal.clone());
+            job.append(&text[range.clone()], 0.0, highlighted);
+            job.append(&text[range.end..], 0.0, normal);
+        }
+        None => job.append(text, 0.0, normal),
+    }
+    job
+}
+
 /// Main application structure
 struct CalculatorApp {
     /// Current input expression
@@ -95,6 +147,12 @@ struct CalculatorApp {
     /// Error message if any
     error: String,
 
+    /// Suggestion for fixing the error, if any
+    error_hint: String,
+
+    /// Byte range of the expression the error refers to
+    error_span: Option<Span>,
+
     /// Warning messages if any
     warnings: Vec<String>,
 
@@ -104,6 +162,9 @@ struct CalculatorApp {
     /// Application settings
     settings: Settings,
 
+    /// Settings as last saved, so the file is only written when they change
+    saved_settings: Settings,
+
     /// Evaluator
     evaluator: Evaluator,
 
@@ -114,7 +175,10 @@ struct CalculatorApp {
     memory_manager: Memory,
 
     /// Memory value (like 'm' in the Python version)
-    memory: f64,
+    memory: Value,
+
+    /// Value on the result line, kept to reformat it when the fraction display changes
+    result_value: Option<Value>,
 
     /// Current language
     language: Language,
@@ -136,6 +200,79 @@ struct CalculatorApp {
 
     /// Whether to show mod list
     show_mod_list: bool,
+
+    /// Whether to show user variables
+    show_variables: bool,
+
+    /// Whether to show the CSV import panel
+    show_csv_import: bool,
+
+    /// CSV import panel state
+    csv_import: CsvImport,
+}
+
+/// State for the CSV import panel
+#[derive(Debug, Clone)]
+struct CsvImport {
+    /// Folder being browsed
+    directory: PathBuf,
+
+    /// Names in the folder, with whether each is a folder; refreshed when the folder changes
+    entries: Vec<(String, bool)>,
+
+    /// File to import
+    path: String,
+
+    /// Delimiter and header choices, detected when `None`
+    options: CsvOptions,
+
+    /// Success message
+    success_message: String,
+
+    /// Error message
+    error_message: String,
+}
+
+impl CsvImport {
+    /// Browses `directory`, listing sub-folders and files that may hold CSV data
+    fn open(&mut self, directory: PathBuf) {
+        let mut entries: Vec<(String, bool)> = fs::read_dir(&directory)
+            .map(|read_dir| {
+                read_dir
+                    .flatten()
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                        let is_data = Path::new(&name).extension().is_some_and(|ext| {
+                            ["csv", "tsv", "txt"]
+                                .iter()
+                                .any(|known| ext.eq_ignore_ascii_case(known))
+                        });
+                        (!name.starts_with('.') && (is_dir || is_data)).then_some((name, is_dir))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Folders first, then files, each alphabetically
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_lowercase().cmp(&b.0.to_lowercase())));
+        self.entries = entries;
+        self.directory = directory;
+    }
+}
+
+impl Default for CsvImport {
+    fn default() -> Self {
+        let mut csv_import = Self {
+            directory: PathBuf::new(),
+            entries: Vec::new(),
+            path: String::new(),
+            options: CsvOptions::default(),
+            success_message: String::new(),
+            error_message: String::new(),
+        };
+        csv_import.open(std::env::current_dir().unwrap_or_default());
+        csv_import
+    }
 }
 
 /// State for the mod creator UI
@@ -178,9 +315,20 @@ impl Default for CalculatorApp {
         let detected_language = Language::detect_system_language();
 
         // Create evaluator and get warnings
-        let evaluator = Evaluator::default();
+        let mut evaluator = Evaluator::default();
         let warnings: Vec<String> = evaluator.get_warnings().to_vec();
 
+        // Load settings from file and apply the modes they enable
+        let settings = Settings::load().unwrap_or_else(|e| {
+            eprintln!("Failed to load settings: {}", e);
+            Settings::new()
+        });
+        evaluator.set_safe_mode(settings.safe_mode);
+        evaluator.set_complex_mode(settings.complex_mode);
+        evaluator.set_decimal_mode(settings.decimal_mode.then_some(settings.decimal_places));
+        evaluator.set_word_size(settings.programmer_mode.then_some(settings.word_size));
+        evaluator.set_angle_mode(settings.angle_mode);
+
         // Load memory from file
         let memory_manager = Memory::load().unwrap_or_else(|e| {
             eprintln!("Failed to load memory: {}", e);
@@ -188,7 +336,14 @@ impl Default for CalculatorApp {
         });
 
         // Restore memory value
-        let memory = memory_manager.get_memory_value();
+        let memory = memory_manager.get_memory_value().clone();
+
+        // Restore user variables
+        for (name, value) in memory_manager.get_variables() {
+            if let Err(e) = evaluator.set_variable(name, value.clone()) {
+                eprintln!("Failed to restore variable '{}': {}", name, e);
+            }
+        }
 
         // Restore history
         let mut history = HistoryManager::default();
@@ -200,13 +355,17 @@ impl Default for CalculatorApp {
             expression: String::new(),
             result: String::new(),
             error: String::new(),
+            error_hint: String::new(),
+            error_span: None,
             warnings,
             history,
-            settings: Settings::default(),
+            saved_settings: settings.clone(),
+            settings,
             evaluator,
             show_history: false,
             memory_manager,
             memory,
+            result_value: None,
             language: detected_language,
             translations: Translations::default(),
             show_mod_creator: false,
@@ -214,6 +373,9 @@ impl Default for CalculatorApp {
             suggestions: Vec::new(),
             selected_suggestion: 0,
             show_mod_list: false,
+            show_variables: false,
+            show_csv_import: false,
+            csv_import: CsvImport::default(),
         }
     }
 }
@@ -258,7 +420,7 @@ impl CalculatorApp {
         }
 
         // Get the last token (word or partial word)
-        let tokens: Vec<&str> = self.expression.split(|c: char| !c.is_alphabetic()).collect();
+        let tokens: Vec<&str> = self.expression.split(|c: char| !is_ident_char(c)).collect();
         if let Some(last_token) = tokens.last() {
             if !last_token.is_empty() {
                 // Suggest built-in functions
@@ -291,6 +453,24 @@ impl CalculatorApp {
                     "s_circle",
                     "s_tri",
                     "s_rect",
+                    "trapezoid_area",
+                    "heron_triangle_area",
+                    "pythagorean_theorem",
+                    "atan2",
+                    "pow",
+                    "hypot",
+                    "max",
+                    "min",
+                    "sum",
+                    "avg",
+                    "gcd",
+                    "lcm",
+                    "is_close",
+                    "is_nan",
+                    "is_inf",
+                    "random",
+                    "randint",
+                    "uniform",
                 ];
 
                 // Filter functions that start with the last token
@@ -301,6 +481,71 @@ impl CalculatorApp {
                     }
                 }
 
+                // Suggest distributions with their parameters, e.g. normpdf(x, mu, sigma)
+                for function in distributions::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest statistics the same way, e.g. percentile(values, p)
+                for function in stats::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest matrix functions the same way, e.g. linsolve(A, b)
+                for function in matrix::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest polynomial functions the same way, e.g. polyroots(p)
+                for function in poly::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest fits the same way, e.g. fit_poly(xs, ys, degree)
+                for function in regression::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest solve the same way, e.g. solve(equation, x)
+                for function in solver::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+
+                // Suggest calculus, plus the bounded forms of sum and prod
+                for function in calculus::FUNCTIONS {
+                    if function.name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", function.name, function.params));
+                    }
+                }
+                for name in ["sum", "prod"] {
+                    if name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}(expr, k, from, to)", name));
+                    }
+                }
+
+                // Suggest symbolic differentiation and initial value problems
+                for (name, params) in symbolic::FUNCTIONS
+                    .iter()
+                    .map(|function| (function.name, function.params))
+                    .chain(ode::FUNCTIONS.iter().map(|function| (function.name, function.params)))
+                {
+                    if name.starts_with(last_token) {
+                        self.suggestions.push(format!("{}({})", name, params));
+                    }
+                }
+
                 // Suggest constants
                 let constants = ["pi", "e"];
                 for constant in constants.iter() {
@@ -309,12 +554,26 @@ impl CalculatorApp {
                     }
                 }
 
+                // Suggest user variables
+                for (name, _) in self.evaluator.list_variables() {
+                    if name.starts_with(last_token) && name != *last_token {
+                        self.suggestions.push(name);
+                    }
+                }
+
                 // Suggest custom mods
-                let mod_list = self.evaluator.list_mods();
+                let mut mod_list = self.evaluator.list_mods();
+                mod_list.sort();
                 for mod_name in mod_list {
                     if mod_name.starts_with(last_token) {
-                        // Get required variables for this mod
-                        if let Some(vars) = self.evaluator.get_required_vars(&mod_name) {
+                        let is_constant = self
+                            .evaluator
+                            .get_mod(&mod_name)
+                            .is_some_and(|mod_def| mod_def.mod_type == ModType::Num);
+                        if is_constant {
+                            // Constant mods are referenced like variables
+                            self.suggestions.push(mod_name);
+                        } else if let Some(vars) = self.evaluator.get_required_vars(&mod_name) {
                             let args = vars.join(", ");
                             self.suggestions.push(format!("{}({})", mod_name, args));
                         } else {
@@ -358,18 +617,39 @@ impl CalculatorApp {
     fn calculate(&mut self) {
         // Clear previous error
         self.error.clear();
-
-        match self.evaluator.evaluate(&self.expression) {
-            Ok(value) => {
-                self.result = value.to_string();
+        self.error_hint.clear();
+        self.error_span = None;
+
+        match self.evaluator.execute(&self.expression) {
+            Ok(Outcome::Defined(name)) => {
+                self.result_value = None;
+                // Show the definition; memory keeps the last numeric result
+                self.result = self
+                    .evaluator
+                    .get_function(&name)
+                    .map(|function| function.to_string())
+                    .unwrap_or(name);
+                self.history.add(self.expression.clone(), self.result.clone());
+                self.memory_manager
+                    .add_to_history(self.expression.clone(), self.result.clone());
+                self.memory_manager
+                    .set_variables(self.evaluator.list_variables().into_iter().collect());
+                if let Err(e) = self.memory_manager.save() {
+                    eprintln!("Failed to save memory: {}", e);
+                }
+            }
+            Ok(Outcome::Value(value)) => {
+                self.result = self.format_value(&value);
+                self.result_value = Some(value.clone());
                 // Add to history
                 self.history.add(self.expression.clone(), self.result.clone());
                 // Store in memory
+                self.memory_manager.set_memory_value(value.clone());
                 self.memory = value;
-                // Update memory manager
-                self.memory_manager.set_memory_value(value);
                 self.memory_manager
                     .add_to_history(self.expression.clone(), self.result.clone());
+                self.memory_manager
+                    .set_variables(self.evaluator.list_variables().into_iter().collect());
                 // Save memory to file
                 if let Err(e) = self.memory_manager.save() {
                     eprintln!("Failed to save memory: {}", e);
@@ -377,7 +657,252 @@ impl CalculatorApp {
             }
             Err(e) => {
                 self.error = e.to_string();
+                self.error_hint = e.suggestion.clone().unwrap_or_default();
+                self.error_span = Some(e.span);
                 self.result = "Error".to_string();
+                self.result_value = None;
+            }
+        }
+    }
+
+    /// Formats a result with the display settings
+    fn format_value(&self, value: &Value) -> String {
+        value.format_with(&FormatOptions {
+            complex_display: self.settings.complex_display,
+            fraction_display: self.settings.fraction_display,
+            decimal_places: self.settings.decimal_places,
+            group_digits: self.settings.group_digits,
+        })
+    }
+
+    /// Shows a matrix as a grid, right-aligning each column in a monospace font
+    fn show_matrix(&self, ui: &mut egui::Ui, id: usize, rows: &[Vec<Value>]) {
+        let cells: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(|value| self.format_value(value)).collect())
+            .collect();
+        let widths: Vec<usize> = (0..cells[0].len())
+            .map(|j| cells.iter().map(|row| row[j].chars().count()).max().unwrap_or(0))
+            .collect();
+        egui::Frame::group(ui.style()).show(ui, |ui| {
+            egui::Grid::new(("result_matrix", id))
+                .spacing([12.0, 2.0])
+                .show(ui, |ui| {
+                    for row in &cells {
+                        for (cell, width) in row.iter().zip(&widths) {
+                            ui.monospace(format!("{:>width$}", cell, width = width));
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    /// Shows the result in every base, the word size selector and a grid of toggleable bits
+    fn show_programmer_panel(&mut self, ui: &mut egui::Ui, display_language: Language) {
+        ui.heading(self.translations.get("programmer_heading", display_language));
+
+        ui.horizontal(|ui| {
+            ui.label(self.translations.get("word_size", display_language));
+            let mut changed = false;
+            for bits in WORD_SIZES {
+                changed |= ui
+                    .selectable_value(&mut self.settings.word_size.bits, bits, bits.to_string())
+                    .changed();
+            }
+            changed |= ui
+                .checkbox(
+                    &mut self.settings.word_size.signed,
+                    self.translations.get("signed", display_language),
+                )
+                .changed();
+            if changed {
+                let word_size = self.settings.word_size;
+                self.evaluator.set_word_size(Some(word_size));
+                // Show the current result as it reads in the new width
+                if let Some(Value::Integer(n)) = &self.result_value {
+                    let value = Value::Integer(word_size.wrap(n));
+                    self.result = self.format_value(&value);
+                    self.result_value = Some(value);
+                }
+            }
+        });
+
+        // Without a result the grid starts from zero, so a value can be built bit by bit
+        let word_size = self.settings.word_size;
+        let n = match &self.result_value {
+            Some(value) => match value.to_integer() {
+                Some(n) => n,
+                None => {
+                    ui.label(self.translations.get("programmer_not_integer", display_language));
+                    return;
+                }
+            },
+            None => Default::default(),
+        };
+
+        egui::Grid::new("programmer_bases").num_columns(2).show(ui, |ui| {
+            for radix in Radix::ALL {
+                ui.label(radix.label());
+                ui.monospace(programmer::format_radix(&n, radix, Some(word_size)));
+                ui.end_row();
+            }
+        });
+
+        // Most significant bit first, 16 bits per row
+        let mut toggled = None;
+        egui::Grid::new("programmer_bits").spacing([2.0, 2.0]).show(ui, |ui| {
+            for row in (0..word_size.bits).rev().step_by(16) {
+                for index in (row.saturating_sub(15)..=row).rev() {
+                    let set = programmer::bit(&n, index, Some(word_size));
+                    let response = ui
+                        .selectable_label(set, egui::RichText::new(if set { "1" } else { "0" }).monospace())
+                        .on_hover_text(format!("{} {}", self.translations.get("bit", display_language), index));
+                    if response.clicked() {
+                        toggled = Some(index);
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(index) = toggled {
+            let value = programmer::toggle_bit(&n, index, Some(word_size));
+            self.expression = programmer::format_radix(&value, Radix::Hexadecimal, Some(word_size));
+            self.calculate();
+        }
+    }
+
+    /// File browser and options for loading the numeric columns of a CSV file into list variables
+    fn show_csv_import_panel(&mut self, ui: &mut egui::Ui, display_language: Language) {
+        ui.heading(self.translations.get("csv_import_heading", display_language));
+
+        if !self.csv_import.success_message.is_empty() {
+            ui.colored_label(egui::Color32::GREEN, &self.csv_import.success_message);
+        }
+        if !self.csv_import.error_message.is_empty() {
+            ui.colored_label(egui::Color32::RED, &self.csv_import.error_message);
+        }
+
+        // Clicking a folder opens it and clicking a file selects it
+        let mut opened = None;
+        ui.horizontal(|ui| {
+            let parent = self.csv_import.directory.parent().map(Path::to_path_buf);
+            if ui
+                .add_enabled(parent.is_some(), egui::Button::new(".."))
+                .on_hover_text(self.translations.get("csv_parent_folder", display_language))
+                .clicked()
+            {
+                opened = parent;
+            }
+            ui.monospace(self.csv_import.directory.display().to_string());
+        });
+        egui::ScrollArea::vertical()
+            .id_source("csv_files")
+            .max_height(160.0)
+            .show(ui, |ui| {
+                let csv_import = &mut self.csv_import;
+                if csv_import.entries.is_empty() {
+                    ui.label(self.translations.get("csv_no_files", display_language));
+                }
+                for (name, is_dir) in &csv_import.entries {
+                    let path = csv_import.directory.join(name);
+                    if *is_dir {
+                        if ui.selectable_label(false, format!("{}/", name)).clicked() {
+                            opened = Some(path);
+                        }
+                    } else {
+                        let path = path.display().to_string();
+                        if ui.selectable_label(csv_import.path == path, name).clicked() {
+                            csv_import.path = path;
+                        }
+                    }
+                }
+            });
+        if let Some(directory) = opened {
+            self.csv_import.open(directory);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(self.translations.get("csv_file", display_language));
+            ui.text_edit_singleline(&mut self.csv_import.path);
+        });
+
+        let auto = self.translations.get("csv_auto", display_language);
+        let tab = self.translations.get("csv_tab", display_language);
+        let delimiter_text = |delimiter: Option<char>| match delimiter {
+            None => auto.clone(),
+            Some('\t') => tab.clone(),
+            Some(c) => format!("'{}'", c),
+        };
+        ui.horizontal(|ui| {
+            ui.label(self.translations.get("csv_delimiter", display_language));
+            egui::ComboBox::from_id_source("csv_delimiter")
+                .selected_text(delimiter_text(self.csv_import.options.delimiter))
+                .show_ui(ui, |ui| {
+                    for delimiter in std::iter::once(None).chain(DELIMITERS.map(Some)) {
+                        ui.selectable_value(
+                            &mut self.csv_import.options.delimiter,
+                            delimiter,
+                            delimiter_text(delimiter),
+                        );
+                    }
+                });
+
+            let header_choices = [
+                (None, auto.clone()),
+                (Some(true), self.translations.get("csv_header_yes", display_language)),
+                (Some(false), self.translations.get("csv_header_no", display_language)),
+            ];
+            ui.label(self.translations.get("csv_header", display_language));
+            let selected = header_choices
+                .iter()
+                .find(|(header, _)| *header == self.csv_import.options.header)
+                .map(|(_, text)| text.clone())
+                .unwrap_or_default();
+            egui::ComboBox::from_id_source("csv_header")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (header, text) in header_choices {
+                        ui.selectable_value(&mut self.csv_import.options.header, header, text);
+                    }
+                });
+        });
+
+        if ui
+            .add_enabled(
+                !self.csv_import.path.trim().is_empty(),
+                egui::Button::new(self.translations.get("csv_import_button", display_language)),
+            )
+            .clicked()
+        {
+            self.import_csv(display_language);
+        }
+        ui.label(self.translations.get("csv_import_hint", display_language));
+    }
+
+    /// Loads the selected file into list variables and persists them
+    fn import_csv(&mut self, display_language: Language) {
+        self.csv_import.success_message.clear();
+        self.csv_import.error_message.clear();
+        let path = PathBuf::from(self.csv_import.path.trim());
+        match self.evaluator.import_csv(&path, &self.csv_import.options) {
+            Ok(names) if names.is_empty() => {
+                self.csv_import.error_message = self.translations.get("csv_no_numeric_columns", display_language);
+            }
+            Ok(names) => {
+                self.csv_import.success_message = format!(
+                    "{} {}",
+                    self.translations.get("csv_imported", display_language),
+                    names.join(", ")
+                );
+                self.memory_manager
+                    .set_variables(self.evaluator.list_variables().into_iter().collect());
+                if let Err(e) = self.memory_manager.save() {
+                    eprintln!("Failed to save memory: {}", e);
+                }
+            }
+            Err(e) => {
+                self.csv_import.error_message = e.to_string();
             }
         }
     }
@@ -391,6 +916,35 @@ impl CalculatorApp {
         }
     }
 
+    /// Deletes a user variable and persists the change
+    fn delete_variable(&mut self, name: &str) {
+        self.evaluator.delete_variable(name);
+        self.memory_manager
+            .set_variables(self.evaluator.list_variables().into_iter().collect());
+        if let Err(e) = self.memory_manager.save() {
+            eprintln!("Failed to save memory: {}", e);
+        }
+    }
+
+    /// Fills the mod creator with a user function so it can be saved as a mod file
+    fn promote_function(&mut self, name: &str) {
+        self.mod_creator = ModCreator::default();
+        match self.evaluator.function_as_mod(name) {
+            Ok(simplified_mod) => {
+                self.mod_creator.mod_id = format!("user.fn.{}", name);
+                self.mod_creator.name = simplified_mod.name.unwrap_or_default();
+                self.mod_creator.mod_type = "fun".to_string();
+                self.mod_creator.required_vars = simplified_mod.needs.unwrap_or_default().join(", ");
+                self.mod_creator.expression = simplified_mod.method.unwrap_or_default();
+                self.mod_creator.filename = name.to_string();
+            }
+            Err(e) => {
+                self.mod_creator.error_message = e.to_string();
+            }
+        }
+        self.show_mod_creator = true;
+    }
+
     /// Saves a mod to file
     fn save_mod(&mut self) {
         // Clear previous messages
@@ -449,10 +1003,6 @@ impl CalculatorApp {
             };
 
         // Create simplified mod structure
-        let mut mods_map = std::collections::HashMap::new();
-
-        let mod_id = self.mod_creator.mod_id.clone();
-
         let simplified_mod = if self.mod_creator.mod_type == "fun" {
             SimplifiedMod {
                 name: Some(self.mod_creator.name.clone()),
@@ -488,55 +1038,9 @@ impl CalculatorApp {
             }
         };
 
-        mods_map.insert(mod_id, simplified_mod);
-
-        // Serialize to TOML manually to match the desired format
-        let mut toml_content = String::new();
-        for (mod_id, mod_def) in mods_map {
-            toml_content.push_str(&format!("[{}]\n", mod_id));
-            if let Some(name) = mod_def.name {
-                toml_content.push_str(&format!("name = \"{}\"\n", name));
-            }
-            if let Some(mod_type) = mod_def.mod_type {
-                toml_content.push_str(&format!("type = \"{}\"\n", mod_type));
-            }
-            if let Some(needs) = mod_def.needs {
-                toml_content.push_str("needs = [");
-                for (i, need) in needs.iter().enumerate() {
-                    if i > 0 {
-                        toml_content.push_str(", ");
-                    }
-                    toml_content.push_str(&format!("\"{}\"", need));
-                }
-                toml_content.push_str("]\n");
-            }
-            if let Some(method) = mod_def.method {
-                toml_content.push_str(&format!("method = \"{}\"\n", method));
-            }
-            if let Some(res) = mod_def.res {
-                toml_content.push_str(&format!("res = {}\n", res));
-            }
-            toml_content.push_str("\n");
-        }
-
-        // Use the manually created TOML content
-        // Save to file
-        let filename = if self.mod_creator.filename.ends_with(".cmfun") {
-            self.mod_creator.filename.clone()
-        } else {
-            format!("{}.cmfun", self.mod_creator.filename)
-        };
-
-        // Create mods directory if it doesn't exist
-        let mods_dir = std::path::Path::new("mods");
-        if let Err(e) = std::fs::create_dir_all(mods_dir) {
-            self.mod_creator.error_message = format!("Failed to create mods directory: {}", e);
-            return;
-        }
-
-        let file_path = mods_dir.join(&filename);
-        match std::fs::write(&file_path, toml_content) {
-            Ok(_) => {
+        // Serialize and save through the shared mod file path
+        match save_mod_file(&self.mod_creator.filename, &self.mod_creator.mod_id, &simplified_mod) {
+            Ok(filename) => {
                 self.mod_creator.success_message = format!("Mod saved to {}", filename);
                 // Reload mods in the evaluator
                 if let Err(e) = self.evaluator.reload_mods() {
@@ -546,7 +1050,7 @@ impl CalculatorApp {
                 self.mod_creator = ModCreator::default();
             }
             Err(e) => {
-                self.mod_creator.error_message = format!("Failed to save mod: {}", e);
+                self.mod_creator.error_message = e.to_string();
             }
         }
     }
@@ -559,7 +1063,7 @@ impl eframe::App for CalculatorApp {
             // Apply selected suggestion
             if self.selected_suggestion < self.suggestions.len() {
                 let suggestion = self.suggestions[self.selected_suggestion].clone();
-                if let Some(last_space) = self.expression.rfind(|c: char| !c.is_alphabetic()) {
+                if let Some(last_space) = self.expression.rfind(|c: char| !is_ident_char(c)) {
                     self.expression = self.expression[..=last_space].to_string() + &suggestion;
                 } else {
                     self.expression = suggestion;
@@ -594,6 +1098,16 @@ impl eframe::App for CalculatorApp {
                 // Show error if any
                 if !self.error.is_empty() {
                     ui.colored_label(egui::Color32::RED, &self.error);
+                    if !self.error_hint.is_empty() {
+                        ui.colored_label(
+                            egui::Color32::DARK_GRAY,
+                            format!(
+                                "{} {}",
+                                self.translations.get("error_hint", display_language),
+                                self.error_hint
+                            ),
+                        );
+                    }
                 }
 
                 // Show warnings if any
@@ -605,11 +1119,19 @@ impl eframe::App for CalculatorApp {
                 ui.horizontal(|ui| {
                     ui.label(self.translations.get("expression", display_language));
 
-                    // Create a text edit widget
-                    let response = ui.text_edit_singleline(&mut self.expression);
+                    // Create a text edit widget, underlining the range the last error refers to
+                    let error_span = self.error_span;
+                    let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                        let mut job = error_layout_job(ui, text, error_span);
+                        job.wrap.max_width = wrap_width;
+                        ui.fonts(|fonts| fonts.layout_job(job))
+                    };
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.expression).layouter(&mut layouter));
 
                     // Generate suggestions when the text changes
                     if response.changed() {
+                        // The old error span no longer matches the edited text
+                        self.error_span = None;
                         self.generate_suggestions();
                     }
 
@@ -627,7 +1149,7 @@ impl eframe::App for CalculatorApp {
                                 // Apply selected suggestion
                                 if self.selected_suggestion < self.suggestions.len() {
                                     let suggestion = self.suggestions[self.selected_suggestion].clone();
-                                    if let Some(last_space) = self.expression.rfind(|c: char| !c.is_alphabetic()) {
+                                    if let Some(last_space) = self.expression.rfind(|c: char| !is_ident_char(c)) {
                                         self.expression = self.expression[..=last_space].to_string() + &suggestion;
                                     } else {
                                         self.expression = suggestion;
@@ -692,7 +1214,7 @@ impl eframe::App for CalculatorApp {
                         if let Some(index) = clicked_index {
                             if index < self.suggestions.len() {
                                 let suggestion = &self.suggestions[index];
-                                if let Some(last_space) = self.expression.rfind(|c: char| !c.is_alphabetic()) {
+                                if let Some(last_space) = self.expression.rfind(|c: char| !is_ident_char(c)) {
                                     self.expression = self.expression[..=last_space].to_string() + suggestion;
                                 } else {
                                     self.expression = suggestion.clone();
@@ -708,8 +1230,58 @@ impl eframe::App for CalculatorApp {
 
                 // Result display
                 ui.horizontal(|ui| {
+                    let angle_mode = self.settings.angle_mode;
+                    if ui
+                        .button(angle_mode.label())
+                        .on_hover_text(self.translations.get("angle_mode_hint", display_language))
+                        .clicked()
+                    {
+                        self.settings.angle_mode = angle_mode.next();
+                        self.evaluator.set_angle_mode(self.settings.angle_mode);
+                    }
                     ui.label(self.translations.get("result", display_language));
-                    ui.label(&self.result);
+                    // Matrices, and lists of them such as lu(A), show as grids instead of one line
+                    let matrices: Option<Vec<&[Vec<Value>]>> = match &self.result_value {
+                        Some(Value::Matrix(rows)) => Some(vec![rows]),
+                        Some(Value::List(items)) if !items.is_empty() => items
+                            .iter()
+                            .map(|item| match item {
+                                Value::Matrix(rows) => Some(rows.as_slice()),
+                                _ => None,
+                            })
+                            .collect(),
+                        _ => None,
+                    };
+                    match matrices {
+                        Some(matrices) => {
+                            for (i, rows) in matrices.into_iter().enumerate() {
+                                self.show_matrix(ui, i, rows);
+                            }
+                        }
+                        None => {
+                            ui.label(&self.result);
+                        }
+                    }
+                    // Fractions can be switched between 7/2, 3 1/2 and 3.5 in place
+                    if let Some(value) = self.result_value.clone().filter(Value::is_fraction) {
+                        let mut changed = false;
+                        for (display, key) in [
+                            (FractionDisplay::Improper, "fraction_display_improper"),
+                            (FractionDisplay::Mixed, "fraction_display_mixed"),
+                            (FractionDisplay::Decimal, "fraction_display_decimal"),
+                        ] {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut self.settings.fraction_display,
+                                    display,
+                                    self.translations.get(key, display_language),
+                                )
+                                .changed();
+                        }
+                        if changed {
+                            self.result = self.format_value(&value);
+                        }
+                    }
                 });
 
                 // Memory display
@@ -724,9 +1296,14 @@ impl eframe::App for CalculatorApp {
                         memory_label
                     };
                     ui.label(display_label);
-                    ui.label(self.memory.to_string());
+                    ui.label(self.format_value(&self.memory));
                 });
 
+                if self.settings.programmer_mode {
+                    ui.separator();
+                    self.show_programmer_panel(ui, display_language);
+                }
+
                 // Language selector
                 ui.horizontal(|ui| {
                     ui.label(self.translations.get("language_label", display_language));
@@ -762,6 +1339,13 @@ impl eframe::App for CalculatorApp {
                         self.show_mod_list = !self.show_mod_list;
                     }
 
+                    if ui
+                        .button(self.translations.get("variables", display_language))
+                        .clicked()
+                    {
+                        self.show_variables = !self.show_variables;
+                    }
+
                     if ui
                         .button(self.translations.get("create_mod", display_language))
                         .clicked()
@@ -769,6 +1353,22 @@ impl eframe::App for CalculatorApp {
                         self.show_mod_creator = !self.show_mod_creator;
                     }
 
+                    if ui
+                        .button(self.translations.get("import_csv", display_language))
+                        .clicked()
+                    {
+                        self.show_csv_import = !self.show_csv_import;
+                    }
+
+                    if ui
+                        .button(self.translations.get("programmer", display_language))
+                        .clicked()
+                    {
+                        self.settings.programmer_mode = !self.settings.programmer_mode;
+                        let word_size = self.settings.programmer_mode.then_some(self.settings.word_size);
+                        self.evaluator.set_word_size(word_size);
+                    }
+
                     if ui.button(self.translations.get("exit", display_language)).clicked() {
                         std::process::exit(0);
                     }
@@ -789,6 +1389,57 @@ impl eframe::App for CalculatorApp {
                     }
                     ui.label("(Uncheck for extended functionality)");
                 });
+                ui.horizontal(|ui| {
+                    if ui
+                        .checkbox(
+                            &mut self.settings.complex_mode,
+                            self.translations.get("complex_mode", display_language),
+                        )
+                        .changed()
+                    {
+                        self.evaluator.set_complex_mode(self.settings.complex_mode);
+                    }
+                    ui.add_enabled_ui(self.settings.complex_mode, |ui| {
+                        ui.radio_value(
+                            &mut self.settings.complex_display,
+                            ComplexDisplay::Rectangular,
+                            self.translations.get("complex_display_rectangular", display_language),
+                        );
+                        ui.radio_value(
+                            &mut self.settings.complex_display,
+                            ComplexDisplay::Polar,
+                            self.translations.get("complex_display_polar", display_language),
+                        );
+                    });
+                });
+                if ui
+                    .checkbox(
+                        &mut self.settings.group_digits,
+                        self.translations.get("group_digits", display_language),
+                    )
+                    .changed()
+                {
+                    if let Some(value) = &self.result_value {
+                        self.result = self.format_value(value);
+                    }
+                }
+                ui.horizontal(|ui| {
+                    let mut changed = ui
+                        .checkbox(
+                            &mut self.settings.decimal_mode,
+                            self.translations.get("decimal_mode", display_language),
+                        )
+                        .changed();
+                    ui.label(self.translations.get("decimal_places", display_language));
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut self.settings.decimal_places).range(0..=1000))
+                        .changed();
+                    if changed {
+                        let decimal_places = self.settings.decimal_places;
+                        self.evaluator
+                            .set_decimal_mode(self.settings.decimal_mode.then_some(decimal_places));
+                    }
+                });
 
                 // Show history if requested
                 if self.show_history {
@@ -798,6 +1449,67 @@ impl eframe::App for CalculatorApp {
                     ui.label(self.translations.get("history_auto_saved", display_language));
                 }
 
+                // Show user variables if requested
+                if self.show_variables {
+                    ui.separator();
+                    ui.heading(self.translations.get("variables_heading", display_language));
+
+                    let variables = self.evaluator.list_variables();
+                    if variables.is_empty() {
+                        ui.label(self.translations.get("no_variables", display_language));
+                    } else {
+                        let mut deleted: Option<String> = None;
+                        for (name, value) in &variables {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("{} = {}", name, self.format_value(value)));
+                                if ui.button(self.translations.get("delete", display_language)).clicked() {
+                                    deleted = Some(name.clone());
+                                }
+                            });
+                        }
+
+                        // Delete outside the loop to avoid borrowing issues
+                        if let Some(name) = deleted {
+                            self.delete_variable(&name);
+                        }
+                    }
+
+                    let functions = self.evaluator.list_functions();
+                    if !functions.is_empty() {
+                        ui.label(self.translations.get("functions_heading", display_language));
+
+                        let mut deleted: Option<String> = None;
+                        let mut promoted: Option<String> = None;
+                        for function in &functions {
+                            ui.horizontal(|ui| {
+                                ui.label(function.to_string());
+                                if ui
+                                    .button(self.translations.get("save_as_mod", display_language))
+                                    .clicked()
+                                {
+                                    promoted = Some(function.name.clone());
+                                }
+                                if ui.button(self.translations.get("delete", display_language)).clicked() {
+                                    deleted = Some(function.name.clone());
+                                }
+                            });
+                        }
+
+                        if let Some(name) = deleted {
+                            self.evaluator.delete_function(&name);
+                        }
+                        if let Some(name) = promoted {
+                            self.promote_function(&name);
+                        }
+                    }
+                }
+
+                // Show CSV import if requested
+                if self.show_csv_import {
+                    ui.separator();
+                    self.show_csv_import_panel(ui, display_language);
+                }
+
                 // Show mod creator if requested
                 if self.show_mod_creator {
                     ui.separator();
@@ -912,6 +1624,13 @@ impl eframe::App for CalculatorApp {
                                             self.translations.get("mod_name_display", display_language),
                                             display_name
                                         ));
+                                        if let (ModType::Num, Some(value)) = (mod_def.mod_type, mod_def.res.res) {
+                                            ui.label(format!(
+                                                "{}: {}",
+                                                self.translations.get("mod_value_display", display_language),
+                                                value
+                                            ));
+                                        }
                                     });
                                     ui.separator();
                                 }
@@ -924,5 +1643,13 @@ impl eframe::App for CalculatorApp {
                 ui.allocate_space(egui::Vec2::new(1.0, 10.0));
             }); // End of ScrollArea
         });
+
+        // Persist settings changed during this frame
+        if self.settings != self.saved_settings {
+            if let Err(e) = self.settings.save() {
+                eprintln!("Failed to save settings: {}", e);
+            }
+            self.saved_settings = self.settings.clone();
+        }
     }
 }
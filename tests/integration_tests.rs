@@ -2,7 +2,14 @@
 
 #[cfg(test)]
 mod tests {
-    use calculator_max::calculator::{math_functions, Evaluator};
+    use calculator_max::calculator::ast::Span;
+    use calculator_max::calculator::csv::CsvOptions;
+    use calculator_max::calculator::{
+        math_functions, programmer, AngleMode, ComplexDisplay, ErrorKind, Evaluator, FormatOptions, FractionDisplay,
+        Outcome, Radix, Value, WordSize,
+    };
+    use calculator_max::memory::Memory;
+    use num_complex::Complex64;
 
     #[test]
     fn test_basic_arithmetic() {
@@ -51,4 +58,1227 @@ mod tests {
         assert_eq!(evaluator.evaluate("s_tri(10, 5)").unwrap(), 25.0);
         assert_eq!(evaluator.evaluate("s_rect(4, 6)").unwrap(), 24.0);
     }
+
+    #[test]
+    fn test_operator_precedence() {
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(evaluator.evaluate("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(evaluator.evaluate("(2 + 3) * 4").unwrap(), 20.0);
+        assert_eq!(evaluator.evaluate("-2^2").unwrap(), -4.0);
+        assert_eq!(evaluator.evaluate("2^3^2").unwrap(), 512.0);
+        assert_eq!(evaluator.evaluate("7 % 4").unwrap(), 3.0);
+        assert_eq!(evaluator.evaluate("3! + 1").unwrap(), 7.0);
+    }
+
+    #[test]
+    fn test_standard_context_functions() {
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(evaluator.evaluate("max(1, 5, 3)").unwrap(), 5.0);
+        assert_eq!(evaluator.evaluate("min(4, 2)").unwrap(), 2.0);
+        assert_eq!(evaluator.evaluate("abs(-3)").unwrap(), 3.0);
+        assert!((evaluator.evaluate("ln(e)").unwrap() - 1.0).abs() < 1e-10);
+        assert!(evaluator.evaluate("sqrt(1, 2)").is_err());
+        assert!(evaluator.evaluate("unknown(1)").is_err());
+    }
+
+    #[test]
+    fn test_error_spans_and_suggestions() {
+        let mut evaluator = Evaluator::new();
+
+        let err = evaluator.evaluate("1 + sqr(4)").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnknownFunction("sqr".to_string()));
+        assert_eq!(err.span, Span::new(4, 10));
+        assert_eq!(err.suggestion.as_deref(), Some("did you mean 'sqrt'?"));
+
+        let err = evaluator.evaluate("s_tri(1)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ArityMismatch { found: 1, .. }));
+
+        let err = evaluator.evaluate("2 + sqrt(-1)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DomainError { .. }));
+        assert_eq!(err.span, Span::new(4, 12));
+
+        let err = evaluator.evaluate("1 / (2 - 2)").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::DivisionByZero);
+        assert_eq!(err.span, Span::new(4, 11));
+
+        let err = evaluator.evaluate("(1 + 2").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnbalancedParen(_)));
+        assert!(err.render("(1 + 2)").contains("\n  ^\n"));
+    }
+
+    #[test]
+    fn test_mod_functions_compose() {
+        // mods/test_correct.cmfun defines a.b.c(x, y) = x * y + 10
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(evaluator.evaluate("a.b.c(1, 2)").unwrap(), 12.0);
+        assert_eq!(evaluator.evaluate("2 * a.b.c(1, 2)").unwrap(), 24.0);
+        assert_eq!(evaluator.evaluate("a.b.c(max(1, 2), 3)").unwrap(), 16.0);
+        assert_eq!(evaluator.evaluate("sqrt(a.b.c(3, 2) - 0)").unwrap(), 4.0);
+        assert_eq!(evaluator.evaluate("a.b.c(a.b.c(0, 0), 1) + 1").unwrap(), 21.0);
+
+        let err = evaluator.evaluate("1 + a.b.c(1)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ArityMismatch { found: 1, .. }));
+        assert_eq!(err.span, Span::new(4, 12));
+        assert_eq!(err.suggestion.as_deref(), Some("a.b.c(x, y)"));
+    }
+
+    #[test]
+    fn test_constant_mods() {
+        // mods/test_correct.cmfun defines the constant b.c.d = 1.0
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(evaluator.evaluate("b.c.d").unwrap(), 1.0);
+        assert_eq!(evaluator.evaluate("b.c.d * 3 + a.b.c(b.c.d, 2)").unwrap(), 15.0);
+
+        let err = evaluator.evaluate("b.c.d(1)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnknownFunction(_)));
+    }
+
+    #[test]
+    fn test_variables_and_statements() {
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(evaluator.evaluate("x = 3.5").unwrap(), 3.5);
+        assert_eq!(evaluator.evaluate("x * 2").unwrap(), 7.0);
+
+        let result = evaluator
+            .evaluate("rate = 0.5; principal = 100\nn = 2; principal * (1 + rate)^n")
+            .unwrap();
+        assert_eq!(result, 225.0);
+        assert_eq!(evaluator.get_variable("rate"), Some(Value::Real(0.5)));
+
+        // Cached results must follow reassignment
+        assert_eq!(evaluator.evaluate("x * 2").unwrap(), 7.0);
+        evaluator.set_variable("x", 10.0).unwrap();
+        assert_eq!(evaluator.evaluate("x * 2").unwrap(), 20.0);
+        assert_eq!(evaluator.evaluate("x = x + 1").unwrap(), 11.0);
+        assert_eq!(evaluator.evaluate("x = x + 1").unwrap(), 12.0);
+
+        let names: Vec<String> = evaluator.list_variables().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["n", "principal", "rate", "x"]);
+
+        assert_eq!(evaluator.delete_variable("x"), Some(Value::Real(12.0)));
+        assert!(matches!(
+            evaluator.evaluate("x * 2").unwrap_err().kind,
+            ErrorKind::UnknownVariable(_)
+        ));
+
+        assert!(matches!(
+            evaluator.evaluate("pi = 3").unwrap_err().kind,
+            ErrorKind::ReadOnly(_)
+        ));
+        assert!(evaluator.set_variable("1x", 1.0).is_err());
+    }
+
+    #[test]
+    fn test_inline_function_definitions() {
+        let mut evaluator = Evaluator::new();
+
+        assert_eq!(
+            evaluator.execute("f(x, y) = x^2 + y").unwrap(),
+            Outcome::Defined("f".to_string())
+        );
+        assert_eq!(evaluator.evaluate("f(3, 1)").unwrap(), 10.0);
+        assert_eq!(evaluator.evaluate("sqrt(f(2, 5)) + f(1, 0)").unwrap(), 4.0);
+        assert_eq!(evaluator.get_function("f").unwrap().to_string(), "f(x, y) = x^2 + y");
+
+        // Redefinition replaces the cached result
+        evaluator.evaluate("g(x) = 2 * x; g(4)").unwrap();
+        evaluator.define_function("g", &["x"], "3 * x").unwrap();
+        assert_eq!(evaluator.evaluate("g(4)").unwrap(), 12.0);
+
+        // A line ending in a definition has no numeric value
+        assert!(matches!(
+            evaluator.evaluate("h(t) = t + 1").unwrap_err().kind,
+            ErrorKind::NoValue(_)
+        ));
+        assert!(matches!(
+            evaluator.evaluate("sqrt(x) = x").unwrap_err().kind,
+            ErrorKind::BuiltinFunction(_)
+        ));
+        assert!(matches!(
+            evaluator.evaluate("f(1)").unwrap_err().kind,
+            ErrorKind::ArityMismatch { .. }
+        ));
+
+        // Only self-contained functions can become mods
+        let simplified = evaluator.function_as_mod("f").unwrap();
+        assert_eq!(simplified.needs, Some(vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(simplified.method.as_deref(), Some("x^2 + y"));
+        evaluator.execute("k = 2; scaled(x) = k * x").unwrap();
+        assert!(evaluator.function_as_mod("scaled").is_err());
+
+        let names: Vec<String> = evaluator.list_functions().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, ["f", "g", "h", "scaled"]);
+        assert!(evaluator.delete_function("g").is_some());
+        assert!(matches!(
+            evaluator.evaluate("g(1)").unwrap_err().kind,
+            ErrorKind::UnknownFunction(_)
+        ));
+    }
+
+    #[test]
+    fn test_result_cache_tracks_dependencies() {
+        let mut evaluator = Evaluator::new();
+
+        // Impure calls are never memoised
+        let samples: Vec<f64> = (0..8).map(|_| evaluator.evaluate("random()").unwrap()).collect();
+        assert!(samples.windows(2).any(|pair| pair[0] != pair[1]));
+
+        // 'm' changes after every evaluation
+        evaluator.set_last_result(1.0);
+        assert_eq!(evaluator.evaluate("m + 1").unwrap(), 2.0);
+        assert_eq!(evaluator.evaluate("m + 1").unwrap(), 3.0);
+
+        // Variables read through user functions are tracked too
+        evaluator.execute("k = 2; scale(x) = k * x").unwrap();
+        assert_eq!(evaluator.evaluate("scale(5)").unwrap(), 10.0);
+        evaluator.set_variable("k", 3.0).unwrap();
+        assert_eq!(evaluator.evaluate("scale(5)").unwrap(), 15.0);
+
+        evaluator.clear_caches();
+        for _ in 0..3 {
+            assert_eq!(evaluator.evaluate("sqrt(16) + pi * 0").unwrap(), 4.0);
+        }
+        let results = evaluator.result_cache_stats();
+        assert_eq!((results.hits, results.misses), (2, 1));
+        let asts = evaluator.ast_cache_stats();
+        assert_eq!((asts.hits, asts.misses), (0, 1));
+
+        evaluator.set_cache_capacity(1);
+        evaluator.evaluate("1 + 1").unwrap();
+        evaluator.evaluate("2 + 2").unwrap();
+        assert_eq!(evaluator.result_cache_stats().len, 1);
+        assert!(evaluator.result_cache_stats().evictions >= 1);
+    }
+
+    #[test]
+    fn test_complex_mode() {
+        let mut evaluator = Evaluator::new();
+
+        // Real mode keeps the old behaviour
+        assert!(matches!(
+            evaluator.evaluate("sqrt(-1)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+        assert_eq!(evaluator.evaluate("2i").unwrap_err().kind, ErrorKind::ComplexDisabled);
+
+        evaluator.set_complex_mode(true);
+        assert_eq!(
+            evaluator.evaluate_value("sqrt(-4)").unwrap(),
+            Value::Complex(Complex64::new(0.0, 2.0))
+        );
+        assert_eq!(evaluator.evaluate("i^2").unwrap(), -1.0);
+        assert_eq!(evaluator.evaluate("(3 + 4i) * (3 - 4i)").unwrap(), 25.0);
+        assert_eq!(evaluator.evaluate("abs(3 + 4i)").unwrap(), 5.0);
+        assert_eq!(evaluator.evaluate("re(3 + 4i) + im(3 + 4i)").unwrap(), 7.0);
+        assert_eq!(
+            evaluator.evaluate_value("conj(1 + 2i)").unwrap(),
+            Value::Complex(Complex64::new(1.0, -2.0))
+        );
+        assert!((evaluator.evaluate("arg(i)").unwrap() - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert!((evaluator.evaluate("arg(-1)").unwrap() - std::f64::consts::PI).abs() < 1e-12);
+        assert!(matches!(
+            evaluator.evaluate("1 + i").unwrap_err().kind,
+            ErrorKind::NotReal(_)
+        ));
+
+        // Euler's identity, displayed without the rounding residue
+        let euler = evaluator.evaluate_value("exp(i * pi) + 1").unwrap();
+        assert_eq!(euler.format(ComplexDisplay::Rectangular, 10), "0");
+        let ln = evaluator.evaluate_value("ln(-1)").unwrap();
+        assert_eq!(ln.format(ComplexDisplay::Rectangular, 4), "3.1416i");
+
+        // Quadratic formula with a negative discriminant, as in mods/README.md
+        evaluator
+            .execute("quadratic(a, b, c) = (-b + sqrt(b * b - 4 * a * c)) / (2 * a)")
+            .unwrap();
+        let root = evaluator.evaluate_value("quadratic(1, 2, 5)").unwrap();
+        assert_eq!(root.format(ComplexDisplay::Rectangular, 10), "-1 + 2i");
+        assert_eq!(root.format(ComplexDisplay::Polar, 4), "2.2361 ∠ 2.0344");
+
+        // Complex values can be stored and 'i' cannot be reassigned
+        evaluator.evaluate_value("z = 1 - i").unwrap();
+        assert_eq!(
+            evaluator.get_variable("z"),
+            Some(Value::Complex(Complex64::new(1.0, -1.0)))
+        );
+        assert!(matches!(
+            evaluator.evaluate("i = 2").unwrap_err().kind,
+            ErrorKind::ReadOnly(_)
+        ));
+        assert!(matches!(
+            evaluator.evaluate("floor(z)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+
+        evaluator.set_complex_mode(false);
+        assert!(matches!(
+            evaluator.evaluate("i").unwrap_err().kind,
+            ErrorKind::UnknownVariable(_)
+        ));
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str, places: usize| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format(ComplexDisplay::Rectangular, places)
+        };
+        assert_eq!(
+            evaluator.evaluate_value("0.1 + 0.2").unwrap().to_string(),
+            "0.30000000000000004"
+        );
+
+        evaluator.set_decimal_mode(Some(30));
+        assert_eq!(evaluator.decimal_precision(), Some(50));
+        assert_eq!(evaluator.evaluate_value("0.1 + 0.2").unwrap().to_string(), "0.3");
+        assert_eq!(
+            evaluator.evaluate_value("factorial(30)").unwrap().to_string(),
+            "265252859812191058636308480000000"
+        );
+        assert_eq!(show(&mut evaluator, "2^100", 0), "1267650600228229401496703205376");
+        assert_eq!(show(&mut evaluator, "1 / 3", 30), "0.333333333333333333333333333333");
+        assert_eq!(show(&mut evaluator, "sqrt(2)", 30), "1.41421356237309504880168872421");
+        assert_eq!(show(&mut evaluator, "pi", 30), "3.14159265358979323846264338328");
+        assert_eq!(show(&mut evaluator, "e", 30), "2.718281828459045235360287471353");
+        assert_eq!(show(&mut evaluator, "ln(10)", 30), "2.302585092994045684017991454684");
+        assert_eq!(show(&mut evaluator, "exp(ln(7))", 30), "7");
+        assert_eq!(show(&mut evaluator, "sin(pi / 6)", 30), "0.5");
+        assert_eq!(show(&mut evaluator, "cos(1)", 30), "0.540302305868139717400936607443");
+        assert_eq!(
+            show(&mut evaluator, "atan(1) * 4", 30),
+            "3.14159265358979323846264338328"
+        );
+        assert_eq!(show(&mut evaluator, "2^0.5", 30), "1.41421356237309504880168872421");
+        assert_eq!(show(&mut evaluator, "log10(1000)", 30), "3");
+
+        // Functions without a decimal version fall back to floats, domain errors are unchanged
+        assert_eq!(show(&mut evaluator, "erf(0)", 10), "0");
+        assert!(matches!(
+            evaluator.evaluate("sqrt(-1)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+        assert!(matches!(
+            evaluator.evaluate("1 / (0.1 + 0.2 - 0.3)").unwrap_err().kind,
+            ErrorKind::DivisionByZero
+        ));
+        assert_eq!(evaluator.evaluate("x = 0.1 * 3; x - 0.3").unwrap(), 0.0);
+
+        evaluator.set_decimal_mode(None);
+        assert_eq!(evaluator.evaluate("0.1 + 0.2").unwrap(), 0.1 + 0.2);
+        assert_eq!(evaluator.evaluate("pi").unwrap(), std::f64::consts::PI);
+    }
+
+    #[test]
+    fn test_exact_fractions() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str, fraction_display| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions {
+                    fraction_display,
+                    ..FormatOptions::default()
+                })
+        };
+
+        assert_eq!(evaluator.evaluate_value("1/3 + 1/6").unwrap().to_string(), "1/2");
+        assert_eq!(show(&mut evaluator, "1/3 + 1/6", FractionDisplay::Decimal), "0.5");
+        assert_eq!(show(&mut evaluator, "(2/3)^-2 - 1", FractionDisplay::Improper), "5/4");
+        assert_eq!(show(&mut evaluator, "(2/3)^-2 - 1", FractionDisplay::Mixed), "1 1/4");
+        assert_eq!(show(&mut evaluator, "1/3 * 3", FractionDisplay::Improper), "1");
+        assert_eq!(show(&mut evaluator, "-7/3 % 2", FractionDisplay::Improper), "-1/3");
+        assert_eq!(
+            show(&mut evaluator, "sqrt(4/9) + abs(-1/9)", FractionDisplay::Improper),
+            "7/9"
+        );
+        assert_eq!(
+            show(&mut evaluator, "max(1/2, 2/3, 3/5)", FractionDisplay::Improper),
+            "2/3"
+        );
+        assert_eq!(evaluator.evaluate("1/3").unwrap(), 1.0 / 3.0);
+
+        // Integer functions
+        assert_eq!(evaluator.evaluate("gcd(12, 18)").unwrap(), 6.0);
+        assert_eq!(evaluator.evaluate("lcm(4, 6) / 2").unwrap(), 6.0);
+        assert!(matches!(
+            evaluator.evaluate("gcd(1/2, 3)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+
+        // Floats and transcendental functions leave exact arithmetic
+        assert!(matches!(
+            evaluator.evaluate_value("1/2 + 0.25").unwrap(),
+            Value::Real(_)
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("sqrt(2) / 2").unwrap(),
+            Value::Real(_)
+        ));
+        assert!(matches!(evaluator.evaluate_value("2^(1/2)").unwrap(), Value::Real(_)));
+        assert!(matches!(
+            evaluator.evaluate("1/(1/3 - 1/3)").unwrap_err().kind,
+            ErrorKind::DivisionByZero
+        ));
+
+        // Fractions survive variables and the memory file format
+        evaluator.evaluate("third = 1/3").unwrap();
+        assert_eq!(evaluator.evaluate_value("third * 6").unwrap().to_string(), "2");
+        let third = evaluator.get_variable("third").unwrap();
+        let json = serde_json::to_string(&third).unwrap();
+        assert_eq!(json, "\"1/3\"");
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), third);
+    }
+
+    #[test]
+    fn test_big_integers() {
+        let mut evaluator = Evaluator::new();
+        let exact =
+            |evaluator: &mut Evaluator, expression: &str| evaluator.evaluate_value(expression).unwrap().to_string();
+
+        let factorial = exact(&mut evaluator, "factorial(200)");
+        assert_eq!(factorial.len(), 375);
+        assert!(factorial.starts_with("78865786736479050355"));
+        assert_eq!(exact(&mut evaluator, "200!"), factorial);
+        assert_eq!(exact(&mut evaluator, "factorial(200) / factorial(199)"), "200");
+
+        let power = exact(&mut evaluator, "2^512");
+        assert_eq!(power.len(), 155);
+        assert!(power.ends_with("649006084096"));
+
+        let binomial = exact(&mut evaluator, "binomial(1000, 500)");
+        assert_eq!(binomial.len(), 300);
+        assert!(binomial.starts_with("27028824094543656951") && binomial.ends_with("9821216320"));
+        assert_eq!(exact(&mut evaluator, "binomial(5, 7)"), "0");
+
+        assert_eq!(exact(&mut evaluator, "modpow(3, 10^18, 10^9 + 7)"), "246336683");
+        assert_eq!(exact(&mut evaluator, "modpow(-2, 5, 7)"), "3");
+        assert!(matches!(
+            evaluator.evaluate("modpow(2, -1, 7)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+
+        // lcm no longer overflows u64
+        assert_eq!(
+            exact(&mut evaluator, "lcm(2^40 + 1, 2^40 - 1)"),
+            "1208925819614629174706175"
+        );
+        assert_eq!(math_functions::lcm(1 << 40, 3 << 40), Some(3 << 40));
+        assert_eq!(math_functions::lcm((1 << 40) + 1, (1 << 40) - 1), None);
+
+        // Integer floats that overflow the exact range are promoted
+        evaluator.set_variable("big", 2f64.powi(50)).unwrap();
+        assert_eq!(exact(&mut evaluator, "big * 2^10 + 1"), "1152921504606846977");
+        assert!(matches!(evaluator.evaluate_value("big * 1.5").unwrap(), Value::Real(_)));
+
+        let grouped = FormatOptions {
+            group_digits: true,
+            ..FormatOptions::default()
+        };
+        let million = evaluator.evaluate_value("10^6 * 1234").unwrap();
+        assert_eq!(million, Value::Integer(1_234_000_000.into()));
+        assert_eq!(million.format_with(&grouped), "1,234,000,000");
+    }
+
+    #[test]
+    fn test_programmer_mode() {
+        let mut evaluator = Evaluator::new();
+        let exact =
+            |evaluator: &mut Evaluator, expression: &str| evaluator.evaluate_value(expression).unwrap().to_string();
+
+        assert_eq!(exact(&mut evaluator, "0xFF + 0o17 + 0b1010"), "280");
+        assert_eq!(
+            exact(&mut evaluator, "0xFFFF_FFFF_FFFF_FFFF + 1"),
+            "18446744073709551616"
+        );
+        assert_eq!(exact(&mut evaluator, "0b1100 & 0b1010"), "8");
+        assert_eq!(exact(&mut evaluator, "0b1100 | 0b1010"), "14");
+        assert_eq!(exact(&mut evaluator, "0b1100 xor 0b1010"), "6");
+        assert_eq!(exact(&mut evaluator, "~5"), "-6");
+        assert_eq!(exact(&mut evaluator, "1 << 2 + 1"), "8");
+        assert_eq!(exact(&mut evaluator, "-16 >> 2"), "-4");
+        assert_eq!(exact(&mut evaluator, "1 << 100 >> 99"), "2");
+        assert_eq!(exact(&mut evaluator, "6.0 & 3"), "2");
+
+        for expression in ["1.5 & 1", "1 << -1", "1 << 10^7"] {
+            assert!(matches!(
+                evaluator.evaluate(expression).unwrap_err().kind,
+                ErrorKind::DomainError { .. }
+            ));
+        }
+
+        // Fixed word sizes wrap every integer result
+        evaluator.set_word_size(Some(WordSize::new(8, false)));
+        assert_eq!(exact(&mut evaluator, "255 + 1"), "0");
+        assert_eq!(exact(&mut evaluator, "~0"), "255");
+        assert_eq!(exact(&mut evaluator, "-1 >> 4"), "15");
+        assert_eq!(exact(&mut evaluator, "1 << 8"), "0");
+        assert_eq!(exact(&mut evaluator, "7 / 2"), "7/2");
+
+        evaluator.set_word_size(Some(WordSize::new(8, true)));
+        assert_eq!(exact(&mut evaluator, "127 + 1"), "-128");
+        assert_eq!(exact(&mut evaluator, "0xFF"), "-1");
+        assert_eq!(exact(&mut evaluator, "-1 >> 4"), "-1");
+
+        evaluator.set_word_size(Some(WordSize::new(32, true)));
+        assert_eq!(exact(&mut evaluator, "2^31"), "-2147483648");
+        evaluator.set_word_size(None);
+        assert_eq!(exact(&mut evaluator, "2^31"), "2147483648");
+
+        let n = evaluator.evaluate_value("-2").unwrap().to_integer().unwrap();
+        let bases: Vec<String> = Radix::ALL
+            .iter()
+            .map(|radix| programmer::format_radix(&n, *radix, Some(WordSize::new(16, true))))
+            .collect();
+        assert_eq!(bases, ["-2", "0xFFFE", "0o177776", "0b1111111111111110"]);
+    }
+
+    #[test]
+    fn test_units() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+
+        assert_eq!(show(&mut evaluator, "5 km + 300 m"), "5.3 km");
+        assert_eq!(show(&mut evaluator, "60 mph to m/s"), "26.8224 m/s");
+        assert_eq!(show(&mut evaluator, "9.81 m/s^2 * 80 kg"), "784.8 m*kg/s^2");
+        assert_eq!(show(&mut evaluator, "9.81 m/s^2 * 80 kg in N"), "784.8 N");
+        assert_eq!(show(&mut evaluator, "100 degC to degF"), "212 degF");
+        assert_eq!(show(&mut evaluator, "2 h + 30 min to min"), "150 min");
+        assert_eq!(show(&mut evaluator, "(3 m)^2"), "9 m^2");
+        assert_eq!(show(&mut evaluator, "1 / (4 s)"), "0.25 s^-1");
+        assert_eq!(show(&mut evaluator, "-2 kWh to MJ"), "-7.2 MJ");
+        assert_eq!(evaluator.evaluate("1 km / (1 m)").unwrap(), 1000.0);
+
+        // Quantities are ordinary values for variables and user functions
+        evaluator.evaluate_value("d = 5 km").unwrap();
+        evaluator.execute("speed(dist, time) = dist / time").unwrap();
+        assert_eq!(show(&mut evaluator, "speed(d, 20 min) to km/h"), "15 km/h");
+
+        let err = evaluator.evaluate("5 km + 3 s").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::IncompatibleUnits("km".to_string(), "s".to_string())
+        );
+        assert_eq!(err.span, Span::new(0, 10));
+        let err = evaluator.evaluate("5 km + 3").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::IncompatibleUnits("km".to_string(), "no unit".to_string())
+        );
+        assert!(matches!(
+            evaluator.evaluate("60 mph to kg").unwrap_err().kind,
+            ErrorKind::IncompatibleUnits(_, _)
+        ));
+
+        let err = evaluator.evaluate("3 kmh").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnknownUnit("kmh".to_string()));
+        assert_eq!(err.span, Span::new(2, 5));
+        let err = evaluator.evaluate("2 pi").unwrap_err();
+        assert_eq!(
+            err.suggestion.as_deref(),
+            Some("'pi' is a variable; use '*' to multiply by it")
+        );
+
+        for expression in ["sqrt(4 m^2)", "(3 m)!", "(2 m)^0.5"] {
+            assert!(matches!(
+                evaluator.evaluate(expression).unwrap_err().kind,
+                ErrorKind::DomainError { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_angle_mode() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+
+        // Radians by default; suffixed literals override the mode
+        assert_eq!(evaluator.angle_mode(), AngleMode::Radians);
+        assert_eq!(evaluator.evaluate("sin(90)").unwrap(), 90f64.sin());
+        assert_eq!(evaluator.evaluate("cos(90°)").unwrap(), 0.0);
+        assert_eq!(evaluator.evaluate("sin(pi)").unwrap(), 0.0);
+        assert_eq!(show(&mut evaluator, "sin(30deg)"), "0.5");
+        assert_eq!(show(&mut evaluator, "180°"), "3.1415926536");
+
+        evaluator.set_angle_mode(AngleMode::Degrees);
+        assert_eq!(evaluator.evaluate("sin(90)").unwrap(), 1.0);
+        assert_eq!(evaluator.evaluate("cos(90)").unwrap(), 0.0);
+        assert_eq!(evaluator.evaluate("sin(-540)").unwrap(), 0.0);
+        assert_eq!(show(&mut evaluator, "sin(30)"), "0.5");
+        assert_eq!(show(&mut evaluator, "tan(36000045)"), "1");
+        assert_eq!(show(&mut evaluator, "asin(0.5)"), "30");
+        assert_eq!(show(&mut evaluator, "acos(-1)"), "180");
+        assert_eq!(show(&mut evaluator, "atan2(1, 1)"), "45");
+        assert_eq!(show(&mut evaluator, "sin(1.5707963267948966rad)"), "1");
+        assert_eq!(show(&mut evaluator, "100grad"), "90");
+        assert_eq!(show(&mut evaluator, "1rad"), "57.2957795131");
+
+        evaluator.set_angle_mode(AngleMode::Gradians);
+        assert_eq!(evaluator.evaluate("sin(100)").unwrap(), 1.0);
+        assert_eq!(show(&mut evaluator, "asin(1)"), "100");
+        assert_eq!(show(&mut evaluator, "cos(90°)"), "0");
+
+        // Decimal mode converts with a precise pi
+        evaluator.set_angle_mode(AngleMode::Degrees);
+        evaluator.set_decimal_mode(Some(30));
+        assert_eq!(show(&mut evaluator, "sin(30)"), "0.5");
+        assert_eq!(
+            evaluator
+                .evaluate_value("atan(1)")
+                .unwrap()
+                .format(ComplexDisplay::Rectangular, 30),
+            "45"
+        );
+        evaluator.set_decimal_mode(None);
+
+        let err = evaluator.evaluate("30 deg").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnknownUnit("deg".to_string()));
+        assert_eq!(
+            err.suggestion.as_deref(),
+            Some("write angles without a space, e.g. 30deg")
+        );
+    }
+
+    #[test]
+    fn test_function_arities() {
+        let mut evaluator = Evaluator::new();
+
+        // Variadic functions
+        assert_eq!(evaluator.evaluate("max(1, 2, 3)").unwrap(), 3.0);
+        assert_eq!(evaluator.evaluate("min(4, -2, 7, 0)").unwrap(), -2.0);
+        assert_eq!(evaluator.evaluate("sum(1, 2, 3, 4)").unwrap(), 10.0);
+        assert_eq!(evaluator.evaluate("hypot(3, 4)").unwrap(), 5.0);
+        assert_eq!(evaluator.evaluate("hypot(2, 3, 6)").unwrap(), 7.0);
+        assert_eq!(evaluator.evaluate("gcd(12, 18, 27)").unwrap(), 3.0);
+        assert_eq!(evaluator.evaluate("lcm(4, 6, 10)").unwrap(), 60.0);
+        assert_eq!(evaluator.evaluate_value("avg(1, 2)").unwrap().to_string(), "3/2");
+        assert_eq!(
+            evaluator.evaluate_value("sum(0.1, 0.2, 1/3)").unwrap().to_string(),
+            "0.6333333333333333"
+        );
+
+        // Fixed arities
+        assert_eq!(evaluator.evaluate("pow(2, 10)").unwrap(), 1024.0);
+        assert_eq!(
+            evaluator.evaluate_value("pow(2, 100)").unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(evaluator.evaluate("atan2(1, 1)").unwrap(), std::f64::consts::FRAC_PI_4);
+        assert_eq!(evaluator.evaluate("trapezoid_area(3, 5, 2)").unwrap(), 8.0);
+        assert_eq!(evaluator.evaluate("heron_triangle_area(3, 4, 5)").unwrap(), 6.0);
+        assert_eq!(evaluator.evaluate("pythagorean_theorem(5, 12)").unwrap(), 13.0);
+        assert_eq!(evaluator.evaluate("is_nan(1)").unwrap(), 0.0);
+        assert_eq!(evaluator.evaluate("is_inf(1e308 * 10)").unwrap(), 1.0);
+        assert!(matches!(
+            evaluator.evaluate("heron_triangle_area(1, 2, 10)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+
+        // Optional arguments
+        assert_eq!(evaluator.evaluate("is_close(1, 1 + 1e-12)").unwrap(), 1.0);
+        assert_eq!(evaluator.evaluate("is_close(1, 1.01)").unwrap(), 0.0);
+        assert_eq!(evaluator.evaluate("is_close(1, 1.01, 0.1)").unwrap(), 1.0);
+        assert_eq!(evaluator.evaluate("is_close(0, 1e-6, 0, 1e-3)").unwrap(), 1.0);
+
+        // Zero-argument and random functions
+        for _ in 0..20 {
+            let x = evaluator.evaluate("random()").unwrap();
+            assert!((0.0..1.0).contains(&x));
+            let n = evaluator.evaluate("randint(1, 6)").unwrap();
+            assert!(n.fract() == 0.0 && (1.0..=6.0).contains(&n));
+            let u = evaluator.evaluate("uniform(-2, 2)").unwrap();
+            assert!((-2.0..2.0).contains(&u));
+        }
+        assert_eq!(evaluator.evaluate("randint(3, 3)").unwrap(), 3.0);
+        assert!(evaluator.evaluate("randint(6, 1)").is_err());
+        assert!(evaluator.evaluate("randint(1.5, 3)").is_err());
+
+        for (expression, expected) in [
+            ("random(0)", "0"),
+            ("hypot()", "at least 1"),
+            ("is_close(1)", "2 to 4"),
+            ("is_close(1, 2, 3, 4, 5)", "2 to 4"),
+            ("pow(2)", "2"),
+        ] {
+            match evaluator.evaluate(expression).unwrap_err().kind {
+                ErrorKind::ArityMismatch { expected: found, .. } => assert_eq!(found, expected, "{}", expression),
+                kind => panic!("expected an arity error for {}, got {:?}", expression, kind),
+            }
+        }
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut evaluator = Evaluator::new();
+
+        assert!((evaluator.evaluate("normcdf(1.96)").unwrap() - 0.975).abs() < 1e-4);
+        assert!((evaluator.evaluate("normcdf(130, 100, 15)").unwrap() - 0.97725).abs() < 1e-5);
+        assert!((evaluator.evaluate("norminv(normcdf(0.3))").unwrap() - 0.3).abs() < 1e-9);
+        assert!((evaluator.evaluate("1 - tcdf(2.228, 10)").unwrap() - 0.025).abs() < 1e-4);
+        assert!((evaluator.evaluate("binompmf(2, 4, 1/2)").unwrap() - 0.375).abs() < 1e-12);
+        assert!((evaluator.evaluate("fcdf(finv(0.9, 3, 7), 3, 7)").unwrap() - 0.9).abs() < 1e-6);
+
+        // Invalid parameters explain themselves instead of giving NaN
+        let err = evaluator.evaluate("normpdf(0, 0, -1)").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::DomainError {
+                name: "normpdf".to_string(),
+                message: "sigma must be positive, got -1".to_string(),
+            }
+        );
+        assert_eq!(err.span, Span::new(0, 17));
+        assert!(matches!(
+            evaluator.evaluate("binompmf(1, 5, 1.2)").unwrap_err().kind,
+            ErrorKind::DomainError { message, .. } if message == "p must be between 0 and 1, got 1.2"
+        ));
+        assert_eq!(evaluator.evaluate("normcdf(0, 0)").unwrap(), 0.5);
+        assert!(matches!(
+            evaluator.evaluate("tcdf(1)").unwrap_err().kind,
+            ErrorKind::ArityMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+
+        assert_eq!(show(&mut evaluator, "[1, 2, 3.5]"), "[1, 2, 3.5]");
+        assert_eq!(show(&mut evaluator, "[1, 2, 3] * 2 + [10, 20, 30]"), "[12, 24, 36]");
+        assert_eq!(show(&mut evaluator, "1 / [2, 4]"), "[1/2, 1/4]");
+        assert_eq!(show(&mut evaluator, "-[1, 2]^2"), "[-1, -4]");
+        assert_eq!(show(&mut evaluator, "[3, 4]!"), "[6, 24]");
+        assert_eq!(show(&mut evaluator, "sqrt([1, 4, 9])"), "[1, 2, 3]");
+        assert_eq!(show(&mut evaluator, "max([1, 7], 3)"), "7");
+        assert_eq!(show(&mut evaluator, "pow([2, 3], 2)"), "[4, 9]");
+
+        evaluator.execute("xs = [2, 4, 4, 4, 5, 5, 7, 9]").unwrap();
+        assert_eq!(show(&mut evaluator, "mean(xs)"), "5");
+        assert_eq!(show(&mut evaluator, "median(xs)"), "9/2");
+        assert_eq!(show(&mut evaluator, "mode(xs)"), "4");
+        assert_eq!(show(&mut evaluator, "sum(xs)"), "40");
+        assert_eq!(show(&mut evaluator, "prod([1, 2, 3, 4])"), "24");
+        assert_eq!(show(&mut evaluator, "min(xs)"), "2");
+        assert_eq!(show(&mut evaluator, "var(xs)"), "32/7");
+        assert_eq!(show(&mut evaluator, "stdev([0, 2, 4])"), "2");
+        assert_eq!(show(&mut evaluator, "percentile(xs, 25)"), "4");
+        assert_eq!(show(&mut evaluator, "quartiles([1, 2, 3, 4, 5])"), "[2, 3, 4]");
+        assert_eq!(show(&mut evaluator, "sort([3, 1.5, -2])"), "[-2, 1.5, 3]");
+        assert_eq!(show(&mut evaluator, "mean(xs - mean(xs))"), "0");
+
+        // Decimal mode keeps the statistics of decimals exact instead of going through floats
+        evaluator.set_decimal_mode(Some(20));
+        assert_eq!(show(&mut evaluator, "mean([0.1, 0.2, 0.3])"), "0.2");
+        assert_eq!(show(&mut evaluator, "var([0.1, 0.2, 0.3])"), "0.01");
+        assert_eq!(show(&mut evaluator, "median([0.3, 0.1, 0.4, 0.2])"), "0.25");
+        assert_eq!(show(&mut evaluator, "var([1, 2, 4])"), "2.3333333333");
+        assert_eq!(
+            evaluator.evaluate_value("mean([0.1, 0.2, 0.3])").unwrap().to_string(),
+            "0.2"
+        );
+        evaluator.set_decimal_mode(None);
+
+        // User functions apply element-wise through their operators
+        evaluator.execute("square(x) = x^2").unwrap();
+        assert_eq!(show(&mut evaluator, "square([1, 2, 3])"), "[1, 4, 9]");
+
+        let err = evaluator.evaluate_value("[1, 2] + [1, 2, 3]").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::DomainError {
+                name: "+".to_string(),
+                message: "lists have different lengths (2 and 3)".to_string(),
+            }
+        );
+        assert!(matches!(
+            evaluator.evaluate_value("mean([])").unwrap_err().kind,
+            ErrorKind::DomainError { message, .. } if message == "the list is empty"
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("[1, 2").unwrap_err().kind,
+            ErrorKind::UnbalancedParen(_)
+        ));
+        assert!(evaluator.evaluate("[1, 2]").is_err());
+
+        // Lists survive a round trip through the memory file
+        let mut memory = Memory::new();
+        memory.set_memory_value(evaluator.evaluate_value("[1/3, 2.5]").unwrap());
+        memory.set_variables(evaluator.list_variables().into_iter().collect());
+        let restored: Memory = toml::from_str(&toml::to_string_pretty(&memory).unwrap()).unwrap();
+        assert_eq!(restored.get_memory_value(), memory.get_memory_value());
+        assert_eq!(restored.get_variables()["xs"], memory.get_variables()["xs"]);
+        let legacy: Memory = toml::from_str("memory_value = 2.5\n[history]\n").unwrap();
+        assert_eq!(legacy.get_memory_value(), &Value::Real(2.5));
+    }
+
+    #[test]
+    fn test_csv_import() {
+        let dir = std::env::temp_dir().join(format!("calculator_max_csv_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("people.csv");
+        std::fs::write(&path, "name;Height (cm);weight\nAda;170;61,5\nBob;NA;80\nCy;160;x\n").unwrap();
+        let quoted = path.display().to_string().replace('\\', "/");
+
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+        assert_eq!(
+            show(&mut evaluator, &format!("import_csv(\"{}\", \"height (cm)\")", quoted)),
+            "[170, 160]"
+        );
+        assert_eq!(
+            show(&mut evaluator, &format!("mean(import_csv(\"{}\", 2))", quoted)),
+            "165"
+        );
+        let err = evaluator
+            .evaluate_value(&format!("import_csv(\"{}\", \"weight\")", quoted))
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::DomainError { message, .. } if message == "row 4, column 3: 'x' is not a number"
+        ));
+        assert!(evaluator.evaluate_value("\"a\" + 1").is_err());
+
+        // The GUI import binds every numeric column, so the bad weight column fails the whole file
+        let err = evaluator.import_csv(&path, &CsvOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "row 4, column 3: 'x' is not a number");
+        std::fs::write(&path, "name;Height (cm);weight\nAda;170;61,5\nBob;NA;80\n").unwrap();
+        let names = evaluator.import_csv(&path, &CsvOptions::default()).unwrap();
+        assert_eq!(names, ["Height_cm", "weight"]);
+        assert_eq!(show(&mut evaluator, "weight"), "[61.5, 80]");
+        assert_eq!(show(&mut evaluator, "sum(Height_cm)"), "170");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_curve_fitting() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+
+        evaluator.execute("xs = [0, 1, 2, 3]; ys = [3, 2, 3, 6]").unwrap();
+        assert_eq!(
+            show(&mut evaluator, "linreg([1, 2, 3], [1, 3, 4])"),
+            "[3/2, -1/3, 27/28]"
+        );
+        assert_eq!(show(&mut evaluator, "fit_poly(xs, ys, 2)"), "[1, -2, 3]");
+
+        // Assigning a fit defines the curve as a function of x
+        assert_eq!(
+            evaluator.execute("f = fit_poly(xs, ys, 2)").unwrap(),
+            Outcome::Defined("f".to_string())
+        );
+        assert_eq!(evaluator.get_function("f").unwrap().to_string(), "f(x) = x^2 - 2*x + 3");
+        assert_eq!(show(&mut evaluator, "f(3.5)"), "8.25");
+        assert_eq!(show(&mut evaluator, "g = fit_linear(xs, 2*xs + 1); g(10)"), "21");
+
+        evaluator
+            .execute("h = fit_exp([0, 1, 2], [2, 2 * e, 2 * e^2])")
+            .unwrap();
+        assert!((evaluator.evaluate("h(3)").unwrap() - 2.0 * 3f64.exp()).abs() < 1e-9);
+        evaluator.execute("p = fit_power([1, 2, 4], [3, 12, 48])").unwrap();
+        assert!((evaluator.evaluate("p(3)").unwrap() - 27.0).abs() < 1e-9);
+
+        let err = evaluator.execute("sin = fit_linear(xs, ys)").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::BuiltinFunction(_)));
+        // Constants stay read-only, so a fit cannot give pi a second meaning
+        let err = evaluator.execute("pi = fit_linear([1, 2, 3], [2, 4, 6])").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ReadOnly("pi".to_string()));
+        assert!(evaluator.get_function("pi").is_none());
+        assert_eq!(evaluator.evaluate("2 * pi").unwrap(), 2.0 * std::f64::consts::PI);
+        assert!(matches!(
+            evaluator.evaluate_value("fit_log([0, 1], [1, 2])").unwrap_err().kind,
+            ErrorKind::DomainError { message, .. } if message == "the x values must be positive, got 0"
+        ));
+    }
+
+    #[test]
+    fn test_matrices() {
+        let mut evaluator = Evaluator::new();
+        let show = |evaluator: &mut Evaluator, expression: &str| {
+            evaluator
+                .evaluate_value(expression)
+                .unwrap()
+                .format_with(&FormatOptions::default())
+        };
+
+        evaluator.execute("A = [[1, 2], [3, 4]]; B = [[0, 1], [1, 0]]").unwrap();
+        assert!(matches!(evaluator.get_variable("A"), Some(Value::Matrix(_))));
+        assert_eq!(show(&mut evaluator, "A + B"), "[[1, 3], [4, 4]]");
+        assert_eq!(show(&mut evaluator, "A - 1"), "[[0, 1], [2, 3]]");
+        assert_eq!(show(&mut evaluator, "2 * A"), "[[2, 4], [6, 8]]");
+        assert_eq!(show(&mut evaluator, "A * B"), "[[2, 1], [4, 3]]");
+        assert_eq!(show(&mut evaluator, "A * [1, 1]"), "[3, 7]");
+        assert_eq!(show(&mut evaluator, "A^2"), "[[7, 10], [15, 22]]");
+        assert_eq!(show(&mut evaluator, "A^-1 * A"), "[[1, 0], [0, 1]]");
+        assert_eq!(show(&mut evaluator, "-A / 2"), "[[-1/2, -1], [-3/2, -2]]");
+        assert_eq!(show(&mut evaluator, "sqrt([[1, 4], [9, 16]])"), "[[1, 2], [3, 4]]");
+        assert_eq!(show(&mut evaluator, "transpose(A)"), "[[1, 3], [2, 4]]");
+        assert_eq!(show(&mut evaluator, "det(A)"), "-2");
+        assert_eq!(show(&mut evaluator, "inverse(A)"), "[[-2, 1], [3/2, -1/2]]");
+        assert_eq!(show(&mut evaluator, "rank([[1, 2], [2, 4]])"), "1");
+        assert_eq!(show(&mut evaluator, "trace(A)"), "5");
+        assert_eq!(show(&mut evaluator, "linsolve(A, [5, 11])"), "[1, 2]");
+        assert_eq!(show(&mut evaluator, "eigenvalues([[2, 0], [0, 3]])"), "[3, 2]");
+        assert_eq!(show(&mut evaluator, "dot([1, 2, 3], [4, 5, 6])"), "32");
+        assert_eq!(show(&mut evaluator, "cross([1, 0, 0], [0, 1, 0])"), "[0, 0, 1]");
+        assert_eq!(show(&mut evaluator, "norm([1.5, 2])"), "2.5");
+        assert_eq!(show(&mut evaluator, "max(A)"), "4");
+        assert_eq!(show(&mut evaluator, "mean(A)"), "5/2");
+
+        // Ragged or mixed lists stay lists
+        assert_eq!(show(&mut evaluator, "[[1, 2], [3]]"), "[[1, 2], [3]]");
+        assert!(matches!(
+            evaluator.evaluate_value("[[1, 2], [3]]").unwrap(),
+            Value::List(_)
+        ));
+
+        let err = evaluator.evaluate_value("A + [[1, 2, 3]]").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::DomainError {
+                name: "+".to_string(),
+                message: "matrices have different sizes (2×2 and 1×3)".to_string(),
+            }
+        );
+        let err = evaluator.evaluate_value("A / B").unwrap_err();
+        assert_eq!(
+            err.suggestion.as_deref(),
+            Some("multiply by the inverse instead, e.g. A * inverse(B)")
+        );
+        assert!(matches!(
+            evaluator.evaluate_value("inverse([[1, 2], [2, 4]])").unwrap_err().kind,
+            ErrorKind::DomainError { message, .. } if message == "the matrix is singular"
+        ));
+
+        // Matrices survive a round trip through the memory file
+        let mut memory = Memory::new();
+        memory.set_memory_value(evaluator.evaluate_value("A / 3").unwrap());
+        memory.set_variables(evaluator.list_variables().into_iter().collect());
+        let restored: Memory = toml::from_str(&toml::to_string_pretty(&memory).unwrap()).unwrap();
+        assert_eq!(restored.get_memory_value(), memory.get_memory_value());
+        assert_eq!(restored.get_variables()["A"], memory.get_variables()["A"]);
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut evaluator = Evaluator::new();
+        let root = |value: Value| value.as_real().unwrap();
+
+        // Every root in the default interval, as a sorted list
+        let Value::List(roots) = evaluator.evaluate_value("solve(x^2 - 2 = 0, x)").unwrap() else {
+            panic!("expected both roots");
+        };
+        assert_eq!(roots.len(), 2);
+        assert!((root(roots[0].clone()) + 2f64.sqrt()).abs() < 1e-12);
+        assert!((root(roots[1].clone()) - 2f64.sqrt()).abs() < 1e-12);
+
+        // Newton's method from a guess
+        let x = root(evaluator.evaluate_value("solve(cos(x) = x, x, 0.5)").unwrap());
+        assert!((x - 0.739_085_133_215_160_6).abs() < 1e-12);
+
+        // Whole roots are exact, and the variable is only bound while solving
+        assert_eq!(
+            evaluator.evaluate_value("solve(x^3 = 27, x, 1)").unwrap().to_string(),
+            "3"
+        );
+        assert_eq!(
+            evaluator
+                .evaluate_value("solve(x^2 - 1 = 0, x, 0, 10)")
+                .unwrap()
+                .to_string(),
+            "1"
+        );
+        assert!(evaluator.get_variable("x").is_none());
+        evaluator.evaluate("x = 5").unwrap();
+        assert_eq!(evaluator.evaluate("solve(t^2 = x + 4, t, 1)").unwrap(), 3.0);
+        assert_eq!(evaluator.evaluate("x").unwrap(), 5.0);
+
+        // Mods and user functions work like any other function
+        // mods/test_correct.cmfun defines a.b.c(x, y) = x * y + 10
+        assert_eq!(
+            evaluator
+                .evaluate_value("solve(a.b.c(x, 2) = 30, x)")
+                .unwrap()
+                .to_string(),
+            "10"
+        );
+        evaluator.execute("f(k) = solve(y^2 = k, y, 1)").unwrap();
+        assert_eq!(evaluator.evaluate("f(16)").unwrap(), 4.0);
+
+        let err = evaluator.evaluate_value("solve(x^2 + 1 = 0, x)").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::NoConvergence {
+                name: "solve".to_string(),
+                message: "no root found in [-100, 100]".to_string(),
+            }
+        );
+        assert!(matches!(
+            evaluator.evaluate_value("solve(exp(x) = 0, x, 1)").unwrap_err().kind,
+            ErrorKind::NoConvergence { .. }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("solve(x^2 = 2, 3)").unwrap_err().kind,
+            ErrorKind::DomainError { .. }
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("solve(x = z, x)").unwrap_err().kind,
+            ErrorKind::UnknownVariable(name) if name == "z"
+        ));
+        let err = evaluator.evaluate_value("sqrt(x = 2)").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MisplacedEquation);
+        assert_eq!(err.span, Span::new(5, 10));
+    }
+
+    #[test]
+    fn test_polynomials() {
+        let mut evaluator = Evaluator::new();
+        let mut show = |input: &str| evaluator.evaluate_value(input).unwrap().to_string();
+
+        // Every root of the quadratic in mods/README.md, complex ones included
+        assert_eq!(show("polyroots([1, -5, 6])"), "[2, 3]");
+        assert_eq!(show("polyroots([1, 2, 5])"), "[-1 + 2i, -1 - 2i]");
+        assert_eq!(show("polyroots([1, -3, 2])"), "[1, 2]");
+
+        assert_eq!(show("polymul([1, 1], [1, -1])"), "[1, 0, -1]");
+        assert_eq!(show("polydiv([1, 0, -1], [1, 1])"), "[[1, -1], [0]]");
+        assert_eq!(show("polyder([1, 0, -1])"), "[2, 0]");
+        assert_eq!(show("polyint(polyder([1, 0, -1]), -1)"), "[1, 0, -1]");
+        assert_eq!(show("polyval([1, -3, 2], 4)"), "6");
+        assert_eq!(show("polyval([1, -3, 2], [0, 1/2])"), "[2, 3/4]");
+
+        // Fitted coefficients can be evaluated and solved directly
+        assert_eq!(show("polyroots(fit_poly([0, 1, 2, 3], [2, 0, 0, 2], 2))"), "[1, 2]");
+
+        assert!(matches!(
+            evaluator.evaluate_value("polyroots([0])").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "polyroots"
+        ));
+
+        // Decimal coefficients are exact, so rational roots stay exact in decimal mode
+        evaluator.set_decimal_mode(Some(20));
+        let mut show = |input: &str| evaluator.evaluate_value(input).unwrap().to_string();
+        assert_eq!(show("polyroots([1, -3, 2])"), "[1, 2]");
+        assert_eq!(show("polyroots([2, -0.5])"), "[0.25]");
+        assert_eq!(show("polyval([0.1, 0.2], 3)"), "0.5");
+    }
+
+    #[test]
+    fn test_calculus() {
+        let mut evaluator = Evaluator::new();
+        let real = |value: Value| value.as_real().unwrap();
+
+        // Sums and products over a bound variable are exact, and plain sums still add their arguments
+        assert_eq!(
+            evaluator.evaluate_value("sum(1/k^2, k, 1, 3)").unwrap().to_string(),
+            "49/36"
+        );
+        assert_eq!(evaluator.evaluate_value("prod(k, k, 1, 5)").unwrap().to_string(), "120");
+        assert_eq!(
+            evaluator.evaluate_value("sum(0*k + 2, k, 1, 10)").unwrap().to_string(),
+            "20"
+        );
+        assert_eq!(evaluator.evaluate_value("prod(k, k, 1, 0)").unwrap().to_string(), "1");
+        assert_eq!(evaluator.evaluate("sum(1, 2, 3)").unwrap(), 6.0);
+        // A term that does not mention k makes a plain sum, whether or not a variable k exists
+        assert!(matches!(
+            evaluator.evaluate_value("sum(5, k, 1, 10)").unwrap_err().kind,
+            ErrorKind::UnknownVariable(name) if name == "k"
+        ));
+        evaluator.execute("k = 4").unwrap();
+        assert_eq!(evaluator.evaluate("sum(1, k, 2, 3)").unwrap(), 10.0);
+        assert_eq!(evaluator.evaluate("prod(2, k, 1, 3)").unwrap(), 24.0);
+        evaluator.execute("a = 1; b = 2; c = 3; d = 4").unwrap();
+        assert_eq!(evaluator.evaluate("sum(a, b, c, d)").unwrap(), 10.0);
+        assert_eq!(evaluator.evaluate("sum(k, k, 1, 100)").unwrap(), 5050.0);
+        assert_eq!(evaluator.evaluate("k").unwrap(), 4.0);
+
+        let area = real(evaluator.evaluate_value("integrate(x^2, x, 0, 3)").unwrap());
+        assert!((area - 9.0).abs() < 1e-12);
+        let area = real(evaluator.evaluate_value("integrate(sin(x), x, pi, 0)").unwrap());
+        assert!((area + 2.0).abs() < 1e-12);
+        let slope = real(evaluator.evaluate_value("deriv(sin(x), x, 0)").unwrap());
+        assert!((slope - 1.0).abs() < 1e-10);
+        assert!(evaluator.get_variable("x").is_none());
+
+        // Mods and user functions work like any other function
+        // mods/test_correct.cmfun defines a.b.c(x, y) = x * y + 10
+        let area = real(evaluator.evaluate_value("integrate(a.b.c(x, 2), x, 0, 1)").unwrap());
+        assert!((area - 11.0).abs() < 1e-12);
+        evaluator.execute("f(t) = deriv(x^3, x, t)").unwrap();
+        assert!((evaluator.evaluate("f(2)").unwrap() - 12.0).abs() < 1e-9);
+        evaluator.execute("harmonic(n) = sum(1/j, j, 1, n)").unwrap();
+        assert_eq!(evaluator.evaluate_value("harmonic(4)").unwrap().to_string(), "25/12");
+
+        assert!(matches!(
+            evaluator.evaluate_value("integrate(1/x, x, 0, 1)").unwrap_err().kind,
+            ErrorKind::NoConvergence { name, .. } if name == "integrate"
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("prod(k, k, 1, 1/2)").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "prod"
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("deriv(x^2, 2, 1)").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "deriv"
+        ));
+
+        // Bounds are decimals in decimal mode, which count as whole numbers when they are
+        evaluator.set_decimal_mode(Some(20));
+        assert_eq!(evaluator.evaluate_value("sum(j, j, 1, 10)").unwrap().to_string(), "55");
+        assert_eq!(evaluator.evaluate_value("prod(j, j, 1, 5)").unwrap().to_string(), "120");
+        assert_eq!(
+            evaluator.evaluate_value("sum(j/10, j, 1, 3)").unwrap().to_string(),
+            "0.6"
+        );
+        assert!(matches!(
+            evaluator.evaluate_value("sum(j, j, 1, 2.5)").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "sum"
+        ));
+    }
+
+    #[test]
+    fn test_symbolic_derivatives() {
+        let mut evaluator = Evaluator::new();
+        let derive = |evaluator: &mut Evaluator, input: &str| match evaluator.evaluate_value(input).unwrap() {
+            Value::Text(text) => text,
+            value => panic!("expected text, got {}", value),
+        };
+
+        assert_eq!(derive(&mut evaluator, "d/dx(x^2 * sin(x))"), "2*x*sin(x) + x^2*cos(x)");
+        assert_eq!(
+            derive(&mut evaluator, "diff(x^3 + 2*x^2 - 5*x + 7, x)"),
+            "3*x^2 + 4*x - 5"
+        );
+        assert_eq!(derive(&mut evaluator, "d/dx(sqrt(x))"), "1/(2*sqrt(x))");
+        assert_eq!(derive(&mut evaluator, "d/dx(exp(2*x) + ln(x))"), "2*exp(2*x) + 1/x");
+        assert_eq!(derive(&mut evaluator, "d/dx(tan(x))"), "1/cos(x)^2");
+        assert_eq!(derive(&mut evaluator, "d/dx(2^x)"), "2^x*ln(2)");
+        assert_eq!(derive(&mut evaluator, "d/dx(x^x)"), "x^x*(ln(x) + 1)");
+        assert_eq!(derive(&mut evaluator, "d/dx(pi)"), "0");
+
+        // Other names stay symbols, whatever their value
+        evaluator.execute("k = 3").unwrap();
+        assert_eq!(derive(&mut evaluator, "d/dt(k*t^2)"), "2*k*t");
+
+        // User functions and mods are expanded
+        // mods/test_correct.cmfun defines a.b.c(x, y) = x * y + 10
+        evaluator.execute("f(t) = t^2 + 1").unwrap();
+        assert_eq!(derive(&mut evaluator, "d/dx(f(3*x))"), "18*x");
+        assert_eq!(derive(&mut evaluator, "d/dx(a.b.c(x, x))"), "2*x");
+
+        // Trigonometric functions follow the angle mode
+        evaluator.set_angle_mode(AngleMode::Degrees);
+        assert_eq!(derive(&mut evaluator, "d/dx(sin(x))"), "pi*cos(x)/180");
+        evaluator.set_angle_mode(AngleMode::Radians);
+
+        let err = evaluator.evaluate_value("d/dx(gamma(x) + 1)").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::DomainError {
+                name: "diff".to_string(),
+                message: "no symbolic derivative of gamma".to_string(),
+            }
+        );
+        assert_eq!(err.span, Span::new(5, 13));
+        assert!(matches!(
+            evaluator.evaluate_value("diff(x^2, 2)").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "diff"
+        ));
+    }
+
+    #[test]
+    fn test_ode() {
+        let mut evaluator = Evaluator::new();
+        let table = |evaluator: &mut Evaluator, input: &str| match evaluator.evaluate_value(input).unwrap() {
+            Value::Matrix(rows) => rows
+                .iter()
+                .map(|row| row.iter().map(|value| value.as_real().unwrap()).collect::<Vec<f64>>())
+                .collect::<Vec<_>>(),
+            value => panic!("expected a table, got {}", value),
+        };
+
+        // Evaluator variables are used in the right-hand side; a step gives fixed RK4 steps
+        evaluator.execute("k = 2").unwrap();
+        let rows = table(&mut evaluator, "ode(dy/dt = -k*y, y0 = 1, t = 0..1, 0.1)");
+        assert_eq!(rows.len(), 11);
+        assert_eq!(rows[0], vec![0.0, 1.0]);
+        assert!((rows[10][0] - 1.0).abs() < 1e-15);
+        assert!((rows[10][1] - (-2f64).exp()).abs() < 1e-5);
+
+        // Without a step the adaptive method meets its tolerance
+        let rows = table(&mut evaluator, "ode(dy/dt = -k*y, y0 = 1, t = 0..10)");
+        let last = rows.last().unwrap();
+        assert_eq!(last[0], 10.0);
+        assert!((last[1] - (-20f64).exp()).abs() < 1e-10);
+        assert!(evaluator.get_variable("y").is_none());
+
+        // Systems have a column per variable, in the order of their equations
+        let rows = table(&mut evaluator, "ode(dx/dt = v, dv/dt = -x, x0 = 1, v = 0, t = 0..pi)");
+        let last = rows.last().unwrap();
+        assert_eq!(last.len(), 3);
+        assert!((last[1] + 1.0).abs() < 1e-7 && last[2].abs() < 1e-7);
+
+        // Mods and user functions work like any other function
+        // mods/test_correct.cmfun defines a.b.c(x, y) = x * y + 10
+        evaluator.execute("decay(y) = -y").unwrap();
+        let rows = table(
+            &mut evaluator,
+            "ode(dy/ds = (a.b.c(y, -1) - 10 + decay(y)) / 2, y0 = 1, s = 0..1)",
+        );
+        assert!((rows.last().unwrap()[1] - (-1f64).exp()).abs() < 1e-8);
+
+        let err = evaluator.evaluate_value("ode(dy/dt = -y, t = 0..1)").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::DomainError {
+                name: "ode".to_string(),
+                message: "the initial value of y is missing".to_string(),
+            }
+        );
+        assert!(matches!(
+            evaluator.evaluate_value("ode(dy/dt = y^2, y0 = 1, t = 0..2)").unwrap_err().kind,
+            ErrorKind::NoConvergence { name, .. } if name == "ode"
+        ));
+        assert!(matches!(
+            evaluator.evaluate_value("ode(dy/dt = -y, y0 = 1, t = 0..1, 0)").unwrap_err().kind,
+            ErrorKind::DomainError { name, .. } if name == "ode"
+        ));
+        let err = evaluator.evaluate_value("max(0..10)").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MisplacedRange);
+        assert_eq!(err.span, Span::new(4, 9));
+    }
 }